@@ -19,80 +19,224 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::{thread, time};
+use std::path::PathBuf;
+use std::{process, thread, time};
 
 use clap::{Arg, App};
 
+use glib::translate::ToGlib;
+
 use gst::{gst_element_error, gst_element_warning};
 use gst::prelude::*;
 
-fn main() {
-    let matches = App::new("me-tv-record")
-        .version(env!("CARGO_PKG_VERSION"))
-        .author("Russel Winder <russel@winder.org.uk>")
-        .about("Record a channel from now for a duration to create an MPEG4 file.
+use me_tv::{epg_event, event_recording, multiplexes, recording_profiles};
 
-A channel name and a duration must be provided.
-")
-        .arg(Arg::with_name("adapter")
-            .short("a")
-            .long("adapter")
-            .value_name("NUMBER")
-            .help("Sets the adapter number to use.")
-            .takes_value(true)
-            .default_value("0"))
-        .arg(Arg::with_name("frontend")
-            .short("f")
-            .long("frontend")
-            .value_name("NUMBER")
-            .help("Sets the frontend number to use.")
-            .takes_value(true)
-            .default_value("0"))
-        .arg(Arg::with_name("channel")
-            .short("c")
-            .long("channel")
-            .value_name("CHANNEL")
-            .help("Sets the channel name, must be specified, no default.")
-            .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("duration")
-            .short("d")
-            .long("duration")
-            .value_name("TIME")
-            .help("Sets the duration of recording in minutes, must be specified, no default.")
-            .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("output")
-            .short("o")
-            .long("output")
-            .value_name("PATH")
-            .help("Path to output file, must be specified, no default.")
-            .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("verbose")
-            .short("v")
-            .long("verbose")
-            .help("sets verbose mode"))
-        .get_matches();
-    let be_verbose = matches.is_present("verbose");
-    let adapter = matches.value_of("adapter").unwrap().parse::<u8>().expect("Couldn't parse adapter value as a positive integer.");
-    let frontend = matches.value_of("frontend").unwrap().parse::<u8>().expect("Couldn't parse frontend value as a positive integer.");
-    let channel = matches.value_of("channel").unwrap();
-    let duration = matches.value_of("duration").unwrap().parse::<u32>().expect("Couldn't parse the provided duration as a positive integer.");
-    let output_path = matches.value_of("output").unwrap();
-    if be_verbose {
-        println!("Recording channel '{}' for {} minutes on adapter {} frontend {}.", channel, duration, adapter, frontend);
+use event_recording::{Action, EventRecording, EventStatus};
+use multiplexes::MultiplexChannel;
+use recording_profiles::RecordingProfile;
+
+/// The time between checks of whether the recording of an event should start or stop.
+const EVENT_TICK_INTERVAL: u64 = 1;
+
+/// Return the paths of the output files for the channels. Either there is one path per
+/// channel, or there is a single path and each channel name is appended to it. The
+/// extension is added to any path that does not have one.
+fn output_paths(outputs: &[&str], channels: &[&str], extension: &str) -> Result<Vec<String>, String> {
+    let with_extension = |mut path: PathBuf| {
+        if path.extension().is_none() {
+            path.set_extension(extension);
+        }
+        path.to_str().unwrap().to_string()
+    };
+    if outputs.len() == channels.len() {
+        Ok(outputs.iter().map(|output| with_extension(PathBuf::from(output))).collect())
+    } else if outputs.len() == 1 {
+        let path = with_extension(PathBuf::from(outputs[0]));
+        let path = PathBuf::from(path);
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let extension = path.extension().unwrap().to_str().unwrap();
+        Ok(channels.iter()
+            .map(|channel| path.with_file_name(format!("{} – {}.{}", stem, channel.replace('/', "-"), extension)).to_str().unwrap().to_string())
+            .collect())
+    } else {
+        Err(format!("There are {} channels but {} output paths, give either one output path or one per channel.", channels.len(), outputs.len()))
+    }
+}
+
+/// Make an element, saying which one could not be made if it cannot, usually because the
+/// GStreamer plugin providing it is not installed.
+fn make_element(factory_name: &str) -> Result<gst::Element, String> {
+    gst::ElementFactory::make(factory_name, None)
+        .map_err(|_| format!("Cannot make a {} element, is the GStreamer plugin providing it installed?", factory_name))
+}
+
+/// Make the source element for a channel, a dvbbasebin.
+fn make_source(channel: &str) -> Result<gst::Element, String> {
+    gst::Element::make_from_uri(gst::URIType::Src, &format!("dvb://{}", channel), None)
+        .map_err(|e| format!("Cannot make a source for the channel '{}' – {}", channel, e))
+}
+
+/// Make a filesink element writing to the output file.
+fn make_filesink(output_path: &str) -> Result<gst::Element, String> {
+    let element = make_element("filesink")?;
+    element.set_property("location", &output_path).map_err(|e| format!("Cannot set the location {} of the filesink – {}", output_path, e))?;
+    Ok(element)
+}
+
+/// Set the adapter and frontend numbers on a dvbbasebin element if they are not already as required.
+fn set_adapter_and_frontend(element: &gst::Element, adapter_number: u8, frontend_number: u8) {
+    let current_adapter_number = element
+        .get_property("adapter")
+        .expect("Could not retrieve adapter number Value")
+        .get::<i32>()
+        .expect("Could not get the i32 value from the adapter number Value")
+        .expect("Option on u32 returned None") as u8;
+    let current_frontend_number = element
+        .get_property("frontend")
+        .expect("Could not retrieve frontend number Value.")
+        .get::<i32>()
+        .expect("Could not get the i32 value from the frontend number Value")
+        .expect ("Option on u32 returned None") as u8;
+    if current_adapter_number != adapter_number {
+        element.set_property("adapter", &(adapter_number as i32)).expect("Could not set adapter number on dvbsrc element");
+    }
+    if current_frontend_number != frontend_number {
+        element.set_property("frontend", &(frontend_number as i32)).expect("Could not set frontend number of dvbsrc element");
+    }
+}
+
+/// Create a pipeline that writes the transport stream of the channel untouched to the output file.
+///
+/// The source element for a dvb:// URI is a dvbbasebin which is set up with the program
+/// number of the channel and so only passes the PAT, the PMT, and the elementary streams
+/// listed in the PMT: video, audio, subtitles, and teletext.
+fn create_transport_stream_pipeline(channel: &str, adapter: u8, frontend: u8, output_path: &str) -> Result<gst::Pipeline, String> {
+    //
+    // Construct the GStreamer graph described by:
+    //
+    //    gst-launch-1.0 -e dvbbasebin <properties set from channel> ! queue ! filesink location=<output-path>
+    //
+    let pipeline = gst::Pipeline::new(None);
+    let source = make_source(channel)?;
+    set_adapter_and_frontend(&source, adapter, frontend);
+    let queue = make_element("queue")?;
+    let filesink = make_filesink(output_path)?;
+    pipeline.add_many(&[&source, &queue, &filesink]).map_err(|e| format!("Could not add the elements to the pipeline – {}", e))?;
+    gst::Element::link_many(&[&source, &queue, &filesink]).map_err(|e| format!("Could not link the elements of the pipeline – {}", e))?;
+    Ok(pipeline)
+}
+
+/// Create a pipeline that writes the transport stream of the channel untouched to the
+/// output file while an event is being broadcast, returning the pipeline and the valve
+/// that is opened when the event starts.
+///
+/// The source element also parses the EIT sections and posts them on the bus, this is
+/// how the event is followed.
+fn create_event_pipeline(channel: &str, adapter: u8, frontend: u8, output_path: &str) -> Result<(gst::Pipeline, gst::Element), String> {
+    //
+    // Construct the GStreamer graph described by:
+    //
+    //    gst-launch-1.0 -e dvbbasebin <properties set from channel> ! valve drop=true ! queue ! filesink location=<output-path>
+    //
+    let pipeline = gst::Pipeline::new(None);
+    let source = make_source(channel)?;
+    set_adapter_and_frontend(&source, adapter, frontend);
+    let valve = make_element("valve")?;
+    valve.set_property("drop", &true).map_err(|e| format!("Cannot set drop on the valve – {}", e))?;
+    let queue = make_element("queue")?;
+    let filesink = make_filesink(output_path)?;
+    pipeline.add_many(&[&source, &valve, &queue, &filesink]).map_err(|e| format!("Could not add the elements to the pipeline – {}", e))?;
+    gst::Element::link_many(&[&source, &valve, &queue, &filesink]).map_err(|e| format!("Could not link the elements of the pipeline – {}", e))?;
+    Ok((pipeline, valve))
+}
+
+/// Return the events of an EIT section if it is for the service. Events with an undefined
+/// start time, as NVOD reference events have, are left out.
+fn event_statuses(section: &gst_mpegts::Section, service_id: u16) -> Option<Vec<EventStatus>> {
+    // The service_id of the events in an EIT section is carried as the table_id_extension.
+    if section.get_subtable_extension() != service_id {
+        return None;
     }
+    let eit = section.get_eit()?;
+    Some(eit.get_events().iter()
+        .filter_map(|event| {
+            let start_time = event.get_start_time();
+            if ! start_time.has_time() {
+                return None;
+            }
+            Some(EventStatus {
+                event_id: event.get_event_id(),
+                start_time: start_time.to_g_date_time()?.to_unix(),
+                duration: event.get_duration(),
+                running_status: epg_event::RunningStatus::from(event.get_running_status().to_glib() as u8),
+            })
+        })
+        .collect())
+}
+
+/// Start or stop writing the recording of an event.
+fn apply_event_action(action: Action, pipeline: &gst::Pipeline, valve: &gst::Element, be_verbose: bool) {
+    match action {
+        Action::Start => {
+            if be_verbose {
+                println!("The event has started, recording.");
+            }
+            valve.set_property("drop", &false).expect("cannot set drop on the valve");
+        },
+        Action::Stop => {
+            if be_verbose {
+                println!("The event has finished, stopping the recording.");
+            }
+            pipeline.send_event(gst::event::Eos::new());
+        },
+        Action::Nothing => {},
+    }
+}
+
+/// Create a pipeline that writes the transport streams of several channels on the same
+/// multiplex untouched to separate files using a single tuner.
+fn create_multi_service_pipeline(channels: &[MultiplexChannel], adapter: u8, frontend: u8, output_paths: &[String]) -> Result<gst::Pipeline, String> {
     //
     // Construct the GStreamer graph described by:
     //
+    //    gst-launch-1.0 -e dvbbasebin <properties set from channel> program-numbers=<id>:<id> name=d
+    //        d.program_<id> ! queue ! filesink location=<output-path>
+    //        d.program_<id> ! queue ! filesink location=<output-path>
+    //
+    let pipeline = gst::Pipeline::new(None);
+    let source = make_source(&channels[0].name)?;
+    set_adapter_and_frontend(&source, adapter, frontend);
+    // The dvb:// URI handler only sets the program number of the channel in the URI.
+    let program_numbers = channels.iter().map(|c| c.service_id.to_string()).collect::<Vec<String>>().join(":");
+    source.set_property("program-numbers", &program_numbers).map_err(|e| format!("Cannot set program-numbers on the source – {}", e))?;
+    pipeline.add(&source).map_err(|e| format!("Could not add the source to the pipeline – {}", e))?;
+    for (channel, output_path) in channels.iter().zip(output_paths.iter()) {
+        let queue = make_element("queue")?;
+        let filesink = make_filesink(output_path)?;
+        pipeline.add_many(&[&queue, &filesink]).map_err(|e| format!("Could not add the elements to the pipeline – {}", e))?;
+        queue.link(&filesink).map_err(|e| format!("Could not link the elements of the pipeline – {}", e))?;
+        let program_pad = source.get_request_pad(&format!("program_{}", channel.service_id))
+            .ok_or_else(|| format!("The source has no pad for program {} of channel '{}'.", channel.service_id, channel.name))?;
+        program_pad.link(&queue.get_static_pad("sink").unwrap()).map_err(|e| format!("Could not link program {} to its queue – {:?}", channel.service_id, e))?;
+    }
+    Ok(pipeline)
+}
+
+/// Create a pipeline that decodes the channel and re-encodes it as per the profile.
+///
+/// A stream for which the profile has no encoder is discarded. The descriptions in the
+/// profile are checked before the pipeline is built so that a mistyped profile is reported
+/// rather than failing once the streams of the channel appear.
+fn create_transcoding_pipeline(channel: &str, adapter: u8, frontend: u8, output_path: &str, profile: &RecordingProfile) -> Result<gst::Pipeline, String> {
+    //
+    // Construct the GStreamer graph described by, for example:
+    //
     //    gst-launch-1.0 -e uridecodebin uri=dvb://<channel> name=d ! queue ! x264enc ! mp4mux name=m ! filesink location=<output-path> d. ! queue ! avenc_ac3 ! m.
     //
-    gst::init().unwrap();
     let pipeline = gst::Pipeline::new(None);
     let uridecodebin = {
-        let element = gst::ElementFactory::make("uridecodebin", None).expect("cannot make uridecodebin");
-        element.set_property("uri", &format!("dvb://{}", channel)).expect("cannot set uri property on uridecodebin");
+        let element = make_element("uridecodebin")?;
+        element.set_property("uri", &format!("dvb://{}", channel)).map_err(|e| format!("Cannot set the uri of the uridecodebin – {}", e))?;
         element.connect("source-setup",  false, {
             let adapter_number = adapter;
             let frontend_number = frontend;
@@ -103,39 +247,29 @@ A channel name and a duration must be provided.
                     .expect("Option on Element was None");
                 if let Some(element_factory) = element.get_factory() {
                     if element_factory.get_name() == "dvbbasebin" {
-                        let current_adapter_number = element
-                            .get_property("adapter")
-                            .expect("Could not retrieve adapter number Value")
-                            .get::<i32>()
-                            .expect("Could not get the i32 value from the adapter number Value")
-                            .expect("Option on u32 returned None") as u8;
-                        let current_frontend_number = element
-                            .get_property("frontend")
-                            .expect("Could not retrieve frontend number Value.")
-                            .get::<i32>()
-                            .expect("Could not get the i32 value from the frontend number Value")
-                            .expect ("Option on u32 returned None") as u8;
-                        if current_adapter_number != adapter_number {
-                            element.set_property("adapter", &(adapter_number as i32)).expect("Could not set adapter number on dvbsrc element");
-                        }
-                        if current_frontend_number != adapter_number {
-                            element.set_property("frontend", &(frontend_number as i32)).expect("Could not set frontend number of dvbsrc element");
-                        }
+                        set_adapter_and_frontend(&element, adapter_number, frontend_number);
                     }
                 }
                 None
             }
-        }).expect("Could not connect a handler to the source-setup signal.");
+        }).map_err(|e| format!("Could not connect a handler to the source-setup signal – {}", e))?;
         element
     };
-    let mp4mux = gst::ElementFactory::make("mp4mux", None).expect("cannot make mp4mux");
-    let filesink = {
-        let element = gst::ElementFactory::make("filesink", None).expect("cannot make filesrc");
-        element.set_property("location", &output_path).expect("cannot set location for filesrc");
-        element
+    let muxer = match profile.muxer {
+        Some(ref muxer) => gst::parse_launch(muxer).map_err(|error| format!("The muxer '{}' of the recording profile '{}' is not valid: {}", muxer, &profile.name, error))?,
+        None => return Err(format!("The recording profile '{}' has no muxer.", &profile.name)),
     };
-    pipeline.add_many(&[&uridecodebin, &mp4mux, &filesink]).expect("could not add elements to pipeline");
-    gst::Element::link_many(&[&mp4mux, &filesink]).expect("could not link elements in pipeline");
+    for (kind, description) in [("video", &profile.video), ("audio", &profile.audio)].iter() {
+        if let Some(description) = description {
+            gst::parse_bin_from_description(description, true)
+                .map_err(|error| format!("The {} encoder '{}' of the recording profile '{}' is not valid: {}", kind, description, &profile.name, error))?;
+        }
+    }
+    let video_description = profile.video.clone();
+    let audio_description = profile.audio.clone();
+    let filesink = make_filesink(output_path)?;
+    pipeline.add_many(&[&uridecodebin, &muxer, &filesink]).map_err(|e| format!("Could not add the elements to the pipeline – {}", e))?;
+    gst::Element::link_many(&[&muxer, &filesink]).map_err(|e| format!("Could not link the elements of the pipeline – {}", e))?;
     // Heed the warnings about strong references, circular references and memory leaks.
     let pipeline_weak_ref = pipeline.downgrade();
     uridecodebin.connect_pad_added(move |d_b, src_pad| {
@@ -158,34 +292,300 @@ A channel name and a duration must be provided.
                 },
             }
         };
-        let insert_sink = |is_audio, is_video| -> Result<(), ()> {
+        let insert_sink = |is_audio, is_video| -> Result<(), String> {
             if is_audio && is_video { panic!("sink is both audio and video at the same time"); }
             if ! is_audio && ! is_video { return Ok(()); }
-            let queue = gst::ElementFactory::make("queue", None).expect("cannot make a queue");
-            let new_element = if is_audio {
-                gst::ElementFactory::make("avenc_ac3", None).expect("cannot make a avenc_ac3")
-            } else {
-                gst::ElementFactory::make("x264enc", None).expect("cannot make a x264enc")
+            let queue = make_element("queue")?;
+            let description = if is_audio { &audio_description } else { &video_description };
+            let description = match description {
+                Some(description) => description,
+                None => {
+                    let fakesink = make_element("fakesink")?;
+                    pipeline.add(&fakesink).map_err(|e| format!("could not add fakesink to pipeline – {}", e))?;
+                    fakesink.sync_state_with_parent().map_err(|e| format!("could not sync state of fakesink with parent – {}", e))?;
+                    src_pad.link(&fakesink.get_static_pad("sink").unwrap()).map_err(|e| format!("linking src_pad to fakesink failed – {:?}", e))?;
+                    return Ok(());
+                },
             };
+            let new_element = gst::parse_bin_from_description(description, true)
+                .map_err(|error| format!("cannot make the encoder '{}': {}", description, error))?
+                .upcast::<gst::Element>();
             let elements = &[&queue, &new_element];
-            pipeline.add_many(elements).expect("could not add elements to pipeline");
-            gst::Element::link_many(elements).expect("could not link elements in pipeline");
+            pipeline.add_many(elements).map_err(|e| format!("could not add elements to pipeline – {}", e))?;
+            gst::Element::link_many(elements).map_err(|e| format!("could not link elements in pipeline – {}", e))?;
             for e in elements {
-                e.sync_state_with_parent().expect("could not sync state of elements with parent");
+                e.sync_state_with_parent().map_err(|e| format!("could not sync state of elements with parent – {}", e))?;
             }
-            let sink_pad = queue.get_static_pad("sink").expect("video queue has no sink pad");
-            src_pad.link(&sink_pad).expect("linking src_pad to sink_pad of new queue failed");
-            let new_element_src_pad = new_element.get_static_pad("src").expect("new element has no src pad");
+            let sink_pad = queue.get_static_pad("sink").ok_or("queue has no sink pad")?;
+            src_pad.link(&sink_pad).map_err(|e| format!("linking src_pad to sink_pad of new queue failed – {:?}", e))?;
+            let new_element_src_pad = new_element.get_static_pad("src").ok_or_else(|| format!("the encoder '{}' has no src pad", description))?;
             let sink_pad_template = if is_audio { "audio_%u" } else { "video_%u" };
-            let mp4mux_sink_pad = mp4mux.get_request_pad(sink_pad_template).expect(&format!("mp4mux has no {} sink pad", sink_pad_template));
-            new_element_src_pad.link(&mp4mux_sink_pad).expect("linking new element to mp4mux failed.");
+            let muxer_sink_pad = muxer.get_request_pad(sink_pad_template).ok_or_else(|| format!("muxer has no {} sink pad", sink_pad_template))?;
+            new_element_src_pad.link(&muxer_sink_pad).map_err(|e| format!("linking new element to muxer failed – {:?}", e))?;
             Ok(())
         };
         if let Err(err) = insert_sink(is_audio, is_video) {
             //  TODO why are the parentheses needed around the string?
-            gst_element_error!(d_b, gst::LibraryError::Failed, ("Failed to insert sink"), ["{:?}", err]);
+            gst_element_error!(d_b, gst::LibraryError::Failed, ("Failed to insert sink"), ["{}", err]);
+        }
+    });
+    Ok(pipeline)
+}
+
+fn main() {
+    let matches = App::new("me-tv-record")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("Russel Winder <russel@winder.org.uk>")
+        .about("Record a channel from now for a duration to create an MPEG-TS file, or
+another format if a transcoding recording profile is requested.
+
+A channel name and a duration must be provided.
+
+Several channels can be recorded at once, each to its own file, if they are
+all on the same multiplex and the ts-passthrough profile is used. Either give
+an output path per channel or a single output path to which the channel names
+are appended.
+
+A programme can be recorded by its EIT event id rather than for the whole
+duration: the recording starts when the broadcaster signals that the event is
+running and stops when it stops, with padding either side. The duration is
+then the longest the programme is waited for. This is how me-tv-scheduler
+runs recordings scheduled by event.
+
+The recording profiles are defined in recording_profiles.yml in the Me TV
+XDG config directory, the file is created with the default profiles if it
+does not exist.
+")
+        .arg(Arg::with_name("adapter")
+            .short("a")
+            .long("adapter")
+            .value_name("NUMBER")
+            .help("Sets the adapter number to use.")
+            .takes_value(true)
+            .default_value("0"))
+        .arg(Arg::with_name("frontend")
+            .short("f")
+            .long("frontend")
+            .value_name("NUMBER")
+            .help("Sets the frontend number to use.")
+            .takes_value(true)
+            .default_value("0"))
+        .arg(Arg::with_name("channel")
+            .short("c")
+            .long("channel")
+            .value_name("CHANNEL")
+            .help("Sets the channel name, must be specified, no default. Can be given more than once.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .required_unless("list_profiles"))
+        .arg(Arg::with_name("duration")
+            .short("d")
+            .long("duration")
+            .value_name("TIME")
+            .help("Sets the duration of recording in minutes, must be specified, no default.")
+            .takes_value(true)
+            .required_unless("list_profiles"))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("PATH")
+            .help("Path to output file, must be specified, no default. If there is no extension the one for the profile is added. Can be given once per channel.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .required_unless("list_profiles"))
+        .arg(Arg::with_name("format")
+            .short("F")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Sets the format of the output file: ts writes the transport stream of the channel untouched, mp4 transcodes to H.264 and AC-3.")
+            .takes_value(true)
+            .possible_values(&["ts", "mp4"])
+            .conflicts_with("profile"))
+        .arg(Arg::with_name("profile")
+            .short("p")
+            .long("profile")
+            .value_name("NAME")
+            .help("Sets the recording profile to use, defaults to ts-passthrough.")
+            .takes_value(true))
+        .arg(Arg::with_name("event_id")
+            .long("event-id")
+            .value_name("ID")
+            .help("Sets the EIT event id of the programme to record, only it is recorded.")
+            .takes_value(true)
+            .requires_all(&["event_start", "event_duration"]))
+        .arg(Arg::with_name("event_start")
+            .long("event-start")
+            .value_name("SECONDS")
+            .help("Sets the start time of the event as currently known, seconds since the Unix epoch.")
+            .takes_value(true)
+            .requires("event_id"))
+        .arg(Arg::with_name("event_duration")
+            .long("event-duration")
+            .value_name("MINUTES")
+            .help("Sets the duration of the event as currently known.")
+            .takes_value(true)
+            .requires("event_id"))
+        .arg(Arg::with_name("padding_before")
+            .long("padding-before")
+            .value_name("MINUTES")
+            .help("Sets the number of minutes to record before the start of the event.")
+            .takes_value(true)
+            .default_value("0")
+            .requires("event_id"))
+        .arg(Arg::with_name("padding_after")
+            .long("padding-after")
+            .value_name("MINUTES")
+            .help("Sets the number of minutes to record after the end of the event.")
+            .takes_value(true)
+            .default_value("0")
+            .requires("event_id"))
+        .arg(Arg::with_name("list_profiles")
+            .short("l")
+            .long("list-profiles")
+            .help("Lists the recording profiles and whether they can be used, then exits."))
+        .arg(Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .help("sets verbose mode"))
+        .get_matches();
+    let be_verbose = matches.is_present("verbose");
+    gst::init().unwrap();
+    let profiles = match recording_profiles::get_recording_profiles() {
+        Ok(profiles) => profiles,
+        Err(error) => {
+            println!("Could not read the recording profiles: {}", error);
+            process::exit(exitcode::CONFIG);
+        },
+    };
+    let is_available = |name: &str| gst::ElementFactory::find(name).is_some();
+    if matches.is_present("list_profiles") {
+        for profile in profiles.iter() {
+            let missing = recording_profiles::missing_elements(profile, false, is_available);
+            if missing.is_empty() {
+                println!("{}", profile.name);
+            } else {
+                println!("{}  (unavailable, missing {})", profile.name, missing.join(", "));
+            }
         }
+        process::exit(exitcode::OK);
+    }
+    let adapter = matches.value_of("adapter").unwrap().parse::<u8>().expect("Couldn't parse adapter value as a positive integer.");
+    let frontend = matches.value_of("frontend").unwrap().parse::<u8>().expect("Couldn't parse frontend value as a positive integer.");
+    let channels = matches.values_of("channel").unwrap().collect::<Vec<&str>>();
+    let duration = matches.value_of("duration").unwrap().parse::<u32>().expect("Couldn't parse the provided duration as a positive integer.");
+    // The --format option predates recording profiles, the formats are the first two profiles.
+    let profile_name = match matches.value_of("format") {
+        Some("mp4") => "mp4-h264-ac3",
+        Some(_) => recording_profiles::DEFAULT_PROFILE_NAME,
+        None => matches.value_of("profile").unwrap_or(recording_profiles::DEFAULT_PROFILE_NAME),
+    };
+    let profile = match recording_profiles::find_profile(&profiles, profile_name) {
+        Some(profile) => profile,
+        None => {
+            println!("There is no recording profile called '{}', the profiles are: {}.",
+                     profile_name, profiles.iter().map(|p| p.name.as_str()).collect::<Vec<&str>>().join(", "));
+            process::exit(exitcode::USAGE);
+        },
+    };
+    let missing = recording_profiles::missing_elements(&profile, matches.is_present("event_id"), is_available);
+    if ! missing.is_empty() {
+        println!("The recording profile '{}' needs the GStreamer elements {} which are not installed.", &profile.name, missing.join(", "));
+        process::exit(exitcode::UNAVAILABLE);
+    }
+    let output_paths = match output_paths(&matches.values_of("output").unwrap().collect::<Vec<&str>>(), &channels, &profile.extension) {
+        Ok(paths) => paths,
+        Err(error) => {
+            println!("{}", error);
+            process::exit(exitcode::USAGE);
+        },
+    };
+    let event_recording = matches.value_of("event_id").map(|event_id| {
+        let number = |name: &str| matches.value_of(name).unwrap().parse::<u32>().unwrap_or_else(|_| panic!("Couldn't parse {} value as a positive integer.", name));
+        EventRecording::new(
+            event_id.parse::<u16>().expect("Couldn't parse the event id as an integer from 0 to 65535."),
+            matches.value_of("event_start").unwrap().parse::<i64>().expect("Couldn't parse the event start as an integer."),
+            60 * number("event_duration"),
+            number("padding_before"),
+            number("padding_after"),
+        )
     });
+    if be_verbose {
+        if let Some(ref recording) = event_recording {
+            println!("Waiting for event {} to record it.", recording.event_id);
+        }
+        for (channel, output_path) in channels.iter().zip(output_paths.iter()) {
+            println!("Recording channel '{}' for {} minutes on adapter {} frontend {} using profile {} to {}.", channel, duration, adapter, frontend, &profile.name, output_path);
+        }
+    }
+    // The valve and the service_id of the channel if an event is being recorded.
+    let mut event_valve = None;
+    let pipeline = if event_recording.is_some() {
+        if channels.len() > 1 || ! profile.is_passthrough() {
+            println!("An event can only be recorded from a single channel using the {} profile.", recording_profiles::DEFAULT_PROFILE_NAME);
+            process::exit(exitcode::USAGE);
+        }
+        let service_id = match ini::Ini::load_from_file(multiplexes::channels_file_path()).ok()
+            .and_then(|ini| multiplexes::find_channels_on_one_multiplex(&ini, &channels).ok()) {
+            Some(multiplex_channels) => multiplex_channels[0].service_id,
+            None => {
+                println!("Could not find the service id of channel '{}' in the channels file {:?}.", channels[0], multiplexes::channels_file_path());
+                process::exit(exitcode::CONFIG);
+            },
+        };
+        match create_event_pipeline(channels[0], adapter, frontend, &output_paths[0]) {
+            Ok((pipeline, valve)) => {
+                event_valve = Some((valve, service_id));
+                pipeline
+            },
+            Err(error) => {
+                println!("{}", error);
+                process::exit(exitcode::UNAVAILABLE);
+            },
+        }
+    } else if channels.len() > 1 {
+        if ! profile.is_passthrough() {
+            println!("Several channels can only be recorded at once using the {} profile.", recording_profiles::DEFAULT_PROFILE_NAME);
+            process::exit(exitcode::USAGE);
+        }
+        let ini = match ini::Ini::load_from_file(multiplexes::channels_file_path()) {
+            Ok(ini) => ini,
+            Err(error) => {
+                println!("Could not read the channels file {:?}: {}", multiplexes::channels_file_path(), error);
+                process::exit(exitcode::CONFIG);
+            },
+        };
+        match multiplexes::find_channels_on_one_multiplex(&ini, &channels) {
+            Ok(multiplex_channels) => match create_multi_service_pipeline(&multiplex_channels, adapter, frontend, &output_paths) {
+                Ok(pipeline) => pipeline,
+                Err(error) => {
+                    println!("{}", error);
+                    process::exit(exitcode::UNAVAILABLE);
+                },
+            },
+            Err(explanation) => {
+                println!("{}", explanation);
+                process::exit(exitcode::USAGE);
+            },
+        }
+    } else if profile.is_passthrough() {
+        match create_transport_stream_pipeline(channels[0], adapter, frontend, &output_paths[0]) {
+            Ok(pipeline) => pipeline,
+            Err(error) => {
+                println!("{}", error);
+                process::exit(exitcode::UNAVAILABLE);
+            },
+        }
+    } else {
+        match create_transcoding_pipeline(channels[0], adapter, frontend, &output_paths[0], &profile) {
+            Ok(pipeline) => pipeline,
+            Err(error) => {
+                println!("{}", error);
+                process::exit(exitcode::CONFIG);
+            },
+        }
+    };
     pipeline.set_state(gst::State::Playing).unwrap();
     thread::spawn({
         let pipeline_weak_ref = pipeline.downgrade();
@@ -209,10 +609,47 @@ A channel name and a duration must be provided.
         }
     }).expect("Error setting ctrl-c handler.");
     let bus = pipeline.get_bus().expect("Pipeline without bus. Shouldn't happen!");
-    while let Some(msg) = bus.timed_pop(gst::CLOCK_TIME_NONE) {
+    let mut event_recording = event_recording;
+    // The present and following events of the channel, from the EIT present/following sections.
+    let mut present_following: [Option<EventStatus>; 2] = [None, None];
+    let timeout = match event_recording {
+        Some(_) => gst::ClockTime::from_seconds(EVENT_TICK_INTERVAL),
+        None => gst::CLOCK_TIME_NONE,
+    };
+    loop {
+        let msg = bus.timed_pop(timeout);
+        let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        if let (Some(recording), Some((valve, _))) = (event_recording.as_mut(), event_valve.as_ref()) {
+            apply_event_action(recording.tick(now), &pipeline, valve, be_verbose);
+        }
+        let msg = match msg {
+            Some(msg) => msg,
+            None if event_recording.is_some() => continue,
+            None => break,
+        };
         use gst::MessageView;
         match msg.view() {
             MessageView::Eos(..) => break,
+            MessageView::Element(element) => {
+                if let (Some(recording), Some((valve, service_id))) = (event_recording.as_mut(), event_valve.as_ref()) {
+                    let is_eit = element.get_structure().map_or(false, |s| s.get_name() == "eit");
+                    let section = if is_eit { gst_mpegts::Section::from_element(&element) } else { None };
+                    if let Some(section) = section {
+                        if let Some(events) = event_statuses(&section, *service_id) {
+                            // Present/following sections for the actual transport stream have
+                            // table_id 0x4E, section 0 is the present event and section 1 the
+                            // following event.
+                            let action = if section.get_table_id() == 0x4E && section.get_section_number() < 2 {
+                                present_following[section.get_section_number() as usize] = events.first().cloned();
+                                recording.update_present_following(present_following[0].as_ref(), present_following[1].as_ref(), now)
+                            } else {
+                                recording.update_schedule(&events, now)
+                            };
+                            apply_event_action(action, &pipeline, valve, be_verbose);
+                        }
+                    }
+                }
+            },
             MessageView::Error(err) => {
                 pipeline.set_state(gst::State::Null).unwrap();
                 println!("Error: {} {} {} {}",
@@ -239,3 +676,25 @@ A channel name and a duration must be provided.
     }
     pipeline.set_state(gst::State::Null).unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn output_path_per_channel() {
+        assert_eq!(output_paths(&["a.ts", "b"], &["BBC ONE Lon", "BBC TWO"], "ts").unwrap(), vec!["a.ts", "b.ts"]);
+    }
+
+    #[test]
+    fn single_output_path_for_several_channels() {
+        assert_eq!(output_paths(&["/tmp/news.ts"], &["BBC ONE Lon", "BBC TWO"], "ts").unwrap(),
+                   vec!["/tmp/news – BBC ONE Lon.ts", "/tmp/news – BBC TWO.ts"]);
+        assert_eq!(output_paths(&["/tmp/news"], &["BBC TWO"], "mkv").unwrap(), vec!["/tmp/news.mkv"]);
+    }
+
+    #[test]
+    fn mismatched_output_paths_are_refused() {
+        assert!(output_paths(&["a", "b"], &["BBC ONE Lon", "BBC TWO", "ITV"], "ts").is_err());
+    }
+}
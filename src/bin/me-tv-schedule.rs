@@ -21,9 +21,15 @@
 
 use std::process;
 
-use clap::{Arg, App};
-use chrono::{Local, NaiveDateTime, NaiveTime};
-use time::Duration;
+use clap::{Arg, ArgGroup, ArgMatches, App, AppSettings, SubCommand};
+use chrono::{Local, NaiveDateTime, NaiveTime, TimeZone};
+
+use me_tv::{content_identifier, dvb_devices, multiplexes, recording_jobs, recording_profiles, recording_rules, tuner_allocation};
+
+use dvb_devices::FrontendId;
+use multiplexes::MultiplexKey;
+use recording_jobs::{RecordingJob, RecordingJobs, ScheduledEvent};
+use recording_rules::RecordingRule;
 
 fn parse_to_datetime(datum: &str) -> Result<NaiveDateTime, &str> {
     let datetime_patterns = [
@@ -53,146 +59,629 @@ fn parse_to_datetime(datum: &str) -> Result<NaiveDateTime, &str> {
     Err(datum)
 }
 
-fn main() {
-    let matches = App::new("me-tv-schedule")
-        .version(env!("CARGO_PKG_VERSION"))
-        .author("Russel Winder <russel@winder.org.uk>")
-        .about("Schedule recording to create an MPEG4 file.
+/// Parse a local date-time command line value to seconds since the Unix epoch.
+fn parse_to_timestamp(datum: &str) -> Result<i64, String> {
+    let datetime = parse_to_datetime(datum).map_err(|d| format!("Could not parse date-time {}.", d))?;
+    match Local.from_local_datetime(&datetime).earliest() {
+        Some(datetime) => Ok(datetime.timestamp()),
+        None => Err(format!("{} is not a valid local time.", datum)),
+    }
+}
 
-A channel name, a start time, a file path, and either an end time
-or a duration must be provided.
+/// Return the start time and duration in minutes of a recording from the command line,
+/// using the values of an existing job for anything not given.
+fn start_time_and_duration(matches: &ArgMatches, job: Option<&RecordingJob>, now: i64) -> Result<(i64, u32), String> {
+    let start_time = match matches.value_of("start_time") {
+        Some(s_t) => {
+            let start_time = parse_to_timestamp(s_t)?;
+            if start_time < now {
+                return Err("Start time is before the present time, cannot schedule a recording in the past.".to_string());
+            }
+            start_time
+        },
+        None => job.ok_or("You must specify the start time of the recording.")?.start_time,
+    };
+    let end_time = match matches.value_of("end_time") {
+        Some(e_t) => Some(parse_to_timestamp(e_t)?),
+        None => None,
+    };
+    let duration = match matches.value_of("duration") {
+        Some(d) => Some(d.parse::<i64>().map_err(|_| "Couldn't parse the provided duration as an integer.".to_string())?),
+        None => None,
+    };
+    let duration = match (end_time, duration) {
+        (Some(e_t), Some(d)) if e_t - start_time != 60 * d =>
+            return Err("Both end time and duration were supplied but there was a conflict between them, just give one or the other.".to_string()),
+        (Some(e_t), _) => (e_t - start_time) / 60,
+        (None, Some(d)) => d,
+        (None, None) => job.ok_or("You must specify either the end time or the duration of the recording.")?.duration as i64,
+    };
+    if duration <= 0 {
+        return Err("Duration must be a positive number of minutes, cannot record backwards.".to_string());
+    }
+    Ok((start_time, duration as u32))
+}
 
-Start and end date-times must be in ISO8601 format. A full date-time is like
-20190123T0559 or 2019-01-23T05:59 basically YYYYMMDD'T'hhmm[ss]
-or YYYY-MM-DD'T'hh:mm[:ss]. For a time today the time alone is specified,
-for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
-")
-        .arg(Arg::with_name("adapter")
+/// Return the recording profile name from the command line if one is given.
+fn profile_name<'a>(matches: &'a ArgMatches) -> Option<&'a str> {
+    // The --format option predates recording profiles, cf. me-tv-record.
+    match matches.value_of("format") {
+        Some("mp4") => Some("mp4-h264-ac3"),
+        Some(_) => Some("ts-passthrough"),
+        None => matches.value_of("profile"),
+    }
+}
+
+/// Return a number from the command line if it is given.
+fn number_value(matches: &ArgMatches, name: &str) -> Result<Option<u8>, String> {
+    match matches.value_of(name) {
+        Some(v) => v.parse::<u8>().map(Some).map_err(|_| format!("Couldn't parse {} value as an integer from 0 to 255.", name)),
+        None => Ok(None),
+    }
+}
+
+/// Return the EIT event to record from the command line, using the values of the
+/// existing event of a job for anything not given.
+fn scheduled_event(matches: &ArgMatches, event: Option<&ScheduledEvent>) -> Result<Option<ScheduledEvent>, String> {
+    let minutes = |name: &str| match matches.value_of(name) {
+        Some(m) => m.parse::<u32>().map(Some).map_err(|_| format!("Couldn't parse {} value as a positive integer.", name)),
+        None => Ok(None),
+    };
+    let (padding_before, padding_after) = (minutes("padding_before")?, minutes("padding_after")?);
+    let event_id = match matches.value_of("event_id") {
+        Some(e) => e.parse::<u16>().map_err(|_| "Couldn't parse the event id as an integer from 0 to 65535.".to_string())?,
+        None => match event {
+            Some(event) => event.event_id,
+            None if padding_before.is_some() || padding_after.is_some() =>
+                return Err("Padding can only be given for the recording of an event.".to_string()),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(ScheduledEvent {
+        event_id,
+        padding_before: padding_before.or_else(|| event.map(|e| e.padding_before)).unwrap_or(recording_jobs::DEFAULT_PADDING_BEFORE),
+        padding_after: padding_after.or_else(|| event.map(|e| e.padding_after)).unwrap_or(recording_jobs::DEFAULT_PADDING_AFTER),
+    }))
+}
+
+/// The arguments describing a recording. When editing a job none are required since
+/// only the values given are changed.
+fn recording_args<'a, 'b>(for_edit: bool) -> Vec<Arg<'a, 'b>> {
+    let mut duration = Arg::with_name("duration")
+        .short("d")
+        .long("duration")
+        .value_name("TIME")
+        .help("Sets the duration of recording in minutes, no default. This must be set unless end-time is, but do not set both.")
+        .takes_value(true)
+        .conflicts_with("end_time");
+    if ! for_edit {
+        duration = duration.required_unless("end_time");
+    }
+    vec![
+        Arg::with_name("adapter")
             .short("a")
             .long("adapter")
             .value_name("NUMBER")
-            .help("Sets the adapter number to use.")
-            .takes_value(true)
-            .default_value("0"))
-        .arg(Arg::with_name("frontend")
+            .help("Sets the adapter number to use, if neither adapter nor frontend is set a frontend that is free at the time is chosen.")
+            .takes_value(true),
+        Arg::with_name("frontend")
             .short("f")
             .long("frontend")
             .value_name("NUMBER")
-            .help("Sets the frontend number to use.")
-            .takes_value(true)
-            .default_value("0"))
-        .arg(Arg::with_name("channel")
+            .help("Sets the frontend number to use, if neither adapter nor frontend is set a frontend that is free at the time is chosen.")
+            .takes_value(true),
+        Arg::with_name("allow_conflicts")
+            .long("allow-conflicts")
+            .help("Schedules the recording even if there are not enough frontends for it."),
+        Arg::with_name("channel")
             .short("c")
             .long("channel")
             .value_name("CHANNEL")
             .help("Sets the channel name, must be specified, no default.")
             .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("start_time")
+            .required(! for_edit),
+        Arg::with_name("start_time")
             .short("s")
             .long("start-time")
             .value_name("DATE-TIME")
             .help("Sets the start date and time (or just time for today) of recording, ISO8601 format, must be specified, no default.")
             .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("end_time")
+            .required(! for_edit),
+        Arg::with_name("end_time")
             .short("e")
             .long("end-time")
             .value_name("DATE-TIME")
             .help("Sets the end date and time (or just time for today) of recording, ISO8601 format, no default. This must be set if duration is not, but do not set both.")
-            .takes_value(true)
-            .conflicts_with("duration"))
-        .arg(Arg::with_name("duration")
-            .short("d")
-            .long("duration")
-            .value_name("TIME")
-            .help("Sets the duration of recording in minutes, no default. This must be set unless end-time is, but do not set both.")
-            .takes_value(true)
-            .required_unless("end_time"))
-        .arg(Arg::with_name("output")
+            .takes_value(true),
+        duration,
+        Arg::with_name("output")
             .short("o")
             .long("output")
             .value_name("PATH")
             .help("Path to output file, must be specified, no default.")
             .takes_value(true)
-            .required(true))
-        .arg(Arg::with_name("verbose")
-            .short("v")
-            .long("verbose")
-            .help("sets verbose mode"))
-        .get_matches();
-    let be_verbose = matches.is_present("verbose");
-    let adapter = matches.value_of("adapter").unwrap().parse::<u8>().expect("Couldn't parse adapter value as a positive integer.");
-    let frontend = matches.value_of("frontend").unwrap().parse::<u8>().expect("Couldn't parse frontend value as a positive integer.");
-    let channel = matches.value_of("channel").unwrap();
-    let start_time = parse_to_datetime(matches.value_of("start_time").unwrap()).expect("Could not parse start time.");
-    let now = {
-        let timestamp = time::now().to_timespec();
-        NaiveDateTime::from_timestamp(timestamp.sec, timestamp.nsec as u32)
+            .required(! for_edit),
+        Arg::with_name("format")
+            .short("F")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Sets the format of the output file, ts or mp4, see me-tv-record.")
+            .takes_value(true)
+            .possible_values(&["ts", "mp4"])
+            .conflicts_with("profile"),
+        Arg::with_name("profile")
+            .short("p")
+            .long("profile")
+            .value_name("NAME")
+            .help("Sets the recording profile to use, defaults to ts-passthrough, see me-tv-record.")
+            .takes_value(true),
+        Arg::with_name("event_id")
+            .long("event-id")
+            .value_name("ID")
+            .help("Sets the EIT event id of the programme to record, the recording then starts and stops when the programme does. The start time and duration must be those of the programme.")
+            .takes_value(true),
+        Arg::with_name("padding_before")
+            .long("padding-before")
+            .value_name("MINUTES")
+            .help("Sets the number of minutes to record before the start of the event, defaults to 2.")
+            .takes_value(true),
+        Arg::with_name("padding_after")
+            .long("padding-after")
+            .value_name("MINUTES")
+            .help("Sets the number of minutes to record after the end of the event, defaults to 10.")
+            .takes_value(true),
+    ]
+}
+
+/// Read the jobs file, apply a change to the jobs, and write the jobs file, exiting the
+/// process if there is a problem. The jobs are left as they were if the change fails.
+fn update_jobs<F, T>(change: F) -> T where F: FnOnce(&mut RecordingJobs) -> Result<T, String> {
+    let result = recording_jobs::update_recording_jobs(&recording_jobs::recording_jobs_path(), |jobs| {
+        let mut changed_jobs = jobs.clone();
+        let result = change(&mut changed_jobs);
+        if result.is_ok() {
+            *jobs = changed_jobs;
+        }
+        result
+    });
+    match result {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            println!("{}", e);
+            process::exit(exitcode::USAGE);
+        },
+        Err(e) => {
+            println!("{}", e);
+            process::exit(exitcode::IOERR);
+        },
+    }
+}
+
+/// Return the frontends that recordings can use. If no adapters are installed, e.g.
+/// a USB adapter is unplugged, assume there is one.
+fn available_frontends() -> Vec<FrontendId> {
+    let frontends = dvb_devices::get_installed_frontends();
+    if frontends.is_empty() {
+        println!("No DVB frontends found, assuming there is just adapter 0 frontend 0.");
+        vec![FrontendId{adapter: 0, frontend: 0}]
+    } else {
+        frontends
+    }
+}
+
+/// Return a function giving the multiplex of a channel, no channel has a multiplex if
+/// the channels file cannot be read.
+fn multiplex_lookup() -> impl Fn(&str) -> Option<MultiplexKey> {
+    let channels = ini::Ini::load_from_file(multiplexes::channels_file_path()).ok();
+    move |channel| channels.as_ref().and_then(|c| multiplexes::get_multiplex_of(c, channel))
+}
+
+/// Set the frontend of a job from the command line, or if none is given choose a
+/// frontend that is free at the time of the job. The adapter and frontend must be given
+/// together.
+fn set_frontend<F>(job: &mut RecordingJob, matches: &ArgMatches, jobs: &[RecordingJob], multiplex_of: &F, frontends: &[FrontendId]) -> Result<(), String>
+    where F: Fn(&str) -> Option<MultiplexKey> {
+    match (number_value(matches, "adapter")?, number_value(matches, "frontend")?) {
+        (None, None) => match tuner_allocation::assign_frontend(job, jobs, multiplex_of, frontends) {
+            Some(frontend) => {
+                job.adapter = frontend.adapter;
+                job.frontend = frontend.frontend;
+            },
+            None if matches.is_present("allow_conflicts") => {},
+            None => {
+                let overlapping = jobs.iter()
+                    .filter(|j| j.id != job.id && j.tuner_start_time() < job.tuner_end_time() && job.tuner_start_time() < j.tuner_end_time())
+                    .map(|j| j.id.to_string())
+                    .collect::<Vec<String>>();
+                return Err(format!(
+                    "All {} frontends are in use by recordings {} at that time.\nUse --allow-conflicts to schedule the recording anyway.",
+                    frontends.len(), overlapping.join(", "),
+                ));
+            },
+        },
+        (Some(adapter), Some(frontend)) => {
+            job.adapter = adapter;
+            job.frontend = frontend;
+        },
+        (Some(_), None) => return Err("An adapter was given without a frontend, give both or neither.".to_string()),
+        (None, Some(_)) => return Err("A frontend was given without an adapter, give both or neither.".to_string()),
+    }
+    Ok(())
+}
+
+/// Check the job with the given id has enough frontends, returning the problems if
+/// it does not, or printing them if conflicts are allowed.
+fn check_conflicts<F>(jobs: &[RecordingJob], id: u32, multiplex_of: &F, frontends: &[FrontendId], allow_conflicts: bool) -> Result<(), String>
+    where F: Fn(&str) -> Option<MultiplexKey> {
+    let conflicts = tuner_allocation::find_conflicts(jobs, multiplex_of, frontends).into_iter()
+        .filter(|c| c.ids.contains(&id))
+        .map(|c| c.description)
+        .collect::<Vec<String>>();
+    if conflicts.is_empty() {
+        Ok(())
+    } else if allow_conflicts {
+        println!("{}", conflicts.join("\n"));
+        Ok(())
+    } else {
+        Err(format!("{}\nUse --allow-conflicts to schedule the recording anyway.", conflicts.join("\n")))
+    }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    Local.timestamp(timestamp, 0).format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn list_jobs() {
+    let jobs = match recording_jobs::read_recording_jobs(&recording_jobs::recording_jobs_path()) {
+        Ok(jobs) => jobs.jobs,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(exitcode::IOERR);
+        },
     };
-    if start_time < now {
-        println!("Start time is before the present time, cannot schedule a recording in the past.");
-        process::exit(exitcode::USAGE);
+    if jobs.is_empty() {
+        println!("There are no scheduled recordings.");
+        return;
     }
-    let end_time = match matches.value_of("end_time") {
-        Some(e_t) => Some(parse_to_datetime(e_t).expect("Could not parse end time.")),
-        None => None,
+    println!("  ID  Start              Minutes  Tuner  Channel               Profile           Output");
+    for job in jobs.iter() {
+        println!(
+            "{:>4}  {:<16}{}  {:>7}  {:<5}  {:<20}  {:<16}  {}{}",
+            job.id,
+            format_timestamp(job.start_time),
+            if job.started { "*" } else { " " },
+            job.duration,
+            format!("{}:{}", job.adapter, job.frontend),
+            job.channel,
+            job.profile,
+            job.output,
+            match job.event {
+                Some(ref e) => format!("  (event {}, padding {}+{} minutes)", e.event_id, e.padding_before, e.padding_after),
+                None => String::new(),
+            },
+        );
+    }
+    if jobs.iter().any(|j| j.started) {
+        println!("\n* Being recorded.");
+    }
+    let conflicts = tuner_allocation::find_conflicts(&jobs, multiplex_lookup(), &available_frontends());
+    if ! conflicts.is_empty() {
+        println!();
+        for conflict in conflicts {
+            println!("{}", conflict.description);
+        }
+    }
+}
+
+fn parse_id(matches: &ArgMatches) -> u32 {
+    match matches.value_of("id").unwrap().parse::<u32>() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Couldn't parse the job id as a positive integer.");
+            process::exit(exitcode::USAGE);
+        },
+    }
+}
+
+fn cancel_job(matches: &ArgMatches, be_verbose: bool) {
+    let id = parse_id(matches);
+    let job = update_jobs(|jobs| {
+        if jobs.jobs.iter().any(|j| j.id == id && j.started) {
+            return Err(format!("Recording {} is being recorded, it can no longer be cancelled.", id));
+        }
+        recording_jobs::remove_job(&mut jobs.jobs, id).ok_or(format!("There is no scheduled recording {}.", id))
+    });
+    if be_verbose {
+        println!("Cancelled recording {} of channel '{}' at {}.", id, job.channel, format_timestamp(job.start_time));
+    }
+}
+
+fn edit_job(matches: &ArgMatches, be_verbose: bool, now: i64) {
+    let id = parse_id(matches);
+    let multiplex_of = multiplex_lookup();
+    let frontends = available_frontends();
+    let allow_conflicts = matches.is_present("allow_conflicts");
+    let job = update_jobs(|jobs| {
+        let mut job = jobs.jobs.iter().find(|j| j.id == id).cloned().ok_or(format!("There is no scheduled recording {}.", id))?;
+        if job.started {
+            return Err(format!("Recording {} is being recorded, it can no longer be changed.", id));
+        }
+        let (start_time, duration) = start_time_and_duration(matches, Some(&job), now)?;
+        job.start_time = start_time;
+        job.duration = duration;
+        if let Some(channel) = matches.value_of("channel") { job.channel = channel.to_string(); }
+        if let Some(output) = matches.value_of("output") { job.output = output.to_string(); }
+        if let Some(profile) = profile_name(matches) { job.profile = profile.to_string(); }
+        job.event = scheduled_event(matches, job.event.as_ref())?;
+        let mut changed = jobs.jobs.clone();
+        recording_jobs::replace_job(&mut changed, job.clone());
+        // Keep the frontend unless one is given or it is no longer usable at the new time.
+        let explicit_frontend = matches.is_present("adapter") || matches.is_present("frontend");
+        if explicit_frontend || check_conflicts(&changed, id, &multiplex_of, &frontends, false).is_err() {
+            set_frontend(&mut job, matches, &jobs.jobs, &multiplex_of, &frontends)?;
+            recording_jobs::replace_job(&mut changed, job.clone());
+        }
+        check_conflicts(&changed, id, &multiplex_of, &frontends, allow_conflicts)?;
+        jobs.jobs = changed;
+        Ok(job)
+    });
+    if be_verbose {
+        println!(
+            "Recording {} is now of channel '{}' at {} for {} minutes to file {} using adapter {}, frontend {}.",
+            id, job.channel, format_timestamp(job.start_time), job.duration, job.output, job.adapter, job.frontend,
+        );
+    }
+}
+
+fn add_job(matches: &ArgMatches, be_verbose: bool, now: i64) {
+    let ((start_time, duration), event) = match start_time_and_duration(matches, None, now).and_then(|t_d| Ok((t_d, scheduled_event(matches, None)?))) {
+        Ok(values) => values,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(exitcode::USAGE);
+        },
     };
-    let duration = match matches.value_of("duration") {
-        Some(d) => Some(Duration::minutes(d.parse::<i64>().expect("Couldn't parse the provided duration as an integer."))),
-        None => None,
+    let mut job = RecordingJob {
+        id: 0,
+        channel: matches.value_of("channel").unwrap().to_string(),
+        start_time,
+        duration,
+        output: matches.value_of("output").unwrap().to_string(),
+        adapter: 0,
+        frontend: 0,
+        profile: profile_name(matches).unwrap_or(recording_profiles::DEFAULT_PROFILE_NAME).to_string(),
+        started: false,
+        event,
+    };
+    let multiplex_of = multiplex_lookup();
+    let frontends = available_frontends();
+    let allow_conflicts = matches.is_present("allow_conflicts");
+    let id = update_jobs(|jobs| {
+        set_frontend(&mut job, matches, &jobs.jobs, &multiplex_of, &frontends)?;
+        let mut changed = jobs.clone();
+        let id = recording_jobs::add_job(&mut changed, job.clone());
+        check_conflicts(&changed.jobs, id, &multiplex_of, &frontends, allow_conflicts)?;
+        *jobs = changed;
+        Ok(id)
+    });
+    if be_verbose {
+        println!(
+            "Scheduled recording {} of channel '{}' at {} for {} minutes to file {} using adapter {}, frontend {}.",
+            id,
+            job.channel,
+            format_timestamp(job.start_time),
+            job.duration,
+            job.output,
+            job.adapter,
+            job.frontend,
+        );
+    } else {
+        println!("Scheduled recording {}.", id);
+    }
+}
+
+fn list_rules() {
+    let rules = match recording_rules::read_recording_rules(&recording_rules::recording_rules_path()) {
+        Ok(rules) => rules,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(exitcode::IOERR);
+        },
     };
-    if end_time.is_none() && duration.is_none() {
-        println!("You must specify either the end time or the duration of the recording.");
-        process::exit(exitcode::USAGE);
+    if rules.is_empty() {
+        println!("There are no recording rules.");
+        return;
+    }
+    println!("  ID  Profile           Programmes");
+    for rule in rules.iter() {
+        println!("{:>4}  {:<16}  {}", rule.id, rule.profile, rule.description());
     }
-    if end_time.is_some() && duration.is_some() {
-        if end_time.unwrap() - start_time != duration.unwrap() {
-            println!("Both end time and duration were supplied but there was a conflict between them, just give one or the other.");
+}
+
+/// Read the rules file, apply a change to the rules, and write the rules file, exiting
+/// the process if there is a problem. The rules are left as they were if the change fails.
+fn update_rules<F, T>(change: F) -> T where F: FnOnce(&mut Vec<RecordingRule>) -> Result<T, String> {
+    let result = recording_rules::update_recording_rules(&recording_rules::recording_rules_path(), |rules| {
+        let mut changed_rules = rules.clone();
+        let result = change(&mut changed_rules);
+        if result.is_ok() {
+            *rules = changed_rules;
+        }
+        result
+    });
+    match result {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            println!("{}", e);
+            process::exit(exitcode::USAGE);
+        },
+        Err(e) => {
+            println!("{}", e);
+            process::exit(exitcode::IOERR);
+        },
+    }
+}
+
+fn add_rule(matches: &ArgMatches, be_verbose: bool) {
+    let rule = RecordingRule {
+        id: 0,
+        series_crid: matches.value_of("series_crid").map(|c| c.to_string()),
+        title_regex: matches.value_of("title_regex").map(|r| r.to_string()),
+        channel: matches.value_of("channel").map(|c| c.to_string()),
+        profile: profile_name(matches).unwrap_or(recording_profiles::DEFAULT_PROFILE_NAME).to_string(),
+        output_directory: matches.value_of("output_directory").map(|d| d.to_string()),
+    };
+    if let Some(ref series_crid) = rule.series_crid {
+        if ! content_identifier::has_authority(series_crid) {
+            println!("The series CRID must include its authority, e.g. crid://fp.bbc.co.uk/KR8SER.");
             process::exit(exitcode::USAGE);
         }
     }
-    let duration = if end_time.is_some() {
-        end_time.unwrap() - start_time
+    if let Some(ref title_regex) = rule.title_regex {
+        if let Err(e) = regex::Regex::new(title_regex) {
+            println!("The title regular expression is not valid – {}", e);
+            process::exit(exitcode::USAGE);
+        }
+    }
+    let description = rule.description();
+    let id = update_rules(|rules| Ok(recording_rules::add_rule(rules, rule)));
+    if be_verbose {
+        println!("Added recording rule {} to record {}.", id, description);
     } else {
-        duration.unwrap()
-    };
-    if duration < Duration::seconds(0) {
-        println!("Duration must be a positive number of minutes, cannot record backwards.");
-        process::exit(exitcode::USAGE);
+        println!("Added recording rule {}.", id);
     }
-    let output_file = matches.value_of("output").unwrap();
+}
+
+fn remove_rule(matches: &ArgMatches, be_verbose: bool) {
+    let id = parse_id(matches);
+    let rule = update_rules(|rules| recording_rules::remove_rule(rules, id).ok_or(format!("There is no recording rule {}.", id)));
     if be_verbose {
-        println!(
-            "Scheduling recording of channel '{}' at {:?} for {} minutes to file {} using adapter {}, frontend {}.",
-            channel,
-            start_time,
-            duration.num_minutes(),
-            output_file,
-            adapter,
-            frontend,
-        );
+        println!("Removed recording rule {} to record {}.", id, rule.description());
+    }
+}
+
+fn main() {
+    let verbose = Arg::with_name("verbose")
+        .short("v")
+        .long("verbose")
+        .help("sets verbose mode");
+    let matches = App::new("me-tv-schedule")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("Russel Winder <russel@winder.org.uk>")
+        .about("Schedule recording to create an MPEG-TS file, or another format
+if a transcoding recording profile is requested.
+
+A channel name, a start time, a file path, and either an end time
+or a duration must be provided.
+
+Start and end date-times must be in ISO8601 format. A full date-time is like
+20190123T0559 or 2019-01-23T05:59 basically YYYYMMDD'T'hhmm[ss]
+or YYYY-MM-DD'T'hh:mm[:ss]. For a time today the time alone is specified,
+for example 0559 or 05:59, basically hhmm[ss] or hh:mm:[:ss].
+
+Unless an adapter or frontend is given, a frontend that is free at the time
+is chosen. Recordings that overlap in time need a frontend each unless their
+channels are on the same multiplex. A recording that needs more frontends
+than there are is refused unless --allow-conflicts is given.
+
+A programme can be recorded by its EIT event id, as shown in the EPG, rather
+than by time. The recording then starts when the broadcaster signals that the
+programme has started and stops when it finishes, with some padding either
+side, and follows the programme if it is moved.
+
+Scheduled recordings are started by me-tv-scheduler which must be running
+at the start time of the recording. The list, cancel, and edit subcommands
+manage the scheduled recordings.
+
+Recording rules schedule recordings automatically: every episode of a series,
+or every programme whose title matches a regular expression, optionally only
+on one channel. Me TV applies the rules as it receives the programme guide, a
+repeat of an episode already scheduled is not recorded again. The rules,
+add-rule, and remove-rule subcommands manage the rules.
+")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .args(&recording_args(false))
+        .arg(verbose.clone())
+        .subcommand(SubCommand::with_name("list")
+            .about("Lists the scheduled recordings."))
+        .subcommand(SubCommand::with_name("cancel")
+            .about("Cancels a scheduled recording.")
+            .arg(Arg::with_name("id")
+                .value_name("ID")
+                .help("The id of the recording as shown by list.")
+                .required(true))
+            .arg(verbose.clone()))
+        .subcommand(SubCommand::with_name("edit")
+            .about("Changes a scheduled recording, only the values given are changed.")
+            .arg(Arg::with_name("id")
+                .value_name("ID")
+                .help("The id of the recording as shown by list.")
+                .required(true))
+            .args(&recording_args(true))
+            .arg(verbose.clone()))
+        .subcommand(SubCommand::with_name("rules")
+            .about("Lists the recording rules."))
+        .subcommand(SubCommand::with_name("add-rule")
+            .about("Adds a rule to record every episode of a series, or every programme with a matching title.")
+            .arg(Arg::with_name("series_crid")
+                .long("series-crid")
+                .value_name("CRID")
+                .help("Records the programmes of the series with this CRID, including its authority, e.g. crid://fp.bbc.co.uk/KR8SER.")
+                .takes_value(true))
+            .arg(Arg::with_name("title_regex")
+                .long("title-regex")
+                .value_name("REGEX")
+                .help("Records the programmes whose title matches this regular expression.")
+                .takes_value(true))
+            .group(ArgGroup::with_name("programmes")
+                .args(&["series_crid", "title_regex"])
+                .multiple(true)
+                .required(true))
+            .arg(Arg::with_name("channel")
+                .short("c")
+                .long("channel")
+                .value_name("CHANNEL")
+                .help("Records only the programmes on this channel, by default programmes on any channel are recorded.")
+                .takes_value(true))
+            .arg(Arg::with_name("output_directory")
+                .short("o")
+                .long("output-directory")
+                .value_name("PATH")
+                .help("Sets the directory the recordings are written to, defaults to the XDG videos directory.")
+                .takes_value(true))
+            .arg(Arg::with_name("format")
+                .short("F")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Sets the format of the output files, ts or mp4, see me-tv-record.")
+                .takes_value(true)
+                .possible_values(&["ts", "mp4"])
+                .conflicts_with("profile"))
+            .arg(Arg::with_name("profile")
+                .short("p")
+                .long("profile")
+                .value_name("NAME")
+                .help("Sets the recording profile to use, defaults to ts-passthrough, see me-tv-record.")
+                .takes_value(true))
+            .arg(verbose.clone()))
+        .subcommand(SubCommand::with_name("remove-rule")
+            .about("Removes a recording rule, recordings already scheduled by it are not cancelled.")
+            .arg(Arg::with_name("id")
+                .value_name("ID")
+                .help("The id of the rule as shown by rules.")
+                .required(true))
+            .arg(verbose))
+        .get_matches();
+    let now = time::now_utc().to_timespec().sec;
+    match matches.subcommand() {
+        ("list", Some(_)) => list_jobs(),
+        ("cancel", Some(sub_matches)) => cancel_job(sub_matches, sub_matches.is_present("verbose") || matches.is_present("verbose")),
+        ("edit", Some(sub_matches)) => edit_job(sub_matches, sub_matches.is_present("verbose") || matches.is_present("verbose"), now),
+        ("rules", Some(_)) => list_rules(),
+        ("add-rule", Some(sub_matches)) => add_rule(sub_matches, sub_matches.is_present("verbose") || matches.is_present("verbose")),
+        ("remove-rule", Some(sub_matches)) => remove_rule(sub_matches, sub_matches.is_present("verbose") || matches.is_present("verbose")),
+        _ => add_job(&matches, matches.is_present("verbose"), now),
     }
-    let echo_process = process::Command::new("echo")
-        .arg(format!(
-            "me-tv-record --channel={} --duration={} --output={} --adapter={} --frontend={}",
-            channel,
-            duration.num_minutes(),
-            output_file,
-            adapter,
-            frontend,
-        ))
-        .stdout(process::Stdio::piped())
-        .spawn()
-        .expect("Failed to start echo process.");
-    let echo_pipe = echo_process.stdout.expect("Failed to open the echo process stdout.");
-    let at_process = process::Command::new("at")
-        .arg(format!("{}", start_time.time().format("%H:%M")))
-        .arg(format!("{}", start_time.date().format("%Y-%m-%d")))
-        .stdin(process::Stdio::from(echo_pipe))
-        .status()
-        .expect("Failed to start at process.");
-    assert!(at_process.success());
 }
 
 #[cfg(test)]
@@ -220,4 +709,66 @@ mod test {
             Err(e) => assert!(false,"failed to parse: {}", e),
         };
     }
+
+    fn edit_matches(arguments: &[&str]) -> ArgMatches<'static> {
+        App::new("edit").args(&recording_args(true)).get_matches_from(["edit"].iter().chain(arguments.iter()))
+    }
+
+    #[test]
+    fn padding_is_only_for_events() {
+        let event = ScheduledEvent{event_id: 17, padding_before: 5, padding_after: 5};
+        assert_eq!(scheduled_event(&edit_matches(&[]), None), Ok(None));
+        assert!(scheduled_event(&edit_matches(&["--padding-after=15"]), None).is_err());
+        assert_eq!(
+            scheduled_event(&edit_matches(&["--event-id=17"]), None),
+            Ok(Some(ScheduledEvent{event_id: 17, padding_before: recording_jobs::DEFAULT_PADDING_BEFORE, padding_after: recording_jobs::DEFAULT_PADDING_AFTER})),
+        );
+        assert_eq!(
+            scheduled_event(&edit_matches(&["--padding-after=15"]), Some(&event)),
+            Ok(Some(ScheduledEvent{event_id: 17, padding_before: 5, padding_after: 15})),
+        );
+    }
+
+    #[test]
+    fn edit_keeps_the_values_not_given() {
+        let job = RecordingJob {
+            id: 1,
+            channel: "BBC TWO".to_string(),
+            start_time: 2000,
+            duration: 30,
+            output: "/tmp/a.ts".to_string(),
+            adapter: 0,
+            frontend: 0,
+            profile: "ts-passthrough".to_string(),
+            started: false,
+            event: None,
+        };
+        assert_eq!(start_time_and_duration(&edit_matches(&[]), Some(&job), 1000), Ok((2000, 30)));
+        assert_eq!(start_time_and_duration(&edit_matches(&["--duration=45"]), Some(&job), 1000), Ok((2000, 45)));
+        assert!(start_time_and_duration(&edit_matches(&["--duration=0"]), Some(&job), 1000).is_err());
+        assert!(start_time_and_duration(&edit_matches(&[]), None, 1000).is_err());
+    }
+
+    #[test]
+    fn adapter_and_frontend_are_given_together() {
+        let mut job = RecordingJob {
+            id: 1,
+            channel: "BBC TWO".to_string(),
+            start_time: 2000,
+            duration: 30,
+            output: "/tmp/a.ts".to_string(),
+            adapter: 0,
+            frontend: 0,
+            profile: "ts-passthrough".to_string(),
+            started: false,
+            event: None,
+        };
+        let multiplex_of = |_: &str| None;
+        let frontends = [FrontendId{adapter: 0, frontend: 0}, FrontendId{adapter: 1, frontend: 0}];
+        assert!(set_frontend(&mut job, &edit_matches(&["--frontend=1"]), &[], &multiplex_of, &frontends).is_err());
+        assert!(set_frontend(&mut job, &edit_matches(&["--adapter=1"]), &[], &multiplex_of, &frontends).is_err());
+        assert!(set_frontend(&mut job, &edit_matches(&["--adapter=one", "--frontend=0"]), &[], &multiplex_of, &frontends).is_err());
+        assert_eq!(set_frontend(&mut job, &edit_matches(&["--adapter=1", "--frontend=0"]), &[], &multiplex_of, &frontends), Ok(()));
+        assert_eq!((job.adapter, job.frontend), (1, 0));
+    }
 }
@@ -0,0 +1,220 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2018–2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, process, thread};
+
+use clap::{Arg, App};
+
+use me_tv::{multiplexes, recording_jobs, tuner_allocation};
+
+use recording_jobs::RecordingJob;
+use tuner_allocation::RecordingGroup;
+
+/// The recordings that have been started, each with its me-tv-record process.
+type Recordings = Vec<(RecordingGroup, process::Child)>;
+
+/// The number of times starting me-tv-record has failed for each job.
+type StartFailures = HashMap<u32, u32>;
+
+/// The longest time, in seconds, between reads of the jobs file so that jobs added or
+/// edited by me-tv-schedule are noticed.
+const POLL_INTERVAL: i64 = 30;
+
+/// How many times starting me-tv-record for a job is tried before the job is dropped, and
+/// how long, in seconds, to wait before trying again.
+const MAXIMUM_START_ATTEMPTS: u32 = 3;
+const START_RETRY_INTERVAL: i64 = 5;
+
+/// Return the me-tv-record to run: the one installed alongside this program if there
+/// is one, otherwise whichever is found on the PATH.
+fn me_tv_record_path() -> PathBuf {
+    if let Ok(mut path) = env::current_exe() {
+        path.set_file_name("me-tv-record");
+        if path.is_file() {
+            return path;
+        }
+    }
+    PathBuf::from("me-tv-record")
+}
+
+fn describe(group: &RecordingGroup) -> String {
+    let ids = group.ids().iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ");
+    let channels = group.jobs.iter().map(|j| format!("'{}'", j.channel)).collect::<Vec<String>>().join(", ");
+    format!("recording {} of channel {}", ids, channels)
+}
+
+/// Start me-tv-record to record a group of jobs.
+fn start_recording(group: &RecordingGroup, now: i64, be_verbose: bool) -> Option<process::Child> {
+    let arguments = group.record_arguments(now);
+    if be_verbose {
+        println!("Starting {}: me-tv-record {}", describe(group), arguments.join(" "));
+    }
+    match process::Command::new(me_tv_record_path()).args(&arguments).spawn() {
+        Ok(child) => Some(child),
+        Err(e) => {
+            println!("Failed to start me-tv-record for {} – {}", describe(group), e);
+            None
+        },
+    }
+}
+
+/// Reap the recordings that have finished, reporting any failures, and return the ids
+/// of their jobs.
+fn reap_recordings(recordings: &mut Recordings, be_verbose: bool) -> Vec<u32> {
+    let mut finished = Vec::new();
+    let mut running = Vec::new();
+    for (group, mut child) in recordings.drain(..) {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                if ! status.success() {
+                    println!("The {} failed: {}", describe(&group), status);
+                } else if be_verbose {
+                    println!("The {} finished.", describe(&group));
+                }
+                finished.extend(group.ids());
+            },
+            Ok(None) => running.push((group, child)),
+            Err(e) => {
+                println!("Could not get the status of the {} – {}", describe(&group), e);
+                finished.extend(group.ids());
+            },
+        }
+    }
+    *recordings = running;
+    finished
+}
+
+/// Mark the jobs of recordings that could not be started as waiting again so that they
+/// are retried, removing those that have failed too often. Return whether any job is to
+/// be retried.
+fn retry_failed_starts(failed: &[RecordingGroup], start_failures: &mut StartFailures) -> bool {
+    let mut retry = Vec::new();
+    let mut given_up = Vec::new();
+    for id in failed.iter().flat_map(|g| g.ids()) {
+        let failures = start_failures.entry(id).or_insert(0);
+        *failures += 1;
+        if *failures < MAXIMUM_START_ATTEMPTS {
+            retry.push(id);
+        } else {
+            start_failures.remove(&id);
+            given_up.push(id);
+        }
+    }
+    let result = recording_jobs::update_recording_jobs(&recording_jobs::recording_jobs_path(), |jobs| {
+        jobs.jobs.retain(|j| ! given_up.contains(&j.id));
+        jobs.jobs.iter_mut().filter(|j| retry.contains(&j.id)).for_each(|j| j.started = false);
+    });
+    if let Err(e) = result {
+        // The jobs stay marked as started, they are removed once their time is over.
+        println!("{}", e);
+        return false;
+    }
+    for id in given_up.iter() {
+        println!("Recording {} has been removed, me-tv-record could not be started for it.", id);
+    }
+    ! retry.is_empty()
+}
+
+/// Remove the finished jobs from the jobs file, start the due ones, and return the
+/// number of seconds until the next job is due.
+fn update_jobs(recordings: &mut Recordings, start_failures: &mut StartFailures, be_verbose: bool) -> i64 {
+    let finished = reap_recordings(recordings, be_verbose);
+    let now = time::now_utc().to_timespec().sec;
+    let running = recordings.iter().flat_map(|(group, _)| group.ids()).collect::<Vec<u32>>();
+    let channels = ini::Ini::load_from_file(multiplexes::channels_file_path()).ok();
+    let result = recording_jobs::update_recording_jobs(&recording_jobs::recording_jobs_path(), |jobs| {
+        // Started jobs not being recorded by this process, e.g. because the scheduler was
+        // restarted, are removed once their time is over.
+        let is_over = |j: &RecordingJob| j.started && j.tuner_end_time() <= now && ! running.contains(&j.id);
+        jobs.jobs.retain(|j| ! finished.contains(&j.id) && ! is_over(j));
+        let groups = tuner_allocation::group_jobs(&jobs.jobs, |channel| channels.as_ref().and_then(|c| multiplexes::get_multiplex_of(c, channel)));
+        let mut due = Vec::new();
+        for group in groups.into_iter().filter(|g| ! g.is_started() && g.start_time() <= now) {
+            let ids = group.ids();
+            if group.end_time() <= now {
+                println!("The {} was missed, it finished before it could be started.", describe(&group));
+                jobs.jobs.retain(|j| ! ids.contains(&j.id));
+            } else {
+                jobs.jobs.iter_mut().filter(|j| ids.contains(&j.id)).for_each(|j| j.started = true);
+                due.push(group);
+            }
+        }
+        (due, recording_jobs::seconds_until_next_job(&jobs.jobs, now))
+    });
+    let (due, seconds_until_next_job) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            // Do not start the jobs, they would be started again on the next read.
+            println!("{}", e);
+            return POLL_INTERVAL;
+        },
+    };
+    let mut failed = Vec::new();
+    for group in due {
+        match start_recording(&group, now, be_verbose) {
+            Some(child) => {
+                group.ids().iter().for_each(|id| { start_failures.remove(id); });
+                recordings.push((group, child));
+            },
+            None => failed.push(group),
+        }
+    }
+    let wait = seconds_until_next_job.unwrap_or(POLL_INTERVAL).clamp(1, POLL_INTERVAL);
+    if ! failed.is_empty() && retry_failed_starts(&failed, start_failures) {
+        wait.min(START_RETRY_INTERVAL)
+    } else {
+        wait
+    }
+}
+
+fn main() {
+    let matches = App::new("me-tv-scheduler")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author("Russel Winder <russel@winder.org.uk>")
+        .about("Start the recordings scheduled using me-tv-schedule at their start time.
+
+Jobs on the same multiplex using the same frontend whose times overlap are
+recorded by a single me-tv-record since a frontend can only be used by one
+program at a time.
+
+A recording of a programme by its EIT event is started a little before the
+programme is due, including the padding, so that me-tv-record can follow the
+programme and record it when it actually runs.
+
+This program runs until it is killed, it should be started at login, for
+example as a desktop autostart application or a systemd user service.
+")
+        .arg(Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .help("sets verbose mode"))
+        .get_matches();
+    let be_verbose = matches.is_present("verbose");
+    let mut recordings = Vec::new();
+    let mut start_failures = HashMap::new();
+    loop {
+        let wait = update_jobs(&mut recordings, &mut start_failures, be_verbose);
+        thread::sleep(Duration::from_secs(wait as u64));
+    }
+}
@@ -0,0 +1,426 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The dialog for editing the channels: renaming, renumbering, re-ordering, hiding,
+//! marking as a favourite, and putting in groups.
+//!
+//! The edits are kept as an overlay on the scanned channels, see `channel_edits`.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use gtk;
+use gtk::prelude::*;
+
+use crate::channel_edits::{self, ChannelEdit};
+use crate::channel_groups;
+use crate::channels_data::{self, ChannelData};
+use crate::control_window::ControlWindow;
+use crate::dialogs::{display_an_error_dialog, display_an_information_dialog};
+
+const NUMBER_COLUMN: i32 = 0;
+const NAME_COLUMN: i32 = 1;
+const HIDDEN_COLUMN: i32 = 2;
+const FAVOURITE_COLUMN: i32 = 3;
+/// The index of the channel in the channels being edited.
+const INDEX_COLUMN: i32 = 4;
+/// The names of the groups the channel is in, separated by commas.
+const GROUPS_COLUMN: i32 = 5;
+
+/// A row of the editor.
+#[derive(Debug)]
+struct Row {
+    index: usize,
+    number: String,
+    name: String,
+    is_hidden: bool,
+    is_favourite: bool,
+    groups: String,
+}
+
+/// The result of the editing.
+#[derive(Debug)]
+struct EditedChannels {
+    edits: Vec<ChannelEdit>,
+    channels: Vec<ChannelData>,
+    /// The service ids of the channels whose number the user has taken away.
+    numbers_cleared: Vec<u16>,
+    /// The new names of the channels renamed, by previous name.
+    renames: HashMap<String, String>,
+}
+
+/// Check that a name can be used as a channel name. Names are the section names of the
+/// channels file so cannot contain brackets or line breaks.
+fn check_name(name: &str) -> Result<(), String> {
+    if name.contains(&['[', ']', '\n'][..]) {
+        return Err(format!("{} cannot be used as a channel name, names cannot contain [, ], or line breaks.", name));
+    }
+    Ok(())
+}
+
+/// Construct the channel edits, and the channels as edited, from the rows of the editor.
+///
+/// Edits of channels not currently in the channels data are kept.
+fn edit_channels(channels: &[ChannelData], edits: &[ChannelEdit], rows: &[Row]) -> Result<EditedChannels, String> {
+    let mut names = HashSet::new();
+    let mut numbers = HashMap::new();
+    let mut result = EditedChannels { edits: Vec::new(), channels: Vec::new(), numbers_cleared: Vec::new(), renames: HashMap::new() };
+    for row in rows.iter() {
+        let channel = &channels[row.index];
+        let previous_edit = edits.iter().find(|e| e.is_of(channel));
+        let had_own_number = previous_edit.map_or(false, |e| e.logical_channel_number.is_some());
+        let mut edit = match previous_edit {
+            Some(e) => ChannelEdit { name: None, logical_channel_number: None, ..e.clone() },
+            None => ChannelEdit::new(channel),
+        };
+        let name = row.name.trim();
+        let name = if name.is_empty() { edit.scanned_name.clone() } else { name.to_string() };
+        check_name(&name)?;
+        if ! names.insert(name.clone()) {
+            return Err(format!("There is more than one channel named {}.", name));
+        }
+        if name != edit.scanned_name {
+            edit.name = Some(name.clone());
+        }
+        if name != channel.name {
+            result.renames.insert(channel.name.clone(), name.clone());
+        }
+        let number = match row.number.trim() {
+            "" => 0,
+            n => n.parse::<u16>().map_err(|_| format!("{} is not a channel number.", n))?,
+        };
+        if number != 0 {
+            if let Some(other) = numbers.insert(number, name.clone()) {
+                return Err(format!("{} and {} both have the number {}.", other, name, number));
+            }
+            if had_own_number || number != channel.logical_channel_number {
+                edit.logical_channel_number = Some(number);
+            }
+        } else if had_own_number {
+            result.numbers_cleared.push(channel.service_id);
+        }
+        edit.is_hidden = row.is_hidden;
+        edit.is_favourite = row.is_favourite;
+        edit.groups = channel_groups::groups_of_text(&row.groups);
+        result.channels.push(ChannelData { name, logical_channel_number: number, ..channel.clone() });
+        result.edits.push(edit);
+    }
+    for edit in edits.iter() {
+        if ! channels.iter().any(|c| edit.is_of(c)) {
+            result.edits.push(edit.clone());
+        }
+    }
+    Ok(result)
+}
+
+/// A dialog listing all the channels, including the hidden ones, for editing.
+#[derive(Debug)]
+struct ChannelEditor {
+    dialog: gtk::Dialog,
+    store: gtk::ListStore,
+    channels: Vec<ChannelData>,
+}
+
+impl ChannelEditor {
+    fn new(control_window: &Rc<ControlWindow>, channels: Vec<ChannelData>) -> ChannelEditor {
+        let dialog = gtk::Dialog::with_buttons(
+            Some("Me TV – Channels"),
+            Some(&control_window.window),
+            gtk::DialogFlags::MODAL,
+            &[("Cancel", gtk::ResponseType::Cancel), ("Save", gtk::ResponseType::Apply)],
+        );
+        dialog.set_default_size(480, 600);
+        let store = gtk::ListStore::new(&[String::static_type(), String::static_type(), bool::static_type(), bool::static_type(), u32::static_type(), String::static_type()]);
+        let edits = channel_edits::get_channel_edits();
+        for (index, channel) in channels.iter().enumerate() {
+            let edit = edits.iter().find(|e| e.is_of(channel));
+            let number = if channel.logical_channel_number == 0 { "".to_string() } else { channel.logical_channel_number.to_string() };
+            let is_hidden = edit.map_or(false, |e| e.is_hidden);
+            let is_favourite = edit.map_or(false, |e| e.is_favourite);
+            let groups = edit.map_or(String::new(), |e| e.groups.join(", "));
+            store.insert_with_values(None, &[0, 1, 2, 3, 4, 5], &[&number, &channel.name, &is_hidden, &is_favourite, &(index as u32), &groups]);
+        }
+        let view = gtk::TreeView::with_model(&store);
+        let number_renderer = gtk::CellRendererText::new();
+        number_renderer.set_property_editable(true);
+        number_renderer.connect_edited({
+            let store = store.clone();
+            move |_, path, text| {
+                let text = text.trim();
+                if text.is_empty() || text.parse::<u16>().map_or(false, |n| n > 0) {
+                    if let Some(iterator) = store.get_iter(&path) {
+                        store.set_value(&iterator, NUMBER_COLUMN as u32, &text.to_value());
+                    }
+                }
+            }
+        });
+        let name_renderer = gtk::CellRendererText::new();
+        name_renderer.set_property_editable(true);
+        name_renderer.connect_edited({
+            let dialog = dialog.clone();
+            let store = store.clone();
+            move |_, path, text| {
+                let text = text.trim();
+                if let Err(e) = check_name(text) {
+                    display_an_error_dialog(Some(&dialog), &e);
+                } else if let Some(iterator) = store.get_iter(&path) {
+                    store.set_value(&iterator, NAME_COLUMN as u32, &text.to_value());
+                }
+            }
+        });
+        for (title, renderer, column_index) in [("Number", &number_renderer, NUMBER_COLUMN), ("Name", &name_renderer, NAME_COLUMN)].iter() {
+            let column = gtk::TreeViewColumn::new();
+            column.set_title(title);
+            column.pack_start(*renderer, true);
+            column.add_attribute(*renderer, "text", *column_index);
+            view.append_column(&column);
+        }
+        for (title, column_index) in [("Hidden", HIDDEN_COLUMN), ("Favourite", FAVOURITE_COLUMN)].iter() {
+            let column_index = *column_index;
+            let renderer = gtk::CellRendererToggle::new();
+            renderer.connect_toggled({
+                let store = store.clone();
+                move |_, path| {
+                    if let Some(iterator) = store.get_iter(&path) {
+                        let value = store.get_value(&iterator, column_index).get_some::<bool>().unwrap();
+                        store.set_value(&iterator, column_index as u32, &(! value).to_value());
+                    }
+                }
+            });
+            let column = gtk::TreeViewColumn::new();
+            column.set_title(title);
+            column.pack_start(&renderer, false);
+            column.add_attribute(&renderer, "active", column_index);
+            view.append_column(&column);
+        }
+        let groups_renderer = gtk::CellRendererText::new();
+        groups_renderer.set_property_editable(true);
+        groups_renderer.connect_edited({
+            let store = store.clone();
+            move |_, path, text| {
+                if let Some(iterator) = store.get_iter(&path) {
+                    store.set_value(&iterator, GROUPS_COLUMN as u32, &channel_groups::groups_of_text(text).join(", ").to_value());
+                }
+            }
+        });
+        let groups_column = gtk::TreeViewColumn::new();
+        groups_column.set_title("Groups");
+        groups_column.pack_start(&groups_renderer, true);
+        groups_column.add_attribute(&groups_renderer, "text", GROUPS_COLUMN);
+        view.append_column(&groups_column);
+        let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.add(&view);
+        let up_button = gtk::Button::with_label("Move up");
+        let down_button = gtk::Button::with_label("Move down");
+        let buttons_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        buttons_box.pack_start(&up_button, false, false, 0);
+        buttons_box.pack_start(&down_button, false, false, 0);
+        let help_label = gtk::Label::new(Some("Clear the number of a channel to use the number broadcast for it.\nSeparate the names of the groups of a channel, such as Kids or News, with commas."));
+        help_label.set_halign(gtk::Align::Start);
+        let content_area = dialog.get_content_area();
+        content_area.set_spacing(6);
+        content_area.set_border_width(10);
+        content_area.pack_start(&scrolled_window, true, true, 0);
+        content_area.pack_start(&buttons_box, false, false, 0);
+        content_area.pack_start(&help_label, false, false, 0);
+        up_button.connect_clicked({
+            let store = store.clone();
+            let view = view.clone();
+            move |_| move_selected(&store, &view, true)
+        });
+        down_button.connect_clicked({
+            let store = store.clone();
+            let view = view.clone();
+            move |_| move_selected(&store, &view, false)
+        });
+        dialog.show_all();
+        ChannelEditor {
+            dialog,
+            store,
+            channels,
+        }
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        if let Some(iterator) = self.store.get_iter_first() {
+            loop {
+                rows.push(Row {
+                    index: self.store.get_value(&iterator, INDEX_COLUMN).get_some::<u32>().unwrap() as usize,
+                    number: self.store.get_value(&iterator, NUMBER_COLUMN).get::<String>().unwrap().unwrap_or_default(),
+                    name: self.store.get_value(&iterator, NAME_COLUMN).get::<String>().unwrap().unwrap_or_default(),
+                    is_hidden: self.store.get_value(&iterator, HIDDEN_COLUMN).get_some::<bool>().unwrap(),
+                    is_favourite: self.store.get_value(&iterator, FAVOURITE_COLUMN).get_some::<bool>().unwrap(),
+                    groups: self.store.get_value(&iterator, GROUPS_COLUMN).get::<String>().unwrap().unwrap_or_default(),
+                });
+                if ! self.store.iter_next(&iterator) { break; }
+            }
+        }
+        rows
+    }
+
+    /// Rewrite the channels file with the edited names, store the edits, and change the
+    /// names of renamed channels wherever channels are referred to by name.
+    fn save(&self) -> Result<(), String> {
+        let edited = edit_channels(&self.channels, &channel_edits::get_channel_edits(), &self.rows())?;
+        channels_data::replace_channels_and_edits(&edited.channels, Some(edited.edits))?;
+        channel_edits::rename_channels(&edited.renames)?;
+        for service_id in edited.numbers_cleared.iter() {
+            channels_data::add_logical_channel_number_for_service_id(*service_id, 0, None);
+        }
+        Ok(())
+    }
+}
+
+/// Return the number of the channel of a row, 0 if it has none.
+fn number_of_row(store: &gtk::ListStore, iterator: &gtk::TreeIter) -> u16 {
+    store.get_value(iterator, NUMBER_COLUMN).get::<String>().unwrap().unwrap_or_default().parse::<u16>().unwrap_or(0)
+}
+
+/// Move the selected channel one place up or down by swapping its number with that of its
+/// neighbour. A channel without a number is first given the number after the highest.
+fn move_selected(store: &gtk::ListStore, view: &gtk::TreeView, up: bool) {
+    let iterator = match view.get_selection().get_selected() {
+        Some((_, iterator)) => iterator,
+        None => return,
+    };
+    let neighbour = iterator.clone();
+    let has_neighbour = if up { store.iter_previous(&neighbour) } else { store.iter_next(&neighbour) };
+    if ! has_neighbour { return; }
+    let mut highest = 0;
+    if let Some(i) = store.get_iter_first() {
+        loop {
+            highest = highest.max(number_of_row(store, &i));
+            if ! store.iter_next(&i) { break; }
+        }
+    }
+    let mut number_of = |i: &gtk::TreeIter| match number_of_row(store, i) {
+        0 => {
+            highest = highest.saturating_add(1);
+            highest
+        },
+        n => n,
+    };
+    let (number, neighbour_number) = if up {
+        let n = number_of(&neighbour);
+        (number_of(&iterator), n)
+    } else {
+        let n = number_of(&iterator);
+        (n, number_of(&neighbour))
+    };
+    store.set_value(&iterator, NUMBER_COLUMN as u32, &neighbour_number.to_string().to_value());
+    store.set_value(&neighbour, NUMBER_COLUMN as u32, &number.to_string().to_value());
+    store.swap(&iterator, &neighbour);
+}
+
+/// Present the channel editor, saving the edits if the user asks.
+pub fn present(control_window: &Rc<ControlWindow>) {
+    let channels = match channels_data::get_all_channels_data_ordered_by_logical_channel_number() {
+        Some(channels) if ! channels.is_empty() => channels,
+        _ => {
+            display_an_information_dialog(Some(&control_window.window), "There are no channels to edit, create or import a channels file first.");
+            return;
+        },
+    };
+    let editor = ChannelEditor::new(control_window, channels);
+    while gtk::ResponseType::from(editor.dialog.run()) == gtk::ResponseType::Apply {
+        match editor.save() {
+            Ok(()) => {
+                control_window.update_channels_store();
+                break;
+            },
+            Err(e) => display_an_error_dialog(Some(&editor.dialog), &e),
+        }
+    }
+    unsafe { editor.dialog.destroy(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dvbv5::Tuning;
+
+    fn channel(name: &str, service_id: u16, logical_channel_number: u16) -> ChannelData {
+        ChannelData {
+            name: name.to_string(),
+            service_id,
+            logical_channel_number,
+            tuning: Tuning { network_id: Some(9018), transport_id: Some(4164), ..Tuning::default() },
+        }
+    }
+
+    fn row(index: usize, number: &str, name: &str) -> Row {
+        Row { index, number: number.to_string(), name: name.to_string(), is_hidden: false, is_favourite: false, groups: "".to_string() }
+    }
+
+    #[test]
+    fn only_changes_become_edits() {
+        let channels = vec![channel("BBC ONE Lon", 4164, 1), channel("QVC", 4500, 16), channel("ITV", 8261, 3)];
+        let rows = vec![
+            row(0, "1", "BBC One"),
+            Row { is_hidden: true, ..row(1, "16", "QVC") },
+            Row { is_favourite: true, groups: "News, Kids".to_string(), ..row(2, "2", "ITV") },
+        ];
+        let edited = edit_channels(&channels, &[], &rows).unwrap();
+        assert_eq!(edited.edits, vec![
+            ChannelEdit { name: Some("BBC One".to_string()), ..ChannelEdit::new(&channels[0]) },
+            ChannelEdit { is_hidden: true, ..ChannelEdit::new(&channels[1]) },
+            ChannelEdit { logical_channel_number: Some(2), is_favourite: true, groups: vec!["News".to_string(), "Kids".to_string()], ..ChannelEdit::new(&channels[2]) },
+        ]);
+        assert_eq!(edited.channels.iter().map(|c| (c.name.as_str(), c.logical_channel_number)).collect::<Vec<(&str, u16)>>(), vec![
+            ("BBC One", 1),
+            ("QVC", 16),
+            ("ITV", 2),
+        ]);
+        assert!(edited.numbers_cleared.is_empty());
+        assert_eq!(edited.renames.into_iter().collect::<Vec<(String, String)>>(), vec![("BBC ONE Lon".to_string(), "BBC One".to_string())]);
+    }
+
+    #[test]
+    fn reverting_keeps_the_scanned_name_and_clears_the_number() {
+        let channels = vec![channel("BBC One", 4164, 7), channel("QVC", 4500, 16)];
+        let edits = vec![
+            ChannelEdit { name: Some("BBC One".to_string()), logical_channel_number: Some(7), ..ChannelEdit::new(&channel("BBC ONE Lon", 4164, 1)) },
+            ChannelEdit { is_hidden: true, ..ChannelEdit::new(&channel("Gone", 4600, 0)) },
+        ];
+        let edited = edit_channels(&channels, &edits, &[row(0, "", ""), row(1, "16", "QVC")]).unwrap();
+        assert_eq!(edited.channels[0].name, "BBC ONE Lon");
+        assert!(edited.edits[0].is_empty());
+        assert_eq!(edited.edits[2], edits[1]);
+        assert_eq!(edited.numbers_cleared, vec![4164]);
+        assert_eq!(edited.renames.get("BBC One").map(|n| n.as_str()), Some("BBC ONE Lon"));
+    }
+
+    #[test]
+    fn repeated_names_and_numbers_are_errors() {
+        let channels = vec![channel("BBC ONE Lon", 4164, 1), channel("QVC", 4500, 16)];
+        assert!(edit_channels(&channels, &[], &[row(0, "1", "QVC"), row(1, "16", "QVC")]).is_err());
+        assert!(edit_channels(&channels, &[], &[row(0, "1", "BBC One"), row(1, "1", "QVC")]).is_err());
+    }
+
+    #[test]
+    fn names_that_cannot_be_section_names_are_errors() {
+        let channels = vec![channel("BBC ONE Lon", 4164, 1), channel("QVC", 4500, 16)];
+        assert!(edit_channels(&channels, &[], &[row(0, "1", "BBC [One]"), row(1, "16", "QVC")]).is_err());
+        assert!(edit_channels(&channels, &[], &[row(0, "1", "BBC\nOne"), row(1, "16", "QVC")]).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The edits the user has made to the channels: renaming, renumbering, hiding, marking as
+//! a favourite, and putting in groups.
+//!
+//! The edits are an overlay on the channels found by a scan rather than changes to the
+//! channels file, so that a rescan does not lose them. A channel is identified by its
+//! original network id, transport stream id, and service id, which do not change from
+//! scan to scan. Names are the keys of the channels file, the dvbsrc element tunes by
+//! name, so the edited names are written to the channels file, on a rescan as well, and
+//! whenever the channels file is read with names other than the edited ones. The
+//! numbers, and whether a channel is hidden or a favourite, are applied to the channels
+//! data as it is read. The recording jobs, recording rules, and XMLTV channel mappings
+//! refer to channels by name so they are changed when a channel is renamed.
+//!
+//! The edits are stored as a YAML file in the XDG config directory.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use xdg;
+
+use crate::channels_data::ChannelData;
+use crate::data_files;
+use crate::recording_jobs;
+use crate::recording_rules;
+use crate::xmltv;
+
+/// The edits of a channel.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ChannelEdit {
+    /// The name of the channel as found by the scan.
+    pub scanned_name: String,
+    pub service_id: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_id: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport_id: Option<u16>,
+    /// The name given by the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The number given by the user, this takes precedence over the logical channel
+    /// number broadcast.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logical_channel_number: Option<u16>,
+    #[serde(default)]
+    pub is_hidden: bool,
+    #[serde(default)]
+    pub is_favourite: bool,
+    /// The names of the groups the user has put the channel in, see `channel_groups`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+impl ChannelEdit {
+    /// Construct the edit of a channel that changes nothing.
+    pub fn new(channel: &ChannelData) -> ChannelEdit {
+        ChannelEdit {
+            scanned_name: channel.name.clone(),
+            service_id: channel.service_id,
+            network_id: channel.tuning.network_id,
+            transport_id: channel.tuning.transport_id,
+            ..ChannelEdit::default()
+        }
+    }
+
+    /// Is this the edit of the channel. Channels imported from lists without the network
+    /// and transport stream ids are identified by service id and name instead.
+    pub fn is_of(&self, channel: &ChannelData) -> bool {
+        if self.service_id != channel.service_id {
+            false
+        } else if channel.tuning.network_id.is_none() && channel.tuning.transport_id.is_none() {
+            channel.name == self.scanned_name || self.name.as_ref() == Some(&channel.name)
+        } else {
+            self.network_id == channel.tuning.network_id && self.transport_id == channel.tuning.transport_id
+        }
+    }
+
+    /// Does this edit change anything.
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.logical_channel_number.is_none() && ! self.is_hidden && ! self.is_favourite && self.groups.is_empty()
+    }
+}
+
+lazy_static! {
+    static ref CHANNEL_EDITS: RwLock<Vec<ChannelEdit>> = RwLock::new(initialise_channel_edits());
+}
+
+fn initialise_channel_edits() -> Vec<ChannelEdit> {
+    match read_channel_edits(&channel_edits_path()) {
+        Ok(edits) => edits,
+        Err(e) => {
+            println!("{}", e);
+            Vec::new()
+        },
+    }
+}
+
+/// Return a `PathBuf` to the channel edits file using the XDG directory structure.
+pub fn channel_edits_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_config_home();
+    path_buf.push("channel_edits.yml");
+    path_buf
+}
+
+/// Read the channel edits from a file, there are no edits if there is no file.
+pub fn read_channel_edits(path: &Path) -> Result<Vec<ChannelEdit>, String> { data_files::read_yaml(path) }
+
+pub fn write_channel_edits(path: &Path, edits: &[ChannelEdit]) -> Result<(), String> { data_files::write_yaml(path, edits) }
+
+/// Return the channel edits currently in force.
+pub fn get_channel_edits() -> Vec<ChannelEdit> { // Used in channel_editor.rs and channel_groups.rs
+    CHANNEL_EDITS.read().unwrap().clone()
+}
+
+/// Replace the channel edits, writing them to the channel edits file. Edits that change
+/// nothing are dropped.
+pub fn set_channel_edits(edits: Vec<ChannelEdit>) -> Result<(), String> { // Used in channel_editor.rs
+    let edits = edits.into_iter().filter(|e| ! e.is_empty()).collect::<Vec<ChannelEdit>>();
+    write_channel_edits(&channel_edits_path(), &edits)?;
+    *CHANNEL_EDITS.write().unwrap() = edits;
+    Ok(())
+}
+
+fn edit_of<'a>(edits: &'a [ChannelEdit], channel: &ChannelData) -> Option<&'a ChannelEdit> {
+    edits.iter().find(|e| e.is_of(channel))
+}
+
+/// Apply the names and numbers given by the edits to the channels.
+pub fn apply_edits(edits: &[ChannelEdit], channels: &mut [ChannelData]) {
+    for channel in channels.iter_mut() {
+        if let Some(edit) = edit_of(edits, channel) {
+            if let Some(ref name) = edit.name {
+                channel.name = name.clone();
+            }
+            if let Some(logical_channel_number) = edit.logical_channel_number {
+                channel.logical_channel_number = logical_channel_number;
+            }
+        }
+    }
+}
+
+/// Apply the names and numbers given by the user to the channels.
+pub fn apply(channels: &mut [ChannelData]) { // Used in channels_data.rs
+    apply_edits(&CHANNEL_EDITS.read().unwrap(), channels);
+}
+
+/// Change a name if it has been renamed.
+fn rename(name: &mut String, renames: &HashMap<String, String>) {
+    if let Some(new_name) = renames.get(name) {
+        *name = new_name.clone();
+    }
+}
+
+/// Change the channel names in the recording jobs, recording rules, and XMLTV channel
+/// mappings, so they refer to the same channels after the channels are renamed.
+/// `renames` maps the previous names to the new names.
+pub fn rename_channels(renames: &HashMap<String, String>) -> Result<(), String> { // Used in channel_editor.rs
+    if renames.is_empty() {
+        return Ok(());
+    }
+    recording_jobs::update_recording_jobs(&recording_jobs::recording_jobs_path(), |jobs| {
+        for job in jobs.jobs.iter_mut() {
+            rename(&mut job.channel, renames);
+        }
+    })?;
+    recording_rules::update_recording_rules(&recording_rules::recording_rules_path(), |rules| {
+        for channel in rules.iter_mut().filter_map(|r| r.channel.as_mut()) {
+            rename(channel, renames);
+        }
+    })?;
+    xmltv::update_channel_mappings(&xmltv::xmltv_channels_path(), |mappings| {
+        for channel in mappings.iter_mut().filter_map(|m| m.channel.as_mut()) {
+            rename(channel, renames);
+        }
+    })
+}
+
+/// Has the user hidden the channel.
+pub fn is_hidden(channel: &ChannelData) -> bool {
+    edit_of(&CHANNEL_EDITS.read().unwrap(), channel).map_or(false, |e| e.is_hidden)
+}
+
+/// Has the user marked the channel as a favourite.
+pub fn is_favourite(channel: &ChannelData) -> bool {
+    edit_of(&CHANNEL_EDITS.read().unwrap(), channel).map_or(false, |e| e.is_favourite)
+}
+
+/// Has the user given the channel a number, in which case the logical channel number
+/// broadcast is not used.
+pub fn has_own_number(channel: &ChannelData) -> bool {
+    edit_of(&CHANNEL_EDITS.read().unwrap(), channel).map_or(false, |e| e.logical_channel_number.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dvbv5::Tuning;
+
+    fn channel(name: &str, service_id: u16, logical_channel_number: u16) -> ChannelData {
+        ChannelData {
+            name: name.to_string(),
+            service_id,
+            logical_channel_number,
+            tuning: Tuning { network_id: Some(9018), transport_id: Some(4164), ..Tuning::default() },
+        }
+    }
+
+    #[test]
+    fn edits_rename_and_renumber_the_channels_of_a_rescan() {
+        let edits = vec![
+            ChannelEdit { name: Some("BBC One".to_string()), ..ChannelEdit::new(&channel("BBC ONE Lon", 4164, 1)) },
+            ChannelEdit { logical_channel_number: Some(9), ..ChannelEdit::new(&channel("QVC", 4500, 16)) },
+            ChannelEdit { is_hidden: true, ..ChannelEdit::new(&channel("Ideal World", 4501, 22)) },
+        ];
+        let mut rescanned = vec![channel("BBC ONE Lon", 4164, 1), channel("QVC", 4500, 16), channel("Ideal World", 4501, 22), channel("ITV", 8261, 3)];
+        apply_edits(&edits, &mut rescanned);
+        assert_eq!(rescanned.iter().map(|c| (c.name.as_str(), c.logical_channel_number)).collect::<Vec<(&str, u16)>>(), vec![
+            ("BBC One", 1),
+            ("QVC", 9),
+            ("Ideal World", 22),
+            ("ITV", 3),
+        ]);
+        let mut applied_again = rescanned.clone();
+        apply_edits(&edits, &mut applied_again);
+        assert_eq!(applied_again, rescanned);
+        assert_eq!(edit_of(&edits, &rescanned[2]).map(|e| e.is_hidden), Some(true));
+        let other_network = ChannelData { tuning: Tuning { network_id: Some(9019), ..rescanned[0].tuning.clone() }, ..channel("BBC ONE Lon", 4164, 1) };
+        assert!(edit_of(&edits, &other_network).is_none());
+        let imported = [ChannelData { tuning: Tuning::default(), ..channel("BBC ONE Lon", 4164, 1) }, ChannelData { tuning: Tuning::default(), ..channel("BBC One", 4164, 1) }];
+        assert!(imported.iter().all(|c| edit_of(&edits, c) == Some(&edits[0])));
+        let imported_other = ChannelData { tuning: Tuning::default(), ..channel("BBC ONE NI", 4164, 1) };
+        assert!(edit_of(&edits, &imported_other).is_none());
+    }
+
+    #[test]
+    fn edits_are_written_and_read() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let edits = vec![
+            ChannelEdit { name: Some("BBC One".to_string()), is_favourite: true, groups: vec!["News".to_string()], ..ChannelEdit::new(&channel("BBC ONE Lon", 4164, 1)) },
+            ChannelEdit { is_hidden: true, ..ChannelEdit::new(&ChannelData { tuning: Tuning::default(), ..channel("QVC", 4500, 16) }) },
+        ];
+        write_channel_edits(file.path(), &edits).unwrap();
+        assert_eq!(read_channel_edits(file.path()).unwrap(), edits);
+        assert!(ChannelEdit::new(&channel("ITV", 8261, 3)).is_empty());
+    }
+
+    #[test]
+    fn names_are_renamed_once() {
+        // Swapping two names must not rename the first back again.
+        let renames = [("BBC ONE Lon".to_string(), "BBC TWO".to_string()), ("BBC TWO".to_string(), "BBC ONE Lon".to_string())]
+            .iter().cloned().collect::<HashMap<String, String>>();
+        let mut names = vec!["BBC ONE Lon".to_string(), "BBC TWO".to_string(), "ITV".to_string()];
+        names.iter_mut().for_each(|n| rename(n, &renames));
+        assert_eq!(names, vec!["BBC TWO".to_string(), "BBC ONE Lon".to_string(), "ITV".to_string()]);
+    }
+}
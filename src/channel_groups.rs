@@ -0,0 +1,149 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Named groups of channels, for example Favourites, Kids, News, and Radio, that the
+//! channel selectors can be switched between.
+//!
+//! Every channel is in the All channels group. The Favourites group is the channels marked
+//! as favourites and the Radio group the channels with sound but no pictures, other groups
+//! are named by the user in the channel editor. Hidden channels are in no group.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::channel_edits::{self, ChannelEdit};
+use crate::channels_data::{self, ChannelData};
+
+pub const ALL_CHANNELS: &str = "All channels";
+pub const FAVOURITES: &str = "Favourites";
+pub const RADIO: &str = "Radio";
+
+/// Return the names of the groups the channel is in, other than All channels.
+fn groups_of(channel: &ChannelData, edits: &[ChannelEdit]) -> Vec<String> {
+    let mut groups = Vec::new();
+    if let Some(edit) = edits.iter().find(|e| e.is_of(channel)) {
+        if edit.is_favourite {
+            groups.push(FAVOURITES.to_string());
+        }
+        for group in edit.groups.iter() {
+            if ! groups.contains(group) {
+                groups.push(group.clone());
+            }
+        }
+    }
+    if channel.tuning.video_pid.is_none() && ! channel.tuning.audio_pids.is_empty() && ! groups.iter().any(|g| g == RADIO) {
+        groups.push(RADIO.to_string());
+    }
+    groups
+}
+
+/// Return the names of the groups that have channels: All channels, Favourites, the groups
+/// named by the user in alphabetical order, and then Radio.
+fn group_names(channels: &[ChannelData], edits: &[ChannelEdit]) -> Vec<String> {
+    let groups = channels.iter().flat_map(|c| groups_of(c, edits)).collect::<BTreeSet<String>>();
+    let mut names = vec![ALL_CHANNELS.to_string()];
+    if groups.contains(FAVOURITES) {
+        names.push(FAVOURITES.to_string());
+    }
+    names.extend(groups.iter().filter(|g| *g != FAVOURITES && *g != RADIO).cloned());
+    if groups.contains(RADIO) {
+        names.push(RADIO.to_string());
+    }
+    names
+}
+
+/// Return the groups named in a text, the names being separated by commas, without All
+/// channels and Favourites which are not given by name.
+pub fn groups_of_text(text: &str) -> Vec<String> { // Used in channel_editor.rs
+    let mut groups: Vec<String> = Vec::new();
+    for group in text.split(',').map(|g| g.trim()) {
+        if ! group.is_empty() && group != ALL_CHANNELS && group != FAVOURITES && ! groups.iter().any(|g| g == group) {
+            groups.push(group.to_string());
+        }
+    }
+    groups
+}
+
+/// Is a channel in the group given the groups it is in, one name per line.
+pub fn is_in_group(groups: &str, group: &str) -> bool { // Used in control_window_button.rs
+    group == ALL_CHANNELS || groups.lines().any(|g| g == group)
+}
+
+/// Return the groups of each channel not hidden, one name per line, by channel name.
+pub fn get_groups_by_channel_name() -> HashMap<String, String> { // Used in control_window.rs
+    let edits = channel_edits::get_channel_edits();
+    channels_data::get_channels_data_ordered_by_logical_channel_number()
+        .unwrap_or_default()
+        .iter()
+        .map(|c| (c.name.clone(), groups_of(c, &edits).join("\n")))
+        .collect()
+}
+
+/// Return the names of the groups that have channels, All channels first.
+pub fn get_group_names() -> Vec<String> { // Used in control_window.rs
+    let channels = channels_data::get_channels_data_ordered_by_logical_channel_number().unwrap_or_default();
+    group_names(&channels, &channel_edits::get_channel_edits())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dvbv5::Tuning;
+
+    fn channel(name: &str, service_id: u16, video_pid: Option<u16>) -> ChannelData {
+        ChannelData {
+            name: name.to_string(),
+            service_id,
+            logical_channel_number: 0,
+            tuning: Tuning { network_id: Some(9018), transport_id: Some(4164), video_pid, audio_pids: vec![102], ..Tuning::default() },
+        }
+    }
+
+    #[test]
+    fn channels_are_in_the_groups_of_their_edits() {
+        let channels = vec![channel("BBC One", 4164, Some(101)), channel("CBeebies", 4671, Some(201)), channel("BBC Radio 4", 6720, None)];
+        let edits = vec![
+            ChannelEdit { is_favourite: true, groups: vec!["News".to_string()], ..ChannelEdit::new(&channels[0]) },
+            ChannelEdit { groups: vec!["Kids".to_string()], ..ChannelEdit::new(&channels[1]) },
+            ChannelEdit { groups: vec!["News".to_string(), "Radio".to_string()], ..ChannelEdit::new(&channels[2]) },
+        ];
+        assert_eq!(groups_of(&channels[0], &edits), vec!["Favourites", "News"]);
+        assert_eq!(groups_of(&channels[1], &edits), vec!["Kids"]);
+        assert_eq!(groups_of(&channels[2], &edits), vec!["News", "Radio"]);
+        assert_eq!(groups_of(&channels[2], &[]), vec!["Radio"]);
+        assert_eq!(group_names(&channels, &edits), vec!["All channels", "Favourites", "Kids", "News", "Radio"]);
+        assert_eq!(group_names(&channels[..2], &[]), vec!["All channels"]);
+    }
+
+    #[test]
+    fn groups_are_read_from_text() {
+        assert_eq!(groups_of_text(" News, Kids,,News , All channels, Favourites"), vec!["News", "Kids"]);
+        assert!(groups_of_text("").is_empty());
+    }
+
+    #[test]
+    fn membership_of_groups() {
+        assert!(is_in_group("", ALL_CHANNELS));
+        assert!(is_in_group("Favourites\nNews", "News"));
+        assert!(! is_in_group("Favourites\nNews", "Kids"));
+        assert!(! is_in_group("Newsround", "News"));
+    }
+}
@@ -0,0 +1,151 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The dialogs for importing and exporting channel lists in the formats of other programs.
+
+use std::rc::Rc;
+
+use gtk;
+use gtk::prelude::*;
+
+use crate::channel_lists::{self, EXPORT_FORMATS};
+use crate::channels_data;
+use crate::control_window::ControlWindow;
+use crate::dialogs::{display_an_error_dialog, display_an_information_dialog};
+
+/// The number of skipped entries listed when telling the user of them.
+const SKIPPED_SHOWN: usize = 10;
+
+/// Return the description of the skipped entries of a channel list for the user.
+fn skipped_text(skipped: &[String]) -> String {
+    let mut text = skipped.iter().take(SKIPPED_SHOWN).cloned().collect::<Vec<String>>().join("\n");
+    if skipped.len() > SKIPPED_SHOWN {
+        text.push_str(&format!("\nand {} more.", skipped.len() - SKIPPED_SHOWN));
+    }
+    text
+}
+
+/// Present a file chooser for a channel list, a zap or VDR channels.conf file, an M3U
+/// playlist, or a DVBv5 channels file, and replace the channels file with the channels
+/// of the list chosen.
+///
+/// Channels of an M3U playlist given by a dvb:// MRL take their tuning from the channel
+/// of that name in the current channels file.
+pub fn present_import(control_window: &Rc<ControlWindow>) {
+    let dialog = gtk::FileChooserDialog::with_buttons(
+        Some("Me TV Import Channels"),
+        Some(&control_window.window),
+        gtk::FileChooserAction::Open,
+        &[("Cancel", gtk::ResponseType::Cancel), ("Import", gtk::ResponseType::Accept)],
+    );
+    let channel_lists_filter = gtk::FileFilter::new();
+    channel_lists_filter.set_name(Some("Channel lists"));
+    for pattern in ["*.conf", "*.m3u", "*.m3u8"].iter() {
+        channel_lists_filter.add_pattern(pattern);
+    }
+    dialog.add_filter(&channel_lists_filter);
+    let all_files_filter = gtk::FileFilter::new();
+    all_files_filter.set_name(Some("All files"));
+    all_files_filter.add_pattern("*");
+    dialog.add_filter(&all_files_filter);
+    let response = gtk::ResponseType::from(dialog.run());
+    let path = dialog.get_filename();
+    unsafe { dialog.destroy(); }
+    let path = match path {
+        Some(path) if response == gtk::ResponseType::Accept => path,
+        _ => return,
+    };
+    let mut list = match channel_lists::read_channel_list(&path) {
+        Ok(list) => list,
+        Err(e) => {
+            display_an_error_dialog(Some(&control_window.window), &e);
+            return;
+        },
+    };
+    list.resolve_names(&channels_data::get_all_channels_data_ordered_by_logical_channel_number().unwrap_or_default());
+    if list.channels.is_empty() {
+        display_an_error_dialog(
+            Some(&control_window.window),
+            &format!("No channels could be read from the {} {}, the channels file has not been changed.", list.format.description(), path.display()),
+        );
+        return;
+    }
+    match channels_data::replace_channels(&list.channels) {
+        Ok(count) => {
+            control_window.update_channels_store();
+            let mut message = format!("{} channels were imported from the {} {}.", count, list.format.description(), path.display());
+            if ! list.skipped.is_empty() {
+                message.push_str(&format!("\n\nThese entries could not be used and were skipped:\n{}", skipped_text(&list.skipped)));
+            }
+            display_an_information_dialog(Some(&control_window.window), &message);
+        },
+        Err(e) => display_an_error_dialog(Some(&control_window.window), &e),
+    }
+}
+
+/// Present a file chooser for writing the channels, with their logical channel numbers, as
+/// a DVBv5 channels file, a VDR channels.conf file, or an M3U playlist of dvb:// MRLs.
+pub fn present_export(control_window: &Rc<ControlWindow>) {
+    let channels = match channels_data::get_channels_data_ordered_by_logical_channel_number() {
+        Some(channels) if ! channels.is_empty() => channels,
+        _ => {
+            display_an_error_dialog(Some(&control_window.window), "There are no channels to export.");
+            return;
+        },
+    };
+    let dialog = gtk::FileChooserDialog::with_buttons(
+        Some("Me TV Export Channels"),
+        Some(&control_window.window),
+        gtk::FileChooserAction::Save,
+        &[("Cancel", gtk::ResponseType::Cancel), ("Export", gtk::ResponseType::Accept)],
+    );
+    dialog.set_do_overwrite_confirmation(true);
+    let format_selector = gtk::ComboBoxText::new();
+    for format in EXPORT_FORMATS.iter() {
+        format_selector.append_text(format.description());
+    }
+    format_selector.connect_changed({
+        let d = dialog.clone();
+        move |f_s| if let Some(index) = f_s.get_active() {
+            d.set_current_name(EXPORT_FORMATS[index as usize].file_name());
+        }
+    });
+    format_selector.set_active(Some(0));
+    dialog.set_extra_widget(&format_selector);
+    let response = gtk::ResponseType::from(dialog.run());
+    let path = dialog.get_filename();
+    let format = EXPORT_FORMATS[format_selector.get_active().unwrap_or(0) as usize];
+    unsafe { dialog.destroy(); }
+    let path = match path {
+        Some(path) if response == gtk::ResponseType::Accept => path,
+        _ => return,
+    };
+    match channel_lists::write_channel_list_file(&path, format, &channels) {
+        Ok(count) => {
+            let mut message = format!("{} channels were exported to the {} {}.", count, format.description(), path.display());
+            if count < channels.len() {
+                message.push_str(&format!("\n\n{} channels could not be given in this format and were left out.", channels.len() - count));
+            }
+            display_an_information_dialog(Some(&control_window.window), &message);
+        },
+        Err(e) => display_an_error_dialog(Some(&control_window.window), &e),
+    }
+}
@@ -0,0 +1,1005 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Channel lists in the formats of other programs.
+//!
+//! Before DVBv5 channels files there were the colon separated channels.conf files of the
+//! zap programs: tzap for terrestrial, czap for cable, szap for satellite, and azap for
+//! ATSC, each with its own sequence of fields. VDR has a colon separated channels.conf of
+//! its own with the tuning parameters packed into a single field, the position of a
+//! channel in the file being its number. An M3U playlist has an `#EXTINF` line for each
+//! channel followed by the URL of the channel, either a dvb:// MRL naming a channel of
+//! the channels file or a VLC style MRL such as dvb-t://frequency=490000000:bandwidth=8
+//! with the service given by an `#EXTVLCOPT:program=` line.
+//!
+//! All of these, and DVBv5 channels files, are read into `ChannelData` so that they can
+//! be written out as the GStreamer dvbsrc channels file.
+//!
+//! The channels can also be written out as a DVBv5 channels file, a VDR channels.conf
+//! file, or an M3U playlist of dvb:// MRLs, keeping the logical channel numbers: as an
+//! LCN key in a DVBv5 file, a key dvbsrc ignores, as positions in a VDR file, and as
+//! tvg-chno attributes in an M3U playlist.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use ini;
+use percent_encoding;
+
+use crate::channels_data::{encode_to_mrl, write_channels_file, ChannelData};
+use crate::dvbv5::{CodeRate, DeliverySystem, GuardInterval, Hierarchy, Inversion, Modulation, Polarization, TransmissionMode, Tuning};
+
+/// The formats of channel list that can be read.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    DVBv5,
+    Zap,
+    VDR,
+    M3U,
+}
+
+/// The formats channel lists can be written in.
+pub const EXPORT_FORMATS: [Format; 3] = [Format::DVBv5, Format::VDR, Format::M3U];
+
+impl Format {
+    pub fn description(self) -> &'static str {
+        match self {
+            Format::DVBv5 => "DVBv5 channels file",
+            Format::Zap => "zap channels.conf",
+            Format::VDR => "VDR channels.conf",
+            Format::M3U => "M3U playlist",
+        }
+    }
+
+    /// The name usually given to a file of the format.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Format::DVBv5 => "dvb-channels.conf",
+            Format::Zap | Format::VDR => "channels.conf",
+            Format::M3U => "channels.m3u",
+        }
+    }
+}
+
+/// The channels of a channel list together with a description of each of the entries
+/// that could not be used, e.g. "line 12".
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChannelList {
+    pub format: Format,
+    pub channels: Vec<ChannelData>,
+    pub skipped: Vec<String>,
+}
+
+impl ChannelList {
+    /// Complete the channels that have only a name, as given by a dvb:// MRL, using the
+    /// known channel of that name. Those for which there is no such channel are skipped.
+    pub fn resolve_names(&mut self, known: &[ChannelData]) {
+        let mut channels = Vec::new();
+        for channel in self.channels.drain(..) {
+            if channel.tuning.frequency.is_some() {
+                channels.push(channel);
+            } else {
+                match known.iter().find(|k| k.name == channel.name) {
+                    Some(k) => channels.push(ChannelData {
+                        logical_channel_number: if channel.logical_channel_number == 0 { k.logical_channel_number } else { channel.logical_channel_number },
+                        ..k.clone()
+                    }),
+                    None => self.skipped.push(format!("\"{}\", not a known channel", channel.name)),
+                }
+            }
+        }
+        self.channels = channels;
+        self.clear_repeated_numbers();
+    }
+
+    /// Clear the number of each channel having the number of an earlier channel, there
+    /// must be only one channel with a given number.
+    fn clear_repeated_numbers(&mut self) {
+        let mut numbers = BTreeSet::new();
+        for channel in self.channels.iter_mut().filter(|c| c.logical_channel_number != 0) {
+            if ! numbers.insert(channel.logical_channel_number) {
+                self.skipped.push(format!("the number {} of \"{}\", a repeated number", channel.logical_channel_number, channel.name));
+                channel.logical_channel_number = 0;
+            }
+        }
+    }
+}
+
+/// Return the format of a channel list, `None` if it is not one of those known. The
+/// first line with content decides.
+pub fn detect_format(text: &str) -> Option<Format> {
+    let line = text.lines().map(str::trim).find(|l| l.starts_with("#EXT") || ! (l.is_empty() || l.starts_with('#')))?;
+    if line.starts_with("#EXT") {
+        return Some(Format::M3U);
+    }
+    if line.starts_with('[') {
+        return Some(Format::DVBv5);
+    }
+    let fields = line.split(':').map(str::trim).collect::<Vec<&str>>();
+    if line.starts_with(':') || (fields.len() >= 13 && fields[3].starts_with(|c: char| "TCSA".contains(c))) {
+        return Some(Format::VDR);
+    }
+    match fields.len() {
+        6 | 8 | 9 | 13 => Some(Format::Zap),
+        _ => None,
+    }
+}
+
+/// Return the lines with content, numbered from 1, lines starting with # being comments.
+fn data_lines(text: &str) -> impl Iterator<Item=(usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| ! (l.is_empty() || l.starts_with('#')))
+}
+
+fn channel(name: &str, service_id: u16, logical_channel_number: u16, tuning: Tuning) -> Option<ChannelData> {
+    if name.is_empty() {
+        return None;
+    }
+    Some(ChannelData { name: name.to_string(), service_id, logical_channel_number, tuning })
+}
+
+/// Return the frequency in Hz given one in Hz, kHz, or MHz, channel lists having all three.
+fn frequency_hz(frequency: u64) -> Option<u32> {
+    match frequency {
+        0 => None,
+        f if f < 10_000 => u32::try_from(f * 1_000_000).ok(),
+        f if f < 10_000_000 => u32::try_from(f * 1_000).ok(),
+        f => u32::try_from(f).ok(),
+    }
+}
+
+/// Return the frequency in kHz, the unit used for satellite, given one in Hz, kHz, or MHz.
+fn satellite_frequency_khz(frequency: u64) -> Option<u32> {
+    match frequency {
+        0 => None,
+        f if f < 100_000 => u32::try_from(f * 1_000).ok(),
+        f if f < 100_000_000 => u32::try_from(f).ok(),
+        f => u32::try_from(f / 1_000).ok(),
+    }
+}
+
+/// Return the PIDs of a field. PIDs are separated by commas, semicolons, or plus signs,
+/// and may be followed by =language or @type as in VDR files. Zero means no PID.
+fn pids(field: &str) -> Vec<u16> {
+    field.split(&[',', ';', '+'][..])
+        .filter_map(|p| p.trim().chars().take_while(char::is_ascii_digit).collect::<String>().parse::<u16>().ok())
+        .filter(|p| *p != 0)
+        .collect()
+}
+
+fn polarization(text: &str) -> Option<Polarization> {
+    match text.trim().chars().next()?.to_ascii_uppercase() {
+        'H' => Some(Polarization::HORIZONTAL),
+        'V' => Some(Polarization::VERTICAL),
+        'L' => Some(Polarization::LEFT),
+        'R' => Some(Polarization::RIGHT),
+        _ => None,
+    }
+}
+
+/// Return the modulation given its name in any of the forms used by channel lists:
+/// QAM_64 as in zap files, QAM/64 as in DVBv5 files, 64QAM as in VLC MRLs, or 8VSB.
+fn modulation(text: &str) -> Option<Modulation> {
+    let text = text.trim().to_ascii_uppercase();
+    Modulation::from_linux_name(&text)
+        .or_else(|| Modulation::from_dvbv5(&text))
+        .or_else(|| match text.as_str() {
+            "QAM" => Some(Modulation::QAM_AUTO),
+            "8PSK" => Some(Modulation::PSK_8),
+            "16APSK" => Some(Modulation::APSK_16),
+            "32APSK" => Some(Modulation::APSK_32),
+            "8VSB" => Some(Modulation::VSB_8),
+            "16VSB" => Some(Modulation::VSB_16),
+            t if t.ends_with("QAM") => Modulation::from_dvbv5(&format!("QAM/{}", &t[..t.len() - 3])),
+            _ => None,
+        })
+}
+
+/// Return the bandwidth in Hz given the name of the Linux DVB API constant, e.g.
+/// BANDWIDTH_8_MHZ or BANDWIDTH_1_712_MHZ, `None` for BANDWIDTH_AUTO.
+fn bandwidth_hz(text: &str) -> Option<u32> {
+    let text = text.trim().to_ascii_uppercase();
+    if ! (text.starts_with("BANDWIDTH_") && text.ends_with("_MHZ")) {
+        return None;
+    }
+    let megahertz = text["BANDWIDTH_".len()..text.len() - "_MHZ".len()].replace('_', ".").parse::<f64>().ok()?;
+    Some((megahertz * 1_000_000.0).round() as u32)
+}
+
+/// Return the channel of a line of a zap channels.conf file, the number of fields
+/// determining which of the zap programs it is for.
+fn zap_channel(line: &str) -> Option<ChannelData> {
+    let fields = line.split(':').map(str::trim).collect::<Vec<&str>>();
+    let mut tuning = Tuning::default();
+    let pids_index = match fields.len() {
+        13 => {  // tzap
+            tuning.delivery_system = Some(DeliverySystem::DVBT);
+            tuning.frequency = Some(frequency_hz(fields[1].parse().ok()?)?);
+            tuning.inversion = Inversion::from_linux_name(fields[2]);
+            tuning.bandwidth_hz = bandwidth_hz(fields[3]);
+            tuning.code_rate_hp = CodeRate::from_linux_name(fields[4]);
+            tuning.code_rate_lp = CodeRate::from_linux_name(fields[5]);
+            tuning.modulation = modulation(fields[6]);
+            tuning.transmission_mode = TransmissionMode::from_linux_name(fields[7]);
+            tuning.guard_interval = GuardInterval::from_linux_name(fields[8]);
+            tuning.hierarchy = Hierarchy::from_linux_name(fields[9]);
+            10
+        },
+        9 => {  // czap
+            tuning.delivery_system = Some(DeliverySystem::DVBC_ANNEX_A);
+            tuning.frequency = Some(frequency_hz(fields[1].parse().ok()?)?);
+            tuning.inversion = Inversion::from_linux_name(fields[2]);
+            tuning.symbol_rate = fields[3].parse().ok();
+            tuning.inner_fec = CodeRate::from_linux_name(fields[4]);
+            tuning.modulation = modulation(fields[5]);
+            6
+        },
+        8 => {  // szap, the frequency in MHz and the symbol rate in kSym/s.
+            tuning.delivery_system = Some(DeliverySystem::DVBS);
+            tuning.frequency = Some(satellite_frequency_khz(fields[1].parse().ok()?)?);
+            tuning.polarization = polarization(fields[2]);
+            if ! fields[3].is_empty() {
+                tuning.other.insert("SAT_NUMBER".to_string(), fields[3].to_string());
+            }
+            tuning.symbol_rate = fields[4].parse::<u32>().ok().map(|s| s * 1_000);
+            5
+        },
+        6 => {  // azap
+            tuning.delivery_system = Some(DeliverySystem::ATSC);
+            tuning.frequency = Some(frequency_hz(fields[1].parse().ok()?)?);
+            tuning.modulation = modulation(fields[2]);
+            3
+        },
+        _ => return None,
+    };
+    tuning.video_pid = pids(fields[pids_index]).first().cloned();
+    tuning.audio_pids = pids(fields[pids_index + 1]);
+    let service_id = fields[pids_index + 2].parse::<u16>().ok().filter(|s| *s != 0)?;
+    channel(fields[0], service_id, 0, tuning)
+}
+
+fn parse_zap(text: &str) -> (Vec<ChannelData>, Vec<String>) {
+    let mut channels = Vec::new();
+    let mut skipped = Vec::new();
+    for (line_number, line) in data_lines(text) {
+        match zap_channel(line) {
+            Some(c) => channels.push(c),
+            None => skipped.push(format!("line {}", line_number)),
+        }
+    }
+    (channels, skipped)
+}
+
+/// Return the letter/number pairs of the parameters field of a VDR channels.conf line,
+/// e.g. B8C23D0G32M64S0T8Y0. The polarization letters have no number.
+fn vdr_parameters(field: &str) -> Vec<(char, Option<u32>)> {
+    let mut result = Vec::new();
+    let mut characters = field.trim().chars().peekable();
+    while let Some(letter) = characters.next() {
+        let mut digits = String::new();
+        while let Some(digit) = characters.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(*digit);
+            characters.next();
+        }
+        result.push((letter.to_ascii_uppercase(), digits.parse().ok()));
+    }
+    result
+}
+
+fn vdr_code_rate(value: u32) -> Option<CodeRate> {
+    match value {
+        0 => Some(CodeRate::FEC_NONE),
+        999 => Some(CodeRate::FEC_AUTO),
+        v => CodeRate::from_dvbv5(&format!("{}/{}", v / 10, v % 10))
+            .or_else(|| CodeRate::from_dvbv5(&format!("{}/{}", v / 100, v % 100))),
+    }
+}
+
+fn vdr_modulation(value: u32) -> Option<Modulation> {
+    match value {
+        2 => Some(Modulation::QPSK),
+        5 => Some(Modulation::PSK_8),
+        6 => Some(Modulation::APSK_16),
+        7 => Some(Modulation::APSK_32),
+        10 => Some(Modulation::VSB_8),
+        11 => Some(Modulation::VSB_16),
+        12 => Some(Modulation::DQPSK),
+        999 => Some(Modulation::QAM_AUTO),
+        v => Modulation::from_dvbv5(&format!("QAM/{}", v)),
+    }
+}
+
+fn vdr_guard_interval(value: u32) -> Option<GuardInterval> {
+    match value {
+        19128 => Some(GuardInterval::GUARD_INTERVAL_19_128),
+        19256 => Some(GuardInterval::GUARD_INTERVAL_19_256),
+        999 => Some(GuardInterval::GUARD_INTERVAL_AUTO),
+        v => GuardInterval::from_dvbv5(&format!("1/{}", v)),
+    }
+}
+
+fn vdr_transmission_mode(value: u32) -> Option<TransmissionMode> {
+    match value {
+        999 => Some(TransmissionMode::TRANSMISSION_MODE_AUTO),
+        v => TransmissionMode::from_dvbv5(&format!("{}K", v)),
+    }
+}
+
+fn vdr_hierarchy(value: u32) -> Option<Hierarchy> {
+    match value {
+        0 => Some(Hierarchy::HIERARCHY_NONE),
+        999 => Some(Hierarchy::HIERARCHY_AUTO),
+        v => Hierarchy::from_dvbv5(&v.to_string()),
+    }
+}
+
+fn vdr_inversion(value: u32) -> Option<Inversion> {
+    match value {
+        0 => Some(Inversion::INVERSION_OFF),
+        1 => Some(Inversion::INVERSION_ON),
+        _ => Some(Inversion::INVERSION_AUTO),
+    }
+}
+
+/// Return the channel of a line of a VDR channels.conf file. The fields are name,
+/// frequency, parameters, source, symbol rate, VPID, APID, TPID, CAID, SID, NID, TID, and
+/// RID. The name may be followed by a short name after a comma and by the provider
+/// after a semicolon, with any colon in it written as |.
+fn vdr_channel(line: &str, number: u16) -> Option<ChannelData> {
+    let fields = line.split(':').map(str::trim).collect::<Vec<&str>>();
+    if fields.len() < 13 {
+        return None;
+    }
+    let name = fields[0].split(';').next()?.split(',').next()?.replace('|', ":");
+    let frequency = fields[1].parse::<u64>().ok()?;
+    let parameters = vdr_parameters(fields[2]);
+    let parameter = |letter: char| parameters.iter().find(|(l, _)| *l == letter).and_then(|(_, v)| *v);
+    let is_second_generation = parameter('S') == Some(1);
+    let symbol_rate = fields[4].parse::<u32>().ok().filter(|s| *s != 0).map(|s| s * 1_000);
+    let mut tuning = Tuning::default();
+    match fields[3].chars().next()? {
+        'T' => {
+            tuning.delivery_system = Some(if is_second_generation { DeliverySystem::DVBT2 } else { DeliverySystem::DVBT });
+            tuning.frequency = Some(frequency_hz(frequency)?);
+            tuning.bandwidth_hz = parameter('B').map(|b| if b == 1712 { 1_712_000 } else { b * 1_000_000 });
+            tuning.code_rate_hp = parameter('C').and_then(vdr_code_rate);
+            tuning.code_rate_lp = parameter('D').and_then(vdr_code_rate);
+            tuning.guard_interval = parameter('G').and_then(vdr_guard_interval);
+            tuning.transmission_mode = parameter('T').and_then(vdr_transmission_mode);
+            tuning.hierarchy = parameter('Y').and_then(vdr_hierarchy);
+        },
+        'C' => {
+            tuning.delivery_system = Some(DeliverySystem::DVBC_ANNEX_A);
+            tuning.frequency = Some(frequency_hz(frequency)?);
+            tuning.symbol_rate = symbol_rate;
+            tuning.inner_fec = parameter('C').and_then(vdr_code_rate);
+        },
+        'S' => {
+            tuning.delivery_system = Some(if is_second_generation { DeliverySystem::DVBS2 } else { DeliverySystem::DVBS });
+            tuning.frequency = Some(satellite_frequency_khz(frequency)?);
+            tuning.polarization = parameters.iter().filter(|(_, v)| v.is_none()).find_map(|(l, _)| polarization(&l.to_string()));
+            tuning.symbol_rate = symbol_rate;
+            tuning.inner_fec = parameter('C').and_then(vdr_code_rate);
+        },
+        'A' => {
+            tuning.delivery_system = Some(DeliverySystem::ATSC);
+            tuning.frequency = Some(frequency_hz(frequency)?);
+        },
+        _ => return None,
+    }
+    tuning.modulation = parameter('M').and_then(vdr_modulation);
+    tuning.inversion = parameter('I').and_then(vdr_inversion);
+    if is_second_generation {
+        tuning.stream_id = parameter('P');
+    }
+    tuning.video_pid = pids(fields[5]).first().cloned();
+    tuning.audio_pids = pids(fields[6]);
+    tuning.network_id = fields[10].parse().ok();
+    tuning.transport_id = fields[11].parse().ok();
+    let service_id = fields[9].parse::<u16>().ok().filter(|s| *s != 0)?;
+    channel(&name, service_id, number, tuning)
+}
+
+/// Read the channels of a VDR channels.conf file. Channels are numbered by their position
+/// in the file, a group separator line of the form `:@100 Name` setting the number of the
+/// next channel.
+fn parse_vdr(text: &str) -> (Vec<ChannelData>, Vec<String>) {
+    let mut channels = Vec::new();
+    let mut skipped = Vec::new();
+    let mut number = 1u32;
+    for (line_number, line) in data_lines(text) {
+        if line.starts_with(':') {
+            if let Some(rest) = line.strip_prefix(":@") {
+                let digits = rest.chars().take_while(char::is_ascii_digit).collect::<String>();
+                number = digits.parse().unwrap_or(number);
+            }
+            continue;
+        }
+        match vdr_channel(line, u16::try_from(number).unwrap_or(0)) {
+            Some(c) => channels.push(c),
+            None => skipped.push(format!("line {}", line_number)),
+        }
+        number += 1;
+    }
+    (channels, skipped)
+}
+
+/// Return the name of the channel of an `#EXTINF` line, the text after the first comma
+/// that is not in the quoted value of an attribute.
+fn extinf_name(line: &str) -> &str {
+    let mut is_quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => is_quoted = ! is_quoted,
+            ',' if ! is_quoted => return line[i + 1..].trim(),
+            _ => (),
+        }
+    }
+    ""
+}
+
+/// Return the value of an attribute of an `#EXTINF` line, e.g. tvg-chno="1".
+fn extinf_attribute<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!("{}=\"", key))? + key.len() + 2;
+    let length = line[start..].find('"')?;
+    Some(&line[start..start + length])
+}
+
+/// Return the channel of a URL of an M3U playlist. A dvb:// MRL gives only the name of a
+/// channel, VLC style MRLs give the tuning parameters as key=value options.
+fn m3u_channel(url: &str, name: &str, number: u16, program: Option<u16>) -> Option<ChannelData> {
+    let separator = url.find("://")?;
+    let scheme = url[..separator].to_ascii_lowercase();
+    let rest = &url[separator + 3..];
+    let delivery_system = match scheme.as_str() {
+        "dvb" => {
+            let channel_name = percent_encoding::percent_decode_str(rest).decode_utf8_lossy();
+            return channel(if channel_name.is_empty() { name } else { &channel_name }, 0, number, Tuning::default());
+        },
+        "dvb-t" => DeliverySystem::DVBT,
+        "dvb-t2" => DeliverySystem::DVBT2,
+        "dvb-c" => DeliverySystem::DVBC_ANNEX_A,
+        "dvb-s" => DeliverySystem::DVBS,
+        "dvb-s2" => DeliverySystem::DVBS2,
+        "atsc" => DeliverySystem::ATSC,
+        _ => return None,
+    };
+    let is_satellite = delivery_system == DeliverySystem::DVBS || delivery_system == DeliverySystem::DVBS2;
+    let mut tuning = Tuning { delivery_system: Some(delivery_system), ..Tuning::default() };
+    let mut program = program;
+    for option in rest.split(':') {
+        let mut key_value = option.splitn(2, '=');
+        let key = key_value.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = key_value.next().unwrap_or("").trim();
+        match key.as_str() {
+            "frequency" => {
+                let frequency = value.parse::<u64>().ok()?;
+                tuning.frequency = if is_satellite { satellite_frequency_khz(frequency) } else { frequency_hz(frequency) };
+            },
+            "bandwidth" => tuning.bandwidth_hz = value.parse::<u32>().ok().filter(|b| *b != 0).map(|b| b * 1_000_000),
+            "srate" => tuning.symbol_rate = value.parse().ok(),
+            "modulation" => tuning.modulation = modulation(value),
+            "polarization" => tuning.polarization = polarization(value),
+            "fec" => tuning.inner_fec = CodeRate::from_dvbv5(value),
+            "code-rate-hp" => tuning.code_rate_hp = CodeRate::from_dvbv5(value),
+            "code-rate-lp" => tuning.code_rate_lp = CodeRate::from_dvbv5(value),
+            "guard" => tuning.guard_interval = GuardInterval::from_dvbv5(value),
+            "transmission" => tuning.transmission_mode = TransmissionMode::from_dvbv5(&format!("{}K", value)),
+            "hierarchy" => tuning.hierarchy = Hierarchy::from_dvbv5(value),
+            "plp" => tuning.stream_id = value.parse().ok(),
+            "program" => program = value.parse().ok(),
+            _ => (),
+        }
+    }
+    tuning.frequency?;
+    channel(name, program.filter(|p| *p != 0)?, number, tuning)
+}
+
+/// Read the channels of an M3U playlist. The number of a channel is given by the
+/// tvg-chno attribute of its `#EXTINF` line. Entries with URLs that are not MRLs of
+/// DVB channels, such as streams on the web, are skipped.
+fn parse_m3u(text: &str) -> (Vec<ChannelData>, Vec<String>) {
+    let mut channels = Vec::new();
+    let mut skipped = Vec::new();
+    let mut entry: Option<(usize, &str, u16)> = None;
+    let mut program = None;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with("#EXTINF:") {
+            if let Some((line_number, _, _)) = entry {
+                skipped.push(format!("line {}", line_number));
+            }
+            let number = extinf_attribute(line, "tvg-chno").and_then(|n| n.trim().parse().ok()).unwrap_or(0);
+            entry = Some((index + 1, extinf_name(line), number));
+            program = None;
+        } else if let Some(value) = line.strip_prefix("#EXTVLCOPT:program=") {
+            program = value.trim().parse().ok();
+        } else if ! (line.is_empty() || line.starts_with('#')) {
+            let (line_number, name, number) = entry.take().unwrap_or((index + 1, "", 0));
+            match m3u_channel(line, name, number, program.take()) {
+                Some(c) => channels.push(c),
+                None => skipped.push(format!("line {}", line_number)),
+            }
+        }
+    }
+    if let Some((line_number, _, _)) = entry {
+        skipped.push(format!("line {}", line_number));
+    }
+    (channels, skipped)
+}
+
+fn parse_dvbv5(text: &str) -> Result<(Vec<ChannelData>, Vec<String>), String> {
+    let ini = ini::Ini::load_from_str(text).map_err(|e| format!("Failed to parse the DVBv5 channels file – {}", e))?;
+    let mut channels = Vec::new();
+    let mut skipped = Vec::new();
+    for (name, properties) in ini.iter() {
+        if let Some(name) = name {
+            match properties.get("SERVICE_ID").and_then(|s| s.trim().parse::<u16>().ok()) {
+                Some(service_id) => channels.push(ChannelData {
+                    name: name.to_string(),
+                    service_id,
+                    logical_channel_number: properties.get("LCN").and_then(|n| n.trim().parse().ok()).unwrap_or(0),
+                    tuning: Tuning::from_properties(properties.iter()),
+                }),
+                None => skipped.push(format!("[{}]", name)),
+            }
+        }
+    }
+    Ok((channels, skipped))
+}
+
+/// Return the channels of a channel list in any of the formats known. Only the first
+/// channel of a given name is kept, the name being the key of a channels file, and only
+/// the first channel of a given number keeps its number.
+pub fn parse_channel_list(text: &str) -> Result<ChannelList, String> {
+    let format = detect_format(text).ok_or_else(|| "The file is not a channel list in any of the formats known.".to_string())?;
+    let (channels, skipped) = match format {
+        Format::DVBv5 => parse_dvbv5(text)?,
+        Format::Zap => parse_zap(text),
+        Format::VDR => parse_vdr(text),
+        Format::M3U => parse_m3u(text),
+    };
+    let mut list = ChannelList { format, channels: Vec::new(), skipped };
+    let mut names = BTreeSet::new();
+    for channel in channels {
+        if names.insert(channel.name.clone()) {
+            list.channels.push(channel);
+        } else {
+            list.skipped.push(format!("\"{}\", a repeated name", channel.name));
+        }
+    }
+    list.clear_repeated_numbers();
+    Ok(list)
+}
+
+/// Read a channel list file.
+pub fn read_channel_list(path: &Path) -> Result<ChannelList, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {} – {}", path.display(), e))?;
+    parse_channel_list(&text)
+}
+
+fn vdr_code_rate_value(code_rate: CodeRate) -> Option<String> {
+    match code_rate {
+        CodeRate::FEC_NONE => Some("0".to_string()),
+        CodeRate::FEC_AUTO => Some("999".to_string()),
+        c => Some(c.to_dvbv5().replace('/', "")),
+    }
+}
+
+fn vdr_modulation_value(modulation: Modulation) -> Option<String> {
+    match modulation {
+        Modulation::QPSK => Some("2".to_string()),
+        Modulation::PSK_8 => Some("5".to_string()),
+        Modulation::APSK_16 => Some("6".to_string()),
+        Modulation::APSK_32 => Some("7".to_string()),
+        Modulation::VSB_8 => Some("10".to_string()),
+        Modulation::VSB_16 => Some("11".to_string()),
+        Modulation::DQPSK => Some("12".to_string()),
+        Modulation::QAM_AUTO => Some("999".to_string()),
+        Modulation::QAM_4_NR => None,
+        m => Some(m.to_dvbv5()["QAM/".len()..].to_string()),
+    }
+}
+
+fn vdr_guard_interval_value(guard_interval: GuardInterval) -> Option<String> {
+    match guard_interval {
+        GuardInterval::GUARD_INTERVAL_AUTO => Some("999".to_string()),
+        GuardInterval::GUARD_INTERVAL_19_128 => Some("19128".to_string()),
+        GuardInterval::GUARD_INTERVAL_19_256 => Some("19256".to_string()),
+        g if g.to_dvbv5().starts_with("1/") => Some(g.to_dvbv5()[2..].to_string()),
+        _ => None,
+    }
+}
+
+fn vdr_transmission_mode_value(transmission_mode: TransmissionMode) -> Option<String> {
+    match transmission_mode {
+        TransmissionMode::TRANSMISSION_MODE_AUTO => Some("999".to_string()),
+        t if t.to_dvbv5().ends_with('K') => Some(t.to_dvbv5().trim_end_matches('K').to_string()),
+        _ => None,
+    }
+}
+
+fn vdr_hierarchy_value(hierarchy: Hierarchy) -> Option<String> {
+    match hierarchy {
+        Hierarchy::HIERARCHY_NONE => Some("0".to_string()),
+        Hierarchy::HIERARCHY_AUTO => Some("999".to_string()),
+        h => Some(h.to_dvbv5().to_string()),
+    }
+}
+
+fn vdr_inversion_value(inversion: Inversion) -> Option<String> {
+    match inversion {
+        Inversion::INVERSION_OFF => Some("0".to_string()),
+        Inversion::INVERSION_ON => Some("1".to_string()),
+        Inversion::INVERSION_AUTO => Some("999".to_string()),
+    }
+}
+
+/// Return the line of a VDR channels.conf file for a channel, `None` if the channel
+/// cannot be given in VDR form. Satellite channels cannot, the orbital position of the
+/// satellite that VDR needs as the source is not known.
+fn vdr_line(channel: &ChannelData) -> Option<String> {
+    let tuning = &channel.tuning;
+    let frequency = tuning.frequency?;
+    let mut parameters = Vec::new();
+    let mut add = |letter: char, value: Option<String>| if let Some(value) = value {
+        parameters.push((letter, value));
+    };
+    let (source, is_second_generation) = match tuning.delivery_system? {
+        DeliverySystem::DVBT | DeliverySystem::DVBT2 => {
+            add('B', tuning.bandwidth_hz.map(|b| if b == 1_712_000 { "1712".to_string() } else { (b / 1_000_000).to_string() }));
+            add('C', tuning.code_rate_hp.and_then(vdr_code_rate_value));
+            add('D', tuning.code_rate_lp.and_then(vdr_code_rate_value));
+            add('G', tuning.guard_interval.and_then(vdr_guard_interval_value));
+            add('T', tuning.transmission_mode.and_then(vdr_transmission_mode_value));
+            add('Y', tuning.hierarchy.and_then(vdr_hierarchy_value));
+            ('T', tuning.delivery_system == Some(DeliverySystem::DVBT2))
+        },
+        DeliverySystem::DVBC_ANNEX_A | DeliverySystem::DVBC_ANNEX_B | DeliverySystem::DVBC_ANNEX_C => {
+            add('C', tuning.inner_fec.and_then(vdr_code_rate_value));
+            ('C', false)
+        },
+        DeliverySystem::ATSC => ('A', false),
+        _ => return None,
+    };
+    add('I', tuning.inversion.and_then(vdr_inversion_value));
+    add('M', tuning.modulation.and_then(vdr_modulation_value));
+    if source == 'T' {
+        add('S', Some(if is_second_generation { "1" } else { "0" }.to_string()));
+        if is_second_generation {
+            add('P', tuning.stream_id.map(|s| s.to_string()));
+        }
+    }
+    parameters.sort();
+    let pid_text = |pids: &[u16]| if pids.is_empty() { "0".to_string() } else { pids.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(",") };
+    Some(format!(
+        "{}:{}:{}:{}:{}:{}:{}:0:0:{}:{}:{}:0",
+        channel.name.replace(':', "|"),
+        frequency / 1_000,
+        parameters.iter().map(|(l, v)| format!("{}{}", l, v)).collect::<String>(),
+        source,
+        if source == 'C' { tuning.symbol_rate.map_or(0, |s| s / 1_000) } else { 0 },
+        tuning.video_pid.unwrap_or(0),
+        pid_text(&tuning.audio_pids),
+        channel.service_id,
+        tuning.network_id.unwrap_or(0),
+        tuning.transport_id.unwrap_or(0),
+    ))
+}
+
+/// Write the channels as a VDR channels.conf file. VDR numbers channels by their position
+/// so a group separator of the form `:@100` is written before a channel whose logical
+/// channel number is after the position, channels are expected to be in order of
+/// logical channel number. Return the number of channels written.
+fn write_vdr<W: Write>(writer: &mut W, channels: &[ChannelData]) -> io::Result<usize> {
+    let mut count = 0;
+    let mut number = 1;
+    for channel in channels.iter() {
+        if let Some(line) = vdr_line(channel) {
+            if channel.logical_channel_number > number {
+                number = channel.logical_channel_number;
+                writeln!(writer, ":@{}", number)?;
+            }
+            writeln!(writer, "{}", line)?;
+            number = number.saturating_add(1);
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Write the channels as an M3U playlist of dvb:// MRLs, the logical channel numbers that
+/// are known as tvg-chno attributes.
+fn write_m3u<W: Write>(writer: &mut W, channels: &[ChannelData]) -> io::Result<usize> {
+    writeln!(writer, "#EXTM3U")?;
+    for channel in channels.iter() {
+        if channel.logical_channel_number == 0 {
+            writeln!(writer, "#EXTINF:-1,{}", channel.name)?;
+        } else {
+            writeln!(writer, "#EXTINF:-1 tvg-chno=\"{}\",{}", channel.logical_channel_number, channel.name)?;
+        }
+        writeln!(writer, "{}", encode_to_mrl(&channel.name))?;
+    }
+    Ok(channels.len())
+}
+
+/// Write the channels as a channel list in one of the `EXPORT_FORMATS`, returning the
+/// number of channels written.
+pub fn write_channel_list<W: Write>(writer: &mut W, format: Format, channels: &[ChannelData]) -> io::Result<usize> {
+    match format {
+        Format::DVBv5 => write_channels_file(writer, channels).map(|_| channels.len()),
+        Format::VDR => write_vdr(writer, channels),
+        Format::M3U => write_m3u(writer, channels),
+        Format::Zap => Err(io::Error::new(io::ErrorKind::InvalidInput, "Channel lists are not written in zap format.")),
+    }
+}
+
+/// Write a channel list file.
+pub fn write_channel_list_file(path: &Path, format: Format, channels: &[ChannelData]) -> Result<usize, String> {
+    let mut file = File::create(path).map_err(|e| format!("Failed to open {} – {}", path.display(), e))?;
+    write_channel_list(&mut file, format, channels).map_err(|e| format!("Failed to write {} – {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZAP: &str = "# Hand tuned.
+BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164
+Das Erste:330000000:INVERSION_AUTO:6900000:FEC_NONE:QAM_256:101:102,103:28106
+Das Erste HD:11494:h:0:22000:5101:5102:10301
+WXYZ:177000000:8VSB:49:52:3
+
+Not a channel:490000000:101:102
+BBC ONE Lon:490000000:INVERSION_AUTO:BANDWIDTH_8_MHZ:FEC_2_3:FEC_AUTO:QAM_64:TRANSMISSION_MODE_8K:GUARD_INTERVAL_1_32:HIERARCHY_NONE:101:102:4164
+";
+
+    const VDR: &str = ":@1 Terrestrial
+BBC ONE Lon,BBC ONE;BBC:490000:B8C23D0G32M64S0T8Y0:T:27500:101=2:102=eng,106=eng;105=eng:0:0:4164:9018:4164:0
+BBC TWO;BBC:490000000:B8C23D0G32M64S0T8Y0:T:27500:201:202=eng:0:0:4287:9018:4164:0
+:@10 Satellite
+Das Erste HD;ARD:11494:HC23M5O35P0S1:S19.2E:22000:5101=27:5102=deu@3,5103=mis@3;5106=deu@106:5104;5105=deu:0:10301:1:1019:0
+Nothing here:0:B8:T:0:0:0:0:0:0:0:0:0
+";
+
+    const M3U: &str = "#EXTM3U
+#EXTINF:-1 tvg-chno=\"1\" tvg-name=\"BBC ONE Lon\",BBC ONE Lon
+dvb://BBC%20ONE%20Lon
+#EXTINF:-1,Das Erste
+#EXTVLCOPT:program=28106
+dvb-c://frequency=330000000:srate=6900000:modulation=256QAM
+#EXTINF:-1 group-title=\"Radio, web\",Radio, on the web
+http://example.com/radio.mp3
+#EXTINF:-1 tvg-chno=\"3\",ITV
+dvb-t://frequency=490000:bandwidth=8
+#EXTINF:-1 tvg-chno=\"4\",Channel 4
+dvb://Channel%204
+";
+
+    #[test]
+    fn formats_are_detected() {
+        assert_eq!(detect_format(ZAP), Some(Format::Zap));
+        assert_eq!(detect_format(VDR), Some(Format::VDR));
+        assert_eq!(detect_format(&VDR[VDR.find('\n').unwrap()..]), Some(Format::VDR));
+        assert_eq!(detect_format(M3U), Some(Format::M3U));
+        assert_eq!(detect_format("\n[BBC ONE Lon]\n\tSERVICE_ID = 4164\n"), Some(Format::DVBv5));
+        assert_eq!(detect_format("Just some text.\n"), None);
+        assert!(parse_channel_list("").is_err());
+    }
+
+    #[test]
+    fn zap_channels_are_read() {
+        let list = parse_channel_list(ZAP).unwrap();
+        assert_eq!(list.format, Format::Zap);
+        assert_eq!(list.channels.iter().map(|c| (c.name.as_str(), c.service_id)).collect::<Vec<(&str, u16)>>(), vec![
+            ("BBC ONE Lon", 4164),
+            ("Das Erste", 28106),
+            ("Das Erste HD", 10301),
+            ("WXYZ", 3),
+        ]);
+        assert_eq!(list.skipped, vec!["line 7".to_string(), "\"BBC ONE Lon\", a repeated name".to_string()]);
+        let terrestrial = &list.channels[0].tuning;
+        assert_eq!(terrestrial.delivery_system, Some(DeliverySystem::DVBT));
+        assert_eq!(terrestrial.frequency, Some(490000000));
+        assert_eq!(terrestrial.bandwidth_hz, Some(8000000));
+        assert_eq!(terrestrial.code_rate_hp, Some(CodeRate::FEC_2_3));
+        assert_eq!(terrestrial.guard_interval, Some(GuardInterval::GUARD_INTERVAL_1_32));
+        assert_eq!(terrestrial.hierarchy, Some(Hierarchy::HIERARCHY_NONE));
+        assert_eq!(terrestrial.video_pid, Some(101));
+        let cable = &list.channels[1].tuning;
+        assert_eq!(cable.delivery_system, Some(DeliverySystem::DVBC_ANNEX_A));
+        assert_eq!(cable.symbol_rate, Some(6900000));
+        assert_eq!(cable.modulation, Some(Modulation::QAM_256));
+        assert_eq!(cable.audio_pids, vec![102, 103]);
+        let satellite = &list.channels[2].tuning;
+        assert_eq!(satellite.frequency, Some(11494000));
+        assert_eq!(satellite.polarization, Some(Polarization::HORIZONTAL));
+        assert_eq!(satellite.symbol_rate, Some(22000000));
+        assert_eq!(satellite.other.get("SAT_NUMBER").map(|s| s.as_str()), Some("0"));
+        assert_eq!(list.channels[3].tuning.modulation, Some(Modulation::VSB_8));
+        assert_eq!(bandwidth_hz("BANDWIDTH_1_712_MHZ"), Some(1712000));
+        assert_eq!(bandwidth_hz("BANDWIDTH_AUTO"), None);
+    }
+
+    #[test]
+    fn vdr_channels_are_read_and_numbered() {
+        let list = parse_channel_list(VDR).unwrap();
+        assert_eq!(list.format, Format::VDR);
+        assert_eq!(list.channels.iter().map(|c| (c.name.as_str(), c.service_id, c.logical_channel_number)).collect::<Vec<(&str, u16, u16)>>(), vec![
+            ("BBC ONE Lon", 4164, 1),
+            ("BBC TWO", 4287, 2),
+            ("Das Erste HD", 10301, 10),
+        ]);
+        assert_eq!(list.skipped, vec!["line 6".to_string()]);
+        let terrestrial = &list.channels[0].tuning;
+        assert_eq!(terrestrial.delivery_system, Some(DeliverySystem::DVBT));
+        assert_eq!(terrestrial.frequency, Some(490000000));
+        assert_eq!(terrestrial.bandwidth_hz, Some(8000000));
+        assert_eq!(terrestrial.code_rate_hp, Some(CodeRate::FEC_2_3));
+        assert_eq!(terrestrial.code_rate_lp, Some(CodeRate::FEC_NONE));
+        assert_eq!(terrestrial.modulation, Some(Modulation::QAM_64));
+        assert_eq!(terrestrial.transmission_mode, Some(TransmissionMode::TRANSMISSION_MODE_8K));
+        assert_eq!(terrestrial.video_pid, Some(101));
+        assert_eq!(terrestrial.audio_pids, vec![102, 106, 105]);
+        assert_eq!(terrestrial.network_id, Some(9018));
+        assert_eq!(list.channels[1].tuning.frequency, Some(490000000));
+        let satellite = &list.channels[2].tuning;
+        assert_eq!(satellite.delivery_system, Some(DeliverySystem::DVBS2));
+        assert_eq!(satellite.frequency, Some(11494000));
+        assert_eq!(satellite.polarization, Some(Polarization::HORIZONTAL));
+        assert_eq!(satellite.modulation, Some(Modulation::PSK_8));
+        assert_eq!(satellite.inner_fec, Some(CodeRate::FEC_2_3));
+        assert_eq!(satellite.symbol_rate, Some(22000000));
+        assert_eq!(satellite.stream_id, Some(0));
+        assert_eq!(satellite.audio_pids, vec![5102, 5103, 5106]);
+        assert_eq!(vdr_code_rate(910), Some(CodeRate::FEC_9_10));
+    }
+
+    #[test]
+    fn m3u_channels_are_read_and_resolved_by_name() {
+        let mut list = parse_channel_list(M3U).unwrap();
+        assert_eq!(list.format, Format::M3U);
+        assert_eq!(list.channels.iter().map(|c| (c.name.as_str(), c.service_id, c.logical_channel_number)).collect::<Vec<(&str, u16, u16)>>(), vec![
+            ("BBC ONE Lon", 0, 1),
+            ("Das Erste", 28106, 0),
+            ("Channel 4", 0, 4),
+        ]);
+        assert_eq!(list.skipped, vec!["line 7".to_string(), "line 9".to_string()]);
+        let cable = &list.channels[1].tuning;
+        assert_eq!(cable.delivery_system, Some(DeliverySystem::DVBC_ANNEX_A));
+        assert_eq!(cable.frequency, Some(330000000));
+        assert_eq!(cable.symbol_rate, Some(6900000));
+        assert_eq!(cable.modulation, Some(Modulation::QAM_256));
+        let known = vec![ChannelData {
+            name: "BBC ONE Lon".to_string(),
+            service_id: 4164,
+            logical_channel_number: 101,
+            tuning: Tuning { frequency: Some(490000000), ..Tuning::default() },
+        }];
+        list.resolve_names(&known);
+        assert_eq!(list.channels.iter().map(|c| (c.name.as_str(), c.service_id, c.logical_channel_number)).collect::<Vec<(&str, u16, u16)>>(), vec![
+            ("BBC ONE Lon", 4164, 1),
+            ("Das Erste", 28106, 0),
+        ]);
+        assert_eq!(list.channels[0].tuning.frequency, Some(490000000));
+        assert_eq!(list.skipped.last(), Some(&"\"Channel 4\", not a known channel".to_string()));
+        assert_eq!(extinf_name("#EXTINF:-1 group-title=\"Radio, web\",Radio, on the web"), "Radio, on the web");
+    }
+
+    #[test]
+    fn dvbv5_channels_are_read() {
+        let list = parse_channel_list("[BBC ONE Lon]
+\tSERVICE_ID = 4164
+\tFREQUENCY = 490000000
+\tDELIVERY_SYSTEM = DVBT
+
+[Broken]
+\tFREQUENCY = 490000000
+").unwrap();
+        assert_eq!(list.format, Format::DVBv5);
+        assert_eq!(list.channels.len(), 1);
+        assert_eq!(list.channels[0].service_id, 4164);
+        assert_eq!(list.channels[0].tuning.delivery_system, Some(DeliverySystem::DVBT));
+        assert_eq!(list.skipped, vec!["[Broken]".to_string()]);
+    }
+
+    #[test]
+    fn repeated_numbers_are_cleared() {
+        let mut list = parse_channel_list("#EXTM3U
+#EXTINF:-1 tvg-chno=\"1\",BBC ONE Lon
+dvb-t://frequency=490000:bandwidth=8:program=4164
+#EXTINF:-1 tvg-chno=\"1\",BBC TWO
+dvb-t://frequency=490000:bandwidth=8:program=4287
+#EXTINF:-1,ITV
+dvb://ITV
+").unwrap();
+        assert_eq!(list.skipped, vec!["the number 1 of \"BBC TWO\", a repeated number".to_string()]);
+        let known = vec![ChannelData { name: "ITV".to_string(), service_id: 8261, logical_channel_number: 1, tuning: Tuning { frequency: Some(490000000), ..Tuning::default() } }];
+        list.resolve_names(&known);
+        assert_eq!(list.channels.iter().map(|c| (c.name.as_str(), c.logical_channel_number)).collect::<Vec<(&str, u16)>>(), vec![
+            ("BBC ONE Lon", 1),
+            ("BBC TWO", 0),
+            ("ITV", 0),
+        ]);
+        assert_eq!(list.skipped.last(), Some(&"the number 1 of \"ITV\", a repeated number".to_string()));
+    }
+
+    /// The terrestrial channels of the VDR list, numbered 1 and 7, and the satellite one,
+    /// not numbered, as they would be in the channels data.
+    fn exported_channels() -> Vec<ChannelData> {
+        let mut channels = parse_channel_list(VDR).unwrap().channels;
+        channels[1].logical_channel_number = 7;
+        channels[2].logical_channel_number = 0;
+        channels
+    }
+
+    #[test]
+    fn dvbv5_export_keeps_logical_channel_numbers() {
+        let channels = exported_channels();
+        let mut buffer = Vec::new();
+        assert_eq!(write_channel_list(&mut buffer, Format::DVBv5, &channels).unwrap(), 3);
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(text.starts_with("[BBC ONE Lon]\n\tSERVICE_ID = 4164\n\tLCN = 1\n\tNETWORK_ID = 9018\n"));
+        let list = parse_channel_list(&text).unwrap();
+        assert_eq!(list.skipped, Vec::<String>::new());
+        assert_eq!(list.channels, channels);
+    }
+
+    #[test]
+    fn vdr_export_numbers_channels_by_position() {
+        let channels = exported_channels();
+        let mut buffer = Vec::new();
+        assert_eq!(write_channel_list(&mut buffer, Format::VDR, &channels).unwrap(), 2);
+        let text = String::from_utf8(buffer).unwrap();
+        assert_eq!(text, "BBC ONE Lon:490000:B8C23D0G32M64S0T8Y0:T:0:101:102,106,105:0:0:4164:9018:4164:0
+:@7
+BBC TWO:490000:B8C23D0G32M64S0T8Y0:T:0:201:202:0:0:4287:9018:4164:0
+");
+        let list = parse_channel_list(&text).unwrap();
+        assert_eq!(list.format, Format::VDR);
+        assert_eq!(list.channels, channels[..2].to_vec());
+    }
+
+    #[test]
+    fn m3u_export_is_of_mrls() {
+        let channels = exported_channels();
+        let mut buffer = Vec::new();
+        assert_eq!(write_channel_list(&mut buffer, Format::M3U, &channels).unwrap(), 3);
+        let text = String::from_utf8(buffer).unwrap();
+        assert_eq!(text, "#EXTM3U
+#EXTINF:-1 tvg-chno=\"1\",BBC ONE Lon
+dvb://BBC%20ONE%20Lon
+#EXTINF:-1 tvg-chno=\"7\",BBC TWO
+dvb://BBC%20TWO
+#EXTINF:-1,Das Erste HD
+dvb://Das%20Erste%20HD
+");
+        let mut list = parse_channel_list(&text).unwrap();
+        list.resolve_names(&channels);
+        assert_eq!(list.channels, channels);
+        assert!(write_channel_list(&mut Vec::new(), Format::Zap, &channels).is_err());
+    }
+}
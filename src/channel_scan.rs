@@ -0,0 +1,756 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The data of a scan for channels.
+//!
+//! A transmitter file from the dtv-scan-tables package is in DVBv5 format: a sequence
+//! of `[CHANNEL]` blocks, one per multiplex, each giving the tuning parameters of the
+//! multiplex. A scan tunes to each multiplex in turn and collects the services of the
+//! multiplex from the PAT, PMT, SDT, and NIT sections received. The audio and video
+//! services found are written out as a GStreamer dvbsrc channels file, which is also
+//! in DVBv5 format, cf. dvbv5.rs.
+//!
+//! The NIT of a network gives the delivery system, and so the tuning parameters, of each
+//! of the multiplexes of the network. These are used to find multiplexes missing from
+//! the transmitter file, or all of them when there is no transmitter file and a scan is
+//! started from the frequency of a single multiplex.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use ini;
+
+use crate::channels_data::ChannelData;
+use crate::dvb_text;
+use crate::dvbv5::{CodeRate, DeliverySystem, GuardInterval, Hierarchy, Inversion, Modulation, TransmissionMode, Tuning};
+
+/// The tuning parameters of a multiplex as given in a transmitter file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Transmitter {
+    /// The DVBv5 key/value pairs in the order of the file.
+    pub properties: Vec<(String, String)>,
+}
+
+impl Transmitter {
+    /// Return the value of a DVBv5 key if the transmitter has it.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// The frequency of the multiplex, Hz for terrestrial and cable, kHz for satellite.
+    pub fn frequency(&self) -> u32 {
+        self.get("FREQUENCY").and_then(|f| f.parse::<u32>().ok()).unwrap_or(0)
+    }
+}
+
+/// Return the transmitters described by the text of a transmitter file.
+pub fn parse_transmitters(text: &str) -> Result<Vec<Transmitter>, String> {
+    let ini = ini::Ini::load_from_str(text).map_err(|e| format!("Failed to parse the transmitter file – {}", e))?;
+    let transmitters = ini.iter()
+        .filter(|(name, _)| name.is_some())
+        .map(|(_, properties)| Transmitter {
+            properties: properties.iter().map(|(k, v)| (k.trim().to_string(), v.trim().to_string())).collect(),
+        })
+        .filter(|transmitter| transmitter.frequency() != 0)
+        .collect::<Vec<Transmitter>>();
+    if transmitters.is_empty() {
+        Err("There are no multiplexes with a frequency in the transmitter file, is it in DVBv5 format?".to_string())
+    } else {
+        Ok(transmitters)
+    }
+}
+
+/// Return the transmitters described by a transmitter file.
+pub fn read_transmitters(path: &Path) -> Result<Vec<Transmitter>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {} – {}", path.display(), e))?;
+    parse_transmitters(&text)
+}
+
+/// Return a transmitter given only the delivery system and frequency, in Hz, of a
+/// multiplex. The other tuning parameters are left to the frontend to determine.
+pub fn transmitter_of_frequency(delivery_system: &DeliverySystem, frequency: u32) -> Transmitter {
+    let modulation = match delivery_system {
+        DeliverySystem::ATSC => "VSB/8",
+        _ => "QAM/AUTO",
+    };
+    Transmitter { properties: vec![
+        ("DELIVERY_SYSTEM".to_string(), delivery_system.to_dvbv5().to_string()),
+        ("FREQUENCY".to_string(), frequency.to_string()),
+        ("MODULATION".to_string(), modulation.to_string()),
+        ("INVERSION".to_string(), "AUTO".to_string()),
+    ]}
+}
+
+/// Two transmitters whose frequencies are this close, in Hz, are taken to be the same
+/// multiplex. Transmitter files and the NIT do not always agree on the offsets from the
+/// channel centre frequency used by some transmitters.
+const FREQUENCY_TOLERANCE: u32 = 500_000;
+
+/// Is the transmitter for a multiplex already in a list of transmitters.
+pub fn is_known(transmitters: &[Transmitter], transmitter: &Transmitter) -> bool {
+    let frequency = transmitter.frequency();
+    transmitters.iter().any(|t| {
+        let f = t.frequency();
+        f.max(frequency) - f.min(frequency) <= FREQUENCY_TOLERANCE
+    })
+}
+
+/// Return the dvbsrc property settings to tune to a transmitter, as property name and
+/// value string pairs. Keys that dvbsrc has no property for are ignored, the frontend
+/// then uses its automatic setting.
+pub fn dvbsrc_properties(transmitter: &Transmitter) -> Result<Vec<(&'static str, String)>, String> {
+    let mut result = Vec::new();
+    for (key, value) in transmitter.properties.iter() {
+        let value = value.to_uppercase();
+        let property = match key.as_str() {
+            "FREQUENCY" => Some(("frequency", value.parse::<u32>().ok().map(|f| f.to_string()))),
+            "DELIVERY_SYSTEM" => Some(("delsys", DeliverySystem::from_dvbv5(&value).map(|d| d.index().to_string()))),
+            "BANDWIDTH_HZ" => Some(("bandwidth-hz", value.parse::<u32>().ok().map(|b| b.to_string()))),
+            "MODULATION" => Some(("modulation", Modulation::from_dvbv5(&value).map(|m| m.index().to_string()))),
+            "CODE_RATE_HP" | "INNER_FEC" => Some(("code-rate-hp", CodeRate::from_dvbv5(&value).map(|c| c.index().to_string()))),
+            "CODE_RATE_LP" => Some(("code-rate-lp", CodeRate::from_dvbv5(&value).map(|c| c.index().to_string()))),
+            "GUARD_INTERVAL" => Some(("guard", GuardInterval::from_dvbv5(&value).map(|g| g.index().to_string()))),
+            "TRANSMISSION_MODE" => Some(("trans-mode", TransmissionMode::from_dvbv5(&value).map(|t| t.index().to_string()))),
+            "HIERARCHY" => Some(("hierarchy", Hierarchy::from_dvbv5(&value).map(|h| h.index().to_string()))),
+            "INVERSION" => Some(("inversion", Inversion::from_dvbv5(&value).map(|i| i.index().to_string()))),
+            // The file has Bd, the dvbsrc property is kBd.
+            "SYMBOL_RATE" => Some(("symbol-rate", value.parse::<u32>().ok().map(|s| (s / 1000).to_string()))),
+            "POLARIZATION" => Some(("polarity", value.chars().next().filter(|c| "HVLR".contains(*c)).map(|c| c.to_string()))),
+            "STREAM_ID" => Some(("stream-id", value.parse::<i32>().ok().map(|s| s.to_string()))),
+            _ => None,
+        };
+        if let Some((name, setting)) = property {
+            match setting {
+                Some(setting) => result.push((name, setting)),
+                None => return Err(format!("The value {} of {} is not understood.", value, key)),
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Return the value of a sequence of binary coded decimal digits, starting at the most
+/// significant nibble of the first byte.
+fn bcd(data: &[u8], digits: usize) -> u32 {
+    (0..digits).fold(0, |value, i| {
+        let byte = data[i / 2];
+        let digit = if i % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+        value * 10 + digit as u32
+    })
+}
+
+/// Return the value of a field of a descriptor as a DVBv5 value, `AUTO` if the value is
+/// reserved.
+fn dvbv5_value(values: &[&str], index: u8) -> String {
+    values.get(index as usize).cloned().unwrap_or("AUTO").to_string()
+}
+
+fn to_properties(pairs: Vec<(&str, String)>) -> Vec<(String, String)> {
+    pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+}
+
+/// Decode a terrestrial delivery system descriptor, tag 0x5A, the data including the tag
+/// and length bytes.
+fn parse_terrestrial_delivery_system_descriptor(data: &[u8]) -> Option<Transmitter> {
+    if data.len() < 9 {
+        return None;
+    }
+    let frequency = u32::from_be_bytes([data[2], data[3], data[4], data[5]]).checked_mul(10)?;
+    let hierarchy = (data[7] >> 3) & 0x03;
+    let code_rates = ["1/2", "2/3", "3/4", "5/6", "7/8"];
+    Some(Transmitter { properties: to_properties(vec![
+        ("DELIVERY_SYSTEM", "DVBT".to_string()),
+        ("FREQUENCY", frequency.to_string()),
+        ("BANDWIDTH_HZ", ["8000000", "7000000", "6000000", "5000000"].get((data[6] >> 5) as usize).cloned().unwrap_or("0").to_string()),
+        ("CODE_RATE_HP", dvbv5_value(&code_rates, data[7] & 0x07)),
+        ("CODE_RATE_LP", if hierarchy == 0 { "NONE".to_string() } else { dvbv5_value(&code_rates, data[8] >> 5) }),
+        ("MODULATION", dvbv5_value(&["QPSK", "QAM/16", "QAM/64"], data[7] >> 6)),
+        ("TRANSMISSION_MODE", dvbv5_value(&["2K", "8K", "4K"], (data[8] >> 1) & 0x03)),
+        ("GUARD_INTERVAL", dvbv5_value(&["1/32", "1/16", "1/8", "1/4"], (data[8] >> 3) & 0x03)),
+        ("HIERARCHY", dvbv5_value(&["NONE", "1", "2", "4"], hierarchy)),
+        ("INVERSION", "AUTO".to_string()),
+    ])})
+}
+
+/// Decode a T2 delivery system descriptor, extension tag 0x04 of tag 0x7F, the data
+/// including the tag and length bytes. There is a transmitter for each centre frequency
+/// of each cell. The descriptor has no frequencies if it only gives the PLP.
+///
+/// gst_mpegts does not give access to the cells of the descriptor so the data is decoded
+/// here.
+fn parse_t2_delivery_system_descriptor(data: &[u8]) -> Vec<Transmitter> {
+    let mut result = Vec::new();
+    if data.len() < 8 || data[2] != 0x04 {
+        return result;
+    }
+    let end = (2 + data[1] as usize).min(data.len());
+    let plp_id = data[3];
+    let bandwidth = ["8000000", "7000000", "6000000", "5000000", "10000000", "1712000"].get(((data[6] >> 2) & 0x0F) as usize).cloned().unwrap_or("0");
+    let guard_interval = dvbv5_value(&["1/32", "1/16", "1/8", "1/4", "1/128", "19/128", "19/256"], data[7] >> 5);
+    let transmission_mode = dvbv5_value(&["2K", "8K", "4K", "1K", "16K", "32K"], (data[7] >> 2) & 0x07);
+    let is_tfs = data[7] & 0x01 != 0;
+    let mut frequencies = Vec::new();
+    let mut i = 8;
+    while i + 2 < end {
+        i += 2; // The cell_id.
+        if is_tfs {
+            let loop_end = (i + 1 + data[i] as usize).min(end);
+            frequencies.extend(data[(i + 1)..loop_end].chunks_exact(4).map(|f| u32::from_be_bytes([f[0], f[1], f[2], f[3]])));
+            i = loop_end;
+        } else {
+            if i + 4 > end {
+                break;
+            }
+            frequencies.push(u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]));
+            i += 4;
+        }
+        // Skip the subcells, with or without TFS.
+        if i < end {
+            i += 1 + data[i] as usize;
+        }
+    }
+    for frequency in frequencies.into_iter().filter_map(|f| f.checked_mul(10)) {
+        result.push(Transmitter { properties: to_properties(vec![
+            ("DELIVERY_SYSTEM", "DVBT2".to_string()),
+            ("FREQUENCY", frequency.to_string()),
+            ("BANDWIDTH_HZ", bandwidth.to_string()),
+            ("CODE_RATE_HP", "AUTO".to_string()),
+            ("CODE_RATE_LP", "AUTO".to_string()),
+            ("MODULATION", "QAM/AUTO".to_string()),
+            ("TRANSMISSION_MODE", transmission_mode.clone()),
+            ("GUARD_INTERVAL", guard_interval.clone()),
+            ("HIERARCHY", "NONE".to_string()),
+            ("STREAM_ID", plp_id.to_string()),
+            ("INVERSION", "AUTO".to_string()),
+        ])});
+    }
+    result
+}
+
+/// Decode a cable delivery system descriptor, tag 0x44, the data including the tag and
+/// length bytes.
+fn parse_cable_delivery_system_descriptor(data: &[u8]) -> Option<Transmitter> {
+    if data.len() < 13 {
+        return None;
+    }
+    Some(Transmitter { properties: to_properties(vec![
+        ("DELIVERY_SYSTEM", "DVBC/ANNEX_A".to_string()),
+        ("FREQUENCY", (bcd(&data[2..6], 8) * 100).to_string()),
+        ("SYMBOL_RATE", (bcd(&data[9..13], 7) * 100).to_string()),
+        ("INNER_FEC", match data[12] & 0x0F {
+            0x0F => "NONE".to_string(), // No convolutional coding.
+            fec => dvbv5_value(&["AUTO", "1/2", "2/3", "3/4", "5/6", "7/8", "8/9", "3/5", "4/5", "9/10"], fec),
+        }),
+        ("MODULATION", dvbv5_value(&["QAM/AUTO", "QAM/16", "QAM/32", "QAM/64", "QAM/128", "QAM/256"], data[8])),
+        ("INVERSION", "AUTO".to_string()),
+    ])})
+}
+
+/// Decode a delivery system descriptor of a transport stream of an NIT, the data
+/// including the tag and length bytes, to the transmitters of the transport stream.
+/// Satellite delivery is not handled, it needs the LNB and DiSEqC setup.
+pub fn parse_delivery_system_descriptor(data: &[u8]) -> Vec<Transmitter> {
+    match data.first() {
+        Some(0x5A) => parse_terrestrial_delivery_system_descriptor(data).into_iter().collect(),
+        Some(0x7F) => parse_t2_delivery_system_descriptor(data),
+        Some(0x44) => parse_cable_delivery_system_descriptor(data).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The sections of a table received, to know when all of them have been.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct TableSections {
+    received: BTreeSet<u8>,
+    last_section_number: Option<u8>,
+}
+
+impl TableSections {
+    fn add(&mut self, section_number: u8, last_section_number: u8) {
+        self.received.insert(section_number);
+        self.last_section_number = Some(last_section_number);
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.last_section_number {
+            Some(last) => (0..=last).all(|n| self.received.contains(&n)),
+            None => false,
+        }
+    }
+}
+
+/// A service of a multiplex as found by a scan.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Service {
+    pub service_id: u16,
+    pub name: Option<String>,
+    pub provider: Option<String>,
+    pub service_type: Option<u8>,
+    pub is_scrambled: bool,
+    pub video_pid: Option<u16>,
+    pub audio_pids: Vec<u16>,
+}
+
+/// The kinds of elementary stream of interest when scanning.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StreamKind {
+    Video,
+    Audio,
+}
+
+/// Return the kind of an elementary stream given its PMT stream type and the tags of its
+/// descriptors. DVB carries AC-3, E-AC-3, DTS, and some AAC audio as private data with a
+/// descriptor saying what it is.
+pub fn stream_kind(stream_type: u8, descriptor_tags: &[u8]) -> Option<StreamKind> {
+    match stream_type {
+        0x01 | 0x02 | 0x10 | 0x1B | 0x24 | 0x42 | 0xEA => Some(StreamKind::Video),
+        0x03 | 0x04 | 0x0F | 0x11 | 0x81 | 0x87 => Some(StreamKind::Audio),
+        0x06 if descriptor_tags.iter().any(|t| [0x6A, 0x7A, 0x7B, 0x7C].contains(t)) => Some(StreamKind::Audio),
+        _ => None,
+    }
+}
+
+/// Decode a service descriptor, the data including the tag and length bytes, to the
+/// service type, provider name, and service name.
+pub fn parse_service_descriptor(data: &[u8]) -> Option<(u8, String, String)> {
+    if *data.first()? != 0x48 {
+        return None;
+    }
+    let end = (2 + *data.get(1)? as usize).min(data.len());
+    let service_type = *data.get(2)?;
+    let provider_length = *data.get(3)? as usize;
+    let provider_end = 4 + provider_length;
+    let name_length = *data[..end].get(provider_end)? as usize;
+    let name_end = provider_end + 1 + name_length;
+    if name_end > end {
+        return None;
+    }
+    Some((service_type, dvb_text::decode(&data[4..provider_end]), dvb_text::decode(&data[(provider_end + 1)..name_end])))
+}
+
+/// Decode a logical channel descriptor, tag 0x83 as used by Freeview and others, the
+/// data including the tag and length bytes, to service_id and logical channel number
+/// pairs of the visible services.
+pub fn parse_logical_channel_descriptor(data: &[u8]) -> Vec<(u16, u16)> {
+    if data.len() < 2 || data[0] != 0x83 {
+        return Vec::new();
+    }
+    let end = (2 + data[1] as usize).min(data.len());
+    data[2..end].chunks_exact(4)
+        .filter(|item| item[2] & 0x80 != 0)
+        .map(|item| (((item[0] as u16) << 8) | item[1] as u16, (((item[2] & 0x03) as u16) << 8) | item[3] as u16))
+        .collect()
+}
+
+/// The data collected from the sections received whilst tuned to a multiplex.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MultiplexScan {
+    pub transmitter: Transmitter,
+    pub original_network_id: Option<u16>,
+    pub transport_stream_id: Option<u16>,
+    services: BTreeMap<u16, Service>,
+    logical_channel_numbers: BTreeMap<u16, u16>,
+    discovered_transmitters: Vec<Transmitter>,
+    is_pat_received: bool,
+    pmts_received: BTreeSet<u16>,
+    sdt_sections: TableSections,
+    nit_sections: TableSections,
+}
+
+impl MultiplexScan {
+    pub fn new(transmitter: Transmitter) -> Self {
+        MultiplexScan {
+            transmitter,
+            original_network_id: None,
+            transport_stream_id: None,
+            services: BTreeMap::new(),
+            logical_channel_numbers: BTreeMap::new(),
+            discovered_transmitters: Vec::new(),
+            is_pat_received: false,
+            pmts_received: BTreeSet::new(),
+            sdt_sections: TableSections::default(),
+            nit_sections: TableSections::default(),
+        }
+    }
+
+    fn service(&mut self, service_id: u16) -> &mut Service {
+        self.services.entry(service_id).or_insert_with(|| Service { service_id, ..Default::default() })
+    }
+
+    /// Add the program numbers of the PAT, program number 0 is the NIT not a service.
+    pub fn add_pat(&mut self, program_numbers: &[u16]) {
+        for program_number in program_numbers.iter().filter(|p| **p != 0) {
+            self.service(*program_number);
+        }
+        self.is_pat_received = true;
+    }
+
+    /// Add the elementary streams of the PMT of a service as (stream type, PID,
+    /// descriptor tags) triples.
+    pub fn add_pmt(&mut self, program_number: u16, streams: &[(u8, u16, Vec<u8>)]) {
+        let service = self.service(program_number);
+        service.video_pid = None;
+        service.audio_pids.clear();
+        for (stream_type, pid, descriptor_tags) in streams.iter() {
+            match stream_kind(*stream_type, descriptor_tags) {
+                Some(StreamKind::Video) if service.video_pid.is_none() => service.video_pid = Some(*pid),
+                Some(StreamKind::Video) => (),
+                Some(StreamKind::Audio) => service.audio_pids.push(*pid),
+                None => (),
+            }
+        }
+        self.pmts_received.insert(program_number);
+    }
+
+    /// Add a section of the SDT of this multiplex.
+    pub fn add_sdt_section(&mut self, original_network_id: u16, transport_stream_id: u16, section_number: u8, last_section_number: u8) {
+        self.original_network_id = Some(original_network_id);
+        self.transport_stream_id = Some(transport_stream_id);
+        self.sdt_sections.add(section_number, last_section_number);
+    }
+
+    /// Add a service of an SDT section given its descriptors data.
+    pub fn add_sdt_service(&mut self, service_id: u16, is_scrambled: bool, descriptors: &[Vec<u8>]) {
+        let service = self.service(service_id);
+        service.is_scrambled = is_scrambled;
+        for data in descriptors.iter() {
+            if let Some((service_type, provider, name)) = parse_service_descriptor(data) {
+                service.service_type = Some(service_type);
+                service.provider = Some(provider);
+                service.name = Some(name);
+            }
+        }
+    }
+
+    /// Add a section of the NIT of this network.
+    pub fn add_nit_section(&mut self, section_number: u8, last_section_number: u8) {
+        self.nit_sections.add(section_number, last_section_number);
+    }
+
+    /// Add the descriptors data of a transport stream of an NIT section.
+    pub fn add_nit_stream(&mut self, descriptors: &[Vec<u8>]) {
+        for data in descriptors.iter() {
+            self.logical_channel_numbers.extend(parse_logical_channel_descriptor(data));
+            for transmitter in parse_delivery_system_descriptor(data) {
+                if ! is_known(&self.discovered_transmitters, &transmitter) {
+                    self.discovered_transmitters.push(transmitter);
+                }
+            }
+        }
+    }
+
+    /// The transmitters of the multiplexes of the network as given by the NIT.
+    pub fn discovered_transmitters(&self) -> &[Transmitter] { &self.discovered_transmitters }
+
+    /// Have all the tables needed been received. The NIT is not needed as it only
+    /// provides logical channel numbers.
+    pub fn is_complete(&self) -> bool {
+        self.is_pat_received
+            && self.services.keys().all(|s| self.pmts_received.contains(s))
+            && self.sdt_sections.is_complete()
+    }
+
+    /// Has the NIT also been received.
+    pub fn is_nit_complete(&self) -> bool { self.nit_sections.is_complete() }
+
+    /// Return the logical channel number of a service, 0 if it is not known.
+    pub fn logical_channel_number(&self, service_id: u16) -> u16 {
+        self.logical_channel_numbers.get(&service_id).cloned().unwrap_or(0)
+    }
+
+    /// Return the audio and video services of the multiplex, those that can be watched
+    /// or listened to. Services without a name are named by their service_id.
+    pub fn channels(&self) -> Vec<Service> {
+        self.services.values()
+            .filter(|s| s.video_pid.is_some() || ! s.audio_pids.is_empty())
+            .map(|s| {
+                let mut service = s.clone();
+                if service.name.as_ref().map_or(true, |n| n.trim().is_empty()) {
+                    service.name = Some(format!("Service {}", s.service_id));
+                }
+                service
+            })
+            .collect()
+    }
+}
+
+/// A channel found by a scan, a service together with the multiplex it is on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScannedChannel {
+    pub service: Service,
+    pub logical_channel_number: u16,
+    pub original_network_id: Option<u16>,
+    pub transport_stream_id: Option<u16>,
+    pub transmitter: Transmitter,
+}
+
+impl ScannedChannel {
+    pub fn name(&self) -> &str { self.service.name.as_ref().map_or("", |n| n.as_str()) }
+
+    /// The tuning parameters of the multiplex together with the PIDs of the service.
+    pub fn tuning(&self) -> Tuning {
+        let mut tuning = Tuning::from_properties(self.transmitter.properties.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        tuning.network_id = self.original_network_id;
+        tuning.transport_id = self.transport_stream_id;
+        tuning.video_pid = self.service.video_pid;
+        tuning.audio_pids = self.service.audio_pids.clone();
+        tuning
+    }
+
+    pub fn channel_data(&self) -> ChannelData {
+        ChannelData {
+            name: self.name().to_string(),
+            service_id: self.service.service_id,
+            logical_channel_number: self.logical_channel_number,
+            tuning: self.tuning(),
+        }
+    }
+}
+
+/// Return the channels of the multiplexes scanned. A channel name is the key of the
+/// channels file so where a service with the same name is on more than one multiplex,
+/// as happens where transmitters overlap, only the first is kept.
+pub fn channels_of(multiplexes: &[MultiplexScan]) -> Vec<ScannedChannel> {
+    let mut names = BTreeSet::new();
+    let mut result = Vec::new();
+    for multiplex in multiplexes.iter() {
+        for service in multiplex.channels() {
+            if names.insert(service.name.clone()) {
+                result.push(ScannedChannel {
+                    logical_channel_number: multiplex.logical_channel_number(service.service_id),
+                    service,
+                    original_network_id: multiplex.original_network_id,
+                    transport_stream_id: multiplex.transport_stream_id,
+                    transmitter: multiplex.transmitter.clone(),
+                });
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channels_data::write_channels_file;
+
+    const TRANSMITTERS: &str = "
+# Crystal Palace
+[CHANNEL]
+\tDELIVERY_SYSTEM = DVBT
+\tFREQUENCY = 490000000
+\tBANDWIDTH_HZ = 8000000
+\tCODE_RATE_HP = 2/3
+\tCODE_RATE_LP = NONE
+\tMODULATION = QAM/64
+\tTRANSMISSION_MODE = 8K
+\tGUARD_INTERVAL = 1/32
+\tHIERARCHY = NONE
+\tINVERSION = AUTO
+
+[CHANNEL]
+\tDELIVERY_SYSTEM = DVBT2
+\tFREQUENCY = 554000000
+\tBANDWIDTH_HZ = 8000000
+\tMODULATION = QAM/256
+\tSTREAM_ID = 0
+";
+
+    fn service_descriptor(service_type: u8, provider: &str, name: &str) -> Vec<u8> {
+        let mut data = vec![0x48, (3 + provider.len() + name.len()) as u8, service_type, provider.len() as u8];
+        data.extend(provider.bytes());
+        data.push(name.len() as u8);
+        data.extend(name.bytes());
+        data
+    }
+
+    #[test]
+    fn transmitters_are_parsed_in_order() {
+        let transmitters = parse_transmitters(TRANSMITTERS).unwrap();
+        assert_eq!(transmitters.len(), 2);
+        assert_eq!(transmitters[0].frequency(), 490000000);
+        assert_eq!(transmitters[0].get("MODULATION"), Some("QAM/64"));
+        assert_eq!(transmitters[0].properties[0], ("DELIVERY_SYSTEM".to_string(), "DVBT".to_string()));
+        assert_eq!(transmitters[1].get("DELIVERY_SYSTEM"), Some("DVBT2"));
+        assert!(parse_transmitters("T 490000000 8MHz 2/3 NONE QAM64 8k 1/32 NONE\n").is_err());
+    }
+
+    #[test]
+    fn transmitter_tuning_gives_dvbsrc_enumeration_values() {
+        let transmitters = parse_transmitters(TRANSMITTERS).unwrap();
+        assert_eq!(dvbsrc_properties(&transmitters[0]).unwrap(), vec![
+            ("delsys", "3".to_string()),
+            ("frequency", "490000000".to_string()),
+            ("bandwidth-hz", "8000000".to_string()),
+            ("code-rate-hp", "2".to_string()),
+            ("code-rate-lp", "0".to_string()),
+            ("modulation", "3".to_string()),
+            ("trans-mode", "1".to_string()),
+            ("guard", "0".to_string()),
+            ("hierarchy", "0".to_string()),
+            ("inversion", "2".to_string()),
+        ]);
+        assert_eq!(dvbsrc_properties(&transmitters[1]).unwrap()[0], ("delsys", "16".to_string()));
+        let satellite = Transmitter { properties: vec![
+            ("SYMBOL_RATE".to_string(), "27500000".to_string()),
+            ("POLARIZATION".to_string(), "HORIZONTAL".to_string()),
+        ]};
+        assert_eq!(dvbsrc_properties(&satellite).unwrap(), vec![("symbol-rate", "27500".to_string()), ("polarity", "H".to_string())]);
+        let broken = Transmitter { properties: vec![("MODULATION".to_string(), "QAM/48".to_string())] };
+        assert!(dvbsrc_properties(&broken).is_err());
+    }
+
+    #[test]
+    fn descriptors_are_decoded() {
+        assert_eq!(parse_service_descriptor(&service_descriptor(1, "BBC", "BBC ONE Lon")), Some((1, "BBC".to_string(), "BBC ONE Lon".to_string())));
+        assert_eq!(parse_service_descriptor(&[0x48, 5, 1, 3, b'B', b'B']), None);
+        // Two services, the second not visible.
+        let lcn = [0x83, 8, 0x10, 0x44, 0xFC, 0x01, 0x10, 0xBF, 0x7C, 0x02];
+        assert_eq!(parse_logical_channel_descriptor(&lcn), vec![(4164, 1)]);
+    }
+
+    #[test]
+    fn delivery_system_descriptors_give_transmitters() {
+        let terrestrial = [0x5A, 11, 0x02, 0xEB, 0xAE, 0x40, 0x1F, 0x81, 0x02, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(parse_delivery_system_descriptor(&terrestrial), parse_transmitters(TRANSMITTERS).unwrap()[..1].to_vec());
+        // Two cells, the second with a subcell.
+        let t2 = [0x7F, 22, 0x04, 0x00, 0x00, 0x01, 0x00, 0x94,
+            0x00, 0x01, 0x03, 0x4D, 0x56, 0x40, 0x00,
+            0x00, 0x02, 0x03, 0x59, 0x8B, 0x40, 0x05, 0x01, 0x03, 0x59, 0x8B, 0x40];
+        let transmitters = parse_delivery_system_descriptor(&t2);
+        assert_eq!(transmitters.iter().map(|t| t.frequency()).collect::<Vec<u32>>(), vec![554000000, 562000000]);
+        assert_eq!(transmitters[0].get("DELIVERY_SYSTEM"), Some("DVBT2"));
+        assert_eq!(transmitters[0].get("GUARD_INTERVAL"), Some("1/128"));
+        assert_eq!(transmitters[0].get("TRANSMISSION_MODE"), Some("32K"));
+        assert_eq!(transmitters[0].get("STREAM_ID"), Some("0"));
+        assert!(parse_delivery_system_descriptor(&[0x7F, 4, 0x04, 0x00, 0x00, 0x01]).is_empty());
+        // Time frequency slicing: two cells, the first with two frequencies and a subcell.
+        let tfs = [0x7F, 31, 0x04, 0x00, 0x00, 0x01, 0x00, 0x95,
+            0x00, 0x01, 0x08, 0x03, 0x4D, 0x56, 0x40, 0x03, 0x59, 0x8B, 0x40, 0x05, 0x01, 0x03, 0x65, 0xC0, 0x40,
+            0x00, 0x02, 0x04, 0x03, 0x71, 0xF5, 0x40, 0x00];
+        let transmitters = parse_delivery_system_descriptor(&tfs);
+        assert_eq!(transmitters.iter().map(|t| t.frequency()).collect::<Vec<u32>>(), vec![554000000, 562000000, 578000000]);
+        let cable = [0x44, 11, 0x03, 0x46, 0x00, 0x00, 0xFF, 0xF2, 0x05, 0x00, 0x69, 0x00, 0x03];
+        let transmitter = &parse_delivery_system_descriptor(&cable)[0];
+        assert_eq!(transmitter.frequency(), 346000000);
+        assert_eq!(transmitter.get("SYMBOL_RATE"), Some("6900000"));
+        assert_eq!(transmitter.get("INNER_FEC"), Some("3/4"));
+        assert_eq!(transmitter.get("MODULATION"), Some("QAM/256"));
+        let uncoded = [0x44, 11, 0x03, 0x46, 0x00, 0x00, 0xFF, 0xF2, 0x05, 0x00, 0x69, 0x00, 0x0F];
+        assert_eq!(parse_delivery_system_descriptor(&uncoded)[0].get("INNER_FEC"), Some("NONE"));
+    }
+
+    #[test]
+    fn multiplexes_are_discovered_from_the_nit() {
+        let transmitters = parse_transmitters(TRANSMITTERS).unwrap();
+        let mut scan = MultiplexScan::new(transmitters[0].clone());
+        // The multiplex itself, at a slightly different frequency, and another one.
+        let itself = vec![0x5A, 11, 0x02, 0xEB, 0x6D, 0x26, 0x1F, 0x81, 0x02, 0xFF, 0xFF, 0xFF, 0xFF];
+        let other = vec![0x5A, 11, 0x03, 0x10, 0x4D, 0x40, 0x1F, 0x81, 0x02, 0xFF, 0xFF, 0xFF, 0xFF];
+        scan.add_nit_stream(&[itself]);
+        scan.add_nit_stream(&[other.clone(), other]);
+        let discovered = scan.discovered_transmitters();
+        assert_eq!(discovered.len(), 2);
+        assert_eq!(discovered[0].frequency(), 489833340);
+        assert!(is_known(&transmitters, &discovered[0]));
+        assert!(! is_known(&transmitters, &discovered[1]));
+        assert_eq!(discovered[1].frequency(), 514000000);
+        let entered = transmitter_of_frequency(&DeliverySystem::DVBT2, 554000000);
+        assert_eq!(dvbsrc_properties(&entered).unwrap(), vec![
+            ("delsys", "16".to_string()),
+            ("frequency", "554000000".to_string()),
+            ("modulation", "6".to_string()),
+            ("inversion", "2".to_string()),
+        ]);
+        assert!(is_known(&transmitters, &entered));
+    }
+
+    #[test]
+    fn a_multiplex_is_complete_when_pat_pmts_and_sdt_are_received() {
+        let transmitter = parse_transmitters(TRANSMITTERS).unwrap().remove(0);
+        let mut scan = MultiplexScan::new(transmitter);
+        scan.add_pat(&[0, 4164, 4287, 4671]);
+        scan.add_sdt_section(9018, 4164, 0, 0);
+        scan.add_sdt_service(4164, false, &[service_descriptor(1, "BBC", "BBC ONE Lon")]);
+        scan.add_sdt_service(4287, false, &[service_descriptor(1, "BBC", "BBC TWO")]);
+        scan.add_pmt(4164, &[(0x02, 101, vec![]), (0x06, 106, vec![0x6A]), (0x03, 102, vec![]), (0x06, 105, vec![0x59])]);
+        scan.add_pmt(4287, &[(0x02, 201, vec![]), (0x03, 202, vec![])]);
+        assert!(! scan.is_complete());
+        scan.add_pmt(4671, &[(0x05, 301, vec![])]);
+        assert!(scan.is_complete());
+        assert!(! scan.is_nit_complete());
+        scan.add_nit_section(0, 0);
+        scan.add_nit_stream(&[vec![0x83, 4, 0x10, 0x44, 0xFC, 0x01]]);
+        assert!(scan.is_nit_complete());
+        let channels = scan.channels();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].video_pid, Some(101));
+        assert_eq!(channels[0].audio_pids, vec![106, 102]);
+        assert_eq!(scan.logical_channel_number(4164), 1);
+        assert_eq!(scan.logical_channel_number(4287), 0);
+    }
+
+    #[test]
+    fn channels_file_has_services_and_tuning_parameters() {
+        let transmitters = parse_transmitters(TRANSMITTERS).unwrap();
+        let mut first = MultiplexScan::new(transmitters[0].clone());
+        first.add_pat(&[4164, 4200]);
+        first.add_sdt_section(9018, 4164, 0, 0);
+        first.add_sdt_service(4164, false, &[service_descriptor(1, "BBC", "BBC ONE Lon")]);
+        first.add_pmt(4164, &[(0x02, 101, vec![]), (0x03, 102, vec![])]);
+        first.add_pmt(4200, &[(0x03, 401, vec![])]);
+        first.add_nit_stream(&[vec![0x83, 4, 0x10, 0x44, 0xFC, 0x01]]);
+        let mut second = MultiplexScan::new(transmitters[1].clone());
+        second.add_pat(&[4164]);
+        second.add_sdt_service(4164, false, &[service_descriptor(1, "BBC", "BBC ONE Lon")]);
+        second.add_pmt(4164, &[(0x1B, 101, vec![])]);
+        let channels = channels_of(&[first, second]);
+        let channels_data = channels.iter().map(|c| c.channel_data()).collect::<Vec<ChannelData>>();
+        assert_eq!(channels_data.iter().map(|c| (c.name.as_str(), c.service_id, c.logical_channel_number)).collect::<Vec<(&str, u16, u16)>>(), vec![
+            ("BBC ONE Lon", 4164, 1),
+            ("Service 4200", 4200, 0),
+        ]);
+        assert_eq!(channels_data[0].tuning.frequency, Some(490000000));
+        assert_eq!(channels_data[0].tuning.video_pid, Some(101));
+        assert_eq!(channels_data[1].tuning.audio_pids, vec![401]);
+        let mut buffer = Vec::new();
+        write_channels_file(&mut buffer, &channels_data[..1]).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), "[BBC ONE Lon]
+\tSERVICE_ID = 4164
+\tLCN = 1
+\tNETWORK_ID = 9018
+\tTRANSPORT_ID = 4164
+\tVIDEO_PID = 101
+\tAUDIO_PID = 102
+\tFREQUENCY = 490000000
+\tMODULATION = QAM/64
+\tBANDWIDTH_HZ = 8000000
+\tINVERSION = AUTO
+\tCODE_RATE_HP = 2/3
+\tCODE_RATE_LP = NONE
+\tGUARD_INTERVAL = 1/32
+\tTRANSMISSION_MODE = 8K
+\tHIERARCHY = NONE
+\tDELIVERY_SYSTEM = DVBT
+
+");
+    }
+}
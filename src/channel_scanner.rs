@@ -0,0 +1,270 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Scanning for channels in process.
+//!
+//! Each multiplex of a transmitter file is tuned to with a dvbsrc element feeding a
+//! tsparse element, which posts the sections of the transport stream as messages on the
+//! bus. The PAT, PMT, SDT, and NIT sections are collected until the services of the
+//! multiplex are known. The dvbsrc element posts the frontend statistics as messages as
+//! well, which gives the signal strength and whether the frontend has locked.
+//!
+//! The multiplexes of the network listed in the NIT but not in the transmitter file are
+//! added to the scan as they are found.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use glib;
+
+use gst;
+use gst::prelude::*;
+
+use gst_mpegts;
+
+use crate::channel_scan::{self, MultiplexScan, Transmitter};
+use crate::channels_data::{replace_channels, ChannelData};
+use crate::dvb_devices::FrontendId;
+use crate::epg_harvester;
+
+/// The time allowed for the frontend to lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The time allowed after lock for the tables to be received. The SDT and NIT need only
+/// be repeated every 10 seconds.
+const SECTIONS_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The progress of a scan as reported to the user interface.
+#[derive(Clone, Debug)]
+pub enum ScanProgress {
+    Tuning{number: usize, count: usize, frequency: u32},
+    Signal{frequency: u32, strength: u8, is_locked: bool},
+    NoLock{frequency: u32},
+    ServicesFound{frequency: u32, names: Vec<String>},
+    MultiplexesDiscovered{frequencies: Vec<u32>},
+    Finished{result: Result<usize, String>},
+    Cancelled,
+}
+
+/// Return the signal strength as a percentage and whether the frontend has locked
+/// given a dvb-frontend-stats structure.
+fn frontend_stats(structure: &gst::StructureRef) -> (u8, bool) {
+    let signal = structure.get::<i32>("signal").ok().flatten().unwrap_or(0);
+    let is_locked = structure.get::<bool>("lock").ok().flatten().unwrap_or(false);
+    ((signal.max(0).min(0xFFFF) * 100 / 0xFFFF) as u8, is_locked)
+}
+
+/// Return the data of each of a list of descriptors.
+fn descriptors_data(descriptors: &[gst_mpegts::Descriptor]) -> Vec<Vec<u8>> {
+    descriptors.iter().map(|d| d.get_data().to_vec()).collect()
+}
+
+/// Add the data of a section to the scan of a multiplex. Only the tables of the actual
+/// network and transport stream are of interest.
+fn add_section(scan: &mut MultiplexScan, section: &gst_mpegts::Section) {
+    match section.get_section_type() {
+        gst_mpegts::SectionType::Pat => {
+            let program_numbers = section.get_pat().iter().map(|p| p.get_program_number()).collect::<Vec<u16>>();
+            scan.add_pat(&program_numbers);
+        },
+        gst_mpegts::SectionType::Pmt => {
+            if let Some(pmt) = section.get_pmt() {
+                let streams = pmt.get_streams().iter()
+                    .map(|stream| {
+                        let tags = stream.get_descriptors().iter().map(|d| d.get_data()[0]).collect::<Vec<u8>>();
+                        (stream.get_stream_type(), stream.get_pid(), tags)
+                    })
+                    .collect::<Vec<(u8, u16, Vec<u8>)>>();
+                scan.add_pmt(pmt.get_program_number(), &streams);
+            }
+        },
+        gst_mpegts::SectionType::Sdt => {
+            if section.get_table_id() == 0x42 {
+                if let Some(sdt) = section.get_sdt() {
+                    for service in sdt.get_services().iter() {
+                        scan.add_sdt_service(service.get_service_id(), service.get_free_ca_mode(), &descriptors_data(&service.get_descriptors()));
+                    }
+                    scan.add_sdt_section(sdt.get_original_network_id(), sdt.get_transport_stream_id(), section.get_section_number(), section.get_last_section_number());
+                }
+            }
+        },
+        gst_mpegts::SectionType::Nit => {
+            if section.get_table_id() == 0x40 {
+                if let Some(nit) = section.get_nit() {
+                    for stream in nit.get_streams().iter() {
+                        scan.add_nit_stream(&descriptors_data(&stream.get_descriptors()));
+                    }
+                    scan.add_nit_section(section.get_section_number(), section.get_last_section_number());
+                }
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Create a pipeline tuned to a transmitter using the given frontend. Only the sections
+/// are wanted so the stream is discarded.
+fn create_pipeline(transmitter: &Transmitter, frontend: &FrontendId) -> Result<gst::Pipeline, String> {
+    //
+    // Construct the GStreamer graph described by:
+    //
+    //    gst-launch-1.0 dvbsrc <properties set from transmitter> ! tsparse ! fakesink
+    //
+    let properties = channel_scan::dvbsrc_properties(transmitter)?;
+    let pipeline = gst::Pipeline::new(None);
+    let source = gst::ElementFactory::make("dvbsrc", None).map_err(|e| format!("Cannot make a dvbsrc – {}", e))?;
+    source.set_property("adapter", &(frontend.adapter as i32)).expect("Could not set adapter number on dvbsrc element");
+    source.set_property("frontend", &(frontend.frontend as i32)).expect("Could not set frontend number of dvbsrc element");
+    for (name, value) in properties.iter() {
+        source.set_property_from_str(name, value);
+    }
+    let parser = gst::ElementFactory::make("tsparse", None).map_err(|e| format!("Cannot make a tsparse – {}", e))?;
+    let sink = gst::ElementFactory::make("fakesink", None).expect("cannot make a fakesink");
+    pipeline.add_many(&[&source, &parser, &sink]).expect("could not add elements to pipeline");
+    gst::Element::link_many(&[&source, &parser, &sink]).expect("could not link elements in pipeline");
+    Ok(pipeline)
+}
+
+/// Scan a multiplex, returning `None` if the frontend did not lock or the scan was cancelled.
+fn scan_multiplex(transmitter: &Transmitter, frontend: &FrontendId, cancel: &AtomicBool, to_cw: &glib::Sender<ScanProgress>) -> Option<MultiplexScan> {
+    let frequency = transmitter.frequency();
+    let pipeline = match create_pipeline(transmitter, frontend) {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            println!("Cannot tune to {} – {}", frequency, e);
+            return None;
+        },
+    };
+    let mut scan = MultiplexScan::new(transmitter.clone());
+    let mut is_failed = pipeline.set_state(gst::State::Playing).is_err();
+    let bus = pipeline.get_bus().expect("Pipeline without bus. Shouldn't happen!");
+    let mut lock_time = None;
+    let mut last_report: Option<Instant> = None;
+    let start = Instant::now();
+    while ! is_failed && ! cancel.load(Ordering::SeqCst) {
+        let deadline = match lock_time {
+            Some(time) => time + SECTIONS_TIMEOUT,
+            None => start + LOCK_TIMEOUT,
+        };
+        if Instant::now() >= deadline || (scan.is_complete() && scan.is_nit_complete()) {
+            break;
+        }
+        let msg = match bus.timed_pop(gst::ClockTime::from_mseconds(200)) {
+            Some(msg) => msg,
+            None => continue,
+        };
+        match msg.view() {
+            gst::MessageView::Element(element) => {
+                if let Some(structure) = element.get_structure() {
+                    match structure.get_name() {
+                        "dvb-frontend-stats" => {
+                            let (strength, is_locked) = frontend_stats(structure);
+                            if is_locked && lock_time.is_none() {
+                                lock_time = Some(Instant::now());
+                            }
+                            if last_report.map_or(true, |t| t.elapsed() >= Duration::from_secs(1)) {
+                                to_cw.send(ScanProgress::Signal{frequency, strength, is_locked}).unwrap();
+                                last_report = Some(Instant::now());
+                            }
+                        },
+                        "pat" | "pmt" | "sdt" | "nit" => {
+                            if let Some(section) = gst_mpegts::Section::from_element(&element) {
+                                add_section(&mut scan, &section);
+                            }
+                        },
+                        _ => (),
+                    }
+                }
+            },
+            gst::MessageView::Error(error) => {
+                // Most likely no lock, or the frontend is being used by another program.
+                println!("Could not scan {} using adapter {} frontend {} – {}", frequency, frontend.adapter, frontend.frontend, error.get_error());
+                is_failed = true;
+            },
+            _ => (),
+        }
+    }
+    pipeline.set_state(gst::State::Null).expect("Could not stop the scan pipeline.");
+    if lock_time.is_none() || cancel.load(Ordering::SeqCst) {
+        return None;
+    }
+    if ! scan.is_complete() {
+        println!("Not all the tables of {} were received, some services may be missing.", frequency);
+    }
+    Some(scan)
+}
+
+/// Write the channels found to the GStreamer dvbsrc channels file, returning the number
+/// of channels. The channels file is left alone if no channels were found.
+fn write_channels(multiplexes: &[MultiplexScan]) -> Result<usize, String> {
+    let channels = channel_scan::channels_of(multiplexes);
+    if channels.is_empty() {
+        return Err("No channels were found, the channels file has not been changed.".to_string());
+    }
+    replace_channels(&channels.iter().map(|c| c.channel_data()).collect::<Vec<ChannelData>>())
+}
+
+/// Scan the multiplexes of the given transmitters using the given frontend, writing the
+/// channels found to the channels file. Multiplexes of the network found in the NIT of a
+/// multiplex that are not in the list are scanned as well. Progress is reported for each
+/// multiplex, the last message being `ScanProgress::Finished`, or
+/// `ScanProgress::Cancelled` if `cancel` was set, in which case the channels file is left
+/// alone.
+///
+/// This is run in a thread of its own, the frontend must not be in use by a channel window.
+pub fn run(mut transmitters: Vec<Transmitter>, frontend: FrontendId, cancel: Arc<AtomicBool>, to_cw: glib::Sender<ScanProgress>) {
+    // Stop any collection of the EPG on the frontend and keep it free whilst scanning.
+    epg_harvester::claim_frontend(&frontend);
+    let mut multiplexes = Vec::new();
+    let mut index = 0;
+    while index < transmitters.len() && ! cancel.load(Ordering::SeqCst) {
+        let transmitter = transmitters[index].clone();
+        index += 1;
+        let frequency = transmitter.frequency();
+        to_cw.send(ScanProgress::Tuning{number: index, count: transmitters.len(), frequency}).unwrap();
+        match scan_multiplex(&transmitter, &frontend, &cancel, &to_cw) {
+            Some(scan) => {
+                let names = scan.channels().into_iter().filter_map(|s| s.name).collect();
+                to_cw.send(ScanProgress::ServicesFound{frequency, names}).unwrap();
+                let mut frequencies = Vec::new();
+                for discovered in scan.discovered_transmitters() {
+                    if ! channel_scan::is_known(&transmitters, discovered) {
+                        frequencies.push(discovered.frequency());
+                        transmitters.push(discovered.clone());
+                    }
+                }
+                if ! frequencies.is_empty() {
+                    to_cw.send(ScanProgress::MultiplexesDiscovered{frequencies}).unwrap();
+                }
+                multiplexes.push(scan);
+            },
+            None if cancel.load(Ordering::SeqCst) => (),
+            None => to_cw.send(ScanProgress::NoLock{frequency}).unwrap(),
+        }
+    }
+    epg_harvester::release_frontend(&frontend);
+    if cancel.load(Ordering::SeqCst) {
+        to_cw.send(ScanProgress::Cancelled).unwrap();
+    } else {
+        to_cw.send(ScanProgress::Finished{result: write_channels(&multiplexes)}).unwrap();
+    }
+}
@@ -19,9 +19,9 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions, create_dir_all};
-use std::io::{Read, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::path::Path;
 use std::sync::RwLock;
 
@@ -32,7 +32,12 @@ use serde_derive::{Serialize, Deserialize};
 use serde_yaml;
 use xdg;
 
+use crate::channel_edits::{self, ChannelEdit};
 use crate::control_window::Message;
+use crate::data_files;
+use crate::dvbv5::Tuning;
+use crate::eit_sections::ServiceKey;
+use crate::multiplexes::{MultiplexKey, channels_file_path};
 
 /// https://url.spec.whatwg.org/#fragment-percent-encode-set
 const FRAGMENT: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
@@ -48,18 +53,23 @@ pub fn encode_to_mrl(channel_name: &String) -> String {
 ///
 /// It is assumed that instances are the data pointed to by various indexes so as to
 /// create lookups between for example logical_channel_number and name.
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ChannelData {
     pub name: String,  // Used in control_window.rs
-    service_id: u16,
+    pub service_id: u16,  // Used in epg_window.rs
     // Channel 0 is not used so 0 can be used as "not yet known".
     pub logical_channel_number: u16,  // Used in control_window.rs.
+    // All the other keys of the channels file entry, cache files from before these were
+    // kept do not have them.
+    #[serde(default)]
+    pub tuning: Tuning,
 }
 
 // A singleton of the channels data currently known.
 //
 // This is initialised from the GStreamer channels data file, then augmented from the
-// Me TV data cache file, and then updated as `LogicalChannelDescriptor` are received.
+// Me TV data cache file and the user's channel edits, and then updated as
+// `LogicalChannelDescriptor` are received.
 // The data is written to the cache file as and when.
 //
 // TODO need to update the ListStore in the ControlWindow instance
@@ -71,12 +81,14 @@ lazy_static! {
 
 /// Construct the value to be used to initialise `CHANNELS_DATA`.
 ///
-/// First read the data from the GStreamer channels data file (if it exists) and then
-/// augment using the Me TV data cache file (if it exists).
+/// First read the data from the GStreamer channels data file (if it exists), then
+/// augment using the Me TV data cache file (if it exists), and then apply the channel edits.
 fn initialise_channels_data() -> Option<Vec<ChannelData>> {
-    match ini::Ini::load_from_file(channels_file_path()) {
+    let path = channels_file_path();
+    match ini::Ini::load_from_file(&path) {
         Ok(ini) => {
             let mut channel_data = process_ini(&ini);
+            let names_in_file = channel_data.iter().map(|c| c.name.clone()).collect::<Vec<String>>();
             if let Some(cache) = read_channels_data_cache(&channels_data_cache_path()) {
                 let table = cache
                     .iter()
@@ -90,15 +102,22 @@ fn initialise_channels_data() -> Option<Vec<ChannelData>> {
                             None => 0,
                         };
                         ChannelData {
-                            name: x.name.clone(),
-                            service_id: x.service_id,
                             logical_channel_number,
+                            ..x.clone()
                         }
                     } else {
                         x.clone()
                     })
                     .collect();
             }
+            channel_edits::apply(&mut channel_data);
+            // dvbsrc tunes by the names in the channels file, so they must be the edited
+            // names. Another program, e.g. dvbv5-scan, may have replaced the file.
+            if channel_data.iter().zip(names_in_file.iter()).any(|(c, name)| c.name != *name) {
+                if let Err(e) = write_channels(&path, &channel_data) {
+                    println!("{}", e);
+                }
+            }
             Some(channel_data)
         },
         Err(_) => None,
@@ -111,7 +130,8 @@ fn process_ini(ini: &ini::Ini) -> Vec<ChannelData> {
         .map(|(name, properties)| ChannelData{
             name: name.unwrap().to_string(),
             service_id: properties.get("SERVICE_ID").unwrap().parse::<u16>().unwrap(),
-            logical_channel_number: 0,
+            logical_channel_number: properties.get("LCN").and_then(|n| n.parse::<u16>().ok()).unwrap_or(0),
+            tuning: Tuning::from_properties(properties.iter()),
         })
         .collect()
 }
@@ -134,17 +154,82 @@ pub fn read_channels_data() -> bool { // Used in control_window.rs after a chann
     }
 }
 
+/// Write the channels as a DVBv5 channels file, the format dvbv5-scan produces, with the
+/// logical channel numbers that are known as LCN keys. dvbsrc ignores the LCN keys.
+pub fn write_channels_file<W: Write>(writer: &mut W, channels: &[ChannelData]) -> io::Result<()> {
+    for channel in channels.iter() {
+        writeln!(writer, "[{}]", channel.name)?;
+        writeln!(writer, "\tSERVICE_ID = {}", channel.service_id)?;
+        if channel.logical_channel_number != 0 {
+            writeln!(writer, "\tLCN = {}", channel.logical_channel_number)?;
+        }
+        for (key, value) in channel.tuning.to_properties().iter() {
+            writeln!(writer, "\t{} = {}", key, value)?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Write a channels file, dvbsrc must never see a partly written one.
+fn write_channels(path: &Path, channels: &[ChannelData]) -> Result<(), String> {
+    data_files::write_atomically(path, |file| write_channels_file(file, channels))
+}
+
+/// Replace the GStreamer dvbsrc channels file with one of the given channels and read it
+/// back, caching the logical channel numbers that are known. The channels are written
+/// with the names given them by the channel edits. Return the number of channels written.
+pub fn replace_channels(channels: &[ChannelData]) -> Result<usize, String> { // Used in channel_scanner.rs and channel_list_dialogs.rs
+    replace_channels_and_edits(channels, None)
+}
+
+/// Replace the channels file as `replace_channels` does, and the channel edits as well
+/// if there are new ones. The channels file is written first, with the names given by
+/// the new edits, so the names in it are always those of channels even if writing the
+/// edits fails.
+pub fn replace_channels_and_edits(channels: &[ChannelData], new_edits: Option<Vec<ChannelEdit>>) -> Result<usize, String> { // Used in channel_editor.rs
+    let mut channels = channels.to_vec();
+    match new_edits {
+        Some(ref edits) => channel_edits::apply_edits(edits, &mut channels),
+        None => channel_edits::apply(&mut channels),
+    }
+    let path = channels_file_path();
+    write_channels(&path, &channels)?;
+    if let Some(edits) = new_edits {
+        channel_edits::set_channel_edits(edits)?;
+    }
+    if ! read_channels_data() {
+        return Err(format!("Failed to read {} after writing it.", path.display()));
+    }
+    for channel in channels.iter().filter(|c| c.logical_channel_number != 0) {
+        add_logical_channel_number_for_service_id(channel.service_id, channel.logical_channel_number, None);
+    }
+    Ok(channels.len())
+}
+
 /// Return a `Vec` containing the (logical number, name) pairs of the channels from the channels data.
 fn get_numbers_and_names_from_channels_data(channels_data: &Vec<ChannelData>) -> Vec<(u16, String)> {
     channels_data.iter().map(|x| (x.logical_channel_number, x.name.clone()) ).collect()
 }
 
-/// Return a `Box<Path>` to the GStreamer dvbsrc plugin channels file using the XDG directory structure.
-pub fn channels_file_path() -> Box<Path> {
-    let xdg_dirs = xdg::BaseDirectories::with_prefix("gstreamer-1.0").expect("Cannot set XDG prefix.");
-    let mut path_buf = xdg_dirs.get_config_home();
-    path_buf.push("dvb-channels.conf");
-    path_buf.into_boxed_path()
+/// Return the channels of the channels data that the user has not hidden.
+fn shown_channels_data(channels_data: &Vec<ChannelData>) -> Vec<ChannelData> {
+    channels_data.iter().filter(|x| ! channel_edits::is_hidden(x)).cloned().collect()
+}
+
+/// Return a `Vec` of the channels data ordered by logical channel number, with the
+/// channels for which the logical channel number is not yet known at the end ordered by name.
+fn order_by_logical_channel_number(channels_data: &Vec<ChannelData>) -> Vec<ChannelData> {
+    let mut result = channels_data.clone();
+    result.sort_by(|a, b| {
+        match (a.logical_channel_number, b.logical_channel_number) {
+            (0, 0) => a.name.cmp(&b.name),
+            (0, _) => std::cmp::Ordering::Greater,
+            (_, 0) => std::cmp::Ordering::Less,
+            (x, y) => x.cmp(&y),
+        }
+    });
+    result
 }
 
 /// Return a `Box<Path>` to the Me TV channels data cache file using the XDG directory structure.
@@ -165,17 +250,61 @@ pub fn channels_data_cache_path() -> Box<Path> {
 /// one indented.
 ///
 /// Logical channel numbers are found by searching the SI packets and caching them.
+///
+/// Channels hidden by the user are not included.
 pub fn get_channels_data() -> Option<Vec<(u16, String)>> {
     let channels_data = CHANNELS_DATA.read().unwrap();
     match &*channels_data {
-        Some(c_d) => Some(get_numbers_and_names_from_channels_data(c_d)),
+        Some(c_d) => Some(get_numbers_and_names_from_channels_data(&shown_channels_data(c_d))),
+        None => None,
+    }
+}
+
+/// Return the channels data, without the channels hidden by the user, ordered by logical
+/// channel number, `None` if there is no channels data.
+pub fn get_channels_data_ordered_by_logical_channel_number() -> Option<Vec<ChannelData>> { // Used in epg_window.rs
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    match &*channels_data {
+        Some(c_d) => Some(order_by_logical_channel_number(&shown_channels_data(c_d))),
+        None => None,
+    }
+}
+
+/// Return all the channels data, including the channels hidden by the user, ordered by
+/// logical channel number, `None` if there is no channels data.
+pub fn get_all_channels_data_ordered_by_logical_channel_number() -> Option<Vec<ChannelData>> { // Used in channel_editor.rs and channel_list_dialogs.rs
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    match &*channels_data {
+        Some(c_d) => Some(order_by_logical_channel_number(c_d)),
         None => None,
     }
 }
 
+/// Return the names of the channels grouped by the multiplex they are on, `None` if there
+/// is no channels data.
+pub fn get_channel_names_by_multiplex() -> Option<BTreeMap<MultiplexKey, Vec<String>>> {
+    let channels_data = CHANNELS_DATA.read().unwrap();
+    let mut groups = BTreeMap::new();
+    for channel_data in channels_data.as_ref()?.iter() {
+        if let Some(multiplex) = MultiplexKey::of_tuning(&channel_data.tuning) {
+            groups.entry(multiplex).or_insert_with(Vec::new).push(channel_data.name.clone());
+        }
+    }
+    Some(groups)
+}
+
+/// Return the multiplex of the named channel if it is known.
+pub fn get_multiplex_of_channel_name(channel_name: &str) -> Option<MultiplexKey> {
+    get_channel_names_by_multiplex()?
+        .into_iter()
+        .find(|(_, names)| names.iter().any(|n| n == channel_name))
+        .map(|(multiplex, _)| multiplex)
+}
+
 /// Update the channels file data.
 ///
-/// For use when getting SI packets that build the Logical Channel Table.
+/// For use when getting SI packets that build the Logical Channel Table. Channels the
+/// user has given a number to keep that number.
 ///
 /// Return `true` if a change was made to the channels data, `false` otherwise.
 pub fn add_logical_channel_number_for_service_id(service_id: u16, logical_channel_number: u16, to_cw: Option<&glib::Sender<Message>>) -> bool {
@@ -190,12 +319,11 @@ pub fn add_logical_channel_number_for_service_id(service_id: u16, logical_channe
                 c_d
                     .iter()
                     .map(|x| {
-                        if x.service_id == service_id && x.logical_channel_number != logical_channel_number {
+                        if x.service_id == service_id && x.logical_channel_number != logical_channel_number && ! channel_edits::has_own_number(x) {
                             rv = true;
                             let cd = ChannelData {
-                                name: x.name.clone(),
-                                service_id: x.service_id,
                                 logical_channel_number,
+                                ..x.clone()
                             };
                             if to_cw.is_some() {
                                 to_cw.unwrap().send(Message::UpdatedLogicalChannelNumber { cd: cd.clone() }).unwrap();
@@ -219,7 +347,8 @@ pub fn add_logical_channel_number_for_service_id(service_id: u16, logical_channe
 /// Return the channel name for a given channel number.
 ///
 /// Return is actually an `Option`, `None` is returned if the logical_channel_number was
-/// not found in the channel data.
+/// not found in the channel data, the channel is hidden by the user, or more than one
+/// channel has the number.
 pub fn get_channel_name_of_logical_channel_number(logical_channel_number: u16) -> Option<String> {
     // Zero is used to mean channel number not known.
     if logical_channel_number == 0 {
@@ -230,11 +359,11 @@ pub fn get_channel_name_of_logical_channel_number(logical_channel_number: u16) -
             Some(c_d) => {
                 // TODO Can we do better than linear search, or does it not matter?
                 //    Freeview from Crystal Palace has a maximum 182 channels as at 2020-07-07.
-                let result: Vec<&ChannelData> = c_d.iter().filter(|x| x.logical_channel_number == logical_channel_number).collect();
+                let result: Vec<&ChannelData> = c_d.iter().filter(|x| x.logical_channel_number == logical_channel_number && ! channel_edits::is_hidden(x)).collect();
                 match result.len() {
                     0 => None,
                     1 => Some(result[0].name.clone()),
-                    _ => panic!("Got more than one channel with the same logical number."),
+                    _ => None,
                 }
             },
             None => None,
@@ -242,34 +371,51 @@ pub fn get_channel_name_of_logical_channel_number(logical_channel_number: u16) -
     }
 }
 
+/// Return the identity of the service of a given channel name. The original network id
+/// and transport stream id are zero, which no broadcast uses, if the channels file does
+/// not give them.
+///
+/// Return is actually an `Option`, `None` is returned if the channel name was not
+/// found in the channel data.
+pub fn get_service_key_of_channel_name(channel_name: &str) -> Option<ServiceKey> { // Used in frontend_window.rs
+    let channel_data = CHANNELS_DATA.read().unwrap();
+    match &*channel_data {
+        Some(c_d) => c_d.iter().find(|x| x.name == channel_name).map(|x| ServiceKey {
+            original_network_id: x.tuning.network_id.unwrap_or(0),
+            transport_stream_id: x.tuning.transport_id.unwrap_or(0),
+            service_id: x.service_id,
+        }),
+        None => None,
+    }
+}
+
+/// Return the channel name for a given service_id.
+///
+/// Return is actually an `Option`, `None` is returned if the service_id was not
+/// found in the channel data.
+pub fn get_channel_name_of_service_id(service_id: u16) -> Option<String> { // Used in epg_manager.rs
+    let channel_data = CHANNELS_DATA.read().unwrap();
+    match &*channel_data {
+        Some(c_d) => c_d.iter().find(|x| x.service_id == service_id).map(|x| x.name.clone()),
+        None => None,
+    }
+}
+
 /// Write the channels data to a cache file.
-fn write_channels_data_cache(path: &Path, channels_data: &Vec<ChannelData>) {
-    if let Err(error) = create_dir_all(path.parent().unwrap()) {
-        panic!("create_dir_all({:?}) failed: {:?}", path.parent().unwrap(), error);
+fn write_channels_data_cache(path: &Path, channels_data: &[ChannelData]) {
+    if let Err(e) = data_files::write_yaml(path, channels_data) {
+        println!("{}", e);
     }
-    match OpenOptions::new().write(true).create(true).open(path) {
-        Ok(mut f) => {
-            let s = serde_yaml::to_string(&channels_data).unwrap();
-            match f.write(s.as_ref()) {
-                Ok(count) => {
-                    assert_eq!(count, s.len());
-                    f.flush().unwrap();
-                },
-                Err(e) => println!("Error writing {:?}, {:?} – {}", path.to_str().unwrap(), f, e),
-            };
-        },
-        Err(e) => println!("Failed to open {} – {}", path.to_str().unwrap(), e),
-    };
 }
 
 /// Read the channels data given a path and return the result.
 fn read_channels_data_cache(path: &Path) -> Option<Vec<ChannelData>> {
     match File::open(path) {
         Ok(mut f) => {
-            let mut buffer = [0u8; 20000];
-            match f.read(&mut buffer) {
-                Ok(count) => {
-                    let s = String::from_utf8_lossy(&buffer[..count]).to_string();
+            // The tuning makes each entry many hundreds of bytes so read the whole file.
+            let mut s = String::new();
+            match f.read_to_string(&mut s) {
+                Ok(_) => {
                     match serde_yaml::from_str::<Vec<ChannelData>>(&s) {
                         Ok(x) => Some(x),
                         Err(e) => {
@@ -294,19 +440,26 @@ fn read_channels_data_cache(path: &Path) -> Option<Vec<ChannelData>> {
 #[cfg(test)]
 mod tests {
 
-    use std::io::Read;
+    use std::fs::File;
+    use std::io::{Read, Write};
     use std::sync::Mutex;
 
     use ini;
     use lazy_static::lazy_static;
     use tempfile;
 
+    use crate::dvbv5::Modulation;
+    use crate::eit_sections::ServiceKey;
+    use crate::multiplexes::MultiplexKey;
+
     use super::{
         add_logical_channel_number_for_service_id,
         channels_file_path,
         encode_to_mrl, process_ini,
         get_numbers_and_names_from_channels_data,
         get_channel_name_of_logical_channel_number,
+        get_service_key_of_channel_name,
+        order_by_logical_channel_number,
         read_channels_data,
         write_channels_data_cache,
         read_channels_data_cache,
@@ -398,6 +551,49 @@ mod tests {
         assert_eq!(bbc_2.name,  "BBC TWO");
         assert_eq!(bbc_2.service_id,  4287);
         assert_eq!(bbc_2.logical_channel_number,  0);
+        assert_eq!(bbc_2.tuning.frequency, Some(490000000));
+        assert_eq!(bbc_2.tuning.modulation, Some(Modulation::QAM_64));
+        assert_eq!(bbc_2.tuning.video_pid, Some(201));
+        assert_eq!(bbc_2.tuning.audio_pids, vec![202, 206]);
+        assert_eq!(bbc_2.tuning.to_properties().iter().find(|(k, _)| k == "PID_06").map(|(_, v)| v.as_str()), Some("205"));
+    }
+
+    #[test]
+    fn process_ini_reads_logical_channel_numbers_of_exported_files() {
+        let ini = ini::Ini::load_from_str("[BBC ONE Lon]\n\tSERVICE_ID = 4164\n\tLCN = 1\n\tFREQUENCY = 490000000\n").unwrap();
+        let result = process_ini(&ini);
+        assert_eq!(result[0].logical_channel_number, 1);
+        assert!(result[0].tuning.other.is_empty());
+    }
+
+    #[test]
+    fn channels_are_grouped_by_multiplex_from_the_tuning() {
+        let data = create_two_entry_channel_data_vec();
+        assert_eq!(MultiplexKey::of_tuning(&data[0].tuning), MultiplexKey::of_tuning(&data[1].tuning));
+        let multiplex = MultiplexKey::of_tuning(&data[0].tuning).unwrap();
+        assert_eq!(multiplex.delivery_system, "DVBT");
+        assert_eq!(multiplex.frequency, 490000000);
+        assert_eq!(MultiplexKey::of_tuning(&ChannelData::default().tuning), None);
+    }
+
+    #[test]
+    fn cache_without_tuning_is_read() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"---\n- name: BBC ONE Lon\n  service_id: 4164\n  logical_channel_number: 1").unwrap();
+        let result = read_channels_data_cache(file.path()).unwrap();
+        assert_eq!(result, vec![ChannelData { name: "BBC ONE Lon".to_string(), service_id: 4164, logical_channel_number: 1, ..Default::default() }]);
+    }
+
+    #[test]
+    fn order_by_logical_channel_number_puts_unknown_numbers_last() {
+        let data = vec![
+            ChannelData { name: "Zebra".to_string(), service_id: 3, logical_channel_number: 0, ..Default::default() },
+            ChannelData { name: "BBC TWO".to_string(), service_id: 2, logical_channel_number: 2, ..Default::default() },
+            ChannelData { name: "Aardvark".to_string(), service_id: 4, logical_channel_number: 0, ..Default::default() },
+            ChannelData { name: "BBC ONE Lon".to_string(), service_id: 1, logical_channel_number: 1, ..Default::default() },
+        ];
+        let names = order_by_logical_channel_number(&data).iter().map(|x| x.name.clone()).collect::<Vec<String>>();
+        assert_eq!(names, vec!["BBC ONE Lon", "BBC TWO", "Aardvark", "Zebra"]);
     }
 
     // Tests need to be able to set specific values to CHANNELS_DATA rather than just
@@ -481,6 +677,22 @@ mod tests {
         assert_eq!(get_channel_name_of_logical_channel_number(1).unwrap(), "BBC ONE Lon");
         assert_eq!(get_channel_name_of_logical_channel_number(2).unwrap(), "BBC TWO");
         assert_eq!(get_channel_name_of_logical_channel_number(10), None);
+        let rc = add_logical_channel_number_for_service_id(4287, 1, None);
+        assert!(rc);
+        assert_eq!(get_channel_name_of_logical_channel_number(1), None);
+    }
+
+    #[test]
+    fn ensure_service_key_accessible_from_channel_name() {
+        let test_lock = TEST_LOCK.lock().unwrap();
+        let data = create_two_entry_channel_data_vec();
+        {
+            let mut channels_data = CHANNELS_DATA.write().unwrap();
+            *channels_data = Some(data);
+        }
+        assert_eq!(get_service_key_of_channel_name("BBC ONE Lon"), Some(ServiceKey{original_network_id: 9018, transport_stream_id: 4164, service_id: 4164}));
+        assert_eq!(get_service_key_of_channel_name("BBC TWO"), Some(ServiceKey{original_network_id: 9018, transport_stream_id: 4164, service_id: 4287}));
+        assert_eq!(get_service_key_of_channel_name("BBC THREE"), None);
     }
 
     #[test]
@@ -495,26 +707,78 @@ mod tests {
         assert!(rc);
         let rc = add_logical_channel_number_for_service_id(4287, 2, None);
         assert!(rc);
-        let mut file_path = tempfile::NamedTempFile::new().unwrap();
+        let file_path = tempfile::NamedTempFile::new().unwrap();
         {
             let mut channels_data = CHANNELS_DATA.write().unwrap();
             write_channels_data_cache(file_path.path(), channels_data.as_ref().unwrap());
         }
-        let mut file = file_path.as_file_mut();
-        // TODO Should not need this but it seems needed.
-        //file.seek(SeekFrom::Start(0)).unwrap();
+        // The file is replaced rather than written to so it must be opened again.
+        let mut file = File::open(file_path.path()).unwrap();
         let mut buffer = [0u8; 4096];
         match file.read(&mut buffer) {
             Ok(count) => {
-                assert_eq!(count, 133);
+                assert_eq!(count, 1215);
                 let result = String::from_utf8_lossy(&buffer[..count]).to_string();
                 assert_eq!(result, "---
 - name: BBC ONE Lon
   service_id: 4164
   logical_channel_number: 1
+  tuning:
+    delivery_system: DVBT
+    frequency: 490000000
+    modulation: QAM_64
+    bandwidth_hz: 8000000
+    inversion: INVERSION_AUTO
+    code_rate_hp: FEC_2_3
+    code_rate_lp: FEC_AUTO
+    guard_interval: GUARD_INTERVAL_1_32
+    transmission_mode: TRANSMISSION_MODE_8K
+    hierarchy: HIERARCHY_NONE
+    network_id: 9018
+    transport_id: 4164
+    video_pid: 101
+    audio_pids:
+      - 102
+      - 106
+    other_pids:
+      \"05\":
+        - 7105
+        - 7103
+      \"06\":
+        - 152
+        - 105
+      0b:
+        - 7219
+        - 7201
 - name: BBC TWO
   service_id: 4287
-  logical_channel_number: 2");
+  logical_channel_number: 2
+  tuning:
+    delivery_system: DVBT
+    frequency: 490000000
+    modulation: QAM_64
+    bandwidth_hz: 8000000
+    inversion: INVERSION_AUTO
+    code_rate_hp: FEC_2_3
+    code_rate_lp: FEC_AUTO
+    guard_interval: GUARD_INTERVAL_1_32
+    transmission_mode: TRANSMISSION_MODE_8K
+    hierarchy: HIERARCHY_NONE
+    network_id: 9018
+    transport_id: 4164
+    video_pid: 201
+    audio_pids:
+      - 202
+      - 206
+    other_pids:
+      \"05\":
+        - 7105
+        - 7103
+      \"06\":
+        - 205
+      0b:
+        - 7219
+        - 7201");
             },
             Err(e) => assert!(false, "Failed to read file {:?} – {}", file_path, e),
         }
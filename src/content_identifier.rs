@@ -0,0 +1,194 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Handling of the content identifier descriptor, TS 102 323 §12.1.
+//!
+//! The descriptor gives the CRIDs, content reference identifiers, of an event: the
+//! programme CRID identifies the programme so that repeats can be spotted, the series
+//! CRID identifies the series the programme is an episode of. Broadcasters usually
+//! omit the authority, e.g. Freeview CRIDs are like "/KR8X5F", so a CRID is only
+//! unique within the default authority of the service, which is given by the default
+//! authority descriptor of the service in the SDT. CRIDs are therefore always used with
+//! the authority, e.g. "crid://fp.bbc.co.uk/KR8X5F", so that the CRIDs of different
+//! broadcasters cannot collide.
+
+/// The CRID types of TS 102 323 Table 12.1, and those used by the UK D-Book.
+const PROGRAMME_CRID_TYPES: [u8; 2] = [0x01, 0x31];
+const SERIES_CRID_TYPES: [u8; 2] = [0x02, 0x32];
+
+/// The CRIDs of an event.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContentIdentifiers {
+    pub programme_crid: Option<String>,
+    pub series_crid: Option<String>,
+}
+
+impl ContentIdentifiers {
+    /// The CRIDs with the default authority added to those without an authority.
+    pub fn with_default_authority(self, default_authority: Option<&str>) -> ContentIdentifiers {
+        ContentIdentifiers {
+            programme_crid: self.programme_crid.map(|c| with_authority(&c, default_authority)),
+            series_crid: self.series_crid.map(|c| with_authority(&c, default_authority)),
+        }
+    }
+
+    /// Is there a CRID without an authority.
+    pub fn lacks_authority(&self) -> bool {
+        self.programme_crid.iter().chain(self.series_crid.iter()).any(|c| ! has_authority(c))
+    }
+}
+
+/// Does the CRID include its authority, i.e. is it of the form "crid://<authority>/<data>".
+pub fn has_authority(crid: &str) -> bool {
+    crid.get(..7).map_or(false, |scheme| scheme.eq_ignore_ascii_case("crid://"))
+}
+
+/// Return the CRID including its authority, a CRID without one has the default authority
+/// added, if there is one.
+pub fn with_authority(crid: &str, default_authority: Option<&str>) -> String {
+    match default_authority {
+        Some(authority) if ! has_authority(crid) => {
+            let authority = authority.trim_end_matches('/');
+            if crid.starts_with('/') { format!("crid://{}{}", authority, crid) } else { format!("crid://{}/{}", authority, crid) }
+        },
+        _ => crid.to_string(),
+    }
+}
+
+/// Parse the data of a default authority descriptor, TS 102 323 §5.2.2, returning the
+/// authority. The data includes the tag and length bytes, as for the content identifier
+/// descriptor. Return `None` if the data is not a well formed default authority descriptor.
+pub fn parse_default_authority_descriptor(data: &[u8]) -> Option<String> {
+    if data.len() < 3 || data[0] != 0x73 {
+        return None;
+    }
+    let end = 2 + data[1] as usize;
+    if end == 2 || end > data.len() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&data[2..end]).to_string())
+}
+
+/// Parse the data of a content identifier descriptor adding the CRIDs to those already
+/// found for the event. The data is as returned by `gst_mpegts::Descriptor::get_data`,
+/// i.e. it includes the tag and length bytes.
+///
+/// CRIDs carried in the content identifier table rather than in the descriptor are
+/// ignored. Return `None` if the data is not a well formed content identifier descriptor.
+pub fn parse_content_identifier_descriptor(data: &[u8], identifiers: &mut ContentIdentifiers) -> Option<()> {
+    if data.len() < 2 || data[0] != 0x76 {
+        return None;
+    }
+    let end = 2 + data[1] as usize;
+    if end > data.len() {
+        return None;
+    }
+    let mut i = 2;
+    while i < end {
+        let crid_type = data[i] >> 2;
+        let crid_location = data[i] & 0x03;
+        i += 1;
+        match crid_location {
+            0 => {
+                let length = *data[..end].get(i)? as usize;
+                let finish = i + 1 + length;
+                if finish > end {
+                    return None;
+                }
+                let crid = String::from_utf8_lossy(&data[(i + 1)..finish]).to_string();
+                if PROGRAMME_CRID_TYPES.contains(&crid_type) {
+                    identifiers.programme_crid = Some(crid);
+                } else if SERIES_CRID_TYPES.contains(&crid_type) {
+                    identifiers.series_crid = Some(crid);
+                }
+                i = finish;
+            },
+            1 => i += 2,
+            _ => return None,
+        }
+    }
+    if i > end { None } else { Some(()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_descriptor_data(crids: &[(u8, &str)]) -> Vec<u8> {
+        let mut data = vec![0x76, 0];
+        for (crid_type, crid) in crids.iter() {
+            data.push(crid_type << 2);
+            data.push(crid.len() as u8);
+            data.extend_from_slice(crid.as_bytes());
+        }
+        data[1] = (data.len() - 2) as u8;
+        data
+    }
+
+    #[test]
+    fn parse_programme_and_series_crids() {
+        let mut identifiers = ContentIdentifiers::default();
+        let data = create_descriptor_data(&[(0x31, "/KR8X5F"), (0x32, "/KR8SER")]);
+        assert_eq!(parse_content_identifier_descriptor(&data, &mut identifiers), Some(()));
+        assert_eq!(identifiers.programme_crid, Some("/KR8X5F".to_string()));
+        assert_eq!(identifiers.series_crid, Some("/KR8SER".to_string()));
+    }
+
+    #[test]
+    fn crids_in_the_content_identifier_table_and_other_types_are_skipped() {
+        let mut identifiers = ContentIdentifiers::default();
+        let mut data = vec![0x76, 3, 0x31 << 2 | 1, 0x12, 0x34];
+        data.extend_from_slice(&create_descriptor_data(&[(0x03, "/REC"), (0x02, "crid://bbc.co.uk/series")])[2..]);
+        data[1] = (data.len() - 2) as u8;
+        assert_eq!(parse_content_identifier_descriptor(&data, &mut identifiers), Some(()));
+        assert_eq!(identifiers, ContentIdentifiers{programme_crid: None, series_crid: Some("crid://bbc.co.uk/series".to_string())});
+    }
+
+    #[test]
+    fn crids_are_given_the_default_authority() {
+        let mut data = vec![0x73, 12];
+        data.extend_from_slice(b"fp.bbc.co.uk");
+        let authority = parse_default_authority_descriptor(&data);
+        assert_eq!(authority, Some("fp.bbc.co.uk".to_string()));
+        assert_eq!(parse_default_authority_descriptor(&data[..data.len() - 1]), None);
+        let identifiers = ContentIdentifiers{programme_crid: Some("/KR8X5F".to_string()), series_crid: Some("crid://itv.com/12345".to_string())};
+        assert!(identifiers.lacks_authority());
+        let identifiers = identifiers.with_default_authority(authority.as_deref());
+        assert!(! identifiers.lacks_authority());
+        assert_eq!(identifiers, ContentIdentifiers{
+            programme_crid: Some("crid://fp.bbc.co.uk/KR8X5F".to_string()),
+            series_crid: Some("crid://itv.com/12345".to_string()),
+        });
+        assert_eq!(with_authority("/12345", Some("itv.com")), "crid://itv.com/12345");
+        assert_ne!(with_authority("/12345", Some("itv.com")), with_authority("/12345", Some("fp.bbc.co.uk")));
+        assert_eq!(with_authority("/12345", None), "/12345");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_data() {
+        let mut identifiers = ContentIdentifiers::default();
+        let data = create_descriptor_data(&[(0x31, "/KR8X5F")]);
+        assert_eq!(parse_content_identifier_descriptor(&data[..data.len() - 1], &mut identifiers), None);
+        assert_eq!(parse_content_identifier_descriptor(&[0x4E, 0], &mut identifiers), None);
+        assert_eq!(parse_content_identifier_descriptor(&[0x76, 1, 0x31 << 2 | 1], &mut identifiers), None);
+    }
+}
@@ -19,11 +19,7 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 use std::cell::{Cell, RefCell};
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
-use std::process;
 use std::rc::Rc;
-use std::thread;
 
 use gio;
 use gio::prelude::*;
@@ -32,18 +28,22 @@ use glib;
 use gtk;
 use gtk::prelude::*;
 
-use tempfile;
-
 use gst_mpegts;
 
 use crate::about;
-use crate::channels_data::{channels_file_path, get_channels_data, read_channels_data, ChannelData};
+use crate::channel_editor;
+use crate::channel_groups::{self, ALL_CHANNELS};
+use crate::channel_list_dialogs;
+use crate::channels_data::{get_channels_data, ChannelData};
 use crate::control_window_button::ControlWindowButton;
 use crate::dialogs::display_an_error_dialog;
-use crate::frontend_manager::FrontendId;
+use crate::eit_sections::ServiceKey;
+use crate::epg_window::{self, EPGWindow};
+use crate::dvb_devices::FrontendId;
 use crate::preferences;
 use crate::preferences_dialog;
 use crate::remote_control::TargettedKeystroke;
+use crate::scan_dialog;
 use crate::transmitter_dialog;
 
 /// A `ControlWindow` is an `gtk::ApplicationWindow` but there is no inheritance
@@ -55,10 +55,12 @@ pub struct ControlWindow {
     frontends_box: gtk::Box,
     label: gtk::Label,
     channels_data_store: gtk::ListStore,
-    pub channels_data_sorter: gtk::TreeModelSort, // Used by ControlWindowButton and FrontendWindow.
+    pub channels_data_sorter: gtk::TreeModelSort, // Used by ControlWindowButton and the preferences dialog.
+    pub channel_groups: RefCell<Vec<String>>, // Used by ControlWindowButton.
     channels_data_loaded: Cell<bool>,
     control_window_buttons: RefCell<Vec<Rc<ControlWindowButton>>>,
     pub to_epg_manager: std::sync::mpsc::Sender<gst_mpegts::Section>, // Used by ControlWindowButton.
+    pub epg_window: RefCell<Option<Rc<EPGWindow>>>, // Used by EPGWindow.
 }
 
 /// All the message types that  can be sent to the ControllerWindow.
@@ -68,6 +70,7 @@ pub enum Message {
     FrontendDisappeared{fei: FrontendId},
     TargettedKeystrokeReceived{tk: TargettedKeystroke},
     UpdatedLogicalChannelNumber{cd: ChannelData},
+    PresentFollowingChanged{service: ServiceKey},
 }
 
 impl ControlWindow {
@@ -100,6 +103,12 @@ impl ControlWindow {
         window.add_action(&epg_action);
         let channels_file_action = gio::SimpleAction::new("create_channels_file", None);
         window.add_action(&channels_file_action);
+        let import_channels_action = gio::SimpleAction::new("import_channels", None);
+        window.add_action(&import_channels_action);
+        let export_channels_action = gio::SimpleAction::new("export_channels", None);
+        window.add_action(&export_channels_action);
+        let edit_channels_action = gio::SimpleAction::new("edit_channels", None);
+        window.add_action(&edit_channels_action);
         let preferences_action = gio::SimpleAction::new("preferences", None);
         window.add_action(&preferences_action);
         let about_action = gio::SimpleAction::new("about", None);
@@ -114,7 +123,8 @@ impl ControlWindow {
         window.add(&main_box);
         window.show_all();
         //
-        let channels_data_store = gtk::ListStore::new(&[String::static_type(), String::static_type()]);
+        // The columns are the channel number, the channel name, and the channel groups one per line.
+        let channels_data_store = gtk::ListStore::new(&[String::static_type(), String::static_type(), String::static_type()]);
         let channels_data_sorter = gtk::TreeModelSort::new(&channels_data_store);
         channels_data_sorter.set_default_sort_func(|model, iter_a, iter_b| {
             // Order by channel number.
@@ -149,20 +159,16 @@ impl ControlWindow {
             label,
             channels_data_store,
             channels_data_sorter,
+            channel_groups: RefCell::new(vec![ALL_CHANNELS.to_string()]),
             channels_data_loaded: Cell::new(false),
             control_window_buttons: RefCell::new(Vec::new()),
             to_epg_manager,
+            epg_window: RefCell::new(None),
         });
         control_window.update_channels_store();
         epg_action.connect_activate({
             let c_w = control_window.clone();
-            move |_, _| {
-                display_an_error_dialog(Some(&c_w.window), if c_w.control_window_buttons.borrow().is_empty() {
-                    "No frontends, so no EPG."
-                } else {
-                    "Should display the EPG window."  // TODO Get the EPG window working.
-                });
-            }
+            move |_, _| epg_window::present(&c_w)
         });
         channels_file_action.connect_activate({
             let c_w = control_window.clone();
@@ -174,6 +180,18 @@ impl ControlWindow {
                 }
             }
         });
+        import_channels_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| channel_list_dialogs::present_import(&c_w)
+        });
+        export_channels_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| channel_list_dialogs::present_export(&c_w)
+        });
+        edit_channels_action.connect_activate({
+            let c_w = control_window.clone();
+            move |_, _| channel_editor::present(&c_w)
+        });
         preferences_action.connect_activate({
             let c_w = control_window.clone();
             move |_, _| preferences_dialog::present(&c_w)
@@ -190,6 +208,7 @@ impl ControlWindow {
                     Message::FrontendDisappeared{fei} => remove_frontend(&c_w, &fei),
                     Message::TargettedKeystrokeReceived{tk} => process_targetted_keystroke(&c_w, &tk),
                     Message::UpdatedLogicalChannelNumber {cd} => add_logical_channel_number(&c_w, &cd),
+                    Message::PresentFollowingChanged{service} => present_following_changed(&c_w, &service),
                 }
                 Continue(true)
             });
@@ -202,97 +221,67 @@ impl ControlWindow {
         self.channels_data_store.clear();
         match get_channels_data() {
             Some(channel_data) => {
+                let groups = channel_groups::get_groups_by_channel_name();
                 for (number, name) in channel_data {
                     let channel_number = if number == 0 { "".to_string() } else { number.to_string() };
-                    self.channels_data_store.insert_with_values(None, &[0, 1], &[&channel_number, &name]);
+                    let groups_of_channel = groups.get(&name).cloned().unwrap_or_default();
+                    self.channels_data_store.insert_with_values(None, &[0, 1, 2], &[&channel_number, &name, &groups_of_channel]);
                 };
                 self.channels_data_loaded.set(true);
             },
             None => {
-                self.channels_data_store.insert_with_values(None, &[0, 1], &[&"", &"No channels file."]);
+                self.channels_data_store.insert_with_values(None, &[0, 1, 2], &[&"", &"No channels file.", &""]);
                 self.channels_data_loaded.set(false);
             }
         }
+        self.channel_groups.replace(channel_groups::get_group_names());
         for button in self.control_window_buttons.borrow().iter() {
+            button.update_channel_groups();
             button.reset_active_channel();
         }
     }
 
     pub fn is_channels_store_loaded(&self) -> bool { self.channels_data_loaded.get() }
 
+    /// Tune a frontend to the named channel and display it, preferring a frontend
+    /// that is not already displaying a channel.
+    ///
+    /// Return `true` if a frontend was tuned, `false` otherwise.
+    pub fn tune_to_channel(&self, channel_name: &str) -> bool { // Used in epg_window.rs
+        let control_window_button = {
+            let buttons = self.control_window_buttons.borrow();
+            match buttons.iter().find(|b| ! b.frontend_button.get_active()).or(buttons.first()) {
+                Some(button) => button.clone(),
+                None => return false,
+            }
+        };
+        if ! control_window_button.set_channel_by_name(channel_name) {
+            return false;
+        }
+        if ! control_window_button.frontend_button.get_active() {
+            control_window_button.frontend_button.set_active(true);
+        }
+        true
+    }
+
 }
 
 /// Ensure that the GStreamer dvbsrc channels file is present.
 ///
-/// If the transmitter files are not present this function will do nothing.
+/// If the transmitter files are not present the scan starts from a frequency given by the user.
 ///
-/// Currently try to use dvbv5-scan to create the file, or if it isn't present, try dvbscan or w_scan.
+/// The scan is done by the channel scanner using a frontend that is not being used by
+/// a channel window, its progress is shown by the scan dialog.
 fn ensure_channel_file_present(control_window: &Rc<ControlWindow>) {
-    match  transmitter_dialog::present(Some(&control_window.window)) {
-        Some(path_to_transmitter_file) => {
-            //  TODO Turn this into a dialog that follows the GNOME HIG. Probably best to create a custom dialog.
-            let start_dialog = gtk::MessageDialog::new(
-                Some(&control_window.window),
-                gtk::DialogFlags::MODAL,
-                gtk::MessageType::Info,
-                gtk::ButtonsType::OkCancel,   // TODO This button type is discourage by the GNOME HIG, incorrect button placements.
-                &format!("Run:\n\n    dvbv5-scan {}\n\n?\n\nYou need to have already closed all open channel viewers for this to work.", path_to_transmitter_file.to_str().unwrap()),
-            );
-            let response = gtk::ResponseType::from(start_dialog.run());
-            unsafe { start_dialog.destroy(); }
-            if response== gtk::ResponseType::Ok {
-                let wait_dialog = gtk::MessageDialog::new(
-                    Some(&control_window.window),
-                    gtk::DialogFlags::MODAL,
-                    gtk::MessageType::Info,
-                    gtk::ButtonsType::None,
-                    &format!("Running:\n\n    dvbv5-scan {}\n\nThis may take a while.", path_to_transmitter_file.to_str().unwrap())
-                );
-                wait_dialog.show_all();
-                let (sender, receiver) = glib::MainContext::channel::<bool>(glib::PRIORITY_DEFAULT);
-                {
-                    let c_w = control_window.clone();
-                    let w_d = wait_dialog.clone();
-                    receiver.attach(None, move |result| {
-                        unsafe { w_d.destroy(); }
-                        if result {
-                            c_w.update_channels_store();
-                        } else {
-                            display_an_error_dialog(Some(&c_w.window), "dvbv5-scan failed to generate a file.");
-                        }
-                        Continue(false)
-                    });
-                };
-                thread::spawn({
-                    let p_t_t_f = path_to_transmitter_file.clone();
-                    move || {
-                        let mut temporary_file = tempfile::NamedTempFile::new().expect("Could not create a temporary file.");
-                        match process::Command::new("dvbv5-scan")
-                            .arg("-o")
-                            .arg(&temporary_file.path())
-                            .arg(&*p_t_t_f)
-                            .output() {
-                            Ok(_) => {
-                                let mut destination = OpenOptions::new()
-                                    .write(true)
-                                    .truncate(true)
-                                    .create(true)
-                                    .open(channels_file_path())
-                                    .expect("Could not open channels file.");
-                                let mut buffer = String::new();
-                                temporary_file.read_to_string(&mut buffer).expect("Could not read temporary channels file.");
-                                destination.write(&buffer.as_bytes()).expect("Could not write channels file.");
-                                if !read_channels_data() {
-                                    panic!("Could not read the file that was just written.");
-                                }
-                                sender.send(true).expect("Could not send result for some reason.")
-                            },
-                            Err(error) => sender.send(false).expect(&format!("Could not send result of error:{}", error)),
-                        };
-                    }
-                });
-            }
+    let frontend = match control_window.control_window_buttons.borrow().iter().find(|b| ! b.frontend_button.get_active()) {
+        Some(button) => button.frontend_id.clone(),
+        None => {
+            display_an_error_dialog(Some(&control_window.window), "All the frontends are in use, close a channel viewer to be able to scan for channels.");
+            return;
         },
+    };
+    match  transmitter_dialog::present(Some(&control_window.window)) {
+        Some((transmitter_file, transmitters)) => scan_dialog::present(control_window, &transmitter_file, transmitters, frontend),
         None => ()  // User already informed of problem.
     }
 }
@@ -319,11 +308,11 @@ fn add_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
                             display_an_error_dialog(Some(&c_w_b.control_window.window), "The channel is the empty string and cannot be tuned to.");
                         } else {
                             // TODO What to do if None is returned?
-                            if let Some(iterator) = control_window.channels_data_sorter.get_iter_first() {
+                            if let Some(iterator) = c_w_b.channels_filter.get_iter_first() {
                                 loop {
-                                    if let Some(channel_name) = control_window.channels_data_sorter.get_value(&iterator, 1).get::<String>().unwrap() {
+                                    if let Some(channel_name) = c_w_b.channels_filter.get_value(&iterator, 1).get::<String>().unwrap() {
                                         if target_channel_name == channel_name {
-                                            match control_window.channels_data_sorter.get_path(&iterator) {
+                                            match c_w_b.channels_filter.get_path(&iterator) {
                                                 Some(mut tree_path) => {
                                                     let index = tree_path.get_indices_with_depth()[0];
                                                     if index < 0 { panic!("index cannot be a negative integer"); }
@@ -335,7 +324,7 @@ fn add_frontend(control_window: &Rc<ControlWindow>, fei: &FrontendId) {
                                             break;
                                         }
                                     }
-                                    if !control_window.channels_data_sorter.iter_next(&iterator) {
+                                    if !c_w_b.channels_filter.iter_next(&iterator) {
                                         display_an_error_dialog(Some(&c_w_b.control_window.window), &format!("The channel {} could not be found for immediate TV display.", target_channel_name));
                                         break;
                                     }
@@ -396,3 +385,10 @@ fn add_logical_channel_number(control_window: &Rc<ControlWindow>, cd: &ChannelDa
         }
     }
 }
+
+/// Process a change of present or following event of a service.
+fn present_following_changed(control_window: &Rc<ControlWindow>, service: &ServiceKey) {
+    for c_w_b in control_window.control_window_buttons.borrow().iter() {
+        c_w_b.on_present_following_changed(service);
+    }
+}
@@ -25,10 +25,13 @@ use std::rc::Rc;
 use gtk;
 use gtk::prelude::*;
 
+use crate::channel_groups::{self, ALL_CHANNELS};
 use crate::channels_data::{encode_to_mrl, get_channel_name_of_logical_channel_number};
 use crate::control_window::ControlWindow;
 use crate::dialogs::display_an_error_dialog;
-use crate::frontend_manager::FrontendId;
+use crate::dvb_devices::FrontendId;
+use crate::eit_sections::ServiceKey;
+use crate::epg_harvester;
 use crate::frontend_window::FrontendWindow;
 use crate::input_event_codes;
 use crate::metvcombobox::{MeTVComboBox, MeTVComboBoxExt};
@@ -44,6 +47,9 @@ pub struct ControlWindowButton {
     pub widget: gtk::Box, // ControlWindow instance needs access to this for packing.
     pub frontend_button: gtk::ToggleButton, // FrontendWindow needs access to this.
     pub channel_selector: MeTVComboBox, // FrontendWindow needs read access to this.
+    pub channels_filter: gtk::TreeModelFilter, // FrontendWindow and ControlWindow need access to this.
+    channel_group: Rc<RefCell<String>>,
+    group_selector: gtk::ComboBoxText,
     frontend_window: RefCell<Option<Rc<FrontendWindow>>>,
     channel_number_dialog: gtk::Dialog,
     channel_number_entry: gtk::Entry,
@@ -54,7 +60,8 @@ impl ControlWindowButton {
     ///
     /// The adapter and frontend numbers for the label for a toggle button that is used
     /// to start and stop a frontend window displaying the stream for that frontend. Below
-    /// is a drop down list button to select the channel to tune the front end to.
+    /// is a drop down list button to select the channel to tune the front end to, and
+    /// below that one to select the group of channels the first offers.
     ///
     /// This function is executed in the GTK event loop thread.
     pub fn new(control_window: &Rc<ControlWindow>, fei: &FrontendId) -> Rc<ControlWindowButton> {
@@ -62,10 +69,21 @@ impl ControlWindowButton {
         let frontend_button = gtk::ToggleButton::with_label(
             format!("adaptor{}\nfrontend{}", frontend_id.adapter, frontend_id.frontend).as_ref()
         );
-        let channel_selector = MeTVComboBox::new_with_model(&control_window.channels_data_sorter);
+        let channel_group = Rc::new(RefCell::new(ALL_CHANNELS.to_string()));
+        let channels_filter = gtk::TreeModelFilter::new(&control_window.channels_data_sorter, None);
+        channels_filter.set_visible_func({
+            let channel_group = channel_group.clone();
+            move |model, iterator| {
+                let groups = model.get_value(iterator, 2).get::<String>().unwrap().unwrap_or_default();
+                channel_groups::is_in_group(&groups, &channel_group.borrow())
+            }
+        });
+        let channel_selector = MeTVComboBox::new_with_model(&channels_filter);
+        let group_selector = gtk::ComboBoxText::new();
         let widget = gtk::Box::new(gtk::Orientation::Vertical, 0);
         widget.pack_start(&frontend_button, true, true, 0);
         widget.pack_start(&channel_selector, true, true, 0);
+        widget.pack_start(&group_selector, true, true, 0);
         let channel_number_dialog = gtk::Dialog::new();
         let channel_number_entry = gtk::Entry::new();
         let max_length = 3;
@@ -82,14 +100,23 @@ impl ControlWindowButton {
             widget,
             frontend_button,
             channel_selector,
+            channels_filter,
+            channel_group,
+            group_selector,
             frontend_window: RefCell::new(None),
             channel_number_dialog,
             channel_number_entry,
         });
+        Self::init_group_selector(&control_window_button, &control_window_button.group_selector);
         control_window_button.reset_active_channel();
         control_window_button.channel_selector.connect_changed({
             let c_w_b = control_window_button.clone();
-            move |_| Self::on_channel_changed(&c_w_b, c_w_b.channel_selector.get_active().unwrap())
+            move |_| {
+                // There is no active channel while the group is being changed.
+                if let Some(index) = c_w_b.channel_selector.get_active() {
+                    Self::on_channel_changed(&c_w_b, index);
+                }
+            }
         });
         control_window_button.frontend_button.connect_toggled({
             let c_w_b = control_window_button.clone();
@@ -115,31 +142,130 @@ impl ControlWindowButton {
 
     /// Set the state of all the channel control widgets.
     fn set_channel_index(&self, channel_index: u32) {
-        let current = self.channel_selector.get_active().unwrap();
-        if current != channel_index {
+        if self.channel_selector.get_active() != Some(channel_index) {
             self.channel_selector.set_active(Some(channel_index));
         }
         if let Some(ref frontend_window) = *self.frontend_window.borrow() {
-            let fe_current = frontend_window.channel_selector.get_active().unwrap();
-            if fe_current != channel_index {
+            if frontend_window.channel_selector.get_active() != Some(channel_index) {
                 frontend_window.channel_selector.set_active(Some(channel_index));
             }
-            let fs_fe_current = frontend_window.fullscreen_channel_selector.get_active().unwrap();
-            if fs_fe_current != channel_index {
+            if frontend_window.fullscreen_channel_selector.get_active() != Some(channel_index) {
                 frontend_window.fullscreen_channel_selector.set_active(Some(channel_index));
             }
         }
     }
 
+    /// Return the index of the named channel in the channels of the active group, `None`
+    /// if it is not in the group.
+    fn index_in_group(&self, channel_name: &str) -> Option<u32> {
+        let model = &self.channels_filter;
+        if let Some(iterator) = model.get_iter_first() {
+            let mut index = 0u32;
+            loop {
+                if let Some(name) = model.get_value(&iterator, 1).get::<String>().unwrap() {
+                    if name == channel_name {
+                        return Some(index);
+                    }
+                }
+                if ! model.iter_next(&iterator) { break }
+                index += 1;
+            }
+        }
+        None
+    }
+
+    /// Set the state of all the channel control widgets to the named channel, changing
+    /// to the All channels group if the channel is not in the active group.
+    ///
+    /// Return `true` if the channel was found in the data model, `false` otherwise.
+    pub fn set_channel_by_name(&self, channel_name: &str) -> bool { // Used in control_window.rs
+        if self.index_in_group(channel_name).is_none() && *self.channel_group.borrow() != ALL_CHANNELS {
+            self.set_channel_group(ALL_CHANNELS);
+        }
+        match self.index_in_group(channel_name) {
+            Some(index) => {
+                self.set_channel_index(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Fill a group selector with the groups that have channels and connect it to this
+    /// button, the selectors of the frontend window being group selectors as well.
+    pub fn init_group_selector(control_window_button: &Rc<ControlWindowButton>, group_selector: &gtk::ComboBoxText) { // Used in frontend_window.rs
+        control_window_button.fill_group_selector(group_selector);
+        group_selector.connect_changed({
+            let c_w_b = control_window_button.clone();
+            move |group_selector| {
+                // There is no active group while the selector is being refilled.
+                if let Some(group) = group_selector.get_active_id() {
+                    c_w_b.set_channel_group(&group);
+                }
+            }
+        });
+    }
+
+    fn fill_group_selector(&self, group_selector: &gtk::ComboBoxText) {
+        group_selector.remove_all();
+        for group in self.control_window.channel_groups.borrow().iter() {
+            group_selector.append(Some(group.as_str()), group);
+        }
+        let group = self.channel_group.borrow().clone();
+        group_selector.set_active_id(Some(&group));
+    }
+
+    /// Refill the group selectors after the groups have changed, the active group becoming
+    /// All channels if it no longer has any channels.
+    pub fn update_channel_groups(&self) { // Used in control_window.rs
+        let group = self.channel_group.borrow().clone();
+        if ! self.control_window.channel_groups.borrow().contains(&group) {
+            self.set_channel_group(ALL_CHANNELS);
+        }
+        self.fill_group_selector(&self.group_selector);
+        if let Some(ref frontend_window) = *self.frontend_window.borrow() {
+            self.fill_group_selector(&frontend_window.group_selector);
+            self.fill_group_selector(&frontend_window.fullscreen_group_selector);
+        }
+    }
+
+    /// Change the group of channels the channel selectors offer. The channel being watched
+    /// stays if it is in the group, otherwise it changes to the first channel of the group.
+    pub fn set_channel_group(&self, group: &str) {
+        if *self.channel_group.borrow() == group {
+            return;
+        }
+        let channel_name = self.channel_selector.get_active_text();
+        self.channel_group.replace(group.to_string());
+        self.channels_filter.refilter();
+        if self.group_selector.get_active_id().as_deref() != Some(group) {
+            self.group_selector.set_active_id(Some(group));
+        }
+        if let Some(ref frontend_window) = *self.frontend_window.borrow() {
+            for group_selector in [&frontend_window.group_selector, &frontend_window.fullscreen_group_selector].iter() {
+                if group_selector.get_active_id().as_deref() != Some(group) {
+                    group_selector.set_active_id(Some(group));
+                }
+            }
+        }
+        match channel_name.and_then(|name| self.index_in_group(&name)) {
+            Some(index) => self.set_channel_index(index),
+            None => self.set_channel_index(0),
+        }
+    }
+
     /// Toggle the button.
     ///
     /// This function is called after the change of state of the frontend_button.
     fn toggle_button(control_window_button: &Rc<ControlWindowButton>) { // Used in control_window.rs
         if control_window_button.frontend_button.get_active() {
             if control_window_button.control_window.is_channels_store_loaded() {
+                // The frontend may be in use collecting the EPG, it must be freed first.
+                epg_harvester::claim_frontend(&control_window_button.frontend_id);
                 let frontend_window = match FrontendWindow::new(control_window_button.clone()) {
                     Ok(frontend_window) => frontend_window,
                     Err(_) => {
+                        epg_harvester::release_frontend(&control_window_button.frontend_id);
                         display_an_error_dialog(Some(&control_window_button.control_window.window), "Could not create a frontend window, most likely because\na GStreamer engine could not be created.");
                         return;
                     },
@@ -152,7 +278,10 @@ impl ControlWindowButton {
             // TODO Should there be an else activity here?
         } else {
             match control_window_button.frontend_window.replace(None) {
-                Some(ref frontend_window) => frontend_window.stop(),
+                Some(ref frontend_window) => {
+                    frontend_window.stop();
+                    epg_harvester::release_frontend(&control_window_button.frontend_id);
+                },
                 None => panic!("Inconsistent state of frontend,"),
             }
         }
@@ -165,6 +294,8 @@ impl ControlWindowButton {
         if let Some(ref frontend_window) = *control_window_button.frontend_window.borrow() {
             if status {
                 // Do not stop the frontend completely just change what is being displayed on it.
+                // A recording is of a channel so it cannot continue over a channel change.
+                frontend_window.set_recording(false);
                 frontend_window.engine.stop();
                 let window_title = "Me TV – ".to_string() + &control_window_button.channel_selector.get_active_text().unwrap();
                 let f_w = &frontend_window.window;
@@ -195,6 +326,7 @@ impl ControlWindowButton {
             control_window_button.set_channel_index(channel_index);
             let channel_name = control_window_button.channel_selector.get_active_text().unwrap();
             frontend_window.engine.set_mrl(&encode_to_mrl(&channel_name));
+            frontend_window.update_now_next();
             preferences::set_last_channel(channel_name, true);
             if status {
                 // TODO Must handle not being able to tune to a channel better than panicking.
@@ -203,24 +335,38 @@ impl ControlWindowButton {
         }
     }
 
+    /// Callback for a change of the present or following event of a service.
+    pub fn on_present_following_changed(&self, service: &ServiceKey) { // Used in control_window.rs
+        if let Some(ref frontend_window) = *self.frontend_window.borrow() {
+            if frontend_window.get_service_key().as_ref() == Some(service) {
+                frontend_window.update_now_next();
+            }
+        }
+    }
+
     /// Process a targetted keystroke.
     pub fn process_targetted_keystroke(&self, tk: &TargettedKeystroke) {
         assert_eq!(self.frontend_id, tk.frontend_id);
         match tk.keystroke {
             input_event_codes::KEY_CHANNELUP => {
                 if tk.value > 0 {
+                    // Channels are changed within the active group.
                     let selector = &self.channel_selector;
-                    let index = selector.get_active().unwrap();
-                    // TODO Need to stop going beyond the number of channels there are.
-                    selector.set_active(Some(index + 1));
+                    let count = self.channels_filter.iter_n_children(None) as u32;
+                    if let Some(index) = selector.get_active() {
+                        if index + 1 < count {
+                            selector.set_active(Some(index + 1));
+                        }
+                    }
                 }
             }
             input_event_codes::KEY_CHANNELDOWN => {
                 if tk.value > 0 {
                     let selector = &self.channel_selector;
-                    let index = selector.get_active().unwrap();
-                    if index > 0 {
-                        selector.set_active(Some(index - 1));
+                    if let Some(index) = selector.get_active() {
+                        if index > 0 {
+                            selector.set_active(Some(index - 1));
+                        }
                     }
                 }
             }
@@ -272,25 +418,9 @@ impl ControlWindowButton {
     fn change_channel_after_keystrokes(&self, channel_number: &str) {
         let channel_number = channel_number.parse::<u16>().unwrap();
         match get_channel_name_of_logical_channel_number(channel_number) {
-            Some(channel_name) => {
-                let index = {
-                    let model = &self.control_window.channels_data_sorter;
-                    let iterator = model.get_iter_first().unwrap();
-                    let mut index = 0u32;
-                    let mut success = false;
-                    loop {
-                        let name = model.get_value(&iterator, 1).get::<String>().unwrap().unwrap();
-                        if name == channel_name {
-                            success = true;
-                            break
-                        }
-                        if ! model.iter_next(&iterator) { break }
-                        index += 1;
-                    }
-                    if ! success { panic!("Failed to find {} in the data model", channel_name); }
-                    index
-                };
-                self.set_channel_index(index);
+            // A channel number may be of a channel not in the active group.
+            Some(channel_name) => if ! self.set_channel_by_name(&channel_name) {
+                panic!("Failed to find {} in the data model", channel_name);
             },
             None => println!("Failed to find channel name from channel number."),
         }
@@ -0,0 +1,125 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Reading and writing the files holding the data of Me TV.
+//!
+//! A file is written to a temporary file in the same directory which is then renamed
+//! over it, so that no reader ever sees a partly written file and a failed write leaves
+//! the previous file in place. A file that more than one process changes, e.g. the
+//! recording jobs, is changed using `update_yaml` which holds an exclusive lock on a
+//! lock file next to it from reading it to writing it, so that no process loses a
+//! change made by another at the same time.
+
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use libc;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_yaml;
+use tempfile;
+
+/// Write a file, replacing it atomically, using `write` to write the contents.
+pub fn write_atomically<F>(path: &Path, write: F) -> Result<(), String> where F: FnOnce(&mut File) -> io::Result<()> {
+    let directory = path.parent().unwrap();
+    create_dir_all(directory).map_err(|e| format!("Failed to create {} – {}", directory.display(), e))?;
+    let mut file = tempfile::NamedTempFile::new_in(directory).map_err(|e| format!("Failed to create a temporary file in {} – {}", directory.display(), e))?;
+    write(file.as_file_mut()).map_err(|e| format!("Failed to write {} – {}", path.display(), e))?;
+    file.persist(path).map_err(|e| format!("Failed to write {} – {}", path.display(), e))?;
+    Ok(())
+}
+
+/// Read a value from a YAML file, the value is the default one if there is no file or
+/// the file is empty.
+pub fn read_yaml<T: DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(format!("Failed to open {} – {}", path.display(), e)),
+    };
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer).map_err(|e| format!("Failed to read {} – {}", path.display(), e))?;
+    if buffer.trim().is_empty() {
+        return Ok(T::default());
+    }
+    serde_yaml::from_str::<T>(&buffer).map_err(|e| format!("Failed to deserialise {} – {}", path.display(), e))
+}
+
+/// Write a value to a YAML file.
+pub fn write_yaml<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), String> {
+    let buffer = serde_yaml::to_string(value).map_err(|e| format!("Failed to serialise {} – {}", path.display(), e))?;
+    write_atomically(path, |file| file.write_all(buffer.as_ref()))
+}
+
+/// Open the lock file of the file at `path` and take an exclusive lock on it, waiting
+/// for any other process holding it. The lock is released when the returned file is
+/// closed.
+fn lock(path: &Path) -> Result<File, String> {
+    let directory = path.parent().unwrap();
+    create_dir_all(directory).map_err(|e| format!("Failed to create {} – {}", directory.display(), e))?;
+    let lock_path = path.with_extension("lock");
+    let file = OpenOptions::new().write(true).create(true).truncate(false).open(&lock_path)
+        .map_err(|e| format!("Failed to open {} – {}", lock_path.display(), e))?;
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(file);
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != ErrorKind::Interrupted {
+            return Err(format!("Failed to lock {} – {}", lock_path.display(), error));
+        }
+    }
+}
+
+/// Read the value in the YAML file at `path`, apply `change` to it, and write it back if
+/// it has been changed, returning the result of `change`. The file is locked throughout
+/// so that the change is not lost to, nor loses, a concurrent change by another process.
+pub fn update_yaml<T, F, R>(path: &Path, change: F) -> Result<R, String>
+    where T: Clone + DeserializeOwned + Default + PartialEq + Serialize, F: FnOnce(&mut T) -> R {
+    let _lock = lock(path)?;
+    let value = read_yaml::<T>(path)?;
+    let mut changed_value = value.clone();
+    let result = change(&mut changed_value);
+    if changed_value != value {
+        write_yaml(path, &changed_value)?;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_yaml_writes_only_changes() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("list.yml");
+        assert_eq!(read_yaml::<Vec<String>>(&path).unwrap(), Vec::<String>::new());
+        update_yaml(&path, |list: &mut Vec<String>| list.push("BBC TWO".to_string())).unwrap();
+        assert!(directory.path().join("list.lock").exists());
+        assert_eq!(read_yaml::<Vec<String>>(&path).unwrap(), vec!["BBC TWO".to_string()]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(update_yaml(&path, |list: &mut Vec<String>| list.is_empty()).unwrap());
+        assert!(! path.exists());
+    }
+}
@@ -34,3 +34,14 @@ pub fn display_an_error_dialog<T: IsA<gtk::Window>>(parent: Option<&T>, message:
     unsafe { message_dialog.destroy(); }
 }
 
+pub fn display_an_information_dialog<T: IsA<gtk::Window>>(parent: Option<&T>, message: &str) {
+    let message_dialog = gtk::MessageDialog::new(
+        parent,
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Info,
+        gtk::ButtonsType::Ok,  // TODO Apparently use of this button type is discourage by the GNOME HIG
+        message,
+    );
+    message_dialog.run();
+    unsafe { message_dialog.destroy(); }
+}
@@ -19,25 +19,12 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::fmt;
 use std::slice::Iter;
 
-use serde_derive::{Deserialize, Serialize};
+pub use crate::dvbv5::DeliverySystem;
 
-/// The various options for DVB delivery system the user is using.
-#[allow(non_camel_case_types)]
-#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
-pub enum DeliverySystem {
-    ATSC,
-    DVBC_ANNEX_A,
-    DVBC_ANNEX_B,
-    DVBT,
-    DVBT2,
-    ISDBT,
-}
-
-// Rust doesn't allow iteration of enum variants directly so we
-// hack it up using a slice. Unpleasant but it does the job.
+// The delivery systems the user can choose from in the preferences, the same
+// enumeration as in channels files.
 static DELIVERY_SYSTEMS: [DeliverySystem;  6] = [
     DeliverySystem::ATSC,
     DeliverySystem::DVBC_ANNEX_A,
@@ -47,46 +34,15 @@ static DELIVERY_SYSTEMS: [DeliverySystem;  6] = [
     DeliverySystem::ISDBT,
 ];
 
-impl DeliverySystem {
-    /// Iterate over the `DeliverySystem` variants.
-    pub fn iterator() -> Iter<'static, DeliverySystem> {
-        DELIVERY_SYSTEMS.iter()
-    }
-
-    /// Return the position of the `DeliverySystem` in the sequence of all possibilities.
-    pub fn get_index(&self) -> u32 {
-        // TODO This is hideous code.
-        let mut i = 0;
-        for d_s in DELIVERY_SYSTEMS.iter() {
-            if d_s == self {
-                return i
-            }
-            i += 1;
-        };
-        panic!("Failure of preferences::DeliverySystem.");
-    }
+/// Iterate over the `DeliverySystem` variants the user can choose from.
+pub fn delivery_systems() -> Iter<'static, DeliverySystem> {
+    DELIVERY_SYSTEMS.iter()
 }
 
-impl fmt::Display for DeliverySystem {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-        // or, alternatively:
-        // fmt::Debug::fmt(self, f)
-    }
-}
-
-impl std::convert::From<&str> for DeliverySystem {
-    fn from(s: &str) -> DeliverySystem {
-        match s {
-            "ATSC" => DeliverySystem::ATSC,
-            "DVBC_ANNEX_A" => DeliverySystem::DVBC_ANNEX_A,
-            "DVBC_ANNEX_B" => DeliverySystem::DVBC_ANNEX_B,
-            "DVBT" => DeliverySystem::DVBT,
-            "DVBT2" => DeliverySystem::DVBT2,
-            "ISDBT" => DeliverySystem::ISDBT,
-            _ => panic!("unknown DeliverySystem variant."),
-        }
-    }
+/// Return the position of the `DeliverySystem` in the sequence of those the user can
+/// choose from, `None` if it is not one of them.
+pub fn index_of(delivery_system: DeliverySystem) -> Option<u32> {
+    DELIVERY_SYSTEMS.iter().position(|d| *d == delivery_system).map(|i| i as u32)
 }
 
 #[cfg(test)]
@@ -95,7 +51,7 @@ mod test {
 
     #[test]
     fn construct_from_string() {
-        assert_eq!(DeliverySystem::from("DVBT2"), DeliverySystem::DVBT2);
+        assert_eq!(DeliverySystem::from_linux_name("DVBT2"), Some(DeliverySystem::DVBT2));
     }
 
     #[test]
@@ -105,12 +61,13 @@ mod test {
 
     #[test]
     fn index_of_dvbt2() {
-        assert_eq!(DeliverySystem::DVBT2.get_index(), 4);
+        assert_eq!(index_of(DeliverySystem::DVBT2), Some(4));
+        assert_eq!(index_of(DeliverySystem::DVBS2), None);
     }
 
     #[test]
     fn iterator_sequence() {
-        let mut i = DeliverySystem::iterator();
+        let mut i = delivery_systems();
         assert_eq!(*i.next().unwrap(), DeliverySystem::ATSC);
         assert_eq!(*i.next().unwrap(), DeliverySystem::DVBC_ANNEX_A);
     }
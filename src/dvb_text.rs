@@ -0,0 +1,322 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Decoding of the text strings in DVB SI tables as per EN 300 468 Annex A.
+//!
+//! If the first byte of a string is in the range 0x20 to 0xFF the string uses the
+//! default table, a variant of ISO/IEC 6937, otherwise the first byte, or bytes,
+//! select the character table used for the rest of the string.
+//!
+//! The control codes 0x80 to 0x9F (0xE080 to 0xE09F for the two byte tables) are
+//! removed other than the CR/LF code 0x8A which becomes a newline. In particular the
+//! emphasis on and off codes 0x86 and 0x87 are dropped.
+
+use encoding_rs::{self, Encoding};
+
+/// The character tables of EN 300 468 Annex A.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CharacterTable {
+    Iso6937,
+    SingleByte(&'static Encoding),
+    MultiByte(&'static Encoding),
+    Utf16,
+    Utf8,
+    Unsupported,
+}
+
+/// Return the single byte encoding for ISO/IEC 8859 part n.
+///
+/// encoding_rs follows the WHATWG Encoding Standard which maps some of the parts to
+/// the corresponding Windows code page, the graphic characters are the same so this is
+/// fine given the control codes are dealt with before decoding.
+fn iso_8859(n: u8) -> Option<&'static Encoding> {
+    match n {
+        1 => Some(encoding_rs::WINDOWS_1252),
+        2 => Some(encoding_rs::ISO_8859_2),
+        3 => Some(encoding_rs::ISO_8859_3),
+        4 => Some(encoding_rs::ISO_8859_4),
+        5 => Some(encoding_rs::ISO_8859_5),
+        6 => Some(encoding_rs::ISO_8859_6),
+        7 => Some(encoding_rs::ISO_8859_7),
+        8 => Some(encoding_rs::ISO_8859_8),
+        9 => Some(encoding_rs::WINDOWS_1254),
+        10 => Some(encoding_rs::ISO_8859_10),
+        11 => Some(encoding_rs::WINDOWS_874),
+        13 => Some(encoding_rs::ISO_8859_13),
+        14 => Some(encoding_rs::ISO_8859_14),
+        15 => Some(encoding_rs::ISO_8859_15),
+        16 => Some(encoding_rs::ISO_8859_16),
+        _ => None,
+    }
+}
+
+/// Determine the character table of a string, returning the table and the number
+/// of bytes used to select it.
+fn select_character_table(data: &[u8]) -> (CharacterTable, usize) {
+    let single_byte = |n| match iso_8859(n) {
+        Some(e) => CharacterTable::SingleByte(e),
+        None => CharacterTable::Unsupported,
+    };
+    match data.first().cloned() {
+        None => (CharacterTable::Iso6937, 0),
+        Some(b) if b >= 0x20 => (CharacterTable::Iso6937, 0),
+        // 0x01 is ISO/IEC 8859-5 through to 0x0B which is ISO/IEC 8859-15, there is no part 12.
+        Some(b) if (0x01..=0x0B).contains(&b) => (single_byte(b + 4), 1),
+        Some(0x10) => {
+            if data.len() < 3 || data[1] != 0x00 { (CharacterTable::Unsupported, data.len().min(3)) }
+            else { (single_byte(data[2]), 3) }
+        },
+        Some(0x11) => (CharacterTable::Utf16, 1),
+        Some(0x12) => (CharacterTable::MultiByte(encoding_rs::EUC_KR), 1),
+        Some(0x13) => (CharacterTable::MultiByte(encoding_rs::GBK), 1),
+        Some(0x14) => (CharacterTable::MultiByte(encoding_rs::BIG5), 1),
+        Some(0x15) => (CharacterTable::Utf8, 1),
+        // 0x1F is followed by an encoding_type_id, e.g. the Freesat Huffman compression,
+        // none of which are supported.
+        Some(0x1F) => (CharacterTable::Unsupported, data.len().min(2)),
+        // The other values are reserved, assume the default table for the rest of the string.
+        Some(_) => (CharacterTable::Iso6937, 1),
+    }
+}
+
+/// The characters of the default table, ISO/IEC 6937 as amended by EN 300 468 Figure A.1,
+/// for the bytes 0xA0 to 0xFF. The non-spacing diacritical marks 0xC1 to 0xCF are
+/// handled separately, unused positions are '\0'.
+const ISO_6937_UPPER_HALF: [char; 96] = [
+    '\u{A0}', '¡', '¢', '£', '€', '¥', '#', '§', '¤', '‘', '“', '«', '←', '↑', '→', '↓',
+    '°', '±', '²', '³', '×', 'µ', '¶', '·', '÷', '’', '”', '»', '¼', '½', '¾', '¿',
+    '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0', '\0',
+    '―', '¹', '®', '©', '™', '♪', '¬', '¦', '\0', '\0', '\0', '\0', '⅛', '⅜', '⅝', '⅞',
+    'Ω', 'Æ', 'Đ', 'ª', 'Ħ', '\0', 'Ĳ', 'Ŀ', 'Ł', 'Ø', 'Œ', 'º', 'Þ', 'Ŧ', 'Ŋ', 'ŉ',
+    'ĸ', 'æ', 'đ', 'ð', 'ħ', 'ı', 'ĳ', 'ŀ', 'ł', 'ø', 'œ', 'ß', 'þ', 'ŧ', 'ŋ', '\u{AD}',
+];
+
+/// For each non-spacing diacritical mark of the default table: the byte, the Unicode
+/// combining character, the base letters that have a precomposed form, and the
+/// precomposed forms.
+const ISO_6937_DIACRITICS: [(u8, char, &str, &str); 13] = [
+    (0xC1, '\u{300}', "AEIOUaeiou", "ÀÈÌÒÙàèìòù"),
+    (0xC2, '\u{301}', "ACEILNORSUYZacegilnorsuyz", "ÁĆÉÍĹŃÓŔŚÚÝŹáćéǵíĺńóŕśúýź"),
+    (0xC3, '\u{302}', "ACEGHIJOSUWYaceghijosuwy", "ÂĈÊĜĤÎĴÔŜÛŴŶâĉêĝĥîĵôŝûŵŷ"),
+    (0xC4, '\u{303}', "AINOUainou", "ÃĨÑÕŨãĩñõũ"),
+    (0xC5, '\u{304}', "AEIOUaeiou", "ĀĒĪŌŪāēīōū"),
+    (0xC6, '\u{306}', "AGUagu", "ĂĞŬăğŭ"),
+    (0xC7, '\u{307}', "CEGIZcegz", "ĊĖĠİŻċėġż"),
+    (0xC8, '\u{308}', "AEIOUYaeiouy", "ÄËÏÖÜŸäëïöüÿ"),
+    (0xCA, '\u{30A}', "AUau", "ÅŮåů"),
+    (0xCB, '\u{327}', "CGKLNRSTcgklnrst", "ÇĢĶĻŅŖŞŢçģķļņŗşţ"),
+    (0xCD, '\u{30B}', "OUou", "ŐŰőű"),
+    (0xCE, '\u{328}', "AEIUaeiu", "ĄĘĮŲąęįų"),
+    (0xCF, '\u{30C}', "CDELNRSTZcdelnrstz", "ČĎĚĽŇŘŠŤŽčďěľňřšťž"),
+];
+
+/// Apply a diacritical mark of the default table to a base character, using the
+/// precomposed character if there is one.
+fn apply_diacritic(mark: u8, base: char) -> String {
+    match ISO_6937_DIACRITICS.iter().find(|(b, _, _, _)| *b == mark) {
+        Some((_, combining, bases, composed)) => {
+            match bases.chars().position(|c| c == base) {
+                Some(i) => composed.chars().nth(i).unwrap().to_string(),
+                None => format!("{}{}", base, combining),
+            }
+        },
+        None => base.to_string(),
+    }
+}
+
+/// Deal with a control code, as a value in the range 0x80 to 0x9F, by pushing
+/// its replacement, if any, onto the string.
+fn push_control_code(s: &mut String, code: u32) {
+    if code == 0x8A {
+        s.push('\n');
+    }
+}
+
+/// Decode a string that uses the default table.
+fn decode_iso_6937(data: &[u8]) -> String {
+    let mut s = String::new();
+    let mut i = 0;
+    while i < data.len() {
+        let b = data[i];
+        i += 1;
+        match b {
+            0x00..=0x1F | 0x7F => {},
+            0x20..=0x7E => s.push(b as char),
+            0x80..=0x9F => push_control_code(&mut s, b as u32),
+            0xC1..=0xCF => {
+                // A non-spacing diacritical mark precedes the character it applies to.
+                if i < data.len() && (0x20..0x7F).contains(&data[i]) {
+                    s.push_str(&apply_diacritic(b, data[i] as char));
+                    i += 1;
+                }
+            },
+            _ => {
+                let c = ISO_6937_UPPER_HALF[(b - 0xA0) as usize];
+                if c != '\0' {
+                    s.push(c);
+                }
+            },
+        }
+    }
+    s
+}
+
+/// Decode a string that uses a single byte table, the control codes are dealt with
+/// here and the runs of other bytes decoded using the encoding.
+fn decode_single_byte(data: &[u8], encoding: &'static Encoding) -> String {
+    let mut s = String::new();
+    let mut start = 0;
+    for (i, &b) in data.iter().enumerate() {
+        if (0x80..=0x9F).contains(&b) {
+            s.push_str(&encoding.decode_without_bom_handling(&data[start..i]).0);
+            push_control_code(&mut s, b as u32);
+            start = i + 1;
+        }
+    }
+    s.push_str(&encoding.decode_without_bom_handling(&data[start..]).0);
+    s
+}
+
+/// Deal with the control codes in an already decoded string. For UTF-8 these are the
+/// C1 control characters, for the two byte tables they are 0xE080 to 0xE09F.
+fn replace_control_codes(text: &str) -> String {
+    let mut s = String::new();
+    for c in text.chars() {
+        match c as u32 {
+            code @ 0x80..=0x9F => push_control_code(&mut s, code),
+            code @ 0xE080..=0xE09F => push_control_code(&mut s, code - 0xE000),
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+/// Return the bytes at the start of a string that select its character table, there are
+/// none if the string uses the default table.
+pub fn character_table_selector(data: &[u8]) -> &[u8] {
+    &data[..select_character_table(data).1]
+}
+
+/// Decode a string from a DVB SI table, the first bytes may select the character table.
+///
+/// Strings that use an unsupported character table decode to an empty string.
+pub fn decode(data: &[u8]) -> String {
+    let (table, count) = select_character_table(data);
+    let data = &data[count..];
+    let s = match table {
+        CharacterTable::Iso6937 => decode_iso_6937(data),
+        CharacterTable::SingleByte(encoding) => decode_single_byte(data, encoding),
+        CharacterTable::MultiByte(encoding) => replace_control_codes(&encoding.decode_without_bom_handling(data).0),
+        CharacterTable::Utf16 => replace_control_codes(&encoding_rs::UTF_16BE.decode_without_bom_handling(data).0),
+        CharacterTable::Utf8 => replace_control_codes(&String::from_utf8_lossy(data)),
+        CharacterTable::Unsupported => String::new(),
+    };
+    // Some broadcasters pad strings with nulls.
+    s.trim_end_matches('\0').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diacritic_tables_are_consistent() {
+        for (_, _, bases, composed) in ISO_6937_DIACRITICS.iter() {
+            assert_eq!(bases.chars().count(), composed.chars().count());
+        }
+    }
+
+    #[test]
+    fn default_table_ascii() {
+        assert_eq!(decode(b"BBC News at Six"), "BBC News at Six");
+        assert_eq!(decode(b""), "");
+    }
+
+    #[test]
+    fn default_table_with_diacritics_and_symbols() {
+        assert_eq!(decode(&[b'C', b'a', b'f', 0xC2, b'e', b' ', 0xA3, b'5']), "Café £5");
+        assert_eq!(decode(&[0xC8, b'O', b'k', b'o']), "Öko");
+        assert_eq!(decode(&[0xCF, b'S', b'k', b'o', b'd', 0xC1, b'a']), "Škodà");
+        // No precomposed form, so a combining character is used.
+        assert_eq!(decode(&[0xCA, b'x']), "x\u{30A}");
+        assert_eq!(decode(&[b'S', b't', b'r', b'a', 0xFB, b'e']), "Straße");
+    }
+
+    // The expected strings of this test are the output of the ISO/IEC 6937 converter of
+    // glibc, `iconv -f ISO_6937 -t UTF-8`, a reading of the standard independent of this
+    // one. The bytes left out are the unused positions and the ones where EN 300 468
+    // Figure A.1 differs from glibc: 0xA4 and 0xA6, which glibc does not convert, and
+    // 0xD0 and 0xE2, which glibc maps to U+2014 and U+00D0 rather than U+2015 and U+0110.
+    #[test]
+    fn default_table_agrees_with_glibc() {
+        let data = b"\xA0\xA1\xA2\xA3\xA5\xA7\xA8\xA9\xAA\xAB\xAC\xAD\xAE\xAF\xB0\xB1\xB2\xB3\xB4\xB5\xB6\xB7\xB8\xB9\xBA\xBB\xBC\xBD\xBE\xBF\
+            \xD1\xD2\xD3\xD4\xD5\xD6\xD7\xDC\xDD\xDE\xDF\xE0\xE1\xE3\xE4\xE6\xE7\xE8\xE9\xEA\xEB\xEC\xED\xEE\xEF\
+            \xF0\xF1\xF2\xF3\xF4\xF5\xF6\xF7\xF8\xF9\xFA\xFB\xFC\xFD\xFE\xFF";
+        assert_eq!(decode(data), "\u{A0}¡¢£¥§¤‘“«←↑→↓°±²³×µ¶·÷’”»¼½¾¿¹®©™♪¬¦⅛⅜⅝⅞ΩÆªĦĲĿŁØŒºÞŦŊŉĸæđðħıĳŀłøœßþŧŋ\u{AD}");
+        let data = b"\xC1a\xC1e\xC1i\xC1o\xC1u\xC1A\xC1E\xC1I\xC1O\xC1U\
+            \xC2a\xC2c\xC2e\xC2i\xC2l\xC2n\xC2o\xC2r\xC2s\xC2u\xC2y\xC2z\xC2A\xC2C\xC2E\xC2I\xC2L\xC2N\xC2O\xC2R\xC2S\xC2U\xC2Y\xC2Z\
+            \xC3a\xC3c\xC3e\xC3g\xC3h\xC3i\xC3j\xC3o\xC3s\xC3u\xC3w\xC3y\xC3A\xC3C\xC3E\xC3G\xC3H\xC3I\xC3J\xC3O\xC3S\xC3U\xC3W\xC3Y\
+            \xC4a\xC4i\xC4n\xC4o\xC4u\xC4A\xC4I\xC4N\xC4O\xC4U\xC5a\xC5e\xC5i\xC5o\xC5u\xC5A\xC5E\xC5I\xC5O\xC5U\
+            \xC6a\xC6g\xC6u\xC6A\xC6G\xC6U\xC7c\xC7e\xC7g\xC7z\xC7C\xC7E\xC7G\xC7I\xC7Z\
+            \xC8a\xC8e\xC8i\xC8o\xC8u\xC8y\xC8A\xC8E\xC8I\xC8O\xC8U\xC8Y\xCAa\xCAu\xCAA\xCAU\
+            \xCBc\xCBg\xCBk\xCBl\xCBn\xCBr\xCBs\xCBt\xCBC\xCBG\xCBK\xCBL\xCBN\xCBR\xCBS\xCBT\
+            \xCDo\xCDu\xCDO\xCDU\xCEa\xCEe\xCEi\xCEu\xCEA\xCEE\xCEI\xCEU\
+            \xCFc\xCFd\xCFe\xCFl\xCFn\xCFr\xCFs\xCFt\xCFz\xCFC\xCFD\xCFE\xCFL\xCFN\xCFR\xCFS\xCFT\xCFZ";
+        assert_eq!(decode(data), "àèìòùÀÈÌÒÙáćéíĺńóŕśúýźÁĆÉÍĹŃÓŔŚÚÝŹâĉêĝĥîĵôŝûŵŷÂĈÊĜĤÎĴÔŜÛŴŶãĩñõũÃĨÑÕŨāēīōūĀĒĪŌŪăğŭĂĞŬ\
+            ċėġżĊĖĠİŻäëïöüÿÄËÏÖÜŸåůÅŮçģķļņŗşţÇĢĶĻŅŖŞŢőűŐŰąęįųĄĘĮŲčďěľňřšťžČĎĚĽŇŘŠŤŽ");
+    }
+
+    #[test]
+    fn control_codes_are_handled() {
+        assert_eq!(decode(&[0x86, b'N', b'e', b'w', b's', 0x87, b':', b' ', b'A', 0x8A, b'B']), "News: A\nB");
+        assert_eq!(decode(&[0x05, 0x86, b'T', 0xFC, b'r', 0x87]), "Tür");
+        assert_eq!(decode(&[0x15, 0xC2, 0x86, b'E', 0xC2, 0x87, 0xC2, 0x8A, b'F']), "E\nF");
+        assert_eq!(decode(&[0x11, 0xE0, 0x86, 0x00, b'G', 0xE0, 0x8A, 0x00, b'H']), "G\nH");
+    }
+
+    #[test]
+    fn iso_8859_tables() {
+        // ISO/IEC 8859-5, Cyrillic.
+        assert_eq!(decode(&[0x01, 0xBF, 0xD5, 0xE0, 0xD2, 0xEB, 0xD9]), "Первый");
+        // ISO/IEC 8859-7, Greek.
+        assert_eq!(decode(&[0x03, 0xC5, 0xD1, 0xD4]), "ΕΡΤ");
+        // ISO/IEC 8859-9, Turkish, as given by 0x05.
+        assert_eq!(decode(&[0x05, b'T', 0xDC, b'R', b'K']), "TÜRK");
+        // ISO/IEC 8859-2 selected using the three byte form.
+        assert_eq!(decode(&[0x10, 0x00, 0x02, b'C', 0xE8, b'T']), "CčT");
+        // ISO/IEC 8859-1 selected using the three byte form.
+        assert_eq!(decode(&[0x10, 0x00, 0x01, b'A', 0xE9]), "Aé");
+    }
+
+    #[test]
+    fn utf8_and_utf16_tables() {
+        assert_eq!(decode(&[0x15, 0xE6, 0x97, 0xA5, 0xE6, 0x9C, 0xAC]), "日本");
+        assert_eq!(decode(&[0x11, 0x65, 0xE5, 0x67, 0x2C]), "日本");
+        assert_eq!(decode(&[0x15, b'A', b'B', 0x00, 0x00]), "AB");
+    }
+
+    #[test]
+    fn unsupported_tables_decode_to_empty_string() {
+        assert_eq!(decode(&[0x1F, 0x01, 0x23, 0x45]), "");
+        assert_eq!(decode(&[0x08, b'A']), "");
+        assert_eq!(decode(&[0x10, 0x00, 0x0C, b'A']), "");
+    }
+}
@@ -0,0 +1,403 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The tuning parameters of a channel in typed form.
+//!
+//! DVBv5 channels files, as used by the GStreamer dvbsrc plugin and written by
+//! dvbv5-scan, give the tuning parameters of each channel as key/value pairs. The values
+//! of the enumerated keys are the names used by the Linux DVB API, the position of a
+//! value in the list of values of a key being the value of the corresponding Linux DVB
+//! API enumeration, and so of the dvbsrc property.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+macro_rules! create_dvbv5_enumeration {
+    ($type_name:ident { $($variant:ident => $text:expr),+ $(,)? }) => {
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+        pub enum $type_name { $($variant),+ }
+
+        impl $type_name {
+            const VALUES: &'static [($type_name, &'static str)] = &[$(($type_name::$variant, $text)),+];
+
+            /// Return the value given its text in a DVBv5 file, ignoring case.
+            pub fn from_dvbv5(text: &str) -> Option<$type_name> {
+                let text = text.trim();
+                Self::VALUES.iter().find(|(_, t)| t.eq_ignore_ascii_case(text)).map(|(v, _)| *v)
+            }
+
+            /// Return the value given the name of its Linux DVB API enumeration constant, as
+            /// used in zap channels files, ignoring case.
+            pub fn from_linux_name(name: &str) -> Option<$type_name> {
+                let name = name.trim();
+                Self::VALUES.iter().find(|(v, _)| format!("{:?}", v).eq_ignore_ascii_case(name)).map(|(v, _)| *v)
+            }
+
+            /// Return the text of the value as written in a DVBv5 file.
+            pub fn to_dvbv5(self) -> &'static str {
+                Self::VALUES.iter().find(|(v, _)| *v == self).unwrap().1
+            }
+
+            /// Return the value of the Linux DVB API enumeration, and so of the dvbsrc property.
+            pub fn index(self) -> usize {
+                Self::VALUES.iter().position(|(v, _)| *v == self).unwrap()
+            }
+        }
+    }
+}
+
+create_dvbv5_enumeration!(DeliverySystem {
+    UNDEFINED => "UNDEFINED",
+    DVBC_ANNEX_A => "DVBC/ANNEX_A",
+    DVBC_ANNEX_B => "DVBC/ANNEX_B",
+    DVBT => "DVBT",
+    DSS => "DSS",
+    DVBS => "DVBS",
+    DVBS2 => "DVBS2",
+    DVBH => "DVBH",
+    ISDBT => "ISDBT",
+    ISDBS => "ISDBS",
+    ISDBC => "ISDBC",
+    ATSC => "ATSC",
+    ATSCMH => "ATSCMH",
+    DTMB => "DTMB",
+    CMMB => "CMMB",
+    DAB => "DAB",
+    DVBT2 => "DVBT2",
+    TURBO => "TURBO",
+    DVBC_ANNEX_C => "DVBC/ANNEX_C",
+});
+
+// The Linux DVB API name, as shown to the user.
+impl fmt::Display for DeliverySystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+create_dvbv5_enumeration!(Modulation {
+    QPSK => "QPSK",
+    QAM_16 => "QAM/16",
+    QAM_32 => "QAM/32",
+    QAM_64 => "QAM/64",
+    QAM_128 => "QAM/128",
+    QAM_256 => "QAM/256",
+    QAM_AUTO => "QAM/AUTO",
+    VSB_8 => "VSB/8",
+    VSB_16 => "VSB/16",
+    PSK_8 => "PSK/8",
+    APSK_16 => "APSK/16",
+    APSK_32 => "APSK/32",
+    DQPSK => "DQPSK",
+    QAM_4_NR => "QAM/4_NR",
+});
+
+create_dvbv5_enumeration!(CodeRate {
+    FEC_NONE => "NONE",
+    FEC_1_2 => "1/2",
+    FEC_2_3 => "2/3",
+    FEC_3_4 => "3/4",
+    FEC_4_5 => "4/5",
+    FEC_5_6 => "5/6",
+    FEC_6_7 => "6/7",
+    FEC_7_8 => "7/8",
+    FEC_8_9 => "8/9",
+    FEC_AUTO => "AUTO",
+    FEC_3_5 => "3/5",
+    FEC_9_10 => "9/10",
+    FEC_2_5 => "2/5",
+});
+
+create_dvbv5_enumeration!(GuardInterval {
+    GUARD_INTERVAL_1_32 => "1/32",
+    GUARD_INTERVAL_1_16 => "1/16",
+    GUARD_INTERVAL_1_8 => "1/8",
+    GUARD_INTERVAL_1_4 => "1/4",
+    GUARD_INTERVAL_AUTO => "AUTO",
+    GUARD_INTERVAL_1_128 => "1/128",
+    GUARD_INTERVAL_19_128 => "19/128",
+    GUARD_INTERVAL_19_256 => "19/256",
+    GUARD_INTERVAL_PN420 => "PN420",
+    GUARD_INTERVAL_PN595 => "PN595",
+    GUARD_INTERVAL_PN945 => "PN945",
+});
+
+create_dvbv5_enumeration!(TransmissionMode {
+    TRANSMISSION_MODE_2K => "2K",
+    TRANSMISSION_MODE_8K => "8K",
+    TRANSMISSION_MODE_AUTO => "AUTO",
+    TRANSMISSION_MODE_4K => "4K",
+    TRANSMISSION_MODE_1K => "1K",
+    TRANSMISSION_MODE_16K => "16K",
+    TRANSMISSION_MODE_32K => "32K",
+    TRANSMISSION_MODE_C1 => "C1",
+    TRANSMISSION_MODE_C3780 => "C3780",
+});
+
+create_dvbv5_enumeration!(Hierarchy {
+    HIERARCHY_NONE => "NONE",
+    HIERARCHY_1 => "1",
+    HIERARCHY_2 => "2",
+    HIERARCHY_4 => "4",
+    HIERARCHY_AUTO => "AUTO",
+});
+
+create_dvbv5_enumeration!(Inversion {
+    INVERSION_OFF => "OFF",
+    INVERSION_ON => "ON",
+    INVERSION_AUTO => "AUTO",
+});
+
+// The dvbsrc polarity property is the first letter, not the index.
+create_dvbv5_enumeration!(Polarization {
+    HORIZONTAL => "HORIZONTAL",
+    VERTICAL => "VERTICAL",
+    LEFT => "LEFT",
+    RIGHT => "RIGHT",
+});
+
+/// The tuning parameters and PIDs of a channel, everything in a DVBv5 channels file
+/// entry other than the SERVICE_ID. A key whose value cannot be parsed, or that is not
+/// one of those with a field, is kept in `other` so nothing is lost.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Tuning {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery_system: Option<DeliverySystem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modulation: Option<Modulation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth_hz: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inversion: Option<Inversion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code_rate_hp: Option<CodeRate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code_rate_lp: Option<CodeRate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guard_interval: Option<GuardInterval>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transmission_mode: Option<TransmissionMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hierarchy: Option<Hierarchy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol_rate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inner_fec: Option<CodeRate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub polarization: Option<Polarization>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_id: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transport_id: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_pid: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audio_pids: Vec<u16>,
+    /// The PID_xx keys, PIDs of other streams by stream type, xx being the stream type in hex.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub other_pids: BTreeMap<String, Vec<u16>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub other: BTreeMap<String, String>,
+}
+
+/// Return a list of PIDs given the space separated text of them.
+fn parse_pids(text: &str) -> Option<Vec<u16>> {
+    text.split_whitespace().map(|p| p.parse::<u16>().ok()).collect()
+}
+
+fn pids_text(pids: &[u16]) -> String {
+    pids.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+impl Tuning {
+    /// Construct from the key/value pairs of a DVBv5 channels file entry. The
+    /// SERVICE_ID and LCN are not part of the tuning, they are ignored.
+    pub fn from_properties<'a, I: IntoIterator<Item=(&'a str, &'a str)>>(properties: I) -> Tuning {
+        let mut tuning = Tuning::default();
+        for (key, value) in properties {
+            let key = key.trim();
+            let value = value.trim();
+            let is_parsed = match key {
+                "SERVICE_ID" | "LCN" => true,
+                "DELIVERY_SYSTEM" => { tuning.delivery_system = DeliverySystem::from_dvbv5(value); tuning.delivery_system.is_some() },
+                "FREQUENCY" => { tuning.frequency = value.parse().ok(); tuning.frequency.is_some() },
+                "MODULATION" => { tuning.modulation = Modulation::from_dvbv5(value); tuning.modulation.is_some() },
+                "BANDWIDTH_HZ" => { tuning.bandwidth_hz = value.parse().ok(); tuning.bandwidth_hz.is_some() },
+                "INVERSION" => { tuning.inversion = Inversion::from_dvbv5(value); tuning.inversion.is_some() },
+                "CODE_RATE_HP" => { tuning.code_rate_hp = CodeRate::from_dvbv5(value); tuning.code_rate_hp.is_some() },
+                "CODE_RATE_LP" => { tuning.code_rate_lp = CodeRate::from_dvbv5(value); tuning.code_rate_lp.is_some() },
+                "GUARD_INTERVAL" => { tuning.guard_interval = GuardInterval::from_dvbv5(value); tuning.guard_interval.is_some() },
+                "TRANSMISSION_MODE" => { tuning.transmission_mode = TransmissionMode::from_dvbv5(value); tuning.transmission_mode.is_some() },
+                "HIERARCHY" => { tuning.hierarchy = Hierarchy::from_dvbv5(value); tuning.hierarchy.is_some() },
+                "SYMBOL_RATE" => { tuning.symbol_rate = value.parse().ok(); tuning.symbol_rate.is_some() },
+                "INNER_FEC" => { tuning.inner_fec = CodeRate::from_dvbv5(value); tuning.inner_fec.is_some() },
+                "POLARIZATION" => { tuning.polarization = Polarization::from_dvbv5(value); tuning.polarization.is_some() },
+                "STREAM_ID" => { tuning.stream_id = value.parse().ok(); tuning.stream_id.is_some() },
+                "NETWORK_ID" => { tuning.network_id = value.parse().ok(); tuning.network_id.is_some() },
+                "TRANSPORT_ID" => { tuning.transport_id = value.parse().ok(); tuning.transport_id.is_some() },
+                "VIDEO_PID" => { tuning.video_pid = value.parse().ok(); tuning.video_pid.is_some() },
+                "AUDIO_PID" => match parse_pids(value) {
+                    Some(pids) => { tuning.audio_pids = pids; true },
+                    None => false,
+                },
+                _ if key.starts_with("PID_") => match parse_pids(value) {
+                    Some(pids) => { tuning.other_pids.insert(key[4..].to_string(), pids); true },
+                    None => false,
+                },
+                _ => false,
+            };
+            if ! is_parsed {
+                tuning.other.insert(key.to_string(), value.to_string());
+            }
+        }
+        tuning
+    }
+
+    /// Return the key/value pairs of a DVBv5 channels file entry, other than the
+    /// SERVICE_ID, in the order dvbv5-scan writes them.
+    pub fn to_properties(&self) -> Vec<(String, String)> {
+        let mut result = Vec::new();
+        let mut push = |key: &str, value: Option<String>| if let Some(value) = value {
+            result.push((key.to_string(), value));
+        };
+        push("NETWORK_ID", self.network_id.map(|v| v.to_string()));
+        push("TRANSPORT_ID", self.transport_id.map(|v| v.to_string()));
+        push("VIDEO_PID", self.video_pid.map(|v| v.to_string()));
+        push("AUDIO_PID", if self.audio_pids.is_empty() { None } else { Some(pids_text(&self.audio_pids)) });
+        for (stream_type, pids) in self.other_pids.iter() {
+            push(&format!("PID_{}", stream_type), Some(pids_text(pids)));
+        }
+        push("FREQUENCY", self.frequency.map(|v| v.to_string()));
+        push("MODULATION", self.modulation.map(|v| v.to_dvbv5().to_string()));
+        push("BANDWIDTH_HZ", self.bandwidth_hz.map(|v| v.to_string()));
+        push("INVERSION", self.inversion.map(|v| v.to_dvbv5().to_string()));
+        push("CODE_RATE_HP", self.code_rate_hp.map(|v| v.to_dvbv5().to_string()));
+        push("CODE_RATE_LP", self.code_rate_lp.map(|v| v.to_dvbv5().to_string()));
+        push("GUARD_INTERVAL", self.guard_interval.map(|v| v.to_dvbv5().to_string()));
+        push("TRANSMISSION_MODE", self.transmission_mode.map(|v| v.to_dvbv5().to_string()));
+        push("HIERARCHY", self.hierarchy.map(|v| v.to_dvbv5().to_string()));
+        push("SYMBOL_RATE", self.symbol_rate.map(|v| v.to_string()));
+        push("INNER_FEC", self.inner_fec.map(|v| v.to_dvbv5().to_string()));
+        push("POLARIZATION", self.polarization.map(|v| v.to_dvbv5().to_string()));
+        push("STREAM_ID", self.stream_id.map(|v| v.to_string()));
+        for (key, value) in self.other.iter() {
+            push(key, Some(value.clone()));
+        }
+        push("DELIVERY_SYSTEM", self.delivery_system.map(|v| v.to_dvbv5().to_string()));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: [(&str, &str); 21] = [
+        ("SERVICE_ID", "4164"),
+        ("NETWORK_ID", "9018"),
+        ("TRANSPORT_ID", "4164"),
+        ("VIDEO_PID", "101"),
+        ("AUDIO_PID", "102 106"),
+        ("PID_0b", "7219 7201"),
+        ("PID_06", "152 105"),
+        ("FREQUENCY", "490000000"),
+        ("MODULATION", "QAM/64"),
+        ("BANDWIDTH_HZ", "8000000"),
+        ("INVERSION", "AUTO"),
+        ("CODE_RATE_HP", "2/3"),
+        ("CODE_RATE_LP", "AUTO"),
+        ("GUARD_INTERVAL", "1/32"),
+        ("TRANSMISSION_MODE", "8K"),
+        ("HIERARCHY", "NONE"),
+        ("STREAM_ID", "0"),
+        ("LNB", "UNIVERSAL"),
+        ("SAT_NUMBER", "lots"),
+        ("AUDIO_PID_COUNT", "x"),
+        ("DELIVERY_SYSTEM", "DVBT2"),
+    ];
+
+    #[test]
+    fn enumeration_values_have_their_linux_dvb_api_value() {
+        assert_eq!(DeliverySystem::from_dvbv5("DVBT"), Some(DeliverySystem::DVBT));
+        assert_eq!(DeliverySystem::DVBT2.index(), 16);
+        assert_eq!(DeliverySystem::DVBC_ANNEX_A.to_dvbv5(), "DVBC/ANNEX_A");
+        assert_eq!(Modulation::from_dvbv5("qam/256"), Some(Modulation::QAM_256));
+        assert_eq!(Modulation::QAM_64.index(), 3);
+        assert_eq!(CodeRate::from_dvbv5("AUTO").map(|c| c.index()), Some(9));
+        assert_eq!(GuardInterval::from_dvbv5("19/256").map(|g| g.index()), Some(7));
+        assert_eq!(TransmissionMode::from_dvbv5("32K").map(|t| t.index()), Some(6));
+        assert_eq!(Hierarchy::from_dvbv5("NONE"), Some(Hierarchy::HIERARCHY_NONE));
+        assert_eq!(Inversion::INVERSION_AUTO.index(), 2);
+        assert_eq!(Modulation::from_dvbv5("QAM/48"), None);
+    }
+
+    #[test]
+    fn enumeration_values_are_found_from_their_linux_names() {
+        assert_eq!(Modulation::from_linux_name("QAM_64"), Some(Modulation::QAM_64));
+        assert_eq!(CodeRate::from_linux_name("FEC_2_3"), Some(CodeRate::FEC_2_3));
+        assert_eq!(GuardInterval::from_linux_name("guard_interval_1_32"), Some(GuardInterval::GUARD_INTERVAL_1_32));
+        assert_eq!(TransmissionMode::from_linux_name("TRANSMISSION_MODE_8K"), Some(TransmissionMode::TRANSMISSION_MODE_8K));
+        assert_eq!(Inversion::from_linux_name("INVERSION_AUTO"), Some(Inversion::INVERSION_AUTO));
+        assert_eq!(Hierarchy::from_linux_name("NONE"), None);
+    }
+
+    #[test]
+    fn tuning_keeps_every_key() {
+        let tuning = Tuning::from_properties(ENTRY.iter().cloned());
+        assert_eq!(tuning.delivery_system, Some(DeliverySystem::DVBT2));
+        assert_eq!(tuning.frequency, Some(490000000));
+        assert_eq!(tuning.modulation, Some(Modulation::QAM_64));
+        assert_eq!(tuning.code_rate_lp, Some(CodeRate::FEC_AUTO));
+        assert_eq!(tuning.stream_id, Some(0));
+        assert_eq!(tuning.video_pid, Some(101));
+        assert_eq!(tuning.audio_pids, vec![102, 106]);
+        assert_eq!(tuning.other_pids.get("0b"), Some(&vec![7219, 7201]));
+        assert_eq!(tuning.other.get("LNB").map(|s| s.as_str()), Some("UNIVERSAL"));
+        assert_eq!(tuning.other.len(), 3);
+        let properties = tuning.to_properties();
+        assert_eq!(properties.len(), ENTRY.len() - 1);
+        let mut expected = ENTRY[1..].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<(String, String)>>();
+        let mut actual = properties.clone();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+        assert_eq!(properties[0], ("NETWORK_ID".to_string(), "9018".to_string()));
+        assert_eq!(properties.last(), Some(&("DELIVERY_SYSTEM".to_string(), "DVBT2".to_string())));
+        assert_eq!(Tuning::from_properties(properties.iter().map(|(k, v)| (k.as_str(), v.as_str()))), tuning);
+    }
+
+    #[test]
+    fn tuning_round_trips_through_yaml() {
+        let tuning = Tuning::from_properties(ENTRY.iter().cloned());
+        let text = serde_yaml::to_string(&tuning).unwrap();
+        assert!(text.contains("modulation: QAM_64"));
+        assert!(! text.contains("symbol_rate"));
+        assert_eq!(serde_yaml::from_str::<Tuning>(&text).unwrap(), tuning);
+        assert_eq!(serde_yaml::from_str::<Tuning>("{}").unwrap(), Tuning::default());
+    }
+}
@@ -0,0 +1,245 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Tracking of the EIT sections received so that repeats can be ignored.
+//!
+//! EN 300 468 has the EIT sent as sub-tables, one per service and table_id, each with a
+//! version_number that changes whenever the content of the sub-table changes. The
+//! sections of the schedule sub-tables, table_id 0x50 to 0x6F, are grouped in segments
+//! of eight, each segment covering three hours, with segment_last_section_number
+//! giving the last section of a segment actually used. The sections are repeated in
+//! a carousel so most sections received have been received before, those can be
+//! skipped without looking at the events. When the version of a schedule sub-table
+//! changes the events of the previous version that are not in the new version are
+//! stale, they are dropped once all the sections of the new version are received. An
+//! event moves from one schedule sub-table of a service to another as the days pass, so
+//! an event is only stale if it is in none of the schedule sub-tables of the service.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::epg_event::EventKey;
+
+/// The identity of a service, cf. `EventKey`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ServiceKey {
+    pub original_network_id: u16,
+    pub transport_stream_id: u16,
+    pub service_id: u16,
+}
+
+/// The header fields of an EIT section that say where it fits in the EIT.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SectionHeader {
+    pub service: ServiceKey,
+    pub table_id: u8,
+    pub version_number: u8,
+    pub section_number: u8,
+    pub last_section_number: u8,
+    pub segment_last_section_number: u8,
+    pub last_table_id: u8,
+}
+
+/// The first table_id of the schedule sub-tables for the actual transport stream.
+const ACTUAL_SCHEDULE_TABLE_ID: u8 = 0x50;
+
+/// The first table_id of the schedule sub-tables for other transport streams.
+const OTHER_SCHEDULE_TABLE_ID: u8 = 0x60;
+
+/// The number of sections in a segment of a schedule sub-table.
+const SECTIONS_PER_SEGMENT: u8 = 8;
+
+fn is_schedule(table_id: u8) -> bool { (ACTUAL_SCHEDULE_TABLE_ID..=0x6F).contains(&table_id) }
+
+/// The sections received of the current version of a sub-table.
+#[derive(Debug)]
+struct SubTable {
+    version_number: u8,
+    last_section_number: u8,
+    last_table_id: u8,
+    segment_last_section_numbers: [Option<u8>; 32],
+    sections: BTreeMap<u8, Vec<EventKey>>,
+    // The events of previous versions, checked for staleness when the sub-table is complete.
+    previous_events: HashSet<EventKey>,
+}
+
+impl SubTable {
+    fn new(header: &SectionHeader) -> SubTable {
+        SubTable {
+            version_number: header.version_number,
+            last_section_number: header.last_section_number,
+            last_table_id: header.last_table_id,
+            segment_last_section_numbers: [None; 32],
+            sections: BTreeMap::new(),
+            previous_events: HashSet::new(),
+        }
+    }
+
+    /// Have all the sections of the sub-table been received. Every segment up to the
+    /// last section has at least one section, an empty segment has one with no events.
+    fn is_complete(&self) -> bool {
+        (0..=self.last_section_number / SECTIONS_PER_SEGMENT).all(|segment| {
+            match self.segment_last_section_numbers[segment as usize] {
+                Some(segment_last_section_number) => {
+                    let first = segment * SECTIONS_PER_SEGMENT;
+                    let last = segment_last_section_number.max(first).min(self.last_section_number);
+                    (first..=last).all(|n| self.sections.contains_key(&n))
+                },
+                None => false,
+            }
+        })
+    }
+}
+
+/// The state of the EIT sub-tables of all the services seen.
+#[derive(Debug, Default)]
+pub struct EitSections {
+    sub_tables: HashMap<(ServiceKey, u8), SubTable>,
+}
+
+impl EitSections {
+    pub fn new() -> EitSections { EitSections::default() }
+
+    /// Is the section one that has not been received before, either a section not
+    /// received or a section of a new version of its sub-table.
+    pub fn is_new(&self, header: &SectionHeader) -> bool {
+        match self.sub_tables.get(&(header.service, header.table_id)) {
+            Some(sub_table) => sub_table.version_number != header.version_number || ! sub_table.sections.contains_key(&header.section_number),
+            None => true,
+        }
+    }
+
+    /// Record the receipt of a section with the keys of the events in it.
+    ///
+    /// Return the keys of the events that are stale: events of a previous version of a
+    /// schedule sub-table, now that the current version is complete, that are not in the
+    /// current version of any schedule sub-table of the service.
+    pub fn add_section(&mut self, header: &SectionHeader, event_keys: &[EventKey]) -> Vec<EventKey> {
+        let key = (header.service, header.table_id);
+        let sub_table = self.sub_tables.entry(key).or_insert_with(|| SubTable::new(header));
+        if sub_table.version_number != header.version_number {
+            let mut previous_events = std::mem::take(&mut sub_table.previous_events);
+            previous_events.extend(sub_table.sections.values().flatten());
+            *sub_table = SubTable::new(header);
+            sub_table.previous_events = previous_events;
+        }
+        sub_table.last_section_number = header.last_section_number;
+        sub_table.last_table_id = header.last_table_id;
+        sub_table.segment_last_section_numbers[(header.section_number / SECTIONS_PER_SEGMENT) as usize] = Some(header.segment_last_section_number);
+        sub_table.sections.insert(header.section_number, event_keys.to_vec());
+        if ! is_schedule(header.table_id) || sub_table.previous_events.is_empty() || ! sub_table.is_complete() {
+            return Vec::new();
+        }
+        let previous_events = std::mem::take(&mut sub_table.previous_events);
+        let current_events = self.sub_tables.iter()
+            .filter(|((service, table_id), _)| *service == header.service && is_schedule(*table_id))
+            .flat_map(|(_, sub_table)| sub_table.sections.values().flatten().cloned())
+            .collect::<HashSet<EventKey>>();
+        let mut stale = previous_events.into_iter().filter(|k| ! current_events.contains(k)).collect::<Vec<EventKey>>();
+        stale.sort();
+        stale
+    }
+
+    /// Have all the sections of all the schedule sub-tables of a service been received.
+    /// The schedule for the actual transport stream and for other transport streams are
+    /// each complete if all the sub-tables up to the last_table_id are complete.
+    pub fn is_schedule_complete(&self, service: &ServiceKey) -> bool {
+        let mut has_schedule = false;
+        for first_table_id in [ACTUAL_SCHEDULE_TABLE_ID, OTHER_SCHEDULE_TABLE_ID].iter() {
+            let first = match self.sub_tables.get(&(*service, *first_table_id)) {
+                Some(sub_table) => sub_table,
+                None => continue,
+            };
+            has_schedule = true;
+            let is_complete = (*first_table_id..=first.last_table_id.max(*first_table_id))
+                .all(|table_id| self.sub_tables.get(&(*service, table_id)).map_or(false, |t| t.is_complete()));
+            if ! is_complete {
+                return false;
+            }
+        }
+        has_schedule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICE: ServiceKey = ServiceKey{original_network_id: 9018, transport_stream_id: 4164, service_id: 4164};
+
+    fn header(table_id: u8, version_number: u8, section_number: u8, last_section_number: u8, segment_last_section_number: u8, last_table_id: u8) -> SectionHeader {
+        SectionHeader{service: SERVICE, table_id, version_number, section_number, last_section_number, segment_last_section_number, last_table_id}
+    }
+
+    fn keys(event_ids: &[u16]) -> Vec<EventKey> {
+        event_ids.iter().map(|event_id| EventKey{original_network_id: 9018, transport_stream_id: 4164, service_id: 4164, event_id: *event_id}).collect()
+    }
+
+    #[test]
+    fn repeated_sections_are_not_new() {
+        let mut sections = EitSections::new();
+        let section = header(0x4E, 3, 0, 1, 1, 0x4E);
+        assert!(sections.is_new(&section));
+        sections.add_section(&section, &keys(&[1]));
+        assert!(! sections.is_new(&section));
+        assert!(sections.is_new(&header(0x4E, 3, 1, 1, 1, 0x4E)));
+        assert!(sections.is_new(&header(0x4E, 4, 0, 1, 1, 0x4E)));
+    }
+
+    #[test]
+    fn schedule_is_complete_when_all_segments_of_all_sub_tables_are_received() {
+        let mut sections = EitSections::new();
+        // Two sub-tables, the first with two segments: sections 0–1 and 8, the second
+        // with an empty segment.
+        sections.add_section(&header(0x50, 1, 0, 15, 1, 0x51), &keys(&[1]));
+        sections.add_section(&header(0x50, 1, 8, 15, 8, 0x51), &keys(&[3]));
+        assert!(! sections.is_schedule_complete(&SERVICE));
+        sections.add_section(&header(0x50, 1, 1, 15, 1, 0x51), &keys(&[2]));
+        assert!(! sections.is_schedule_complete(&SERVICE));
+        sections.add_section(&header(0x51, 1, 0, 0, 0, 0x51), &[]);
+        assert!(sections.is_schedule_complete(&SERVICE));
+        sections.add_section(&header(0x50, 2, 0, 15, 1, 0x51), &keys(&[1]));
+        assert!(! sections.is_schedule_complete(&SERVICE));
+    }
+
+    #[test]
+    fn events_not_in_a_new_version_are_stale_once_it_is_complete() {
+        let mut sections = EitSections::new();
+        sections.add_section(&header(0x50, 1, 0, 1, 1, 0x50), &keys(&[1, 2]));
+        sections.add_section(&header(0x50, 1, 1, 1, 1, 0x50), &keys(&[3]));
+        assert!(sections.add_section(&header(0x50, 2, 0, 1, 1, 0x50), &keys(&[2])).is_empty());
+        assert_eq!(sections.add_section(&header(0x50, 2, 1, 1, 1, 0x50), &keys(&[3, 4])), keys(&[1]));
+        // Present/following events are expected to move between sections.
+        sections.add_section(&header(0x4E, 1, 0, 1, 1, 0x4E), &keys(&[5]));
+        sections.add_section(&header(0x4E, 1, 1, 1, 1, 0x4E), &keys(&[6]));
+        sections.add_section(&header(0x4E, 2, 0, 1, 1, 0x4E), &keys(&[6]));
+        assert!(sections.add_section(&header(0x4E, 2, 1, 1, 1, 0x4E), &keys(&[7])).is_empty());
+    }
+
+    #[test]
+    fn events_moving_between_sub_tables_are_not_stale() {
+        let mut sections = EitSections::new();
+        sections.add_section(&header(0x50, 1, 0, 0, 0, 0x51), &keys(&[1]));
+        sections.add_section(&header(0x51, 1, 0, 0, 0, 0x51), &keys(&[2, 3]));
+        // As the days pass event 2 moves from the second sub-table to the first.
+        assert!(sections.add_section(&header(0x50, 2, 0, 0, 0, 0x51), &keys(&[1, 2])).is_empty());
+        assert_eq!(sections.add_section(&header(0x51, 2, 0, 0, 0, 0x51), &keys(&[4])), keys(&[3]));
+    }
+}
@@ -0,0 +1,489 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use lazy_static::lazy_static;
+use serde_yaml;
+use xdg;
+
+use crate::data_files;
+use crate::eit_sections::ServiceKey;
+use crate::epg_event::{Event, EventKey, IMPORTED_NETWORK_ID};
+
+/// How long, in seconds, an event is kept in the guide after it has finished.
+const EXPIRY_GRACE_PERIOD: i64 = 3600;
+
+// A singleton of the programme guide currently known.
+//
+// This is initialised from the Me TV EPG data cache file and then updated as
+// EIT sections are received. The data is written to the cache file by the EPG
+// manager as and when there have been changes.
+lazy_static! {
+    static ref EPG_DATA: RwLock<BTreeMap<EventKey, Event>> = RwLock::new(initialise_epg_data());
+}
+
+// The present and following events of each service, as given by the EIT present/following
+// sections, keyed by service since service ids are only unique within a transport stream.
+// This is transient data so is not cached.
+lazy_static! {
+    static ref PRESENT_FOLLOWING: RwLock<HashMap<ServiceKey, (Option<EventKey>, Option<EventKey>)>> = RwLock::new(HashMap::new());
+}
+
+// Has there been a change to EPG_DATA since the cache file was last written.
+static IS_DIRTY: AtomicBool = AtomicBool::new(false);
+
+/// Construct the value to be used to initialise `EPG_DATA`.
+///
+/// Events that finished a while ago are not worth loading so they are dropped.
+fn initialise_epg_data() -> BTreeMap<EventKey, Event> {
+    match read_epg_data_cache(&epg_data_cache_path()) {
+        Some(events) => {
+            let now = now();
+            events.into_iter()
+                .filter(|e| e.end_time() + EXPIRY_GRACE_PERIOD > now)
+                .map(|e| (e.key, e))
+                .collect()
+        },
+        None => BTreeMap::new(),
+    }
+}
+
+/// The current time as seconds since the Unix epoch, UTC.
+pub fn now() -> i64 {
+    time::now_utc().to_timespec().sec
+}
+
+/// Initialise the EPG data system, reading the cache file if it exists so that
+/// the guide is available before any tuning happens.
+pub fn init() {
+    let epg_data = EPG_DATA.read().unwrap();
+    println!("Read {} events from the EPG data cache.", epg_data.len());
+}
+
+/// Return a `Box<Path>` to the Me TV EPG data cache file using the XDG directory structure.
+pub fn epg_data_cache_path() -> Box<Path> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_cache_home();
+    path_buf.push("epg_data.yml");
+    path_buf.into_boxed_path()
+}
+
+/// The keys of the imported events of a service that overlap the given time period.
+///
+/// Imported events have the smallest original_network_id so they are all at the start
+/// of the map.
+fn overlapping_imported_events(epg_data: &BTreeMap<EventKey, Event>, service_id: u16, start_time: i64, end_time: i64) -> Vec<EventKey> {
+    let first_broadcast_key = EventKey{original_network_id: IMPORTED_NETWORK_ID + 1, transport_stream_id: 0, service_id: 0, event_id: 0};
+    epg_data.range(..first_broadcast_key)
+        .filter(|(k, e)| k.service_id == service_id && e.start_time < end_time && e.end_time() > start_time)
+        .map(|(k, _)| *k)
+        .collect()
+}
+
+/// Add an event to the EPG data, replacing any event with the same key. Events received
+/// off air replace any imported events of the service they overlap.
+///
+/// Return `true` if a change was made to the EPG data, `false` otherwise.
+pub fn add_event(event: Event) -> bool {
+    let mut epg_data = EPG_DATA.write().unwrap();
+    match epg_data.get(&event.key) {
+        Some(e) if *e == event => false,
+        _ => {
+            if ! event.is_imported() {
+                for key in overlapping_imported_events(&epg_data, event.key.service_id, event.start_time, event.end_time()) {
+                    epg_data.remove(&key);
+                }
+            }
+            epg_data.insert(event.key, event);
+            IS_DIRTY.store(true, Ordering::SeqCst);
+            true
+        },
+    }
+}
+
+/// Remove the event with the given key, if there is one.
+///
+/// Return `true` if a change was made to the EPG data, `false` otherwise.
+pub fn remove_event(key: &EventKey) -> bool {
+    let rv = EPG_DATA.write().unwrap().remove(key).is_some();
+    if rv {
+        IS_DIRTY.store(true, Ordering::SeqCst);
+    }
+    rv
+}
+
+/// Replace all the imported events with the given ones. An event received off air is
+/// taken to be more accurate than an imported one so imported events that overlap
+/// events of the same service received off air are dropped.
+///
+/// Return the number of imported events added.
+pub fn replace_imported_events(events: Vec<Event>) -> usize {
+    let mut epg_data = EPG_DATA.write().unwrap();
+    epg_data.retain(|_, e| ! e.is_imported());
+    let mut broadcast_times = HashMap::new();
+    for e in epg_data.values() {
+        broadcast_times.entry(e.key.service_id).or_insert_with(Vec::new).push((e.start_time, e.end_time()));
+    }
+    let mut count = 0;
+    for event in events.into_iter().filter(|e| e.is_imported()) {
+        let is_overlapped = broadcast_times.get(&event.key.service_id)
+            .map_or(false, |times| times.iter().any(|(start_time, end_time)| *start_time < event.end_time() && *end_time > event.start_time));
+        if ! is_overlapped {
+            epg_data.insert(event.key, event);
+            count += 1;
+        }
+    }
+    IS_DIRTY.store(true, Ordering::SeqCst);
+    count
+}
+
+/// Return the event with the given key if there is one.
+pub fn get_event(key: &EventKey) -> Option<Event> {
+    EPG_DATA.read().unwrap().get(key).cloned()
+}
+
+/// Return all the events for a given service, ordered by start time.
+pub fn get_events_for_service(service_id: u16) -> Vec<Event> {
+    let epg_data = EPG_DATA.read().unwrap();
+    let mut events = epg_data.values()
+        .filter(|e| e.key.service_id == service_id)
+        .cloned()
+        .collect::<Vec<Event>>();
+    events.sort_by_key(|e| e.start_time);
+    events
+}
+
+/// Return all the events that overlap the time period given, ordered by start time.
+pub fn get_events_between(start_time: i64, end_time: i64) -> Vec<Event> {
+    let epg_data = EPG_DATA.read().unwrap();
+    let mut events = epg_data.values()
+        .filter(|e| e.start_time < end_time && e.end_time() > start_time)
+        .cloned()
+        .collect::<Vec<Event>>();
+    events.sort_by_key(|e| e.start_time);
+    events
+}
+
+/// Record the present event of a service as given by an EIT present/following section.
+///
+/// Return `true` if this is a change, `false` otherwise.
+pub fn set_present_event(service: ServiceKey, key: Option<EventKey>) -> bool {
+    let mut present_following = PRESENT_FOLLOWING.write().unwrap();
+    let entry = present_following.entry(service).or_insert((None, None));
+    let rv = entry.0 != key;
+    entry.0 = key;
+    rv
+}
+
+/// Record the following event of a service as given by an EIT present/following section.
+///
+/// Return `true` if this is a change, `false` otherwise.
+pub fn set_following_event(service: ServiceKey, key: Option<EventKey>) -> bool {
+    let mut present_following = PRESENT_FOLLOWING.write().unwrap();
+    let entry = present_following.entry(service).or_insert((None, None));
+    let rv = entry.1 != key;
+    entry.1 = key;
+    rv
+}
+
+/// Return the present and following events of a service.
+///
+/// The EIT present/following data is used if it has been received, otherwise the
+/// events are inferred from the schedule data using the given time.
+pub fn get_present_following(service: &ServiceKey, time: i64) -> (Option<Event>, Option<Event>) {
+    let (present_key, following_key) = match PRESENT_FOLLOWING.read().unwrap().get(service) {
+        Some(keys) => *keys,
+        None => (None, None),
+    };
+    let events = get_events_for_service(service.service_id);
+    let present = match present_key {
+        Some(key) => get_event(&key),
+        None => events.iter().find(|e| e.is_on_at(time)).cloned(),
+    };
+    let following = match following_key {
+        Some(key) => get_event(&key),
+        None => {
+            let after = match present {
+                Some(ref p) => p.end_time(),
+                None => time,
+            };
+            events.iter().find(|e| e.start_time >= after).cloned()
+        },
+    };
+    (present, following)
+}
+
+/// Remove the events that finished before the given time, less a grace period.
+///
+/// Return `true` if a change was made to the EPG data, `false` otherwise.
+pub fn remove_expired_events(time: i64) -> bool {
+    let mut epg_data = EPG_DATA.write().unwrap();
+    let count = epg_data.len();
+    epg_data.retain(|_, e| e.end_time() + EXPIRY_GRACE_PERIOD > time);
+    let rv = epg_data.len() != count;
+    if rv {
+        IS_DIRTY.store(true, Ordering::SeqCst);
+    }
+    rv
+}
+
+/// Write the EPG data to the cache file if there have been changes since the last write.
+/// If the write fails the data stays dirty so that it is tried again.
+pub fn write_epg_data_cache_if_dirty() {
+    if IS_DIRTY.swap(false, Ordering::SeqCst) {
+        let events = EPG_DATA.read().unwrap().values().cloned().collect::<Vec<Event>>();
+        if let Err(e) = write_epg_data_cache(&epg_data_cache_path(), &events) {
+            println!("{}", e);
+            IS_DIRTY.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Write the EPG data to a cache file.
+fn write_epg_data_cache(path: &Path, events: &[Event]) -> Result<(), String> {
+    data_files::write_yaml(path, events)
+}
+
+/// Read the EPG data given a path and return the result.
+fn read_epg_data_cache(path: &Path) -> Option<Vec<Event>> {
+    match File::open(path) {
+        Ok(mut f) => {
+            let mut buffer = String::new();
+            match f.read_to_string(&mut buffer) {
+                Ok(_) => {
+                    match serde_yaml::from_str::<Vec<Event>>(&buffer) {
+                        Ok(x) => Some(x),
+                        Err(e) => {
+                            println!("Failed to deserialise {} – {}", path.to_str().unwrap(), e);
+                            None
+                        },
+                    }
+                },
+                Err(e) => {
+                    println!("Failed to read {} – {}", path.to_str().unwrap(), e);
+                    None
+                },
+            }
+        },
+        Err(e) => {
+            println!("Failed to open {} – {}", path.to_str().unwrap(), e);
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Mutex;
+
+    use lazy_static::lazy_static;
+    use tempfile;
+
+    use crate::epg_event::RunningStatus;
+
+    use super::*;
+
+    fn service(service_id: u16) -> ServiceKey {
+        ServiceKey { original_network_id: 9018, transport_stream_id: 4164, service_id }
+    }
+
+    fn create_event(service_id: u16, event_id: u16, start_time: i64, duration: u32, title: &str) -> Event {
+        Event {
+            key: EventKey {
+                original_network_id: 9018,
+                transport_stream_id: 4164,
+                service_id,
+                event_id,
+            },
+            start_time,
+            duration,
+            title: title.to_string(),
+            short_text: "".to_string(),
+            extended_text: "".to_string(),
+            items: vec![],
+            genre: None,
+            running_status: RunningStatus::NotRunning,
+            programme_crid: None,
+            series_crid: None,
+        }
+    }
+
+    // EPG_DATA is a global so tests that use it must not run concurrently,
+    // cf. the comment in channels_data.rs.
+    lazy_static! {
+        static ref TEST_LOCK: Mutex<bool> = Mutex::new(false);
+    }
+
+    #[test]
+    fn running_status_from_u8() {
+        assert_eq!(RunningStatus::from(0), RunningStatus::Undefined);
+        assert_eq!(RunningStatus::from(4), RunningStatus::Running);
+        assert_eq!(RunningStatus::from(7), RunningStatus::Undefined);
+    }
+
+    #[test]
+    fn event_is_on_at() {
+        let event = create_event(4164, 1, 1000, 60, "News");
+        assert!(!event.is_on_at(999));
+        assert!(event.is_on_at(1000));
+        assert!(event.is_on_at(1059));
+        assert!(!event.is_on_at(1060));
+    }
+
+    #[test]
+    fn event_fraction_elapsed() {
+        let event = create_event(4164, 1, 1000, 100, "News");
+        assert_eq!(event.fraction_elapsed_at(900), 0.0);
+        assert_eq!(event.fraction_elapsed_at(1025), 0.25);
+        assert_eq!(event.fraction_elapsed_at(1200), 1.0);
+    }
+
+    #[test]
+    fn synopsis_combines_short_text_extended_text_and_items() {
+        let mut event = create_event(4164, 1, 1000, 60, "Film");
+        event.short_text = "A thriller.".to_string();
+        assert_eq!(event.synopsis(), "A thriller.");
+        event.extended_text = "A man is chased across America.".to_string();
+        event.items = vec![("Director".to_string(), "Alfred Hitchcock".to_string())];
+        assert_eq!(event.synopsis(), "A thriller.\n\nA man is chased across America.\n\nDirector: Alfred Hitchcock");
+        event.extended_text = "A thriller. A man is chased across America.".to_string();
+        assert_eq!(event.synopsis(), "A thriller. A man is chased across America.\n\nDirector: Alfred Hitchcock");
+    }
+
+    #[test]
+    fn present_following_inferred_from_schedule_if_not_received() {
+        let _test_lock = TEST_LOCK.lock().unwrap();
+        let start_time = now();
+        add_event(create_event(5, 1, start_time - 600, 1800, "Present"));
+        add_event(create_event(5, 2, start_time + 1200, 1800, "Following"));
+        let (present, following) = get_present_following(&service(5), start_time);
+        assert_eq!(present.unwrap().title, "Present");
+        assert_eq!(following.unwrap().title, "Following");
+    }
+
+    #[test]
+    fn present_following_taken_from_eit_if_received() {
+        let _test_lock = TEST_LOCK.lock().unwrap();
+        let start_time = now();
+        let overrunning = create_event(6, 1, start_time - 1800, 1200, "Overrunning");
+        let delayed = create_event(6, 2, start_time - 600, 1800, "Delayed");
+        add_event(overrunning.clone());
+        add_event(delayed.clone());
+        assert!(set_present_event(service(6), Some(overrunning.key)));
+        assert!(!set_present_event(service(6), Some(overrunning.key)));
+        assert!(set_following_event(service(6), Some(delayed.key)));
+        let (present, following) = get_present_following(&service(6), start_time);
+        assert_eq!(present.unwrap().title, "Overrunning");
+        assert_eq!(following.unwrap().title, "Delayed");
+        // The same service id on another transport stream is another service.
+        let other_service = ServiceKey { transport_stream_id: 4222, ..service(6) };
+        let (present, _) = get_present_following(&other_service, start_time);
+        assert_eq!(present.unwrap().title, "Delayed");
+    }
+
+    #[test]
+    fn add_event_only_changes_data_when_event_changes() {
+        let _test_lock = TEST_LOCK.lock().unwrap();
+        let start_time = now();
+        let event = create_event(1, 1, start_time, 1800, "News");
+        assert!(add_event(event.clone()));
+        assert!(!add_event(event.clone()));
+        let mut new_event = event.clone();
+        new_event.title = "Weather".to_string();
+        assert!(add_event(new_event.clone()));
+        assert_eq!(get_event(&event.key).unwrap(), new_event);
+    }
+
+    #[test]
+    fn events_are_selected_by_service_and_time() {
+        let _test_lock = TEST_LOCK.lock().unwrap();
+        let start_time = now();
+        add_event(create_event(2, 2, start_time + 1800, 1800, "Second"));
+        add_event(create_event(2, 1, start_time, 1800, "First"));
+        add_event(create_event(3, 1, start_time, 3600, "Other"));
+        let events = get_events_for_service(2);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].title, "First");
+        assert_eq!(events[1].title, "Second");
+        let titles = get_events_between(start_time + 1800, start_time + 3600).iter().map(|e| e.title.clone()).collect::<Vec<String>>();
+        assert!(titles.contains(&"Second".to_string()));
+        assert!(titles.contains(&"Other".to_string()));
+        assert!(!titles.contains(&"First".to_string()));
+    }
+
+    #[test]
+    fn expired_events_are_removed() {
+        let _test_lock = TEST_LOCK.lock().unwrap();
+        let start_time = now();
+        let event = create_event(4, 1, start_time - 3 * EXPIRY_GRACE_PERIOD, 60, "Old");
+        add_event(event.clone());
+        assert!(remove_expired_events(start_time));
+        assert_eq!(get_event(&event.key), None);
+    }
+
+    #[test]
+    fn removed_events_are_gone() {
+        let _test_lock = TEST_LOCK.lock().unwrap();
+        let event = create_event(8, 1, now(), 1800, "Cancelled");
+        add_event(event.clone());
+        assert!(remove_event(&event.key));
+        assert!(!remove_event(&event.key));
+        assert_eq!(get_event(&event.key), None);
+    }
+
+    #[test]
+    fn broadcast_events_replace_overlapping_imported_events() {
+        let _test_lock = TEST_LOCK.lock().unwrap();
+        let start_time = now();
+        let imported = |event_id, start_time, title| {
+            let mut event = create_event(7, event_id, start_time, 1800, title);
+            event.key.original_network_id = IMPORTED_NETWORK_ID;
+            event.key.transport_stream_id = 0;
+            event
+        };
+        add_event(create_event(7, 1, start_time, 1800, "Broadcast"));
+        assert_eq!(replace_imported_events(vec![imported(1, start_time + 900, "Overlapped"), imported(2, start_time + 1800, "Imported"), imported(3, start_time + 3600, "Later")]), 2);
+        let titles = || get_events_for_service(7).iter().map(|e| e.title.clone()).collect::<Vec<String>>();
+        assert_eq!(titles(), vec!["Broadcast", "Imported", "Later"]);
+        add_event(create_event(7, 2, start_time + 1800, 1200, "Broadcast Later"));
+        assert_eq!(titles(), vec!["Broadcast", "Broadcast Later", "Later"]);
+        assert_eq!(replace_imported_events(vec![]), 0);
+        assert_eq!(titles(), vec!["Broadcast", "Broadcast Later"]);
+    }
+
+    #[test]
+    fn write_and_read_epg_data_cache() {
+        let events = vec![
+            create_event(4164, 1, 1000, 60, "News"),
+            create_event(4287, 2, 2000, 120, "Weather"),
+        ];
+        let file = tempfile::NamedTempFile::new().unwrap();
+        write_epg_data_cache(file.path(), &events).unwrap();
+        assert_eq!(read_epg_data_cache(file.path()).unwrap(), events);
+    }
+}
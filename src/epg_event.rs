@@ -0,0 +1,123 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The events, i.e. programmes, of the programme guide.
+//!
+//! These are the types shared by the programme guide of me-tv, cf. epg_data.rs, and the
+//! recording tools that need to know about events.
+
+use serde_derive::{Serialize, Deserialize};
+
+/// The identity of an event as defined by EN 300 468: the event_id is only unique
+/// within a service, and a service is only identified by the triple of
+/// original_network_id, transport_stream_id, and service_id.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct EventKey {
+    pub original_network_id: u16,
+    pub transport_stream_id: u16,
+    pub service_id: u16,
+    pub event_id: u16,
+}
+
+/// The original_network_id of the key of events that were not received off air but
+/// imported, e.g. from an XMLTV file. 0x0000 is reserved by ETSI TS 101 162 so no
+/// broadcast event has it.
+pub const IMPORTED_NETWORK_ID: u16 = 0;
+
+/// The running status of an event as per EN 300 468 Table 6.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum RunningStatus {
+    Undefined,
+    NotRunning,
+    StartsInAFewSeconds,
+    Pausing,
+    Running,
+    ServiceOffAir,
+}
+
+impl std::convert::From<u8> for RunningStatus {
+    fn from(value: u8) -> RunningStatus {
+        match value {
+            1 => RunningStatus::NotRunning,
+            2 => RunningStatus::StartsInAFewSeconds,
+            3 => RunningStatus::Pausing,
+            4 => RunningStatus::Running,
+            5 => RunningStatus::ServiceOffAir,
+            _ => RunningStatus::Undefined,
+        }
+    }
+}
+
+/// An event, i.e. a programme, as assembled from the EIT sections.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub key: EventKey,
+    pub start_time: i64, // Seconds since the Unix epoch, UTC.
+    pub duration: u32, // Seconds.
+    pub title: String,
+    pub short_text: String,
+    #[serde(default)]
+    pub extended_text: String,
+    #[serde(default)]
+    pub items: Vec<(String, String)>, // (item_description, item) pairs, e.g. cast and director.
+    pub genre: Option<String>,
+    pub running_status: RunningStatus,
+    #[serde(default)]
+    pub programme_crid: Option<String>,
+    #[serde(default)]
+    pub series_crid: Option<String>,
+}
+
+impl Event {
+    /// The time, seconds since the Unix epoch UTC, at which the event finishes.
+    pub fn end_time(&self) -> i64 { self.start_time + self.duration as i64 }
+
+    /// Is the event being broadcast at the given time, seconds since the Unix epoch UTC.
+    pub fn is_on_at(&self, time: i64) -> bool { self.start_time <= time && time < self.end_time() }
+
+    /// The fraction, in the range 0.0 to 1.0, of the event that has been broadcast at
+    /// the given time, seconds since the Unix epoch UTC.
+    pub fn fraction_elapsed_at(&self, time: i64) -> f64 {
+        if self.duration == 0 || time <= self.start_time { 0.0 }
+        else if time >= self.end_time() { 1.0 }
+        else { (time - self.start_time) as f64 / self.duration as f64 }
+    }
+
+    /// Was the event imported rather than received in an EIT section.
+    pub fn is_imported(&self) -> bool { self.key.original_network_id == IMPORTED_NETWORK_ID }
+
+    /// The full description of the event: the short text, then the extended text, then
+    /// the item/description pairs one per line.
+    pub fn synopsis(&self) -> String {
+        let mut parts = Vec::new();
+        // Some broadcasters repeat the short text as the start of the extended text.
+        if ! self.short_text.is_empty() && ! self.extended_text.starts_with(&self.short_text) {
+            parts.push(self.short_text.clone());
+        }
+        if ! self.extended_text.is_empty() {
+            parts.push(self.extended_text.clone());
+        }
+        if ! self.items.is_empty() {
+            parts.push(self.items.iter().map(|(d, i)| format!("{}: {}", d, i)).collect::<Vec<String>>().join("\n"));
+        }
+        parts.join("\n\n")
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Collecting the programme guide of every multiplex in the background.
+//!
+//! The EIT of a multiplex is only received whilst a frontend is tuned to it, so the
+//! guide for the channels of other multiplexes is mostly empty. When enabled in the
+//! preferences the harvester tunes a free frontend to each multiplex of the channels
+//! file in turn, every few hours, passing the EIT sections received during the dwell
+//! time to the EPG manager just as a channel window does. A frontend is free if no
+//! channel window is using it and no scheduled recording needs it during the dwell
+//! time. Opening a channel window on the frontend being used stops the harvest of the
+//! multiplex immediately.
+
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use gst;
+use gst::prelude::*;
+
+use gst_mpegts;
+
+use crate::channels_data::{encode_to_mrl, get_channel_names_by_multiplex};
+use crate::dvb_devices::{FrontendId, get_installed_frontends};
+use crate::epg_data;
+use crate::preferences;
+use crate::recording_jobs;
+use crate::tuner_allocation;
+
+/// The time between checks of whether a harvest is due or a frontend has become free.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The delay after start up before the first harvest, so as not to get in the way of
+/// a channel window being opened at start up.
+const START_UP_DELAY: Duration = Duration::from_secs(120);
+
+/// The use of the frontends. Both are guarded by the one lock so that a frontend cannot
+/// be claimed by a channel window between the harvester choosing it and starting to use it.
+#[derive(Default)]
+struct Frontends {
+    // The frontends being used by channel windows.
+    in_use: Vec<FrontendId>,
+    // The frontend and pipeline of the harvest in progress if there is one.
+    harvest: Option<(FrontendId, gst::Pipeline)>,
+}
+
+lazy_static! {
+    static ref FRONTENDS: Mutex<Frontends> = Mutex::new(Frontends::default());
+}
+
+/// Record that a channel window is using a frontend, stopping any harvest using it.
+pub fn claim_frontend(fei: &FrontendId) { // Used in control_window_button.rs
+    let mut frontends = FRONTENDS.lock().unwrap();
+    frontends.in_use.push(fei.clone());
+    if frontends.harvest.as_ref().map_or(false, |(frontend, _)| frontend == fei) {
+        let (_, pipeline) = frontends.harvest.take().unwrap();
+        pipeline.set_state(gst::State::Null).expect("Could not stop the EPG harvest pipeline.");
+    }
+}
+
+/// Record that a channel window has finished using a frontend.
+pub fn release_frontend(fei: &FrontendId) { // Used in control_window_button.rs
+    let mut frontends = FRONTENDS.lock().unwrap();
+    if let Some(index) = frontends.in_use.iter().position(|f| f == fei) {
+        frontends.in_use.remove(index);
+    }
+}
+
+/// Return a frontend that can be used for the dwell time starting now, if there is one.
+fn find_free_frontend(in_use: &[FrontendId], dwell_time: Duration) -> Option<FrontendId> {
+    let jobs = match recording_jobs::read_recording_jobs(&recording_jobs::recording_jobs_path()) {
+        Ok(jobs) => jobs.jobs,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        },
+    };
+    let now = epg_data::now();
+    tuner_allocation::free_frontend(&jobs, &get_installed_frontends(), in_use, now, now + dwell_time.as_secs() as i64)
+}
+
+/// Create a pipeline tuned to the multiplex of a channel. Only the sections are wanted so
+/// the stream is discarded.
+fn create_pipeline(channel_name: &str, frontend: &FrontendId) -> Option<gst::Pipeline> {
+    //
+    // Construct the GStreamer graph described by:
+    //
+    //    gst-launch-1.0 dvbbasebin <properties set from channel> ! fakesink
+    //
+    let pipeline = gst::Pipeline::new(None);
+    let source = match gst::Element::make_from_uri(gst::URIType::Src, &encode_to_mrl(&channel_name.to_string()), None) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Cannot make a source for {} – {}", channel_name, e);
+            return None;
+        },
+    };
+    source.set_property("adapter", &(frontend.adapter as i32)).expect("Could not set adapter number on dvbsrc element");
+    source.set_property("frontend", &(frontend.frontend as i32)).expect("Could not set frontend number of dvbsrc element");
+    let sink = gst::ElementFactory::make("fakesink", None).expect("cannot make a fakesink");
+    pipeline.add_many(&[&source, &sink]).expect("could not add elements to pipeline");
+    gst::Element::link_many(&[&source, &sink]).expect("could not link elements in pipeline");
+    Some(pipeline)
+}
+
+/// Is the harvest using the given pipeline still in progress, a channel window may have
+/// claimed its frontend.
+fn is_harvesting(pipeline: &gst::Pipeline) -> bool {
+    FRONTENDS.lock().unwrap().harvest.as_ref().map_or(false, |(_, p)| p == pipeline)
+}
+
+/// Start harvesting the multiplex of a channel using a free frontend, choosing the
+/// frontend and starting the pipeline under the lock so that no channel window can
+/// claim the frontend in between.
+///
+/// Return `None` if no frontend is free, otherwise the frontend and the pipeline, or
+/// `None` for the pipeline if it could not be created or started.
+fn start_harvest(channel_name: &str, dwell_time: Duration) -> Option<(FrontendId, Option<gst::Pipeline>)> {
+    let mut frontends = FRONTENDS.lock().unwrap();
+    let frontend = find_free_frontend(&frontends.in_use, dwell_time)?;
+    let pipeline = create_pipeline(channel_name, &frontend).and_then(|pipeline| {
+        match pipeline.set_state(gst::State::Playing) {
+            Ok(_) => Some(pipeline),
+            Err(_) => {
+                pipeline.set_state(gst::State::Null).expect("Could not stop the EPG harvest pipeline.");
+                None
+            },
+        }
+    });
+    if let Some(ref pipeline) = pipeline {
+        frontends.harvest = Some((frontend.clone(), pipeline.clone()));
+    }
+    Some((frontend, pipeline))
+}
+
+/// Collect the EIT sections of the multiplex of a channel for the dwell time using the
+/// pipeline started by `start_harvest`.
+///
+/// Return `false` if the harvest was stopped before the end of the dwell time, so the
+/// multiplex should be harvested later.
+fn harvest_multiplex(channel_name: &str, frontend: &FrontendId, pipeline: gst::Pipeline, dwell_time: Duration, to_epg_manager: &Sender<gst_mpegts::Section>) -> bool {
+    let mut is_failed = false;
+    let bus = pipeline.get_bus().expect("Pipeline without bus. Shouldn't happen!");
+    let end = Instant::now() + dwell_time;
+    while ! is_failed && Instant::now() < end && preferences::get_epg_harvest() && is_harvesting(&pipeline) {
+        // Wake up every second so that a claim of the frontend is noticed promptly.
+        let msg = match bus.timed_pop(gst::ClockTime::from_seconds(1)) {
+            Some(msg) => msg,
+            None => continue,
+        };
+        match msg.view() {
+            gst::MessageView::Element(element) => {
+                if element.get_structure().map_or(false, |s| s.get_name() == "eit") {
+                    if let Some(section) = gst_mpegts::Section::from_element(&element) {
+                        to_epg_manager.send(section).unwrap();
+                    }
+                }
+            },
+            gst::MessageView::Error(error) => {
+                // Most likely the frontend is being used by another program.
+                println!("Could not collect the EIT of the multiplex of {} using adapter {} frontend {} – {}",
+                         channel_name, frontend.adapter, frontend.frontend, error.get_error());
+                is_failed = true;
+            },
+            _ => (),
+        }
+    }
+    let mut frontends = FRONTENDS.lock().unwrap();
+    if frontends.harvest.as_ref().map_or(false, |(_, p)| *p == pipeline) {
+        frontends.harvest.take();
+        pipeline.set_state(gst::State::Null).expect("Could not stop the EPG harvest pipeline.");
+    }
+    is_failed || Instant::now() >= end
+}
+
+/// The main dæmon for collecting the programme guide.
+///
+/// Each harvest tunes to every multiplex in turn, waiting for a frontend to be free
+/// when none is. The next harvest is due the harvest interval after the last finished.
+pub fn run(to_epg_manager: Sender<gst_mpegts::Section>) {
+    thread::sleep(START_UP_DELAY);
+    let mut pending: Vec<String> = Vec::new();
+    let mut next_harvest = Instant::now();
+    loop {
+        if ! preferences::get_epg_harvest() {
+            pending.clear();
+        } else if pending.is_empty() && Instant::now() >= next_harvest {
+            // One channel of each multiplex is enough to tune to it.
+            if let Some(multiplexes) = get_channel_names_by_multiplex() {
+                pending = multiplexes.values().rev().filter_map(|names| names.first().cloned()).collect();
+            }
+        }
+        let dwell_time = Duration::from_secs(preferences::get_epg_harvest_dwell_time() as u64);
+        while let Some(channel_name) = pending.last().cloned() {
+            if ! preferences::get_epg_harvest() {
+                break;
+            }
+            let is_finished = match start_harvest(&channel_name, dwell_time) {
+                // A multiplex that cannot be tuned is not retried.
+                Some((_, None)) => true,
+                Some((frontend, Some(pipeline))) => harvest_multiplex(&channel_name, &frontend, pipeline, dwell_time, &to_epg_manager),
+                None => break,
+            };
+            if is_finished {
+                pending.pop();
+                if pending.is_empty() {
+                    next_harvest = Instant::now() + Duration::from_secs(3600 * preferences::get_epg_harvest_interval() as u64);
+                }
+            }
+        }
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
@@ -19,7 +19,10 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::panic;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
 
 use glib;
 use glib::translate::{from_glib, ToGlib};
@@ -28,7 +31,20 @@ use gst;
 use gst_mpegts;
 
 use crate::control_window::Message;
-use crate::channels_data::add_logical_channel_number_for_service_id;
+use crate::channels_data::{add_logical_channel_number_for_service_id, get_channel_name_of_service_id, get_multiplex_of_channel_name};
+use crate::content_identifier::{self, ContentIdentifiers};
+use crate::dvb_devices::{self, FrontendId};
+use crate::dvb_text;
+use crate::eit_sections::{EitSections, SectionHeader, ServiceKey};
+use crate::epg_data;
+use crate::epg_event;
+use crate::extended_event::{self, ExtendedEventDescriptor};
+use crate::preferences;
+use crate::recording_jobs;
+use crate::recording_profiles;
+use crate::recording_rules;
+use crate::tuner_allocation;
+use crate::xmltv;
 
 static PRINT_BAT: bool = false;
 static PRINT_CAT: bool = false;
@@ -43,6 +59,47 @@ static PRINT_TOT: bool = false;
 
 static PRINT_DATA: bool = false;
 
+/// The minimum time between writes of the EPG data cache file.
+const EPG_DATA_CACHE_WRITE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The minimum time between evaluations of the recording rules, EIT sections arrive
+/// many times a second.
+const RECORDING_RULES_EVALUATION_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The minimum time between checks of whether the XMLTV file has changed.
+const XMLTV_FILE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Convert a `gst::DateTime` to seconds since the Unix epoch, `None` if the time is
+/// undefined, as it is for the NVOD reference events, all the bits of the start time
+/// being 1.
+fn unix_time_of(date_time: &gst::DateTime) -> Option<i64> {
+    if ! date_time.has_time() {
+        return None;
+    }
+    date_time.to_g_date_time().map(|d| d.to_unix())
+}
+
+/// Decode `count` strings, each prefixed by a length byte, from the data of a descriptor
+/// starting at position `start`. The data includes the tag and length bytes.
+///
+/// The descriptor parsers of gst_mpegts are not used for strings since they do not
+/// handle all the character tables and can panic on some real data.
+fn decode_length_prefixed_strings(data: &[u8], start: usize, count: usize) -> Option<Vec<String>> {
+    let end = (2 + *data.get(1)? as usize).min(data.len());
+    let mut i = start;
+    let mut strings = Vec::new();
+    for _ in 0..count {
+        let length = *data[..end].get(i)? as usize;
+        let finish = i + 1 + length;
+        if finish > end {
+            return None;
+        }
+        strings.push(dvb_text::decode(&data[(i + 1)..finish]));
+        i = finish;
+    }
+    Some(strings)
+}
+
 fn build_bat(bat: &gst_mpegts::BAT, to_cw: &glib::Sender<Message>) {
     // Do not seem to get any of these on BBC News on Freeview from Crystal Palace.
     if PRINT_BAT {
@@ -67,14 +124,181 @@ fn build_cat(cat: &Vec<gst_mpegts::Descriptor>, to_cw: &glib::Sender<Message>) {
     }
 }
 
-fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
+/// Update the recordings scheduled by event for events of a service whose start time or
+/// duration has changed so that they follow the events.
+fn follow_recorded_events(service_id: u16, moved_events: &[(u16, i64, u32)]) {
+    let channel_name = match get_channel_name_of_service_id(service_id) {
+        Some(channel_name) => channel_name,
+        None => return,
+    };
+    let result = recording_jobs::update_recording_jobs(&recording_jobs::recording_jobs_path(), |jobs| {
+        for (event_id, start_time, duration) in moved_events {
+            recording_jobs::follow_event(&mut jobs.jobs, &channel_name, *event_id, *start_time, *duration);
+        }
+    });
+    if let Err(e) = result {
+        println!("{}", e);
+    }
+}
+
+/// Schedule recordings of the programmes that the recording rules say should be
+/// recorded and that are not already scheduled.
+fn apply_recording_rules() {
+    let rules = match recording_rules::read_recording_rules(&recording_rules::recording_rules_path()) {
+        Ok(rules) => rules,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        },
+    };
+    if rules.is_empty() {
+        return;
+    }
+    let now = epg_data::now();
+    let events = epg_data::get_events_between(now, i64::MAX).into_iter()
+        .filter_map(|event| get_channel_name_of_service_id(event.key.service_id).map(|channel_name| (channel_name, event)))
+        .collect::<Vec<(String, epg_event::Event)>>();
+    let profiles = recording_profiles::get_recording_profiles().unwrap_or_else(|_| recording_profiles::default_profiles());
+    let directory = glib::get_user_special_dir(glib::UserDirectory::Videos)
+        .unwrap_or_else(|| PathBuf::from(std::env::var("HOME").expect("HOME is not set.")));
+    let mut frontends = dvb_devices::get_installed_frontends();
+    if frontends.is_empty() {
+        frontends.push(FrontendId{adapter: 0, frontend: 0});
+    }
+    let episodes_path = recording_rules::scheduled_episodes_path();
+    // The scheduled episodes are read and written whilst the jobs file is locked so they
+    // stay consistent with the jobs.
+    let result = recording_jobs::update_recording_jobs(&recording_jobs::recording_jobs_path(), |jobs| {
+        let mut episodes = recording_rules::read_scheduled_episodes(&episodes_path)?;
+        let recordings = recording_rules::find_recordings(&rules, &events, &jobs.jobs, &episodes, now);
+        let mut changed_jobs = jobs.clone();
+        recording_rules::schedule_recordings(&recordings, &mut changed_jobs, &mut episodes, |rule, channel_name, event, jobs| {
+            let extension = recording_profiles::find_profile(&profiles, &rule.profile).map_or("ts".to_string(), |p| p.extension);
+            let mut job = recording_rules::create_job(rule, channel_name, event, &directory, &extension);
+            match tuner_allocation::assign_frontend(&job, jobs, get_multiplex_of_channel_name, &frontends) {
+                Some(frontend) => {
+                    job.adapter = frontend.adapter;
+                    job.frontend = frontend.frontend;
+                    println!("Scheduling {} on {} for recording rule {}.", &event.title, channel_name, rule.id);
+                    Some(job)
+                },
+                None => {
+                    println!("Cannot schedule {} on {} for recording rule {}, all the frontends are in use at that time.", &event.title, channel_name, rule.id);
+                    None
+                },
+            }
+        });
+        if changed_jobs != *jobs {
+            // The episodes are written before the jobs so that failing to write the jobs
+            // loses the recordings rather than having the episodes scheduled again.
+            recording_rules::write_scheduled_episodes(&episodes_path, &episodes)?;
+            *jobs = changed_jobs;
+        }
+        Ok(())
+    });
+    if let Err(e) = result.and_then(|r| r) {
+        println!("{}", e);
+    }
+}
+
+/// Import the XMLTV file, or remove the imported programmes if there is no XMLTV file.
+///
+/// Return `true` if the import, or the removal, succeeded.
+fn import_xmltv_file(path: &str) -> bool {
+    if path.is_empty() {
+        epg_data::replace_imported_events(Vec::new());
+        return true;
+    }
+    match xmltv::import(Path::new(path)) {
+        Ok(count) => {
+            println!("Imported {} programmes from {}.", count, path);
+            true
+        },
+        Err(e) => {
+            println!("{}", e);
+            false
+        },
+    }
+}
+
+/// Add the events of an EIT section to the EPG data, returning `true` if a change was
+/// made to the EPG data.
+///
+/// Sections already received are ignored, the carousel repeats every section many times.
+/// The CRIDs of the events are given the default authority of the service, if the SDT
+/// saying what that is has not been received yet a section with CRIDs lacking an
+/// authority is not recorded as received so that it is processed again.
+fn build_eit(
+    section: &gst_mpegts::Section, eit: &gst_mpegts::EIT, eit_sections: &mut EitSections,
+    default_authorities: &HashMap<ServiceKey, Option<String>>, to_cw: &glib::Sender<Message>,
+) -> bool {
+    // The service_id of the events in an EIT section is carried as the table_id_extension.
+    let service_id = section.get_subtable_extension();
+    let header = SectionHeader {
+        service: ServiceKey {
+            original_network_id: eit.get_original_network_id(),
+            transport_stream_id: eit.get_transport_stream_id(),
+            service_id,
+        },
+        table_id: section.get_table_id(),
+        version_number: section.get_version_number(),
+        section_number: section.get_section_number(),
+        last_section_number: section.get_last_section_number(),
+        segment_last_section_number: eit.get_segment_last_section_number(),
+        last_table_id: eit.get_last_table_id(),
+    };
+    if ! eit_sections.is_new(&header) {
+        return false;
+    }
+    let default_authority = default_authorities.get(&header.service);
+    let mut lacks_authority = false;
+    // Only present/following sections, table_id 0x4E for the actual transport stream and
+    // 0x4F for other transport streams, carry a running_status, in schedule sections it
+    // is always 0, i.e. undefined.
+    let is_present_following = section.get_table_id() == 0x4E || section.get_table_id() == 0x4F;
+    let mut moved_events = Vec::new();
+    let mut is_epg_changed = false;
     if PRINT_EIT {
-        println!("========  EIT section.");
+        println!("========  EIT section:  service_id = {}, transport_stream_id = {}, original_network_id = {}",
+                 service_id, eit.get_transport_stream_id(), eit.get_original_network_id());
     }
+    let mut event_keys = Vec::new();
     for event in eit.get_events().iter() {
         if PRINT_EIT {
             println!("    EITEvent:  event_id = {:?}", event.get_event_id());
         }
+        let start_time = match unix_time_of(&event.get_start_time()) {
+            Some(start_time) => start_time,
+            None => continue,
+        };
+        let mut epg_event = epg_event::Event {
+            key: epg_event::EventKey {
+                original_network_id: eit.get_original_network_id(),
+                transport_stream_id: eit.get_transport_stream_id(),
+                service_id,
+                event_id: event.get_event_id(),
+            },
+            start_time,
+            duration: event.get_duration(),
+            title: String::new(),
+            short_text: String::new(),
+            extended_text: String::new(),
+            items: Vec::new(),
+            genre: None,
+            running_status: epg_event::RunningStatus::from(event.get_running_status().to_glib() as u8),
+            programme_crid: None,
+            series_crid: None,
+        };
+        if let Some(previous) = epg_data::get_event(&epg_event.key) {
+            if ! is_present_following {
+                epg_event.running_status = previous.running_status;
+            }
+            if previous.start_time != epg_event.start_time || previous.duration != epg_event.duration {
+                moved_events.push((epg_event.key.event_id, epg_event.start_time, epg_event.duration));
+            }
+        }
+        let mut extended_event_descriptors: Vec<ExtendedEventDescriptor> = Vec::new();
+        let mut content_identifiers = ContentIdentifiers::default();
         for d in event.get_descriptors().iter() {
             match d.get_tag() {
                 gst_mpegts::DVBDescriptorType::Component => {
@@ -86,14 +310,39 @@ fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
                 gst_mpegts::DVBDescriptorType::Content => {
                     let c = d.parse_dvb_content().unwrap();
                     for item in c.iter() {
+                        let description = gst_mpegts::content_description(item.get_content_nibble_1().to_glib(), item.get_content_nibble_2());
                         if PRINT_EIT {
-                            println!("            {}", gst_mpegts::content_description(item.get_content_nibble_1().to_glib(), item.get_content_nibble_2()))
+                            println!("            {}", &description)
+                        }
+                        // Only the first, most significant, item is used as the genre.
+                        if epg_event.genre.is_none() {
+                            epg_event.genre = Some(description.to_string());
                         }
                     }
                 },
                 gst_mpegts::DVBDescriptorType::ContentIdentifier => {
-                    if PRINT_EIT {
-                        println!("            Unknown processing technique");
+                    // There is no parser for this descriptor in gst_mpegts.
+                    match content_identifier::parse_content_identifier_descriptor(&d.get_data(), &mut content_identifiers) {
+                        Some(_) => {
+                            if PRINT_EIT {
+                                println!("            ContentIdentifier  {:?}", &content_identifiers);
+                            }
+                        },
+                        None => println!("************  Could not parse a content identifier descriptor."),
+                    }
+                },
+                gst_mpegts::DVBDescriptorType::ExtendedEvent => {
+                    // The data is parsed directly rather than using parse_dvb_extended_event
+                    // since the strings need to be assembled across descriptors before decoding.
+                    match extended_event::parse_extended_event_descriptor(&d.get_data()) {
+                        Some(descriptor) => {
+                            if PRINT_EIT {
+                                println!("            ExtendedEvent  {} of {}, {}",
+                                         &descriptor.descriptor_number, &descriptor.last_descriptor_number, &descriptor.language_code);
+                            }
+                            extended_event_descriptors.push(descriptor);
+                        },
+                        None => println!("************  Could not parse an extended event descriptor."),
                     }
                 },
                 gst_mpegts::DVBDescriptorType::Linkage => {
@@ -103,19 +352,20 @@ fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
                     }
                 }
                 gst_mpegts::DVBDescriptorType::ShortEvent => {
-                    // TODO It seems this can panic
-                    //   assertion failed: !ptr.is_null()', /home/users/russel/.cargo/git/checkouts/glib-928cf7b282977403/3a64675/src/gstring.rs:51:9
-                    //   on real data from Freeview.
-                    //
-                    // See https://gitlab.freedesktop.org/gstreamer/gst-plugins-bad/-/issues/1333
-                    match panic::catch_unwind(|| {
-                        let (language_code, title, blurb) = d.parse_dvb_short_event().unwrap();
-                        if PRINT_EIT {
-                            println!("            {}, {}, {}", &language_code, &title, &blurb);
-                        }
-                    }) {
-                        Ok(_) => {},
-                        Err(_) => println!("************  parse_dvb_short_event panicked, assume there is a 0x1f encoding byte in the string."),
+                    // parse_dvb_short_event panics on some real data from Freeview, see
+                    // https://gitlab.freedesktop.org/gstreamer/gst-plugins-bad/-/issues/1333
+                    // so the data is decoded directly: the language code followed by the
+                    // event name and the text.
+                    let data = d.get_data();
+                    match decode_length_prefixed_strings(&data, 5, 2) {
+                        Some(strings) => {
+                            if PRINT_EIT {
+                                println!("            {}, {}, {}", String::from_utf8_lossy(&data[2..5]), &strings[0], &strings[1]);
+                            }
+                            epg_event.title = strings[0].clone();
+                            epg_event.short_text = strings[1].clone();
+                        },
+                        None => println!("************  Could not decode a short event descriptor."),
                     }
                 },
                 gst_mpegts::DVBDescriptorType::PrivateDataSpecifier => {
@@ -148,12 +398,7 @@ fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
                             }
                             let language_code = String::from_utf8_lossy(&data[i..(i+3)]).to_string();
                             i += 3;
-                            let (encoding, count) = gst_mpegts::select_encoding(data[i..(i+3)].to_vec());
-                            i += count;
-                            let message = match encoding {
-                                Some(e) =>  e.decode(&data[i..]).0.to_string(),
-                                None => "String with language code could not be processed.".to_string(),
-                            };
+                            let message = dvb_text::decode(&data[i..]);
                             if PRINT_EIT {
                                 println!("            MiscDescriptorType::__Unknown({}):  language_code = {}, private_data = {}", &x.to_glib(), &language_code, &message);
                             }
@@ -167,7 +412,48 @@ fn build_eit(eit: &gst_mpegts::EIT, to_cw: &glib::Sender<Message>) {
                 println!("        {:?}", d.get_data());
             }
         }
+        if ! extended_event_descriptors.is_empty() {
+            match extended_event::assemble_synopsis(&extended_event_descriptors, dvb_text::decode) {
+                Some(synopsis) => {
+                    epg_event.extended_text = synopsis.text;
+                    epg_event.items = synopsis.items;
+                },
+                None => println!("************  Incomplete extended event descriptor sequence for event_id {}.", epg_event.key.event_id),
+            }
+        }
+        let content_identifiers = content_identifiers.with_default_authority(default_authority.and_then(|a| a.as_deref()));
+        lacks_authority |= default_authority.is_none() && content_identifiers.lacks_authority();
+        epg_event.programme_crid = content_identifiers.programme_crid;
+        epg_event.series_crid = content_identifiers.series_crid;
+        event_keys.push(epg_event.key);
+        is_epg_changed |= epg_data::add_event(epg_event);
+    }
+    if ! moved_events.is_empty() {
+        follow_recorded_events(service_id, &moved_events);
+    }
+    let was_schedule_complete = eit_sections.is_schedule_complete(&header.service);
+    if ! lacks_authority {
+        for key in eit_sections.add_section(&header, &event_keys) {
+            is_epg_changed |= epg_data::remove_event(&key);
+        }
     }
+    if PRINT_EIT && ! was_schedule_complete && eit_sections.is_schedule_complete(&header.service) {
+        println!("========  EIT schedule complete:  service_id = {}", service_id);
+    }
+    // EIT present/following sections for the actual transport stream have table_id 0x4E,
+    // section 0 is the present event and section 1 the following event.
+    if section.get_table_id() == 0x4E {
+        let key = event_keys.first().cloned();
+        let is_changed = match section.get_section_number() {
+            0 => epg_data::set_present_event(header.service, key),
+            1 => epg_data::set_following_event(header.service, key),
+            _ => false,
+        };
+        if is_changed {
+            to_cw.send(Message::PresentFollowingChanged{service: header.service}).unwrap();
+        }
+    }
+    is_epg_changed
 }
 
 fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
@@ -178,7 +464,7 @@ fn build_nit(nit: &gst_mpegts::NIT, to_cw: &glib::Sender<Message>) {
         // EN 300 468 Table 12 states which descriptors are allowed.
         match descriptor.get_tag() {
             gst_mpegts::DVBDescriptorType::NetworkName => {
-                let name = descriptor.parse_dvb_network_name().unwrap();
+                let name = dvb_text::decode(&descriptor.get_data()[2..]);
                 if PRINT_NIT {
                     println!("    NetworkName:  {}", &name);
                 }
@@ -450,7 +736,9 @@ fn build_pmt(pmt: &gst_mpegts::PMT, to_cw: &glib::Sender<Message>) {
     }
 }
 
-fn build_sdt(sdt: &gst_mpegts::SDT, to_cw: &glib::Sender<Message>) {
+/// Process an SDT section, recording the default authority of each service, `None` if
+/// the service has none.
+fn build_sdt(sdt: &gst_mpegts::SDT, default_authorities: &mut HashMap<ServiceKey, Option<String>>, to_cw: &glib::Sender<Message>) {
     if PRINT_SDT {
         println!("========  SDT section:  original_network_id = {:?}, transport_stream_id ={:?}", &sdt.get_original_network_id(), &sdt.get_transport_stream_id());
     }
@@ -468,12 +756,13 @@ free_ca_mode = {}",
                      service.get_free_ca_mode(),
             );
         }
+        let mut default_authority = None;
         for descriptor in service.get_descriptors().iter() {
             match descriptor.get_tag() {
                 gst_mpegts::DVBDescriptorType::DefaultAuthority => {
-                    let authority = descriptor.parse_dvb_default_authority();
+                    default_authority = content_identifier::parse_default_authority_descriptor(&descriptor.get_data());
                     if PRINT_SDT {
-                        println!("        DefaultAuthority:  {:?}", authority);
+                        println!("        DefaultAuthority:  {:?}", &default_authority);
                     }
                 },
                 // TODO Process Extension descriptors.
@@ -513,9 +802,15 @@ free_ca_mode = {}",
                         }
                     },
                 gst_mpegts::DVBDescriptorType::Service => {
-                    let (service_type, service_name, some_string_possibly_empty) = descriptor.parse_dvb_service().unwrap();
-                    if PRINT_SDT {
-                        println!("        Service:  {:?}, '{}', '{}'", service_type, service_name, some_string_possibly_empty);
+                    // The data is the service_type followed by the provider name and the service name.
+                    let data = descriptor.get_data();
+                    match decode_length_prefixed_strings(&data, 3, 2) {
+                        Some(strings) => {
+                            if PRINT_SDT {
+                                println!("        Service:  {:?}, '{}', '{}'", data[2], &strings[1], &strings[0]);
+                            }
+                        },
+                        None => println!("************  Could not decode a service descriptor."),
                     }
                 },
                 x => println!("************  Got an unhandled descriptor of type {:?}", x)
@@ -524,6 +819,12 @@ free_ca_mode = {}",
                 println!("            {:?}", descriptor.get_data());
             }
         }
+        let service_key = ServiceKey {
+            original_network_id: sdt.get_original_network_id(),
+            transport_stream_id: sdt.get_transport_stream_id(),
+            service_id: service.get_service_id(),
+        };
+        default_authorities.insert(service_key, default_authority);
     }
 }
 
@@ -585,8 +886,49 @@ fn build_tot(tot: &gst_mpegts::TOT, to_cw: &glib::Sender<Message>) {
 /// This is a separate process executed by a thread other than the Glib event loop thread
 /// so as to avoid that thread having to do too much work.
 pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiver<gst_mpegts::Section>) {
+    let mut last_cache_write = Instant::now();
+    // The rules are evaluated against the cached EPG data at start up as well.
+    let mut are_rules_pending = true;
+    let mut last_rules_evaluation = Instant::now() - RECORDING_RULES_EVALUATION_INTERVAL;
+    let rules_modified = || std::fs::metadata(recording_rules::recording_rules_path()).and_then(|m| m.modified()).ok();
+    let mut last_rules_modified = rules_modified();
+    // The XMLTV file is imported at start up and again whenever it, or the preference, changes.
+    let mut last_xmltv_check = Instant::now() - XMLTV_FILE_CHECK_INTERVAL;
+    let xmltv_file_state = || {
+        let path = preferences::get_xmltv_file().unwrap_or_default();
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        (path, modified)
+    };
+    let mut last_xmltv_file_state = None;
+    let mut eit_sections = EitSections::new();
+    // The default authority of each service for which an SDT has been received.
+    let mut default_authorities = HashMap::new();
     loop {
-        match from_gstreamer.recv() {
+        if last_xmltv_check.elapsed() >= XMLTV_FILE_CHECK_INTERVAL {
+            let state = xmltv_file_state();
+            if last_xmltv_file_state.as_ref() != Some(&state) {
+                are_rules_pending |= import_xmltv_file(&state.0);
+                last_xmltv_file_state = Some(state);
+            }
+            last_xmltv_check = Instant::now();
+        }
+        if last_cache_write.elapsed() > EPG_DATA_CACHE_WRITE_INTERVAL {
+            epg_data::remove_expired_events(epg_data::now());
+            epg_data::write_epg_data_cache_if_dirty();
+            last_cache_write = Instant::now();
+        }
+        if last_rules_evaluation.elapsed() >= RECORDING_RULES_EVALUATION_INTERVAL {
+            // A new or changed rule may match programmes already in the EPG data.
+            let modified = rules_modified();
+            if are_rules_pending || modified != last_rules_modified {
+                apply_recording_rules();
+                are_rules_pending = false;
+                last_rules_modified = modified;
+            }
+            last_rules_evaluation = Instant::now();
+        }
+        // Sections only arrive whilst a frontend is tuned, the files must be checked anyway.
+        match from_gstreamer.recv_timeout(RECORDING_RULES_EVALUATION_INTERVAL) {
             Ok(mut section) => {
                 match section.get_section_type() {
                     gst_mpegts::SectionType::AtscCvct => {},
@@ -607,7 +949,7 @@ pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiv
                     },
                     gst_mpegts::SectionType::Eit => {
                         if let Some(eit) = section.get_eit() {
-                            build_eit(&eit, &to_cw);
+                            are_rules_pending |= build_eit(&section, &eit, &mut eit_sections, &default_authorities, &to_cw);
                         } else {
                             println!("********  Got an EIT that wasn't an EIT {:?}", &section);
                             println!("********      Section type: {:?}", &section.get_section_type());
@@ -633,7 +975,7 @@ pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiv
                     },
                     gst_mpegts::SectionType::Sdt => {
                         if let Some(sdt) = section.get_sdt() {
-                            build_sdt(&sdt, &to_cw);
+                            build_sdt(&sdt, &mut default_authorities, &to_cw);
                         } else {
                             println!("******** Got a SDT that wasn't a SDT {:?}", &section);
                         }
@@ -663,6 +1005,7 @@ pub fn run(to_cw: glib::Sender<Message>, from_gstreamer: std::sync::mpsc::Receiv
                     },
                 }
             },
+            Err(RecvTimeoutError::Timeout) => {},
             Err(e) => {
                 println!("********  failed to receive a section {:?}", e);
             }
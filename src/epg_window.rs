@@ -0,0 +1,409 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use chrono::{Local, TimeZone};
+use glib;
+use gtk;
+use gtk::prelude::*;
+use pango;
+
+use crate::channels_data::{get_channels_data_ordered_by_logical_channel_number, get_multiplex_of_channel_name, ChannelData};
+use crate::control_window::ControlWindow;
+use crate::dialogs::{display_an_error_dialog, display_an_information_dialog};
+use crate::dvb_devices::{self, FrontendId};
+use crate::epg_data;
+use crate::epg_event::Event;
+use crate::recording_jobs::{self, DEFAULT_PADDING_AFTER, DEFAULT_PADDING_BEFORE, RecordingJob, ScheduledEvent};
+use crate::recording_profiles::DEFAULT_PROFILE_NAME;
+use crate::recording_rules::{self, RecordingRule};
+use crate::tuner_allocation;
+
+/// The number of seconds represented by each column of the grid.
+const SECONDS_PER_COLUMN: i64 = 300;
+
+/// The number of grid columns used for the channel names.
+const CHANNEL_COLUMNS: i32 = 6;
+
+/// The number of seconds between the time labels at the top of the grid.
+const TIME_LABEL_INTERVAL: i64 = 1800;
+
+/// The number of seconds of guide displayed at any one time.
+const TIME_SPAN: i64 = 3 * 3600;
+
+/// A window displaying the programme guide as a time × channel grid.
+#[derive(Debug)]
+pub struct EPGWindow {
+    control_window: Rc<ControlWindow>,
+    pub window: gtk::Window,
+    header_bar: gtk::HeaderBar,
+    grid: gtk::Grid,
+    start_time: Cell<i64>,
+}
+
+impl EPGWindow {
+    /// Construct a new guide window starting at the current time slot.
+    ///
+    /// This function is executed in the GTK event loop thread.
+    fn new(control_window: &Rc<ControlWindow>) -> Rc<EPGWindow> {
+        let window = gtk::Window::new(gtk::WindowType::Toplevel);
+        window.set_title("Me TV – Programme Guide");
+        window.set_transient_for(Some(&control_window.window));
+        window.set_default_size(960, 540);
+        let header_bar = gtk::HeaderBar::new();
+        header_bar.set_title(Some("Me TV – Programme Guide"));
+        header_bar.set_show_close_button(true);
+        let previous_button = gtk::Button::new();
+        previous_button.set_image(Some(&gtk::Image::from_icon_name(Some("go-previous-symbolic"), gtk::IconSize::Button.into())));
+        let now_button = gtk::Button::with_label("Now");
+        let next_button = gtk::Button::new();
+        next_button.set_image(Some(&gtk::Image::from_icon_name(Some("go-next-symbolic"), gtk::IconSize::Button.into())));
+        header_bar.pack_start(&previous_button);
+        header_bar.pack_start(&now_button);
+        header_bar.pack_start(&next_button);
+        window.set_titlebar(Some(&header_bar));
+        let grid = gtk::Grid::new();
+        grid.set_column_homogeneous(true);
+        grid.set_row_spacing(2);
+        grid.set_column_spacing(2);
+        let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.add(&grid);
+        window.add(&scrolled_window);
+        let epg_window = Rc::new(EPGWindow {
+            control_window: control_window.clone(),
+            window,
+            header_bar,
+            grid,
+            start_time: Cell::new(start_of_slot(epg_data::now())),
+        });
+        previous_button.connect_clicked({
+            let e_w = epg_window.clone();
+            move |_| {
+                e_w.start_time.set(e_w.start_time.get() - TIME_SPAN / 2);
+                Self::populate(&e_w);
+            }
+        });
+        now_button.connect_clicked({
+            let e_w = epg_window.clone();
+            move |_| {
+                e_w.start_time.set(start_of_slot(epg_data::now()));
+                Self::populate(&e_w);
+            }
+        });
+        next_button.connect_clicked({
+            let e_w = epg_window.clone();
+            move |_| {
+                e_w.start_time.set(e_w.start_time.get() + TIME_SPAN / 2);
+                Self::populate(&e_w);
+            }
+        });
+        epg_window.window.connect_delete_event({
+            let c_w = control_window.clone();
+            move |_, _| {
+                c_w.epg_window.replace(None);
+                Inhibit(false)
+            }
+        });
+        // The guide data is being updated all the time channels are being watched
+        // so regularly redisplay the grid.
+        glib::timeout_add_seconds_local(60, {
+            let e_w = Rc::downgrade(&epg_window);
+            move || {
+                match e_w.upgrade() {
+                    Some(e_w) => {
+                        if ! e_w.window.is_visible() { return Continue(false); }
+                        Self::populate(&e_w);
+                        Continue(true)
+                    },
+                    None => Continue(false),
+                }
+            }
+        });
+        Self::populate(&epg_window);
+        epg_window.window.show_all();
+        epg_window
+    }
+
+    /// Fill the grid with the channels and the events for the current time period.
+    fn populate(epg_window: &Rc<EPGWindow>) {
+        let grid = &epg_window.grid;
+        for child in grid.get_children() {
+            grid.remove(&child);
+        }
+        let start_time = epg_window.start_time.get();
+        epg_window.header_bar.set_subtitle(Some(&Local.timestamp(start_time, 0).format("%A %e %B").to_string()));
+        let mut time = start_time;
+        while time < start_time + TIME_SPAN {
+            let label = gtk::Label::new(Some(&Local.timestamp(time, 0).format("%H:%M").to_string()));
+            label.set_xalign(0.0);
+            grid.attach(&label, column_of(time - start_time), 0, column_of(TIME_LABEL_INTERVAL) - CHANNEL_COLUMNS, 1);
+            time += TIME_LABEL_INTERVAL;
+        }
+        match get_channels_data_ordered_by_logical_channel_number() {
+            Some(channels_data) => {
+                let events = epg_data::get_events_between(start_time, start_time + TIME_SPAN);
+                for (index, channel_data) in channels_data.iter().enumerate() {
+                    let row = index as i32 + 1;
+                    let label = gtk::Label::new(Some(&channel_label_text(channel_data)));
+                    label.set_xalign(0.0);
+                    label.set_ellipsize(pango::EllipsizeMode::End);
+                    grid.attach(&label, 0, row, CHANNEL_COLUMNS, 1);
+                    // NB service_id is assumed unique across the multiplexes that can be received.
+                    for event in events.iter().filter(|e| e.key.service_id == channel_data.service_id) {
+                        if let Some((left, width)) = columns_of_event(event, start_time) {
+                            let button = gtk::Button::with_label(&event.title);
+                            if let Some(child) = button.get_child() {
+                                if let Ok(button_label) = child.downcast::<gtk::Label>() {
+                                    button_label.set_ellipsize(pango::EllipsizeMode::End);
+                                    button_label.set_xalign(0.0);
+                                }
+                            }
+                            button.set_tooltip_text(Some(&format!("{}\n{}", &event.title, &event.synopsis())));
+                            button.connect_clicked({
+                                let e_w = epg_window.clone();
+                                let channel_name = channel_data.name.clone();
+                                let event = event.clone();
+                                move |_| Self::on_event_clicked(&e_w, &channel_name, &event)
+                            });
+                            grid.attach(&button, left, row, width, 1);
+                        }
+                    }
+                }
+            },
+            None => {
+                let label = gtk::Label::new(Some("No channels file."));
+                grid.attach(&label, 0, 1, CHANNEL_COLUMNS, 1);
+            },
+        }
+        grid.show_all();
+    }
+
+    /// Offer the user the choice of watching the channel of the event, scheduling a
+    /// recording of the event, or recording every episode of the series of the event.
+    fn on_event_clicked(epg_window: &Rc<EPGWindow>, channel_name: &str, event: &Event) {
+        let dialog = gtk::MessageDialog::new(
+            Some(&epg_window.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &format!("{}\n\n{}   {} – {}\n\n{}",
+                     &event.title,
+                     channel_name,
+                     Local.timestamp(event.start_time, 0).format("%a %H:%M"),
+                     Local.timestamp(event.end_time(), 0).format("%H:%M"),
+                     &event.synopsis()),
+        );
+        dialog.add_button("_Watch", gtk::ResponseType::Accept);
+        dialog.add_button("_Record", gtk::ResponseType::Apply);
+        if event.series_crid.is_some() {
+            dialog.add_button("Record _Series", gtk::ResponseType::Yes);
+        }
+        dialog.add_button("_Close", gtk::ResponseType::Close);
+        let response = gtk::ResponseType::from(dialog.run());
+        unsafe { dialog.destroy(); }
+        match response {
+            gtk::ResponseType::Accept => {
+                if ! epg_window.control_window.tune_to_channel(channel_name) {
+                    display_an_error_dialog(Some(&epg_window.window), &format!("Could not tune to {}, perhaps there are no frontends.", channel_name));
+                }
+            },
+            gtk::ResponseType::Apply => schedule_recording(&epg_window.window, channel_name, event),
+            gtk::ResponseType::Yes => record_series(&epg_window.window, channel_name, event),
+            _ => {},
+        }
+    }
+}
+
+/// Return the start of the time slot containing the given time.
+fn start_of_slot(time: i64) -> i64 {
+    time - time.rem_euclid(TIME_LABEL_INTERVAL)
+}
+
+/// Return the grid column for an offset in seconds from the start of the displayed period.
+fn column_of(offset: i64) -> i32 {
+    CHANNEL_COLUMNS + ((offset + SECONDS_PER_COLUMN / 2) / SECONDS_PER_COLUMN) as i32
+}
+
+/// Return the left column and the width in columns of the grid cell for an event, or
+/// `None` if the event is not displayed in the period starting at `start_time`.
+///
+/// Both ends are rounded to the nearest column so that adjacent events never overlap.
+fn columns_of_event(event: &Event, start_time: i64) -> Option<(i32, i32)> {
+    let end_time = start_time + TIME_SPAN;
+    if event.end_time() <= start_time || event.start_time >= end_time {
+        return None;
+    }
+    let left = column_of(event.start_time.max(start_time) - start_time);
+    let right = column_of(event.end_time().min(end_time) - start_time);
+    if right > left { Some((left, right - left)) } else { None }
+}
+
+/// The text used to label a channel row.
+fn channel_label_text(channel_data: &ChannelData) -> String {
+    if channel_data.logical_channel_number == 0 {
+        channel_data.name.clone()
+    } else {
+        format!("{}  {}", channel_data.logical_channel_number, channel_data.name)
+    }
+}
+
+/// Return the path of the file to record an event to.
+fn recording_path(channel_name: &str, event: &Event) -> PathBuf {
+    let mut path = glib::get_user_special_dir(glib::UserDirectory::Videos)
+        .unwrap_or_else(|| PathBuf::from(std::env::var("HOME").expect("HOME is not set.")));
+    path.push(recording_rules::recording_file_name(channel_name, event, "ts"));
+    path
+}
+
+/// Add a recording rule to record every episode of the series of an event on the
+/// channel, the EPG manager schedules the recordings.
+fn record_series(parent: &gtk::Window, channel_name: &str, event: &Event) {
+    let result = recording_rules::update_recording_rules(&recording_rules::recording_rules_path(), |rules| {
+        if rules.iter().any(|r| r.series_crid == event.series_crid && r.channel.as_deref() == Some(channel_name)) {
+            return Err(format!("The series of {} is already being recorded.", &event.title));
+        }
+        recording_rules::add_rule(rules, RecordingRule {
+            id: 0,
+            series_crid: event.series_crid.clone(),
+            title_regex: None,
+            channel: Some(channel_name.to_string()),
+            profile: DEFAULT_PROFILE_NAME.to_string(),
+            output_directory: None,
+        });
+        Ok(())
+    });
+    match result.and_then(|r| r) {
+        Ok(_) => display_an_information_dialog(Some(parent), &format!("Every episode of {} on {} will be recorded.", &event.title, channel_name)),
+        Err(error) => display_an_error_dialog(Some(parent), &error),
+    }
+}
+
+/// Schedule a recording of an event on a frontend that is free at the time.
+///
+/// If the event has already started the recording starts as soon as possible.
+fn schedule_recording(parent: &gtk::Window, channel_name: &str, event: &Event) {
+    let now = epg_data::now();
+    if event.end_time() <= now {
+        display_an_error_dialog(Some(parent), &format!("{} has already finished.", &event.title));
+        return;
+    }
+    let start_time = event.start_time.max(now);
+    let output_path = recording_path(channel_name, event);
+    let mut job = RecordingJob {
+        id: 0,
+        channel: channel_name.to_string(),
+        start_time,
+        duration: ((event.end_time() - start_time + 59) / 60) as u32,
+        output: output_path.to_str().unwrap().to_string(),
+        adapter: 0,
+        frontend: 0,
+        profile: DEFAULT_PROFILE_NAME.to_string(),
+        started: false,
+        event: None,
+    };
+    // A programme that has not started is recorded by event so that the recording
+    // follows the programme if it starts late or is moved. Imported programmes are not
+    // in the EIT so can only be recorded by time.
+    if start_time == event.start_time && ! event.is_imported() {
+        job.event = Some(ScheduledEvent{event_id: event.key.event_id, padding_before: DEFAULT_PADDING_BEFORE, padding_after: DEFAULT_PADDING_AFTER});
+    }
+    let mut frontends = dvb_devices::get_installed_frontends();
+    if frontends.is_empty() {
+        frontends.push(FrontendId{adapter: 0, frontend: 0});
+    }
+    let result = recording_jobs::update_recording_jobs(&recording_jobs::recording_jobs_path(), |jobs| -> Result<(), String> {
+        let frontend = tuner_allocation::assign_frontend(&job, &jobs.jobs, get_multiplex_of_channel_name, &frontends)
+            .ok_or_else(|| format!("{} cannot be recorded, all the frontends are in use at that time.", &event.title))?;
+        job.adapter = frontend.adapter;
+        job.frontend = frontend.frontend;
+        recording_jobs::add_job(jobs, job);
+        Ok(())
+    });
+    match result.and_then(|r| r) {
+        Ok(_) => display_an_information_dialog(Some(parent), &format!("Recording of {} scheduled to:\n\n{}", &event.title, output_path.to_str().unwrap())),
+        Err(error) => display_an_error_dialog(Some(parent), &error),
+    }
+}
+
+/// Display the guide window, creating it if it is not already being displayed.
+pub fn present(control_window: &Rc<ControlWindow>) {
+    if let Some(ref epg_window) = *control_window.epg_window.borrow() {
+        epg_window.window.present();
+        return;
+    }
+    let epg_window = EPGWindow::new(control_window);
+    control_window.epg_window.replace(Some(epg_window));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::epg_event::{EventKey, RunningStatus};
+
+    fn create_event(start_time: i64, duration: u32) -> Event {
+        Event {
+            key: EventKey { original_network_id: 9018, transport_stream_id: 4164, service_id: 4164, event_id: 1 },
+            start_time,
+            duration,
+            title: "News".to_string(),
+            short_text: "".to_string(),
+            extended_text: "".to_string(),
+            items: vec![],
+            genre: None,
+            running_status: RunningStatus::NotRunning,
+            programme_crid: None,
+            series_crid: None,
+        }
+    }
+
+    #[test]
+    fn start_of_slot_rounds_down_to_the_half_hour() {
+        assert_eq!(start_of_slot(1800), 1800);
+        assert_eq!(start_of_slot(3599), 1800);
+    }
+
+    #[test]
+    fn event_within_the_period() {
+        assert_eq!(columns_of_event(&create_event(3600, 1800), 0), Some((CHANNEL_COLUMNS + 12, 6)));
+    }
+
+    #[test]
+    fn event_overlapping_the_start_of_the_period_is_clipped() {
+        assert_eq!(columns_of_event(&create_event(-600, 1800), 0), Some((CHANNEL_COLUMNS, 4)));
+    }
+
+    #[test]
+    fn events_outside_the_period_are_not_displayed() {
+        assert_eq!(columns_of_event(&create_event(-1800, 1800), 0), None);
+        assert_eq!(columns_of_event(&create_event(TIME_SPAN, 1800), 0), None);
+    }
+
+    #[test]
+    fn adjacent_events_do_not_overlap() {
+        let (left_a, width_a) = columns_of_event(&create_event(0, 1000), 0).unwrap();
+        let (left_b, _) = columns_of_event(&create_event(1000, 1000), 0).unwrap();
+        assert_eq!(left_a + width_a, left_b);
+    }
+}
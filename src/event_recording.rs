@@ -0,0 +1,218 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2018–2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Recording an event, i.e. a programme, as signalled by the EIT rather than at fixed times.
+//!
+//! EN 300 468 gives each event in the EIT present/following sections a running_status.
+//! An event is recorded from when it starts running until it stops, with padding before
+//! and after since broadcasters are not always accurate. Some broadcasters do not set
+//! the running_status, for those the event being the present event is taken to mean it
+//! is running. The start time and duration of the event are updated from every EIT
+//! section that includes it so the recording follows the event if it is moved.
+//!
+//! There is no limit here on how long an event is waited for, the recording is run
+//! for a maximum duration that allows for the schedule overrunning.
+
+use crate::epg_event::RunningStatus;
+
+/// What an EIT section says about an event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EventStatus {
+    pub event_id: u16,
+    pub start_time: i64, // Seconds since the Unix epoch, UTC.
+    pub duration: u32, // Seconds.
+    pub running_status: RunningStatus,
+}
+
+/// The state of the recording of an event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum State {
+    Waiting,
+    Recording,
+    Finishing{stop_time: i64},
+    Finished,
+}
+
+/// What should be done to the recording as a result of an update.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Nothing,
+    Start,
+    Stop,
+}
+
+/// The recording of an event of a service.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EventRecording {
+    pub event_id: u16,
+    pub start_time: i64, // Seconds since the Unix epoch, UTC.
+    pub duration: u32, // Seconds.
+    pub padding_before: i64, // Seconds.
+    pub padding_after: i64, // Seconds.
+    pub state: State,
+    has_run: bool,
+    has_present_following: bool,
+}
+
+impl EventRecording {
+    /// Create the recording of an event with the start time and duration, in seconds,
+    /// currently known and the padding in minutes.
+    pub fn new(event_id: u16, start_time: i64, duration: u32, padding_before: u32, padding_after: u32) -> EventRecording {
+        EventRecording {
+            event_id,
+            start_time,
+            duration,
+            padding_before: 60 * padding_before as i64,
+            padding_after: 60 * padding_after as i64,
+            state: State::Waiting,
+            has_run: false,
+            has_present_following: false,
+        }
+    }
+
+    fn end_time(&self) -> i64 { self.start_time + self.duration as i64 }
+
+    /// Is the event running according to the present event of an EIT present/following section.
+    fn is_running(&self, present: Option<&EventStatus>) -> bool {
+        match present {
+            Some(event) if event.event_id == self.event_id => match event.running_status {
+                RunningStatus::Running | RunningStatus::Pausing | RunningStatus::StartsInAFewSeconds | RunningStatus::Undefined => true,
+                RunningStatus::NotRunning | RunningStatus::ServiceOffAir => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn change_state(&mut self, state: State) -> Action {
+        let action = match (self.state, state) {
+            (State::Waiting, State::Recording) => Action::Start,
+            (State::Finishing{..}, State::Finished) | (State::Recording, State::Finished) => Action::Stop,
+            _ => Action::Nothing,
+        };
+        self.state = state;
+        action
+    }
+
+    /// Follow any change of the start time or duration of the event.
+    fn follow(&mut self, event: Option<&EventStatus>) {
+        if let Some(event) = event.filter(|e| e.event_id == self.event_id) {
+            self.start_time = event.start_time;
+            self.duration = event.duration;
+        }
+    }
+
+    /// Update the recording using the events of an EIT schedule section of the service.
+    pub fn update_schedule(&mut self, events: &[EventStatus], now: i64) -> Action {
+        self.follow(events.iter().find(|e| e.event_id == self.event_id));
+        self.tick(now)
+    }
+
+    /// Update the recording using the present and following events of the service as
+    /// given by the EIT present/following sections.
+    pub fn update_present_following(&mut self, present: Option<&EventStatus>, following: Option<&EventStatus>, now: i64) -> Action {
+        self.follow(present);
+        self.follow(following);
+        self.has_present_following = true;
+        let is_running = self.is_running(present)
+            || following.map_or(false, |e| e.event_id == self.event_id && e.running_status == RunningStatus::StartsInAFewSeconds);
+        if is_running {
+            self.has_run = true;
+        }
+        let action = match self.state {
+            State::Waiting if is_running => self.change_state(State::Recording),
+            State::Recording if self.has_run && ! is_running => self.change_state(State::Finishing{stop_time: now + self.padding_after}),
+            State::Finishing{..} if is_running => self.change_state(State::Recording),
+            _ => Action::Nothing,
+        };
+        match action {
+            Action::Nothing => self.tick(now),
+            _ => action,
+        }
+    }
+
+    /// Update the recording for the passing of time: padding starts the recording before
+    /// the scheduled start, and without present/following sections the recording stops
+    /// at the scheduled end.
+    pub fn tick(&mut self, now: i64) -> Action {
+        match self.state {
+            State::Waiting if now >= self.start_time - self.padding_before => self.change_state(State::Recording),
+            // Without present/following sections the scheduled times are all there is.
+            State::Recording if ! self.has_present_following && now >= self.end_time() + self.padding_after => self.change_state(State::Finished),
+            State::Finishing{stop_time} if now >= stop_time => self.change_state(State::Finished),
+            _ => Action::Nothing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(event_id: u16, start_time: i64, running_status: RunningStatus) -> EventStatus {
+        EventStatus{event_id, start_time, duration: 1800, running_status}
+    }
+
+    #[test]
+    fn recording_follows_running_status_with_padding() {
+        let mut recording = EventRecording::new(2, 10000, 1800, 2, 10);
+        assert_eq!(recording.tick(9000), Action::Nothing);
+        assert_eq!(recording.tick(9880), Action::Start);
+        let previous = status(1, 8000, RunningStatus::Running);
+        let event = status(2, 10000, RunningStatus::NotRunning);
+        assert_eq!(recording.update_present_following(Some(&previous), Some(&event), 10000), Action::Nothing);
+        let event = status(2, 10000, RunningStatus::Running);
+        assert_eq!(recording.update_present_following(Some(&event), Some(&status(3, 11800, RunningStatus::NotRunning)), 10010), Action::Nothing);
+        assert_eq!(recording.update_present_following(Some(&status(3, 11800, RunningStatus::Running)), None, 11800), Action::Nothing);
+        assert_eq!(recording.state, State::Finishing{stop_time: 12400});
+        assert_eq!(recording.tick(12399), Action::Nothing);
+        assert_eq!(recording.tick(12400), Action::Stop);
+        assert_eq!(recording.state, State::Finished);
+    }
+
+    #[test]
+    fn recording_follows_an_event_that_is_moved() {
+        let mut recording = EventRecording::new(2, 10000, 1800, 2, 10);
+        assert_eq!(recording.update_schedule(&[status(2, 12000, RunningStatus::Undefined)], 9880), Action::Nothing);
+        assert_eq!(recording.start_time, 12000);
+        assert_eq!(recording.tick(11880), Action::Start);
+        // An overrun of the previous event delays the start, the recording continues.
+        assert_eq!(recording.update_present_following(Some(&status(1, 8000, RunningStatus::Running)), Some(&status(2, 12000, RunningStatus::NotRunning)), 13000), Action::Nothing);
+        assert_eq!(recording.tick(14400), Action::Nothing);
+        assert_eq!(recording.update_present_following(Some(&status(2, 12000, RunningStatus::Running)), None, 14500), Action::Nothing);
+        assert_eq!(recording.update_present_following(Some(&status(3, 14000, RunningStatus::Running)), None, 16300), Action::Nothing);
+        assert_eq!(recording.tick(16900), Action::Stop);
+    }
+
+    #[test]
+    fn present_event_is_running_if_running_status_is_not_set() {
+        let mut recording = EventRecording::new(2, 10000, 1800, 0, 0);
+        assert_eq!(recording.update_present_following(Some(&status(2, 10000, RunningStatus::Undefined)), None, 9990), Action::Start);
+        assert_eq!(recording.update_present_following(Some(&status(3, 11800, RunningStatus::Undefined)), None, 11790), Action::Stop);
+    }
+
+    #[test]
+    fn recording_stops_at_the_scheduled_end_without_present_following() {
+        let mut recording = EventRecording::new(2, 10000, 1800, 2, 10);
+        assert_eq!(recording.tick(9880), Action::Start);
+        assert_eq!(recording.tick(12399), Action::Nothing);
+        assert_eq!(recording.tick(12400), Action::Stop);
+    }
+}
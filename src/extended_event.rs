@@ -0,0 +1,227 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Handling of the extended event descriptor, EN 300 468 §6.2.15.
+//!
+//! The description of an event can be too long for a single descriptor so it is
+//! split over a sequence of descriptors numbered 0 to last_descriptor_number. The
+//! descriptors are parsed from the raw data and the strings are left encoded until
+//! the whole sequence is assembled: a string may be split anywhere, including within
+//! a multi-byte character or a control code, so the pieces are joined before decoding.
+
+use crate::dvb_text;
+
+/// The content of an extended event descriptor with the strings still encoded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExtendedEventDescriptor {
+    pub descriptor_number: u8,
+    pub last_descriptor_number: u8,
+    pub language_code: String,
+    pub items: Vec<(Vec<u8>, Vec<u8>)>, // (item_description, item) pairs.
+    pub text: Vec<u8>,
+}
+
+/// The synopsis of an event assembled from a sequence of extended event descriptors.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Synopsis {
+    pub items: Vec<(String, String)>, // (item_description, item) pairs, e.g. ("Director", "…").
+    pub text: String,
+}
+
+/// Take a field prefixed by a length byte at position `i` of `data`, the field
+/// must not extend beyond `end`.
+fn take_field(data: &[u8], i: &mut usize, end: usize) -> Option<Vec<u8>> {
+    if *i >= end {
+        return None;
+    }
+    let start = *i + 1;
+    let finish = start + data[*i] as usize;
+    if finish > end {
+        return None;
+    }
+    *i = finish;
+    Some(data[start..finish].to_vec())
+}
+
+/// Parse the data of an extended event descriptor. The data is as returned by
+/// `gst_mpegts::Descriptor::get_data`, i.e. it includes the tag and length bytes.
+///
+/// Return `None` if the data is not a well formed extended event descriptor.
+pub fn parse_extended_event_descriptor(data: &[u8]) -> Option<ExtendedEventDescriptor> {
+    if data.len() < 7 || data[0] != 0x4E {
+        return None;
+    }
+    let end = 2 + data[1] as usize;
+    if end > data.len() {
+        return None;
+    }
+    let items_end = 7 + data[6] as usize;
+    if items_end > end {
+        return None;
+    }
+    let mut i = 7;
+    let mut items = Vec::new();
+    while i < items_end {
+        let item_description = take_field(data, &mut i, items_end)?;
+        let item = take_field(data, &mut i, items_end)?;
+        items.push((item_description, item));
+    }
+    let text = take_field(data, &mut i, end)?;
+    Some(ExtendedEventDescriptor {
+        descriptor_number: data[2] >> 4,
+        last_descriptor_number: data[2] & 0x0F,
+        language_code: String::from_utf8_lossy(&data[3..6]).to_string(),
+        items,
+        text,
+    })
+}
+
+/// Append a piece of a string split across descriptors to the string so far. Some
+/// broadcasters start every piece with the character table selector, a repeat of the
+/// selector of the first piece is dropped.
+fn append_piece(string: &mut Vec<u8>, piece: &[u8]) {
+    let selector = dvb_text::character_table_selector(string);
+    if ! string.is_empty() && ! selector.is_empty() && piece.starts_with(selector) {
+        let count = selector.len();
+        string.extend_from_slice(&piece[count..]);
+    } else {
+        string.extend_from_slice(piece);
+    }
+}
+
+/// Assemble a sequence of extended event descriptors into a synopsis using `decode`
+/// to decode the strings, each string being decoded once all its pieces are joined.
+///
+/// Only the descriptors with the same language as the first one are used. Return
+/// `None` if the sequence is not complete.
+pub fn assemble_synopsis<F>(descriptors: &[ExtendedEventDescriptor], decode: F) -> Option<Synopsis>
+    where F: Fn(&[u8]) -> String
+{
+    let first = descriptors.first()?;
+    let mut sequence = descriptors.iter()
+        .filter(|d| d.language_code == first.language_code)
+        .collect::<Vec<&ExtendedEventDescriptor>>();
+    sequence.sort_by_key(|d| d.descriptor_number);
+    sequence.dedup_by_key(|d| d.descriptor_number);
+    if sequence.len() != first.last_descriptor_number as usize + 1
+        || sequence.iter().enumerate().any(|(i, d)| d.descriptor_number as usize != i) {
+        return None;
+    }
+    let mut items: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    let mut text = Vec::new();
+    for descriptor in sequence.iter() {
+        for (item_description, item) in descriptor.items.iter() {
+            // An item with an empty description is a continuation of the previous item,
+            // this happens when an item is split across descriptors.
+            match items.last_mut() {
+                Some(last) if item_description.is_empty() => append_piece(&mut last.1, item),
+                _ => items.push((item_description.clone(), item.clone())),
+            }
+        }
+        append_piece(&mut text, &descriptor.text);
+    }
+    Some(Synopsis {
+        items: items.iter().map(|(item_description, item)| (decode(item_description), decode(item))).collect(),
+        text: decode(&text),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8]) -> String { String::from_utf8_lossy(data).to_string() }
+
+    fn create_descriptor_data(descriptor_number: u8, last_descriptor_number: u8, items: &[(&str, &str)], text: &[u8]) -> Vec<u8> {
+        let mut items_data = Vec::new();
+        for (item_description, item) in items.iter() {
+            items_data.push(item_description.len() as u8);
+            items_data.extend_from_slice(item_description.as_bytes());
+            items_data.push(item.len() as u8);
+            items_data.extend_from_slice(item.as_bytes());
+        }
+        let mut data = vec![0x4E, 0, (descriptor_number << 4) | last_descriptor_number, b'e', b'n', b'g', items_data.len() as u8];
+        data.extend(items_data);
+        data.push(text.len() as u8);
+        data.extend_from_slice(text);
+        data[1] = (data.len() - 2) as u8;
+        data
+    }
+
+    #[test]
+    fn parse_descriptor_with_items_and_text() {
+        let data = create_descriptor_data(1, 2, &[("Director", "Alfred Hitchcock")], b"A thriller.");
+        let descriptor = parse_extended_event_descriptor(&data).unwrap();
+        assert_eq!(descriptor.descriptor_number, 1);
+        assert_eq!(descriptor.last_descriptor_number, 2);
+        assert_eq!(descriptor.language_code, "eng");
+        assert_eq!(descriptor.items, vec![(b"Director".to_vec(), b"Alfred Hitchcock".to_vec())]);
+        assert_eq!(descriptor.text, b"A thriller.".to_vec());
+    }
+
+    #[test]
+    fn parse_rejects_truncated_data() {
+        let data = create_descriptor_data(0, 0, &[("Cast", "Cary Grant")], b"A thriller.");
+        assert_eq!(parse_extended_event_descriptor(&data[..data.len() - 1]), None);
+        assert_eq!(parse_extended_event_descriptor(&data[..9]), None);
+        assert_eq!(parse_extended_event_descriptor(&[0x4D, 0, 0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn assemble_descriptors_in_descriptor_number_order() {
+        let descriptors = vec![
+            parse_extended_event_descriptor(&create_descriptor_data(1, 1, &[("", "Eva Marie Saint")], b"chased across America.")).unwrap(),
+            parse_extended_event_descriptor(&create_descriptor_data(0, 1, &[("Cast", "Cary Grant, ")], b"A man is ")).unwrap(),
+        ];
+        let synopsis = assemble_synopsis(&descriptors, decode).unwrap();
+        assert_eq!(synopsis.text, "A man is chased across America.");
+        assert_eq!(synopsis.items, vec![("Cast".to_string(), "Cary Grant, Eva Marie Saint".to_string())]);
+    }
+
+    #[test]
+    fn characters_split_across_descriptors_are_decoded_whole() {
+        // A UTF-8 é, 0xC3 0xA9, split between the descriptors, once with the selector
+        // repeated at the start of the second descriptor and once without.
+        for second_text in [&b"\xA9 au lait."[..], &b"\x15\xA9 au lait."[..]].iter() {
+            let descriptors = vec![
+                parse_extended_event_descriptor(&create_descriptor_data(0, 1, &[], b"\x15Caf\xC3")).unwrap(),
+                parse_extended_event_descriptor(&create_descriptor_data(1, 1, &[], second_text)).unwrap(),
+            ];
+            assert_eq!(assemble_synopsis(&descriptors, dvb_text::decode).unwrap().text, "Café au lait.");
+        }
+        // A diacritic of the default table, 0xC2 then the letter, split between the descriptors.
+        let descriptors = vec![
+            parse_extended_event_descriptor(&create_descriptor_data(0, 1, &[], b"Caf\xC2")).unwrap(),
+            parse_extended_event_descriptor(&create_descriptor_data(1, 1, &[], b"e.")).unwrap(),
+        ];
+        assert_eq!(assemble_synopsis(&descriptors, dvb_text::decode).unwrap().text, "Café.");
+    }
+
+    #[test]
+    fn incomplete_sequence_is_not_assembled() {
+        let descriptors = vec![
+            parse_extended_event_descriptor(&create_descriptor_data(0, 2, &[], b"Part one, ")).unwrap(),
+            parse_extended_event_descriptor(&create_descriptor_data(2, 2, &[], b"part three.")).unwrap(),
+        ];
+        assert_eq!(assemble_synopsis(&descriptors, decode), None);
+        assert_eq!(assemble_synopsis(&[], decode), None);
+    }
+}
@@ -19,9 +19,6 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::fs;
-use std::os::unix::fs::FileTypeExt;
-use std::path::PathBuf;
 use std::sync::mpsc::channel;
 
 use glib;
@@ -31,77 +28,15 @@ use notify::{Watcher, RecursiveMode, RawEvent, op, raw_watcher};
 use regex::Regex;
 
 use crate::control_window::Message;
-
-/// A struct to represent the identity of a specific frontend currently
-/// available on the system.
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct FrontendId {
-    pub adapter: u8,
-    pub frontend: u8,
-}
-
-/// The path in the filesystem to the DVB related special files.
-fn dvb_base_path() -> PathBuf { PathBuf::from("/dev/dvb") }
-
-/// Return the path to the adapter director for a given adapter.
-fn adapter_path(id: u8) -> PathBuf {
-    let mut result = dvb_base_path();
-    result.push("adapter".to_string() + &id.to_string());
-    result
-}
-
-/// Return the path to the special file for a given frontend.
-fn frontend_path(fei: &FrontendId) -> PathBuf {
-    let mut result = adapter_path(fei.adapter);
-    result.push("frontend".to_string() + &fei.frontend.to_string());
-    result
-}
-
-/// Return the path to the special file of the demux for a given frontend.
-fn demux_path(fei: &FrontendId) -> PathBuf {
-    let mut result = adapter_path(fei.adapter);
-    result.push("demux".to_string() + &fei.frontend.to_string());
-    result
-}
-
-/// Return the path to the special file of the data for a given frontend.
-fn dvr_path(fei: &FrontendId) -> PathBuf {
-    let mut result = adapter_path(fei.adapter);
-    result.push("dvr".to_string() + &fei.frontend.to_string());
-    result
-}
+use crate::dvb_devices::{FrontendId, get_installed_frontends};
 
 /// Search for any adapters already installed on start of the application.
 ///
 /// Inform the GUI and the remote control manager of the presence of
 /// any adaptors and frontends.
 pub fn add_already_installed_adaptors(to_cw: &mut glib::Sender<Message>) {
-    if dvb_base_path().is_dir() {
-        let mut adapter_number = 0;
-        loop {
-            if adapter_path(adapter_number).is_dir() {
-                let mut fei = FrontendId{adapter: adapter_number, frontend: 0};
-                loop {
-                    // TODO Is it worth doing the check for special file or just check for existence.
-                    let path = frontend_path(&fei);
-                    match fs::metadata(&path) {
-                        Ok(m) => {
-                            // NB m.is_file() is false for special files. :-(
-                            // Assume the special devices were are dealing with are
-                            // character devices not block devices.
-                            if m.file_type().is_char_device() {
-                                to_cw.send(Message::FrontendAppeared{fei: fei.clone()}).unwrap();
-                            }
-                        },
-                        Err(_) => break,
-                    };
-                    fei.frontend += 1;
-                }
-            } else {
-                break;
-            }
-            adapter_number += 1;
-        }
+    for fei in get_installed_frontends() {
+        to_cw.send(Message::FrontendAppeared{fei}).unwrap();
     }
 }
 
@@ -190,30 +125,6 @@ mod tests {
 
     use quickcheck::quickcheck;
 
-    quickcheck! {
-        fn adapter_path_is_correct(id: u8) -> bool {
-            adapter_path(id).to_str().unwrap() == format!("/dev/dvb/adapter{}", id)
-        }
-    }
-
-    quickcheck! {
-        fn frontend_path_is_correct(a: u8, f: u8) -> bool {
-            frontend_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/frontend{}", a, f)
-        }
-    }
-
-    quickcheck! {
-        fn demux_path_is_correct(a: u8, f: u8) -> bool {
-            demux_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/demux{}", a, f)
-        }
-    }
-
-    quickcheck! {
-        fn dvr_path_is_correct(a: u8, f: u8) -> bool {
-            dvr_path(&FrontendId{adapter: a, frontend: f}).to_str().unwrap() == format!("/dev/dvb/adapter{}/dvr{}", a, f)
-        }
-    }
-
     quickcheck! {
         fn check_frontend_id_from_with_correct_structure(adapter: u8, frontend: u8) -> bool {
             Some(FrontendId{adapter: adapter, frontend: frontend}) == frontend_id_from(&format!("/dev/dvb/adapter{}/frontend{}", adapter, frontend))
@@ -19,9 +19,11 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
+use chrono::{Local, TimeZone};
 // use glib;
 use glib::prelude::*;
 use gdk;
@@ -29,8 +31,12 @@ use gdk::prelude::*;
 use gtk;
 use gtk::prelude::*;
 
-use crate::channels_data::encode_to_mrl;
+use crate::channels_data::{encode_to_mrl, get_service_key_of_channel_name};
 use crate::control_window_button::ControlWindowButton;
+use crate::dialogs::display_an_error_dialog;
+use crate::eit_sections::ServiceKey;
+use crate::epg_data;
+use crate::epg_event::Event;
 use crate::gstreamer_engine::GStreamerEngine;
 use crate::metvcombobox::{MeTVComboBox, MeTVComboBoxExt};
 use crate::preferences;
@@ -48,14 +54,22 @@ pub struct FrontendWindow {
     control_window_button: Rc<ControlWindowButton>,
     pub window: gtk::Window,  // ControlWindowButton instance needs access to this.
     pub close_button: gtk::Button, // ControlWindowButton instance needs access to this.
+    header_bar: gtk::HeaderBar,
+    programme_progress: gtk::ProgressBar,
+    record_button: gtk::ToggleButton,
     fullscreen_button: gtk::Button,
     volume_adjustment: gtk::Adjustment,
     pub volume_button: gtk::VolumeButton,  // ControlWindowButton instance uses this.
     pub channel_selector: MeTVComboBox, // ControlWindowButton instance needs access to this.
+    pub group_selector: gtk::ComboBoxText, // ControlWindowButton instance needs access to this.
     fullscreen_toolbar: gtk::Toolbar,
     fullscreen_unfullscreen_button: gtk::Button,
     fullscreen_volume_button: gtk::VolumeButton,
     pub fullscreen_channel_selector: MeTVComboBox, // ControlWindowButton instance needs access to this.
+    pub fullscreen_group_selector: gtk::ComboBoxText, // ControlWindowButton instance needs access to this.
+    fullscreen_now_next_label: gtk::Label,
+    fullscreen_programme_progress: gtk::ProgressBar,
+    fullscreen_record_button: gtk::ToggleButton,
     inhibitor: u32,
     pub engine: GStreamerEngine, // ControlWindowButton instance needs access to this.
 }
@@ -94,27 +108,49 @@ impl FrontendWindow {
             // Can only set the fullscreen_button actions after fullscreen_toolbar has been defined.
             f_b
         };
+        let record_button = {
+            let r_b = gtk::ToggleButton::new();
+            r_b.set_image(Some(&gtk::Image::from_icon_name(Some("media-record-symbolic"), gtk::IconSize::Button.into())));
+            r_b.set_tooltip_text(Some("Record the channel being watched."));
+            // Can only set the record_button actions after the frontend window has been created.
+            r_b
+        };
         let volume_adjustment = gtk::Adjustment::new(0.2, 0.0, 1.0, 0.01, 0.05, 0.0);
         // Cannot clone engine so have to wait for construction of the frontend window
         // to be able to define the action associated with the volume_adjustment.
         let volume_button = gtk::VolumeButton::new();
         let channel_selector = {
-            let c_s = MeTVComboBox::new_with_model(&control_window_button.control_window.channels_data_sorter);
+            let c_s = MeTVComboBox::new_with_model(&control_window_button.channels_filter);
             c_s.set_active(control_window_button.channel_selector.get_active());
             c_s.connect_changed({
                 let c_w_b = control_window_button.clone();
-                move |channel_selector| ControlWindowButton::on_channel_changed(&c_w_b, channel_selector.get_active().unwrap())
+                move |channel_selector| {
+                    if let Some(index) = channel_selector.get_active() {
+                        ControlWindowButton::on_channel_changed(&c_w_b, index);
+                    }
+                }
             });
             c_s
         };
+        let group_selector = gtk::ComboBoxText::new();
+        ControlWindowButton::init_group_selector(&control_window_button, &group_selector);
+        let programme_progress = {
+            let p_p = gtk::ProgressBar::new();
+            p_p.set_valign(gtk::Align::Center);
+            p_p.set_size_request(80, -1);
+            p_p
+        };
         let header_bar = {
             let h_b = gtk::HeaderBar::new();
             h_b.set_title(Some(&("Me TV – ".to_string() + &control_window_button.channel_selector.get_active_text().unwrap())));
             h_b.set_show_close_button(false);  // TODO Why have a special close button instead of the standard one?
             h_b.pack_end(&close_button);
             h_b.pack_end(&fullscreen_button);
+            h_b.pack_end(&record_button);
             h_b.pack_end(&volume_button);
             h_b.pack_start(&channel_selector);
+            h_b.pack_start(&group_selector);
+            h_b.pack_start(&programme_progress);
             h_b.show_all();
             h_b
         };
@@ -181,11 +217,15 @@ impl FrontendWindow {
         };
         let fullscreen_channel_selector = {
             let mut f_c_s = fullscreen_toolbar_builder.get_object::<MeTVComboBox>("fullscreen_channel_selector").unwrap();
-            f_c_s.init_with_model(&control_window_button.control_window.channels_data_sorter);
+            f_c_s.init_with_model(&control_window_button.channels_filter);
             f_c_s.set_active(control_window_button.channel_selector.get_active());
             f_c_s.connect_changed({
                 let c_w_b = control_window_button.clone();
-                move |f_c_s| ControlWindowButton::on_channel_changed(&c_w_b, f_c_s.get_active().unwrap())
+                move |f_c_s| {
+                    if let Some(index) = f_c_s.get_active() {
+                        ControlWindowButton::on_channel_changed(&c_w_b, index);
+                    }
+                }
             });
             //
             // TODO There appear to be no 'event-after' events posted for a ComboBox or it's child.
@@ -237,6 +277,15 @@ impl FrontendWindow {
             */
             f_c_s
         };
+        let fullscreen_group_selector = fullscreen_toolbar_builder.get_object::<gtk::ComboBoxText>("fullscreen_group_selector").unwrap();
+        ControlWindowButton::init_group_selector(&control_window_button, &fullscreen_group_selector);
+        let fullscreen_now_next_label = fullscreen_toolbar_builder.get_object::<gtk::Label>("fullscreen_now_next_label").unwrap();
+        let fullscreen_programme_progress = fullscreen_toolbar_builder.get_object::<gtk::ProgressBar>("fullscreen_programme_progress").unwrap();
+        let fullscreen_record_button = {
+            let f_r_b = fullscreen_toolbar_builder.get_object::<gtk::ToggleButton>("fullscreen_record_button").unwrap();
+            f_r_b.connect_event_after(|_, _| { add_timeout(); });
+            f_r_b
+        };
         let volume = volume_adjustment.get_value();
         volume_button.set_value(volume);
         fullscreen_volume_button.set_value(volume);
@@ -292,14 +341,22 @@ impl FrontendWindow {
             control_window_button: control_window_button.clone(),
             window,
             close_button,
+            header_bar,
+            programme_progress,
+            record_button,
             fullscreen_button,
             volume_adjustment,
             volume_button,
             channel_selector,
+            group_selector,
             fullscreen_toolbar,
             fullscreen_unfullscreen_button,
             fullscreen_volume_button,
             fullscreen_channel_selector,
+            fullscreen_group_selector,
+            fullscreen_now_next_label,
+            fullscreen_programme_progress,
+            fullscreen_record_button,
             inhibitor,
             engine,
         });
@@ -307,10 +364,88 @@ impl FrontendWindow {
             let f_w = frontend_window.clone();
             move |v_a| f_w.engine.set_volume(v_a.get_value())
         });
+        for button in [&frontend_window.record_button, &frontend_window.fullscreen_record_button].iter() {
+            button.connect_toggled({
+                let f_w = frontend_window.clone();
+                move |r_b| f_w.set_recording(r_b.get_active())
+            });
+        }
+        frontend_window.update_now_next();
+        // Keep the programme progress moving even when there are no EIT changes.
+        glib::timeout_add_seconds_local(30, {
+            let f_w = Rc::downgrade(&frontend_window);
+            move || {
+                match f_w.upgrade() {
+                    Some(f_w) => {
+                        if ! f_w.window.is_visible() { return Continue(false); }
+                        f_w.update_now_next();
+                        Continue(true)
+                    },
+                    None => Continue(false),
+                }
+            }
+        });
         Ok(frontend_window)
     }
 
+    /// Return the service of the channel being displayed.
+    pub fn get_service_key(&self) -> Option<ServiceKey> { // Used in control_window_button.rs
+        match self.control_window_button.channel_selector.get_active_text() {
+            Some(channel_name) => get_service_key_of_channel_name(&channel_name),
+            None => None,
+        }
+    }
+
+    /// Update the display of the present and following events of the channel being displayed.
+    pub fn update_now_next(&self) { // Used in control_window_button.rs
+        let now = epg_data::now();
+        let (present, following) = match self.get_service_key() {
+            Some(service) => epg_data::get_present_following(&service, now),
+            None => (None, None),
+        };
+        let text = now_next_text(present.as_ref(), following.as_ref());
+        self.header_bar.set_subtitle(Some(&text));
+        self.fullscreen_now_next_label.set_text(&text);
+        let synopsis = match present {
+            Some(ref event) => Some(event.synopsis()),
+            None => None,
+        };
+        self.fullscreen_now_next_label.set_tooltip_text(synopsis.as_deref());
+        let fraction = match present {
+            Some(ref event) => event.fraction_elapsed_at(now),
+            None => 0.0,
+        };
+        for progress_bar in [&self.programme_progress, &self.fullscreen_programme_progress].iter() {
+            progress_bar.set_fraction(fraction);
+            progress_bar.set_visible(present.is_some());
+            progress_bar.set_tooltip_text(synopsis.as_deref());
+        }
+    }
+
+    /// Start or stop recording the channel being watched, keeping the record buttons
+    /// consistent with the state of the recording.
+    pub fn set_recording(&self, record: bool) { // Used in control_window_button.rs
+        if record != self.engine.is_recording() {
+            if record {
+                let channel_name = self.control_window_button.channel_selector.get_active_text().unwrap();
+                let path = recording_path(&channel_name);
+                match self.engine.start_recording(&path) {
+                    Ok(_) => println!("Recording {} to {}", &channel_name, path.to_str().unwrap()),
+                    Err(error) => display_an_error_dialog(Some(&self.window), &format!("Could not start recording.\n\n{}", error)),
+                }
+            } else {
+                self.engine.stop_recording();
+            }
+        }
+        // Setting the state of the buttons causes a recursive call, but it does nothing.
+        let is_recording = self.engine.is_recording();
+        for button in [&self.record_button, &self.fullscreen_record_button].iter() {
+            button.set_active(is_recording);
+        }
+    }
+
     pub fn stop(&self) {
+        self.set_recording(false);
         if self.inhibitor  != 0 {
             let application = self.control_window_button.control_window.window.get_application().unwrap();
             application.uninhibit(self.inhibitor);
@@ -322,6 +457,23 @@ impl FrontendWindow {
     }
 }
 
+/// Return the path of the file to record the channel being watched to.
+fn recording_path(channel_name: &str) -> PathBuf {
+    let mut path = glib::get_user_special_dir(glib::UserDirectory::Videos)
+        .unwrap_or_else(|| PathBuf::from(std::env::var("HOME").expect("HOME is not set.")));
+    path.push(format!("{} – {}.ts", channel_name, Local::now().format("%Y-%m-%d %H:%M:%S")).replace('/', "-"));
+    path
+}
+
+/// The text describing the present and following events of a channel.
+fn now_next_text(present: Option<&Event>, following: Option<&Event>) -> String {
+    let describe = |label: &str, event: &Event| format!("{} {} {}", label, Local.timestamp(event.start_time, 0).format("%H:%M"), &event.title);
+    let mut text = Vec::new();
+    if let Some(event) = present { text.push(describe("Now:", event)); }
+    if let Some(event) = following { text.push(describe("Next:", event)); }
+    text.join("    ")
+}
+
 fn hide_cursor(widget: &gtk::Widget) {
     if let Some(window) = widget.get_window() {
         window.set_cursor(gdk::Cursor::from_name(&widget.get_display(), "none").as_ref());
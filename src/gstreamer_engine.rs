@@ -19,8 +19,11 @@
  *  along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
+use std::cell::{Cell, RefCell};
+use std::path::Path;
 use std::process::Command;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 //use gio;
 //use gio::prelude::*;
@@ -59,6 +62,10 @@ pub struct GStreamerEngine {
     playbin: gst::Element,
     video_element: gst::Element,
     pub video_widget: gtk::Widget, // FrontendWindow uses this for the overlay.
+    // The appsrc element of the recording pipeline when recording, the buffers from the
+    // playbin source are pushed into it. It is accessed from the streaming thread.
+    recording_source: Arc<Mutex<Option<gst::Element>>>,
+    recording_pipeline: RefCell<Option<gst::Pipeline>>,
 }
 
 impl GStreamerEngine {
@@ -111,6 +118,39 @@ impl GStreamerEngine {
                 None
             }
         }).expect("Could not connect a handler to the element-setup signal.");
+        let recording_source = Arc::new(Mutex::new(None::<gst::Element>));
+        playbin.connect("source-setup", false, {
+            let r_s = recording_source.clone();
+            move |values| {
+                // The source element for a dvb:// URI is a dvbbasebin, the src pad of which
+                // delivers the transport stream of the channel being watched. Recording is
+                // then just a matter of copying the buffers to the recording pipeline, so
+                // there is no need for another tuner.
+                let source = values[1]
+                    .get::<gst::Element>()
+                    .expect("Failed to get a handle on the source Element")
+                    .expect("Got None rather than an Some<Element>");
+                match source.get_static_pad("src") {
+                    Some(pad) => {
+                        pad.add_probe(gst::PadProbeType::BUFFER, {
+                            let r_s = r_s.clone();
+                            move |_, probe_info| {
+                                if let Some(gst::PadProbeData::Buffer(ref buffer)) = probe_info.data {
+                                    if let Some(ref appsrc) = *r_s.lock().unwrap() {
+                                        if let Err(error) = appsrc.emit("push-buffer", &[buffer]) {
+                                            println!("********  Failed to push a buffer to the recording: {}", error);
+                                        }
+                                    }
+                                }
+                                gst::PadProbeReturn::Ok
+                            }
+                        });
+                    },
+                    None => println!("********  The source element has no src pad so recording is not possible."),
+                }
+                None
+            }
+        }).expect("Could not connect a handler to the source-setup signal.");
         let bus = playbin.get_bus().unwrap();
         // The compiler cannot determine that the bus watch callback will be executed by the
         // same thread that the gtk::Application and ControlWindowButtons objects are created
@@ -342,6 +382,8 @@ impl GStreamerEngine {
                 playbin,
                 video_element: video_element.expect("'video_element' is None, this cannot happen."),
                 video_widget: video_widget.expect("'video_widget is None, this cannot happen."),
+                recording_source,
+                recording_pipeline: RefCell::new(None),
             };
             engine.video_element.set_property("force-aspect-ratio", &true).expect("Could not set 'force-aspect-ration' property");
             engine.playbin.set_property("video-sink", &engine.video_element).expect("Could not set 'video-sink' property");
@@ -393,6 +435,77 @@ impl GStreamerEngine {
         self.playbin.set_state(gst::State::Null).unwrap();
     }
 
+    /// Start writing the transport stream of the channel being watched to the file at `path`.
+    pub fn start_recording(&self, path: &Path) -> Result<(), String> {
+        let make_element = |factory_name: &str| {
+            gst::ElementFactory::make(factory_name, None).map_err(|_| format!("Could not create a '{}' element.", factory_name))
+        };
+        let appsrc = make_element("appsrc")?;
+        let queue = make_element("queue")?;
+        let filesink = make_element("filesink")?;
+        appsrc.set_property("caps", &gst::Caps::new_simple("video/mpegts", &[("systemstream", &true), ("packetsize", &188i32)]))
+            .expect("Could not set 'caps' property.");
+        appsrc.set_property("is-live", &true).expect("Could not set 'is-live' property.");
+        filesink.set_property("location", &path.to_str().unwrap()).expect("Could not set 'location' property.");
+        let pipeline = gst::Pipeline::new(Some("recording"));
+        pipeline.add_many(&[&appsrc, &queue, &filesink]).expect("Could not add elements to the recording pipeline.");
+        gst::Element::link_many(&[&appsrc, &queue, &filesink]).expect("Could not link the elements of the recording pipeline.");
+        if pipeline.set_state(gst::State::Playing).is_err() {
+            pipeline.set_state(gst::State::Null).unwrap();
+            return Err(format!("Could not start writing to {}.", path.to_str().unwrap()));
+        }
+        *self.recording_source.lock().unwrap() = Some(appsrc);
+        self.recording_pipeline.replace(Some(pipeline));
+        Ok(())
+    }
+
+    /// Stop recording, if there is a recording, ensuring all the data is written to the file.
+    ///
+    /// The end of stream has to get through the queue to the filesink before the pipeline
+    /// can be shut down. Rather than wait for it here on the GTK main thread, the pipeline is
+    /// shut down from a bus watch when the end of stream (or an error) arrives, or after
+    /// two seconds if neither does.
+    pub fn stop_recording(&self) {
+        let appsrc = self.recording_source.lock().unwrap().take();
+        if let Some(pipeline) = self.recording_pipeline.replace(None) {
+            match appsrc {
+                Some(appsrc) => {
+                    let is_finished = Rc::new(Cell::new(false));
+                    let bus = pipeline.get_bus().unwrap();
+                    bus.add_watch_local({
+                        let pipeline = pipeline.clone();
+                        let is_finished = is_finished.clone();
+                        move |_, msg| {
+                            match msg.view() {
+                                gst::MessageView::Eos(_) | gst::MessageView::Error(_) => {
+                                    if ! is_finished.replace(true) {
+                                        pipeline.set_state(gst::State::Null).unwrap();
+                                    }
+                                    Continue(false)
+                                },
+                                _ => Continue(true),
+                            }
+                        }
+                    }).expect("Could not add a watch to the bus of the recording.");
+                    glib::timeout_add_seconds_local(2, move || {
+                        if ! is_finished.replace(true) {
+                            println!("********  The recording did not finish within two seconds, stopping it anyway.");
+                            let _ = bus.remove_watch();
+                            pipeline.set_state(gst::State::Null).unwrap();
+                        }
+                        Continue(false)
+                    });
+                    appsrc.emit("end-of-stream", &[]).expect("Could not send end of stream to the recording.");
+                },
+                None => { pipeline.set_state(gst::State::Null).unwrap(); },
+            }
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording_pipeline.borrow().is_some()
+    }
+
     pub fn get_volume(&self) -> f64 {
         self.playbin.get_property("volume").unwrap().get().unwrap().unwrap()
     }
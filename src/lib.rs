@@ -0,0 +1,35 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The parts of Me TV that have no user interface, shared by the me-tv application and
+//! the me-tv-record, me-tv-schedule, and me-tv-scheduler command line tools.
+
+pub mod content_identifier;
+pub mod data_files;
+pub mod dvb_devices;
+pub mod dvbv5;
+pub mod epg_event;
+pub mod event_recording;
+pub mod multiplexes;
+pub mod recording_jobs;
+pub mod recording_profiles;
+pub mod recording_rules;
+pub mod tuner_allocation;
@@ -25,6 +25,9 @@ use std::thread;
 #[cfg(not(test))]
 use clap;
 
+#[cfg(not(test))]
+use exitcode;
+
 #[cfg(not(test))]
 use gio::prelude::*;
 //#[cfg(not(test))]
@@ -36,13 +39,29 @@ use gst;
 #[cfg(not(test))]
 use gst_mpegts;
 
+// The modules shared with the recording tools are in the library, cf. lib.rs.
+use me_tv::{content_identifier, data_files, dvb_devices, dvbv5, epg_event, multiplexes, recording_jobs, recording_profiles, recording_rules, tuner_allocation};
+
 mod about;
+mod channel_editor;
+mod channel_edits;
+mod channel_groups;
+mod channel_list_dialogs;
+mod channel_lists;
+mod channel_scan;
+mod channel_scanner;
 mod channels_data;
 mod control_window;
 mod control_window_button;
 mod dialogs;
 mod dvb;
+mod dvb_text;
+mod eit_sections;
+mod epg_data;
+mod epg_harvester;
 mod epg_manager;
+mod epg_window;
+mod extended_event;
 mod frontend_manager;
 mod frontend_window;
 mod gstreamer_engine;
@@ -51,7 +70,9 @@ mod metvcombobox;
 mod preferences;
 mod preferences_dialog;
 mod remote_control;
+mod scan_dialog;
 mod transmitter_dialog;
+mod xmltv;
 
 #[cfg(not(test))]
 fn main() {
@@ -72,7 +93,21 @@ fn main() {
         .arg(clap::Arg::with_name("no_gl")
             .long("no-gl")
             .help("Do not try to use OpenGL."))
+        .arg(clap::Arg::with_name("export_xmltv")
+            .long("export-xmltv")
+            .value_name("PATH")
+            .help("Write the programme guide gathered from the EIT to PATH as XMLTV, - for standard output, and exit."))
         .get_matches();
+    if let Some(path) = cli_matches.value_of("export_xmltv") {
+        match xmltv::export(path) {
+            Ok(_) => std::process::exit(exitcode::OK),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(exitcode::IOERR);
+            },
+        }
+    }
+    epg_data::init();
     if cli_matches.is_present("no_gl") {
         preferences::set_use_opengl(false, false);
     }
@@ -85,7 +120,7 @@ fn main() {
         let (to_epg_manager, from_gstreamer) = std::sync::mpsc::channel::<gst_mpegts::Section>();
         //  This variable is no longer used since the application menu was
         //  removed, but the ControlWindow instance must be created at this time.
-        let _control_window = control_window::ControlWindow::new(&app, from_manager, to_epg_manager);
+        let _control_window = control_window::ControlWindow::new(&app, from_manager, to_epg_manager.clone());
         // Spawn a thread to run the frontend manager process.
         thread::spawn({
             let t_c_w = to_control_window.clone();
@@ -101,8 +136,12 @@ fn main() {
             let t_c_w = to_control_window.clone();
             move ||{ epg_manager::run(t_c_w, from_gstreamer); }
         });
+        // Spawn a thread to run the collection of the EPG of all multiplexes.
+        thread::spawn(move || epg_harvester::run(to_epg_manager));
     });
     // Get a glib-gio warning if activate is not handled.
     application.connect_activate(move |_| { });
     application.run(&[]);
+    // The EPG manager writes the cache only every so often, keep what it has not written.
+    epg_data::write_epg_data_cache_if_dirty();
 }
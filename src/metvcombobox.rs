@@ -38,9 +38,9 @@ impl MeTVComboBoxExt for MeTVComboBox {
     /// Create a new `MeTVComboBox` and set the data model.
     ///
     /// It is assumed that the `TreeModel` is actually a `ListStore` or a
-    /// `TreeModelSort` backed by a `ListStore` with the `ListStore` having
-    /// two columns (`String`, `String`) being the channel number and
-    /// the channel name.
+    /// `TreeModelSort` or `TreeModelFilter` backed by a `ListStore` with the
+    /// first two columns (`String`, `String`) of the `ListStore` being the
+    /// channel number and the channel name.
     fn new_with_model<T: IsA<gtk::TreeModel>>(model: &T) -> MeTVComboBox {
         let mut combobox = gtk::ComboBox::new();
         combobox.init_with_model(model);
@@ -50,9 +50,9 @@ impl MeTVComboBoxExt for MeTVComboBox {
     /// Initialise and set the data model of a `MeTVComboBox`.
     ///
     /// It is assumed that the `TreeModel` is actually a `ListStore` or a
-    /// `TreeModelSort` backed by a `ListStore` with the `ListStore` having
-    /// two columns (`String`, `String`) being the channel number and
-    /// the channel name.
+    /// `TreeModelSort` or `TreeModelFilter` backed by a `ListStore` with the
+    /// first two columns (`String`, `String`) of the `ListStore` being the
+    /// channel number and the channel name.
     fn init_with_model<T: IsA<gtk::TreeModel>>(&mut self, model: &T) {
         self.set_model(Some(model));
         let number_renderer = gtk::CellRendererText::new();
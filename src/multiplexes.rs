@@ -0,0 +1,234 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Grouping the entries of a DVBv5 channels file by multiplex.
+//!
+//! All the channels on a multiplex can be received using a single tuner, a multiplex
+//! is identified by the delivery system and frequency, and for satellite delivery by
+//! the polarisation and stream_id as well. The key of a multiplex is only ever built
+//! from the typed tuning parameters of a channel, so the application and the command
+//! line tools agree on which channels share a multiplex.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use ini;
+use serde_derive::{Deserialize, Serialize};
+use xdg;
+
+use crate::dvbv5::Tuning;
+
+/// The tuning parameters that identify a multiplex.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct MultiplexKey {
+    pub delivery_system: String,
+    pub frequency: u32,
+    pub polarization: Option<String>,
+    pub stream_id: Option<u32>,
+}
+
+impl MultiplexKey {
+    /// Return the multiplex of the tuning parameters of a channel, `None` if there is no
+    /// frequency.
+    pub fn of_tuning(tuning: &Tuning) -> Option<MultiplexKey> {
+        Some(MultiplexKey {
+            delivery_system: tuning.delivery_system.map_or("", |d| d.to_dvbv5()).to_string(),
+            frequency: tuning.frequency?,
+            polarization: tuning.polarization.map(|p| p.to_dvbv5().to_string()),
+            stream_id: tuning.stream_id,
+        })
+    }
+}
+
+impl fmt::Display for MultiplexKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} Hz", self.delivery_system, self.frequency)?;
+        if let Some(ref polarization) = self.polarization {
+            write!(f, " {}", polarization)?;
+        }
+        if let Some(stream_id) = self.stream_id {
+            write!(f, " stream {}", stream_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// A channel as needed for recording: its name, service_id, and multiplex.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MultiplexChannel {
+    pub name: String,
+    pub service_id: u16,
+    pub multiplex: MultiplexKey,
+}
+
+/// Return the path to the GStreamer dvbsrc plugin channels file using the XDG directory
+/// structure.
+pub fn channels_file_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("gstreamer-1.0").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_config_home();
+    path_buf.push("dvb-channels.conf");
+    path_buf
+}
+
+/// Return the multiplex of a channels file entry, `None` if the entry has no frequency.
+pub fn multiplex_key_of(properties: &ini::Properties) -> Option<MultiplexKey> {
+    MultiplexKey::of_tuning(&Tuning::from_properties(properties.iter()))
+}
+
+/// Return the channels of a channels file that have a multiplex and a service_id.
+pub fn get_multiplex_channels(ini: &ini::Ini) -> Vec<MultiplexChannel> {
+    ini.iter()
+        .filter_map(|(name, properties)| {
+            Some(MultiplexChannel {
+                name: name?.to_string(),
+                service_id: properties.get("SERVICE_ID")?.trim().parse::<u16>().ok()?,
+                multiplex: multiplex_key_of(properties)?,
+            })
+        })
+        .collect()
+}
+
+/// Return the multiplex of the named channel, `None` if the channel is not in the channels file.
+pub fn get_multiplex_of(ini: &ini::Ini, channel_name: &str) -> Option<MultiplexKey> {
+    multiplex_key_of(ini.section(Some(channel_name))?)
+}
+
+/// Return the names of the channels of a channels file grouped by multiplex.
+pub fn group_by_multiplex(ini: &ini::Ini) -> BTreeMap<MultiplexKey, Vec<String>> {
+    let mut groups = BTreeMap::new();
+    for channel in get_multiplex_channels(ini) {
+        groups.entry(channel.multiplex).or_insert_with(Vec::new).push(channel.name);
+    }
+    groups
+}
+
+/// Return the channels with the given names if they are all on the same multiplex,
+/// otherwise return an explanation of why not.
+///
+/// Each service can only be written to one file, so a channel given twice, or two names
+/// for the same service, are refused.
+pub fn find_channels_on_one_multiplex(ini: &ini::Ini, names: &[&str]) -> Result<Vec<MultiplexChannel>, String> {
+    let channels = get_multiplex_channels(ini);
+    let mut result: Vec<MultiplexChannel> = Vec::new();
+    for name in names.iter() {
+        match channels.iter().find(|c| c.name == *name) {
+            Some(channel) => {
+                if let Some(other) = result.iter().find(|c| c.service_id == channel.service_id && c.multiplex == channel.multiplex) {
+                    return Err(if other.name == channel.name {
+                        format!("The channel '{}' is given more than once.", name)
+                    } else {
+                        format!("The channels '{}' and '{}' are the same service so cannot both be recorded.", other.name, name)
+                    });
+                }
+                result.push(channel.clone())
+            },
+            None => return Err(format!("There is no channel called '{}' with tuning data in the channels file.", name)),
+        }
+    }
+    if let Some(first) = result.first() {
+        let others = result.iter().filter(|c| c.multiplex != first.multiplex).collect::<Vec<&MultiplexChannel>>();
+        if ! others.is_empty() {
+            let mut explanation = format!("The channels are not all on the same multiplex so cannot be recorded with a single tuner:\n    {} is on {}", first.name, first.multiplex);
+            for channel in others {
+                explanation.push_str(&format!("\n    {} is on {}", channel.name, channel.multiplex));
+            }
+            return Err(explanation);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNELS: &str = "
+[BBC ONE Lon]
+        SERVICE_ID = 4164
+        FREQUENCY = 490000000
+        DELIVERY_SYSTEM = DVBT
+
+[BBC TWO]
+        SERVICE_ID = 4287
+        FREQUENCY = 490000000
+        DELIVERY_SYSTEM = DVBT
+
+[ITV]
+        SERVICE_ID = 8261
+        FREQUENCY = 514000000
+        DELIVERY_SYSTEM = DVBT
+
+[Sat 1]
+        SERVICE_ID = 17500
+        FREQUENCY = 12544000
+        POLARIZATION = horizontal
+        DELIVERY_SYSTEM = DVBS2
+
+[Sat 2]
+        SERVICE_ID = 17501
+        FREQUENCY = 12544000
+        POLARIZATION = VERTICAL
+        DELIVERY_SYSTEM = DVBS2
+";
+
+    #[test]
+    fn channels_are_grouped_by_frequency_and_polarization() {
+        let ini = ini::Ini::load_from_str(CHANNELS).unwrap();
+        let groups = group_by_multiplex(&ini);
+        assert_eq!(groups.len(), 4);
+        let key = MultiplexKey { delivery_system: "DVBT".to_string(), frequency: 490000000, polarization: None, stream_id: None };
+        assert_eq!(groups.get(&key).unwrap(), &vec!["BBC ONE Lon".to_string(), "BBC TWO".to_string()]);
+        assert_eq!(get_multiplex_of(&ini, "BBC TWO"), Some(key));
+        assert_eq!(get_multiplex_of(&ini, "Dave"), None);
+        assert_eq!(get_multiplex_of(&ini, "Sat 1").unwrap().polarization, Some("HORIZONTAL".to_string()));
+    }
+
+    #[test]
+    fn channels_on_one_multiplex_are_accepted() {
+        let ini = ini::Ini::load_from_str(CHANNELS).unwrap();
+        let channels = find_channels_on_one_multiplex(&ini, &["BBC TWO", "BBC ONE Lon"]).unwrap();
+        assert_eq!(channels.iter().map(|c| c.service_id).collect::<Vec<u16>>(), vec![4287, 4164]);
+    }
+
+    #[test]
+    fn channels_on_different_multiplexes_are_refused() {
+        let ini = ini::Ini::load_from_str(CHANNELS).unwrap();
+        let explanation = find_channels_on_one_multiplex(&ini, &["BBC ONE Lon", "ITV"]).unwrap_err();
+        assert!(explanation.contains("ITV is on DVBT 514000000 Hz"));
+        assert!(find_channels_on_one_multiplex(&ini, &["Sat 1", "Sat 2"]).is_err());
+        assert!(find_channels_on_one_multiplex(&ini, &["BBC ONE Lon", "Dave"]).is_err());
+    }
+
+    #[test]
+    fn the_same_service_twice_is_refused() {
+        let ini = ini::Ini::load_from_str(&format!("{}
+[BBC ONE]
+        SERVICE_ID = 4164
+        FREQUENCY = 490000000
+        DELIVERY_SYSTEM = DVBT
+", CHANNELS)).unwrap();
+        let explanation = find_channels_on_one_multiplex(&ini, &["BBC TWO", "BBC TWO"]).unwrap_err();
+        assert!(explanation.contains("'BBC TWO' is given more than once"));
+        let explanation = find_channels_on_one_multiplex(&ini, &["BBC ONE Lon", "BBC ONE"]).unwrap_err();
+        assert!(explanation.contains("'BBC ONE Lon' and 'BBC ONE' are the same service"));
+    }
+}
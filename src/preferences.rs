@@ -42,8 +42,21 @@ struct Preferences {
     last_channel: String,
     nongl_deinterlace_method: String,
     gl_deinterlace_method: String,
+    // Added after the first release so must default for older preferences files.
+    #[serde(default)]
+    xmltv_file: String,
+    #[serde(default)]
+    epg_harvest: bool,
+    #[serde(default = "default_epg_harvest_dwell_time")]
+    epg_harvest_dwell_time: u32, // Seconds.
+    #[serde(default = "default_epg_harvest_interval")]
+    epg_harvest_interval: u32, // Hours.
 }
 
+fn default_epg_harvest_dwell_time() -> u32 { 120 }
+
+fn default_epg_harvest_interval() -> u32 { 6 }
+
 // TODO Replace the Mutex with a RwLock.
 lazy_static! {
     static ref PREFERENCES: Mutex<RefCell<Preferences>> = Mutex::new(RefCell::new(Preferences{
@@ -55,6 +68,10 @@ lazy_static! {
         last_channel: String::from(""),
         nongl_deinterlace_method: "".to_string(),
         gl_deinterlace_method: "".to_string(),
+        xmltv_file: "".to_string(),
+        epg_harvest: false,
+        epg_harvest_dwell_time: default_epg_harvest_dwell_time(),
+        epg_harvest_interval: default_epg_harvest_interval(),
     }));
 }
 
@@ -143,12 +160,7 @@ macro_rules! create_setter {
     }
 }
 
-pub fn get_delivery_system() -> dvb::DeliverySystem {
-    match PREFERENCES.lock() {
-        Ok(preferences) => preferences.borrow().delivery_system.clone(),  //  Must clone here so can't use the macro.
-        Err(_) => dvb::DeliverySystem::DVBT,
-    }
-}
+create_getter!(get_delivery_system, delivery_system, dvb::DeliverySystem, dvb::DeliverySystem::DVBT);
 create_setter!(set_delivery_system, delivery_system, dvb::DeliverySystem);
 
 create_getter!(get_use_opengl, use_opengl, bool, true);
@@ -171,3 +183,15 @@ create_setter!(set_nongl_deinterlace_method, nongl_deinterlace_method, String);
 
 create_option_getter!(get_gl_deinterlace_method, gl_deinterlace_method, String, None);
 create_setter!(set_gl_deinterlace_method, gl_deinterlace_method, String);
+
+create_option_getter!(get_xmltv_file, xmltv_file, String, None);
+create_setter!(set_xmltv_file, xmltv_file, String);
+
+create_getter!(get_epg_harvest, epg_harvest, bool, false);
+create_setter!(set_epg_harvest, epg_harvest, bool);
+
+create_getter!(get_epg_harvest_dwell_time, epg_harvest_dwell_time, u32, default_epg_harvest_dwell_time());
+create_setter!(set_epg_harvest_dwell_time, epg_harvest_dwell_time, u32);
+
+create_getter!(get_epg_harvest_interval, epg_harvest_interval, u32, default_epg_harvest_interval());
+create_setter!(set_epg_harvest_interval, epg_harvest_interval, u32);
@@ -40,12 +40,14 @@ fn create(control_window: &ControlWindow) -> gtk::Window {
     let menu_builder = gtk::Builder::from_string(include_str!("resources/preferences_dialog.glade.xml"));
     let _delivery_system_comboboxtext = {
         let comboboxtext = menu_builder.get_object::<gtk::ComboBoxText>("delivery_system").unwrap();
-        for delivery_system in dvb::DeliverySystem::iterator() {
+        for delivery_system in dvb::delivery_systems() {
             comboboxtext.append_text(&delivery_system.to_string());
         }
-        comboboxtext.set_active(Some(preferences::get_delivery_system().get_index()));
+        comboboxtext.set_active(dvb::index_of(preferences::get_delivery_system()));
         comboboxtext.connect_changed(
-            move |selector| preferences::set_delivery_system(selector.get_active_text().unwrap().as_str().into(), true)
+            move |selector| if let Some(delivery_system) = dvb::DeliverySystem::from_linux_name(selector.get_active_text().unwrap().as_str()) {
+                preferences::set_delivery_system(delivery_system, true)
+            }
         );
         comboboxtext
     };
@@ -122,6 +124,56 @@ fn create(control_window: &ControlWindow) -> gtk::Window {
         );
         comboboxtext
     };
+    let _xmltv_file_selector = {
+        let file_chooser_button = menu_builder.get_object::<gtk::FileChooserButton>("xmltv_file").unwrap();
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some("XMLTV files"));
+        filter.add_pattern("*.xml");
+        file_chooser_button.add_filter(&filter);
+        if let Some(path) = preferences::get_xmltv_file() {
+            if path != "" {
+                file_chooser_button.set_filename(&path);
+            }
+        }
+        file_chooser_button.connect_file_set(
+            move |chooser| if let Some(path) = chooser.get_filename() {
+                preferences::set_xmltv_file(path.to_str().unwrap().to_string(), true)
+            }
+        );
+        let clear_button = menu_builder.get_object::<gtk::Button>("clear_xmltv_file").unwrap();
+        clear_button.connect_clicked({
+            let chooser = file_chooser_button.clone();
+            move |_| {
+                chooser.unselect_all();
+                preferences::set_xmltv_file("".to_string(), true);
+            }
+        });
+        file_chooser_button
+    };
+    let _epg_harvest_button = {
+        let button = menu_builder.get_object::<gtk::CheckButton>("epg_harvest").unwrap();
+        button.set_active(preferences::get_epg_harvest());
+        button.connect_toggled(
+            move |b| preferences::set_epg_harvest(b.get_active(), true)
+        );
+        button
+    };
+    let _epg_harvest_dwell_time_spinbutton = {
+        let spinbutton = menu_builder.get_object::<gtk::SpinButton>("epg_harvest_dwell_time").unwrap();
+        spinbutton.set_value(preferences::get_epg_harvest_dwell_time() as f64);
+        spinbutton.connect_value_changed(
+            move |s| preferences::set_epg_harvest_dwell_time(s.get_value_as_int() as u32, true)
+        );
+        spinbutton
+    };
+    let _epg_harvest_interval_spinbutton = {
+        let spinbutton = menu_builder.get_object::<gtk::SpinButton>("epg_harvest_interval").unwrap();
+        spinbutton.set_value(preferences::get_epg_harvest_interval() as f64);
+        spinbutton.connect_value_changed(
+            move |s| preferences::set_epg_harvest_interval(s.get_value_as_int() as u32, true)
+        );
+        spinbutton
+    };
     let preferences_dialog = {
         let window = menu_builder.get_object::<gtk::Window>("preferences_dialog").unwrap();
         window.set_transient_for(Some(&control_window.window));
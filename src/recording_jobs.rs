@@ -0,0 +1,273 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The list of scheduled recordings.
+//!
+//! me-tv-schedule adds, edits, and cancels jobs, me-tv-scheduler starts the recordings
+//! when they are due and removes them when they have finished, and me-tv adds jobs for
+//! the recording rules and follows the events being recorded. The list is stored, with
+//! the id to give the next job, as a YAML file in the XDG data directory. Every change
+//! of the list is made using `update_recording_jobs` so that no process loses a change
+//! made by another at the same time, cf. data_files.rs.
+
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+use xdg;
+
+use crate::data_files;
+
+/// How long, in seconds, before the padded start of the recording of an event the
+/// tuner is started, so that the EIT sections for the event have been received.
+pub const EVENT_LEAD_TIME: i64 = 120;
+
+/// How long, in seconds, after its scheduled end the recording of an event waits for
+/// the event to finish, in case the schedule is overrunning.
+pub const EVENT_MAXIMUM_DELAY: i64 = 3600;
+
+/// The padding, in minutes, used when none is given.
+pub const DEFAULT_PADDING_BEFORE: u32 = 2;
+pub const DEFAULT_PADDING_AFTER: u32 = 10;
+
+/// The EIT event recorded by a job: the recording starts and stops as the running
+/// status of the event says it does rather than at the times of the job.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ScheduledEvent {
+    pub event_id: u16,
+    pub padding_before: u32, // Minutes.
+    pub padding_after: u32, // Minutes.
+}
+
+/// A scheduled recording. For the recording of an event the start time and duration
+/// are those of the event.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RecordingJob {
+    pub id: u32,
+    pub channel: String,
+    pub start_time: i64, // Seconds since the Unix epoch, UTC.
+    pub duration: u32, // Minutes.
+    pub output: String,
+    pub adapter: u8,
+    pub frontend: u8,
+    pub profile: String,
+    #[serde(default)]
+    pub started: bool,
+    #[serde(default)]
+    pub event: Option<ScheduledEvent>,
+}
+
+/// The scheduled recordings and the id to give the next one. Ids are never reused so an
+/// id reported to the user, or held by me-tv-scheduler for a running recording, cannot
+/// come to mean a different recording.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RecordingJobs {
+    #[serde(default)]
+    pub next_id: u32,
+    #[serde(default)]
+    pub jobs: Vec<RecordingJob>,
+}
+
+impl RecordingJob {
+    /// The end time of the recording in seconds since the Unix epoch, UTC.
+    pub fn end_time(&self) -> i64 { self.start_time + 60 * self.duration as i64 }
+
+    /// The time from which the job needs a tuner, seconds since the Unix epoch, UTC.
+    pub fn tuner_start_time(&self) -> i64 {
+        match self.event {
+            Some(ref event) => self.start_time - 60 * event.padding_before as i64 - EVENT_LEAD_TIME,
+            None => self.start_time,
+        }
+    }
+
+    /// The time until which the job needs a tuner, seconds since the Unix epoch, UTC.
+    /// The recording of an event may run later if the event is late.
+    pub fn tuner_end_time(&self) -> i64 {
+        match self.event {
+            Some(ref event) => self.end_time() + 60 * event.padding_after as i64,
+            None => self.end_time(),
+        }
+    }
+}
+
+/// Return a `PathBuf` to the recording jobs file using the XDG directory structure.
+pub fn recording_jobs_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_data_home();
+    path_buf.push("recording_jobs.yml");
+    path_buf
+}
+
+/// Read the recording jobs from a file, there are no jobs if there is no file.
+pub fn read_recording_jobs(path: &Path) -> Result<RecordingJobs, String> { data_files::read_yaml(path) }
+
+pub fn write_recording_jobs(path: &Path, jobs: &RecordingJobs) -> Result<(), String> { data_files::write_yaml(path, jobs) }
+
+/// Read the recording jobs from the file at `path`, apply `change` to them, and write
+/// them back if they have been changed, returning the result of `change`.
+pub fn update_recording_jobs<F, T>(path: &Path, change: F) -> Result<T, String> where F: FnOnce(&mut RecordingJobs) -> T {
+    data_files::update_yaml(path, change)
+}
+
+/// Add a job to the list giving it a new id, the list is kept in start time order.
+/// Returns the id of the job.
+pub fn add_job(jobs: &mut RecordingJobs, mut job: RecordingJob) -> u32 {
+    // The ids of the jobs are checked as well in case the file has been edited by hand.
+    job.id = jobs.jobs.iter().map(|j| j.id + 1).fold(jobs.next_id.max(1), u32::max);
+    jobs.next_id = job.id + 1;
+    let id = job.id;
+    jobs.jobs.push(job);
+    jobs.jobs.sort_by_key(|j| (j.start_time, j.id));
+    id
+}
+
+/// Remove the job with the given id from the list, returning it if there was one.
+pub fn remove_job(jobs: &mut Vec<RecordingJob>, id: u32) -> Option<RecordingJob> {
+    let index = jobs.iter().position(|j| j.id == id)?;
+    Some(jobs.remove(index))
+}
+
+/// Replace the job with the same id as the given one, returning false if there is no
+/// such job.
+pub fn replace_job(jobs: &mut [RecordingJob], job: RecordingJob) -> bool {
+    match jobs.iter_mut().find(|j| j.id == job.id) {
+        Some(j) => *j = job,
+        None => return false,
+    }
+    jobs.sort_by_key(|j| (j.start_time, j.id));
+    true
+}
+
+/// Update the jobs waiting to record an event of a channel for a change of the start
+/// time or duration, in seconds, of the event. Return `true` if a job was changed.
+///
+/// Jobs being recorded are left alone, me-tv-record follows the event itself.
+pub fn follow_event(jobs: &mut [RecordingJob], channel: &str, event_id: u16, start_time: i64, duration: u32) -> bool {
+    let duration = (duration + 59) / 60;
+    let mut is_changed = false;
+    for job in jobs.iter_mut() {
+        if ! job.started && job.channel == channel && job.event.as_ref().map_or(false, |e| e.event_id == event_id)
+            && (job.start_time != start_time || job.duration != duration) {
+            job.start_time = start_time;
+            job.duration = duration;
+            is_changed = true;
+        }
+    }
+    if is_changed {
+        jobs.sort_by_key(|j| (j.start_time, j.id));
+    }
+    is_changed
+}
+
+/// The number of seconds from `now` until the next job is due, `None` if there are no
+/// jobs waiting to be started.
+pub fn seconds_until_next_job(jobs: &[RecordingJob], now: i64) -> Option<i64> {
+    jobs.iter().filter(|j| ! j.started).map(|j| (j.tuner_start_time() - now).max(0)).min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(channel: &str, start_time: i64, duration: u32) -> RecordingJob {
+        RecordingJob {
+            id: 0,
+            channel: channel.to_string(),
+            start_time,
+            duration,
+            output: format!("/tmp/{} recording.ts", channel),
+            adapter: 0,
+            frontend: 0,
+            profile: "ts-passthrough".to_string(),
+            started: false,
+            event: None,
+        }
+    }
+
+    #[test]
+    fn jobs_are_given_new_ids_and_kept_in_start_time_order() {
+        let mut jobs = RecordingJobs::default();
+        assert_eq!(add_job(&mut jobs, job("BBC TWO", 2000, 30)), 1);
+        assert_eq!(add_job(&mut jobs, job("BBC ONE Lon", 1000, 30)), 2);
+        assert_eq!(jobs.jobs.iter().map(|j| j.id).collect::<Vec<u32>>(), vec![2, 1]);
+        assert_eq!(remove_job(&mut jobs.jobs, 2).unwrap().channel, "BBC ONE Lon");
+        assert_eq!(remove_job(&mut jobs.jobs, 2), None);
+        assert_eq!(add_job(&mut jobs, job("ITV", 3000, 30)), 3);
+        assert_eq!(remove_job(&mut jobs.jobs, 3).unwrap().channel, "ITV");
+        assert_eq!(add_job(&mut jobs, job("ITV", 3000, 30)), 4);
+    }
+
+    #[test]
+    fn started_jobs_are_not_waiting() {
+        let mut jobs = RecordingJobs::default();
+        add_job(&mut jobs, job("BBC TWO", 500, 10));
+        add_job(&mut jobs, job("ITV", 2000, 10));
+        let mut jobs = jobs.jobs;
+        assert_eq!(seconds_until_next_job(&jobs, 1000), Some(0));
+        jobs[0].started = true;
+        assert_eq!(seconds_until_next_job(&jobs, 1000), Some(1000));
+        jobs[1].started = true;
+        assert_eq!(seconds_until_next_job(&jobs, 1000), None);
+    }
+
+    #[test]
+    fn event_jobs_follow_the_event() {
+        let mut jobs = RecordingJobs::default();
+        let mut event_job = job("BBC TWO", 3000, 30);
+        event_job.event = Some(ScheduledEvent{event_id: 17, padding_before: 2, padding_after: 10});
+        add_job(&mut jobs, event_job);
+        add_job(&mut jobs, job("BBC TWO", 3000, 30));
+        let mut jobs = jobs.jobs;
+        assert_eq!((jobs[0].tuner_start_time(), jobs[0].tuner_end_time()), (3000 - 120 - EVENT_LEAD_TIME, 3000 + 1800 + 600));
+        assert!(! follow_event(&mut jobs, "BBC TWO", 17, 3000, 1800));
+        assert!(follow_event(&mut jobs, "BBC TWO", 17, 3300, 1790));
+        assert_eq!((jobs[0].start_time, jobs[0].duration), (3000, 30));
+        assert_eq!((jobs[1].start_time, jobs[1].duration), (3300, 30));
+    }
+
+    #[test]
+    fn write_and_read_recording_jobs() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("recording_jobs.yml");
+        assert_eq!(read_recording_jobs(&path).unwrap(), RecordingJobs::default());
+        let mut jobs = RecordingJobs::default();
+        add_job(&mut jobs, job("BBC ONE Lon", 1000, 30));
+        write_recording_jobs(&path, &jobs).unwrap();
+        assert_eq!(read_recording_jobs(&path).unwrap(), jobs);
+    }
+
+    #[test]
+    fn update_recording_jobs_writes_only_changes() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("recording_jobs.yml");
+        let id = update_recording_jobs(&path, |jobs| add_job(jobs, job("BBC ONE Lon", 1000, 30))).unwrap();
+        assert_eq!(id, 1);
+        assert!(directory.path().join("recording_jobs.lock").exists());
+        let id = update_recording_jobs(&path, |jobs| add_job(jobs, job("BBC TWO", 2000, 30))).unwrap();
+        assert_eq!(id, 2);
+        assert_eq!(read_recording_jobs(&path).unwrap().jobs.iter().map(|j| j.id).collect::<Vec<u32>>(), vec![1, 2]);
+        update_recording_jobs(&path, |jobs| remove_job(&mut jobs.jobs, 2)).unwrap();
+        let id = update_recording_jobs(&path, |jobs| add_job(jobs, job("ITV", 3000, 30))).unwrap();
+        assert_eq!(id, 3);
+        std::fs::remove_file(&path).unwrap();
+        assert!(update_recording_jobs(&path, |jobs| jobs.jobs.is_empty()).unwrap());
+        assert!(! path.exists());
+    }
+}
@@ -0,0 +1,212 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Recording profiles: the container and codecs to use when recording.
+//!
+//! The profiles are stored as a YAML file in the XDG config directory, if there is
+//! no file the default profiles are written to it so that users have something to
+//! edit. The video and audio entries are GStreamer pipeline descriptions, e.g.
+//! "audioconvert ! avenc_aac", so encoder properties can be set in the profile.
+
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_yaml;
+use xdg;
+
+/// The name of the profile used if none is specified.
+pub const DEFAULT_PROFILE_NAME: &str = "ts-passthrough";
+
+/// A recording profile. A profile with no muxer writes the transport stream of the
+/// channel untouched, otherwise the channel is decoded and re-encoded.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RecordingProfile {
+    pub name: String,
+    pub extension: String,
+    #[serde(default)]
+    pub muxer: Option<String>,
+    #[serde(default)]
+    pub video: Option<String>,
+    #[serde(default)]
+    pub audio: Option<String>,
+}
+
+impl RecordingProfile {
+    fn new(name: &str, extension: &str, muxer: Option<&str>, video: Option<&str>, audio: Option<&str>) -> RecordingProfile {
+        RecordingProfile {
+            name: name.to_string(),
+            extension: extension.to_string(),
+            muxer: muxer.map(|s| s.to_string()),
+            video: video.map(|s| s.to_string()),
+            audio: audio.map(|s| s.to_string()),
+        }
+    }
+
+    /// Is this the profile that writes the transport stream untouched.
+    pub fn is_passthrough(&self) -> bool { self.muxer.is_none() }
+
+    /// The names of all the GStreamer elements needed to record using this profile. Recording
+    /// an event, rather than for a duration, also needs a valve to hold back the stream until
+    /// the event starts.
+    pub fn element_names(&self, is_event: bool) -> Vec<String> {
+        let mut names = if self.is_passthrough() {
+            vec!["dvbbasebin".to_string(), "queue".to_string()]
+        } else {
+            vec!["uridecodebin".to_string(), "queue".to_string(), "fakesink".to_string()]
+        };
+        for description in [&self.muxer, &self.video, &self.audio].iter().filter_map(|d| d.as_ref()) {
+            names.extend(element_names_of_description(description));
+        }
+        if is_event {
+            names.push("valve".to_string());
+        }
+        names.push("filesink".to_string());
+        let mut unique_names = Vec::new();
+        for name in names {
+            if ! unique_names.contains(&name) {
+                unique_names.push(name);
+            }
+        }
+        unique_names
+    }
+}
+
+/// The element names used in a GStreamer pipeline description, which is a sequence of
+/// element names each with optional properties separated by '!'.
+fn element_names_of_description(description: &str) -> Vec<String> {
+    description.split('!')
+        .filter_map(|element| element.split_whitespace().next())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// The profiles used if there is no recording profiles file.
+pub fn default_profiles() -> Vec<RecordingProfile> {
+    vec![
+        RecordingProfile::new(DEFAULT_PROFILE_NAME, "ts", None, None, None),
+        RecordingProfile::new("mp4-h264-ac3", "mp4", Some("mp4mux"), Some("x264enc"), Some("avenc_ac3")),
+        RecordingProfile::new("mkv-h264-aac", "mkv", Some("matroskamux"), Some("videoconvert ! x264enc"), Some("audioconvert ! avenc_aac")),
+        RecordingProfile::new("mp4-h265", "mp4", Some("mp4mux"), Some("videoconvert ! x265enc ! h265parse"), Some("audioconvert ! avenc_aac")),
+        RecordingProfile::new("webm-vp9-opus", "webm", Some("webmmux"), Some("videoconvert ! vp9enc"), Some("audioconvert ! audioresample ! opusenc")),
+    ]
+}
+
+/// Return a `PathBuf` to the recording profiles file using the XDG directory structure.
+pub fn recording_profiles_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_config_home();
+    path_buf.push("recording_profiles.yml");
+    path_buf
+}
+
+/// Write recording profiles to a file.
+fn write_recording_profiles(path: &Path, profiles: &Vec<RecordingProfile>) -> Result<(), String> {
+    create_dir_all(path.parent().unwrap()).map_err(|e| format!("Failed to create {:?} – {}", path.parent().unwrap(), e))?;
+    let mut file = OpenOptions::new().write(true).truncate(true).create(true).open(path)
+        .map_err(|e| format!("Failed to open {} – {}", path.to_str().unwrap(), e))?;
+    let buffer = serde_yaml::to_string(profiles).unwrap();
+    file.write_all(buffer.as_ref()).map_err(|e| format!("Failed to write {} – {}", path.to_str().unwrap(), e))
+}
+
+/// Read recording profiles from a file.
+fn read_recording_profiles(path: &Path) -> Result<Vec<RecordingProfile>, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {} – {}", path.to_str().unwrap(), e))?;
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer).map_err(|e| format!("Failed to read {} – {}", path.to_str().unwrap(), e))?;
+    serde_yaml::from_str::<Vec<RecordingProfile>>(&buffer).map_err(|e| format!("Failed to deserialise {} – {}", path.to_str().unwrap(), e))
+}
+
+/// Return the recording profiles, writing the default profiles to the recording
+/// profiles file if it does not exist.
+pub fn get_recording_profiles() -> Result<Vec<RecordingProfile>, String> {
+    let path = recording_profiles_path();
+    if path.is_file() {
+        read_recording_profiles(&path)
+    } else {
+        let profiles = default_profiles();
+        if let Err(e) = write_recording_profiles(&path, &profiles) {
+            println!("{}", e);
+        }
+        Ok(profiles)
+    }
+}
+
+/// Return the profile with the given name if there is one.
+pub fn find_profile(profiles: &[RecordingProfile], name: &str) -> Option<RecordingProfile> {
+    profiles.iter().find(|p| p.name == name).cloned()
+}
+
+/// Return the names of the elements needed by the profile, and to record an event if
+/// `is_event`, for which `is_available` returns false.
+pub fn missing_elements<F>(profile: &RecordingProfile, is_event: bool, is_available: F) -> Vec<String> where F: Fn(&str) -> bool {
+    profile.element_names(is_event).into_iter().filter(|name| ! is_available(name)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_profiles_include_the_default_profile() {
+        let profiles = default_profiles();
+        assert!(find_profile(&profiles, DEFAULT_PROFILE_NAME).unwrap().is_passthrough());
+        assert!(! find_profile(&profiles, "mkv-h264-aac").unwrap().is_passthrough());
+        assert_eq!(find_profile(&profiles, "avi-xvid"), None);
+    }
+
+    #[test]
+    fn element_names_include_all_the_elements_of_the_descriptions() {
+        let profile = find_profile(&default_profiles(), "webm-vp9-opus").unwrap();
+        assert_eq!(profile.element_names(false), vec![
+            "uridecodebin", "queue", "fakesink", "webmmux", "videoconvert", "vp9enc", "audioconvert", "audioresample", "opusenc", "filesink",
+        ]);
+        let profile = RecordingProfile::new("x", "mkv", Some("matroskamux"), Some("x264enc  tune=zerolatency ! h264parse"), None);
+        assert_eq!(element_names_of_description(profile.video.as_ref().unwrap()), vec!["x264enc", "h264parse"]);
+        let profile = find_profile(&default_profiles(), DEFAULT_PROFILE_NAME).unwrap();
+        assert_eq!(profile.element_names(true), vec!["dvbbasebin", "queue", "valve", "filesink"]);
+    }
+
+    #[test]
+    fn missing_elements_are_reported() {
+        let profile = find_profile(&default_profiles(), "mp4-h264-ac3").unwrap();
+        assert_eq!(missing_elements(&profile, false, |name| name != "x264enc" && name != "avenc_ac3"), vec!["x264enc", "avenc_ac3"]);
+        assert!(missing_elements(&profile, false, |_| true).is_empty());
+        let profile = find_profile(&default_profiles(), DEFAULT_PROFILE_NAME).unwrap();
+        assert!(missing_elements(&profile, false, |name| name != "valve").is_empty());
+        assert_eq!(missing_elements(&profile, true, |name| name != "valve"), vec!["valve"]);
+    }
+
+    #[test]
+    fn write_and_read_recording_profiles() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("recording_profiles.yml");
+        write_recording_profiles(&path, &default_profiles()).unwrap();
+        assert_eq!(read_recording_profiles(&path).unwrap(), default_profiles());
+    }
+
+    #[test]
+    fn profiles_with_optional_fields_missing_can_be_read() {
+        let profiles = serde_yaml::from_str::<Vec<RecordingProfile>>("---\n- name: ts\n  extension: ts\n").unwrap();
+        assert!(profiles[0].is_passthrough());
+    }
+}
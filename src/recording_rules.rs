@@ -0,0 +1,365 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+
+//! Rules for recording programmes automatically.
+//!
+//! A rule records every episode of a series, given by its series CRID, or every
+//! programme whose title matches a regular expression, optionally only those on one
+//! channel. The EPG manager evaluates the rules whenever new EIT data arrives and
+//! schedules a recording by event of each programme found. The episodes scheduled are
+//! remembered, by programme CRID or failing that by title and short text, so that
+//! repeats are not recorded again. CRIDs always include their authority, cf.
+//! content_identifier.rs, so the CRIDs of different broadcasters do not collide.
+//!
+//! The rules are stored as a YAML file in the XDG config directory so they can be
+//! edited by hand, the episodes scheduled in the XDG data directory.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, TimeZone};
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::data_files;
+use crate::epg_event::Event;
+use crate::recording_jobs::{DEFAULT_PADDING_AFTER, DEFAULT_PADDING_BEFORE, RecordingJob, RecordingJobs, ScheduledEvent, add_job};
+
+/// A rule saying which programmes to record. At least one of `series_crid` and
+/// `title_regex` is set.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RecordingRule {
+    pub id: u32,
+    #[serde(default)]
+    pub series_crid: Option<String>,
+    #[serde(default)]
+    pub title_regex: Option<String>,
+    #[serde(default)]
+    pub channel: Option<String>,
+    pub profile: String,
+    #[serde(default)]
+    pub output_directory: Option<String>,
+}
+
+impl RecordingRule {
+    /// A description of the rule for listing.
+    pub fn description(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(ref series_crid) = self.series_crid {
+            parts.push(format!("series {}", series_crid));
+        }
+        if let Some(ref title_regex) = self.title_regex {
+            parts.push(format!("titles matching '{}'", title_regex));
+        }
+        match self.channel {
+            Some(ref channel) => parts.push(format!("on '{}'", channel)),
+            None => parts.push("on any channel".to_string()),
+        }
+        parts.join(" ")
+    }
+
+    fn matches(&self, title_regex: Option<&Regex>, channel_name: &str, event: &Event) -> bool {
+        self.channel.as_ref().map_or(true, |c| c == channel_name)
+            && self.series_crid.as_ref().map_or(true, |s| event.series_crid.as_ref() == Some(s))
+            && title_regex.map_or(true, |r| r.is_match(&event.title))
+    }
+}
+
+/// Return a `PathBuf` to the recording rules file using the XDG directory structure.
+pub fn recording_rules_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_config_home();
+    path_buf.push("recording_rules.yml");
+    path_buf
+}
+
+/// Return a `PathBuf` to the file of episodes scheduled by rules using the XDG directory
+/// structure.
+pub fn scheduled_episodes_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_data_home();
+    path_buf.push("scheduled_episodes.yml");
+    path_buf
+}
+
+pub fn read_recording_rules(path: &Path) -> Result<Vec<RecordingRule>, String> { data_files::read_yaml(path) }
+
+pub fn write_recording_rules(path: &Path, rules: &[RecordingRule]) -> Result<(), String> { data_files::write_yaml(path, rules) }
+
+/// Read the recording rules from the file at `path`, apply `change` to them, and write
+/// them back if they have been changed, returning the result of `change`. The rules are
+/// changed by me-tv-schedule and me-tv so every change is made this way.
+pub fn update_recording_rules<F, T>(path: &Path, change: F) -> Result<T, String> where F: FnOnce(&mut Vec<RecordingRule>) -> T {
+    data_files::update_yaml(path, change)
+}
+
+pub fn read_scheduled_episodes(path: &Path) -> Result<Vec<String>, String> { data_files::read_yaml(path) }
+
+pub fn write_scheduled_episodes(path: &Path, episodes: &[String]) -> Result<(), String> { data_files::write_yaml(path, episodes) }
+
+/// Add a rule to the list giving it a new id. Returns the id of the rule.
+pub fn add_rule(rules: &mut Vec<RecordingRule>, mut rule: RecordingRule) -> u32 {
+    rule.id = rules.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+    let id = rule.id;
+    rules.push(rule);
+    id
+}
+
+/// Remove the rule with the given id from the list, returning it if there was one.
+pub fn remove_rule(rules: &mut Vec<RecordingRule>, id: u32) -> Option<RecordingRule> {
+    let index = rules.iter().position(|r| r.id == id)?;
+    Some(rules.remove(index))
+}
+
+/// Return what identifies the episode an event is of, if anything does: the programme
+/// CRID, or the title and short text since the short text usually describes the episode.
+pub fn episode_of(event: &Event) -> Option<String> {
+    match event.programme_crid {
+        Some(ref crid) => Some(crid.clone()),
+        None if ! event.short_text.is_empty() => Some(format!("{}\n{}", event.title, event.short_text)),
+        None => None,
+    }
+}
+
+/// Is there a job recording the event. Imported events are recorded by time since they
+/// are not in the EIT, so for those the job must start when the event does.
+fn is_scheduled(jobs: &[RecordingJob], channel_name: &str, event: &Event) -> bool {
+    jobs.iter().any(|j| j.channel == channel_name && match j.event {
+        Some(ref e) => ! event.is_imported() && e.event_id == event.key.event_id,
+        None => event.is_imported() && j.start_time == event.start_time,
+    })
+}
+
+/// Return the events, each with the name of its channel, that the rules say should be
+/// recorded, with the rule that matched, in start time order.
+///
+/// Events that have started, that are already scheduled, or that are of episodes
+/// already scheduled are skipped. All the showings of an episode are returned so that
+/// a later showing can be recorded if an earlier one cannot, e.g. because all the
+/// frontends are in use at the time, the caller records the first one it can. A rule
+/// with an invalid regular expression matches nothing.
+pub fn find_recordings<'a, 'b>(
+    rules: &'a [RecordingRule], events: &'b [(String, Event)], jobs: &[RecordingJob], scheduled_episodes: &[String], now: i64,
+) -> Vec<(&'a RecordingRule, &'b (String, Event))> {
+    let rules = rules.iter()
+        .filter_map(|rule| match rule.title_regex.as_ref().map(|r| Regex::new(r)) {
+            Some(Ok(regex)) => Some((rule, Some(regex))),
+            Some(Err(e)) => {
+                println!("The title regular expression of recording rule {} is not valid – {}", rule.id, e);
+                None
+            },
+            None => Some((rule, None)),
+        })
+        .collect::<Vec<(&RecordingRule, Option<Regex>)>>();
+    let mut events = events.iter().filter(|(_, event)| event.start_time > now).collect::<Vec<&(String, Event)>>();
+    events.sort_by_key(|(_, event)| event.start_time);
+    let mut recordings = Vec::new();
+    for channel_and_event in events {
+        let (channel_name, event) = channel_and_event;
+        if is_scheduled(jobs, channel_name, event) {
+            continue;
+        }
+        let rule = match rules.iter().find(|(rule, regex)| rule.matches(regex.as_ref(), channel_name, event)) {
+            Some((rule, _)) => rule,
+            None => continue,
+        };
+        if episode_of(event).map_or(false, |e| scheduled_episodes.contains(&e)) {
+            continue;
+        }
+        recordings.push((*rule, channel_and_event));
+    }
+    recordings
+}
+
+/// Add the jobs for the recordings found by `find_recordings` to the jobs, and their
+/// episodes to the scheduled episodes, recording each episode once. `create_job` returns
+/// the job for a recording, with its frontend assigned given the jobs so far, or `None`
+/// if it cannot be recorded, in which case the next showing of the episode is tried.
+/// Return the ids of the jobs added.
+pub fn schedule_recordings<F>(recordings: &[(&RecordingRule, &(String, Event))], jobs: &mut RecordingJobs, episodes: &mut Vec<String>, mut create_job: F) -> Vec<u32>
+    where F: FnMut(&RecordingRule, &str, &Event, &[RecordingJob]) -> Option<RecordingJob> {
+    let mut ids = Vec::new();
+    for (rule, (channel_name, event)) in recordings.iter() {
+        let episode = episode_of(event);
+        if episode.as_ref().map_or(false, |e| episodes.contains(e)) {
+            continue;
+        }
+        if let Some(job) = create_job(rule, channel_name, event, &jobs.jobs) {
+            ids.push(add_job(jobs, job));
+            episodes.extend(episode);
+        }
+    }
+    ids
+}
+
+/// Return the name of the file to record an event to.
+pub fn recording_file_name(channel_name: &str, event: &Event, extension: &str) -> String {
+    format!("{} – {} – {}.{}",
+            channel_name,
+            event.title,
+            Local.timestamp(event.start_time, 0).format("%Y-%m-%d %H:%M"),
+            extension,
+    ).replace('/', "-")
+}
+
+/// Create the job for recording an event found by a rule, the frontend is still to be
+/// assigned. Imported events are not in the EIT so they are recorded by time.
+pub fn create_job(rule: &RecordingRule, channel_name: &str, event: &Event, directory: &Path, extension: &str) -> RecordingJob {
+    let directory = rule.output_directory.as_ref().map_or(directory, |d| Path::new(d));
+    RecordingJob {
+        id: 0,
+        channel: channel_name.to_string(),
+        start_time: event.start_time,
+        duration: (event.duration + 59) / 60,
+        output: directory.join(recording_file_name(channel_name, event, extension)).to_str().unwrap().to_string(),
+        adapter: 0,
+        frontend: 0,
+        profile: rule.profile.clone(),
+        started: false,
+        event: if event.is_imported() {
+            None
+        } else {
+            Some(ScheduledEvent{event_id: event.key.event_id, padding_before: DEFAULT_PADDING_BEFORE, padding_after: DEFAULT_PADDING_AFTER})
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epg_event::{EventKey, IMPORTED_NETWORK_ID, RunningStatus};
+
+    fn event(service_id: u16, event_id: u16, start_time: i64, title: &str, programme_crid: Option<&str>, series_crid: Option<&str>) -> Event {
+        Event {
+            key: EventKey{original_network_id: 9018, transport_stream_id: 4164, service_id, event_id},
+            start_time,
+            duration: 1800,
+            title: title.to_string(),
+            short_text: String::new(),
+            extended_text: String::new(),
+            items: vec![],
+            genre: None,
+            running_status: RunningStatus::NotRunning,
+            programme_crid: programme_crid.map(|c| c.to_string()),
+            series_crid: series_crid.map(|c| c.to_string()),
+        }
+    }
+
+    fn rule(series_crid: Option<&str>, title_regex: Option<&str>, channel: Option<&str>) -> RecordingRule {
+        RecordingRule {
+            id: 1,
+            series_crid: series_crid.map(|c| c.to_string()),
+            title_regex: title_regex.map(|r| r.to_string()),
+            channel: channel.map(|c| c.to_string()),
+            profile: "ts-passthrough".to_string(),
+            output_directory: None,
+        }
+    }
+
+    fn found(rules: &[RecordingRule], events: &[(String, Event)], jobs: &[RecordingJob], episodes: &[String]) -> Vec<u16> {
+        find_recordings(rules, events, jobs, episodes, 1000).iter().map(|(_, (_, e))| e.key.event_id).collect()
+    }
+
+    #[test]
+    fn series_rule_finds_the_showings_of_episodes_not_scheduled() {
+        let events = vec![
+            ("BBC TWO".to_string(), event(4287, 3, 9000, "Gardeners' World", Some("crid://fp.bbc.co.uk/EP2"), Some("crid://fp.bbc.co.uk/GW"))),
+            ("BBC TWO".to_string(), event(4287, 1, 2000, "Gardeners' World", Some("crid://fp.bbc.co.uk/EP1"), Some("crid://fp.bbc.co.uk/GW"))),
+            ("BBC TWO".to_string(), event(4287, 2, 5000, "Gardeners' World", Some("crid://fp.bbc.co.uk/EP1"), Some("crid://fp.bbc.co.uk/GW"))),
+            ("BBC TWO".to_string(), event(4287, 4, 500, "Gardeners' World", Some("crid://fp.bbc.co.uk/EP0"), Some("crid://fp.bbc.co.uk/GW"))),
+            ("BBC TWO".to_string(), event(4287, 5, 3000, "Newsnight", Some("crid://fp.bbc.co.uk/NN"), None)),
+        ];
+        let rules = vec![rule(Some("crid://fp.bbc.co.uk/GW"), None, None)];
+        assert_eq!(found(&rules, &events, &[], &[]), vec![1, 2, 3]);
+        assert_eq!(found(&rules, &events, &[], &["crid://fp.bbc.co.uk/EP2".to_string()]), vec![1, 2]);
+    }
+
+    #[test]
+    fn each_episode_is_scheduled_once_at_the_first_showing_that_can_be_recorded() {
+        let events = vec![
+            ("BBC TWO".to_string(), event(4287, 1, 2000, "Gardeners' World", Some("crid://fp.bbc.co.uk/EP1"), Some("crid://fp.bbc.co.uk/GW"))),
+            ("BBC TWO".to_string(), event(4287, 2, 5000, "Gardeners' World", Some("crid://fp.bbc.co.uk/EP1"), Some("crid://fp.bbc.co.uk/GW"))),
+            ("BBC TWO".to_string(), event(4287, 3, 7000, "Gardeners' World", Some("crid://fp.bbc.co.uk/EP1"), Some("crid://fp.bbc.co.uk/GW"))),
+            ("BBC TWO".to_string(), event(4287, 4, 9000, "Gardeners' World", Some("crid://fp.bbc.co.uk/EP2"), Some("crid://fp.bbc.co.uk/GW"))),
+        ];
+        let rules = vec![rule(Some("crid://fp.bbc.co.uk/GW"), None, None)];
+        let recordings = find_recordings(&rules, &events, &[], &[], 1000);
+        let mut jobs = RecordingJobs::default();
+        let mut episodes = Vec::new();
+        // No frontend is free at the time of the first showing.
+        let ids = schedule_recordings(&recordings, &mut jobs, &mut episodes, |rule, channel_name, event, _| {
+            if event.start_time == 2000 { None } else { Some(create_job(rule, channel_name, event, Path::new("/tmp"), "ts")) }
+        });
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(jobs.jobs.iter().map(|j| j.start_time).collect::<Vec<i64>>(), vec![5000, 9000]);
+        assert_eq!(episodes, vec!["crid://fp.bbc.co.uk/EP1".to_string(), "crid://fp.bbc.co.uk/EP2".to_string()]);
+    }
+
+    #[test]
+    fn title_rule_records_matching_titles_on_the_channel() {
+        let events = vec![
+            ("BBC ONE Lon".to_string(), event(4164, 1, 2000, "Doctor Who", None, None)),
+            ("BBC ONE Lon".to_string(), event(4164, 2, 5000, "Doctor Who Confidential", None, None)),
+            ("BBC THREE".to_string(), event(4288, 1, 2000, "Doctor Who", None, None)),
+        ];
+        let rules = vec![rule(None, Some("^Doctor Who$"), Some("BBC ONE Lon"))];
+        assert_eq!(found(&rules, &events, &[], &[]), vec![1]);
+        let job = create_job(&rules[0], "BBC ONE Lon", &events[0].1, Path::new("/tmp"), "ts");
+        assert_eq!(job.output, format!("/tmp/BBC ONE Lon – Doctor Who – {}.ts", Local.timestamp(2000, 0).format("%Y-%m-%d %H:%M")));
+        assert!(found(&rules, &events, &[job], &[]).is_empty());
+        assert_eq!(found(&[rule(None, Some("^Doctor Who"), None)], &events, &[], &[]).len(), 3);
+        assert!(found(&[rule(None, Some("(Doctor"), None)], &events, &[], &[]).is_empty());
+    }
+
+    #[test]
+    fn imported_events_are_recorded_by_time() {
+        let mut imported = event(4164, 33, 2000, "Doctor Who", None, None);
+        imported.key.original_network_id = IMPORTED_NETWORK_ID;
+        let events = vec![("BBC ONE Lon".to_string(), imported)];
+        let rules = vec![rule(None, Some("^Doctor Who$"), None)];
+        let job = create_job(&rules[0], "BBC ONE Lon", &events[0].1, Path::new("/tmp"), "ts");
+        assert_eq!((job.start_time, job.duration, job.event.clone()), (2000, 30, None));
+        let mut event_job = job.clone();
+        event_job.event = Some(ScheduledEvent{event_id: 33, padding_before: 0, padding_after: 0});
+        assert_eq!(found(&rules, &events, &[event_job], &[]), vec![33]);
+        assert!(found(&rules, &events, &[job], &[]).is_empty());
+    }
+
+    #[test]
+    fn episodes_are_identified_by_crid_or_short_text() {
+        let mut e = event(4164, 1, 2000, "Doctor Who", None, None);
+        assert_eq!(episode_of(&e), None);
+        e.short_text = "Blink".to_string();
+        assert_eq!(episode_of(&e), Some("Doctor Who\nBlink".to_string()));
+        e.programme_crid = Some("crid://fp.bbc.co.uk/DW42".to_string());
+        assert_eq!(episode_of(&e), Some("crid://fp.bbc.co.uk/DW42".to_string()));
+    }
+
+    #[test]
+    fn crids_of_different_authorities_do_not_collide() {
+        let events = vec![
+            ("BBC TWO".to_string(), event(4287, 1, 2000, "Gardeners' World", Some("crid://fp.bbc.co.uk/12345"), Some("crid://fp.bbc.co.uk/GW"))),
+            ("ITV".to_string(), event(8261, 2, 2000, "Coronation Street", Some("crid://itv.com/12345"), Some("crid://itv.com/GW"))),
+        ];
+        assert_eq!(found(&[rule(Some("crid://fp.bbc.co.uk/GW"), None, None)], &events, &[], &[]), vec![1]);
+        assert_eq!(found(&[rule(None, Some("."), None)], &events, &[], &["crid://fp.bbc.co.uk/12345".to_string()]), vec![2]);
+    }
+}
@@ -35,7 +35,7 @@ use notify::{Watcher, RecursiveMode, RawEvent, op, raw_watcher};
 use regex::Regex;
 
 use crate::control_window::Message;
-use crate::frontend_manager::FrontendId;
+use crate::dvb_devices::FrontendId;
 use crate::input_event_codes;
 
 #[derive(Debug)]
@@ -0,0 +1,232 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! The dialog showing the progress of a scan for channels.
+//!
+//! The scan is run by the channel scanner in a thread of its own, the dialog shows the
+//! multiplex being tuned to, its signal strength and lock status, the services found so
+//! far, and how many of the multiplexes have been scanned. The scan can be cancelled at
+//! any time, the channels file is then left as it was.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use glib;
+use gtk;
+use gtk::prelude::*;
+
+use crate::channel_scan::Transmitter;
+use crate::channel_scanner::{self, ScanProgress};
+use crate::control_window::ControlWindow;
+use crate::dialogs::{display_an_error_dialog, display_an_information_dialog};
+use crate::dvb_devices::FrontendId;
+
+struct ScanDialog {
+    dialog: gtk::Dialog,
+    frequency_label: gtk::Label,
+    signal_bar: gtk::LevelBar,
+    lock_label: gtk::Label,
+    progress_bar: gtk::ProgressBar,
+    services_store: gtk::ListStore,
+    services_label: gtk::Label,
+    multiplex_count: Cell<usize>,
+    multiplexes_scanned: Cell<usize>,
+    services_found: Cell<usize>,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Return the text of a frequency in Hz for presenting to the user.
+fn frequency_text(frequency: u32) -> String {
+    format!("{:.1} MHz", frequency as f64 / 1_000_000.0)
+}
+
+impl ScanDialog {
+    fn new(control_window: &Rc<ControlWindow>, transmitter_file: &str, frontend: &FrontendId) -> Rc<ScanDialog> {
+        let dialog = gtk::Dialog::with_buttons(
+            Some("Me TV – Scanning for Channels"),
+            Some(&control_window.window),
+            gtk::DialogFlags::MODAL,
+            &[("Cancel", gtk::ResponseType::Cancel)],
+        );
+        dialog.set_default_size(480, 420);
+        let transmitter_label = gtk::Label::new(Some(&format!(
+            "Scanning {} using adapter {} frontend {}.", transmitter_file, frontend.adapter, frontend.frontend)));
+        let frequency_label = gtk::Label::new(Some("Starting the scan."));
+        frequency_label.set_halign(gtk::Align::Start);
+        let signal_bar = gtk::LevelBar::new_for_interval(0.0, 100.0);
+        let lock_label = gtk::Label::new(Some("No lock"));
+        let signal_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+        signal_box.pack_start(&gtk::Label::new(Some("Signal")), false, false, 0);
+        signal_box.pack_start(&signal_bar, true, true, 0);
+        signal_box.pack_start(&lock_label, false, false, 0);
+        let progress_bar = gtk::ProgressBar::new();
+        progress_bar.set_show_text(true);
+        let services_store = gtk::ListStore::new(&[String::static_type(), String::static_type()]);
+        let services_view = gtk::TreeView::with_model(&services_store);
+        for (index, title) in ["Frequency", "Service"].iter().enumerate() {
+            let renderer = gtk::CellRendererText::new();
+            let column = gtk::TreeViewColumn::new();
+            column.set_title(title);
+            column.pack_start(&renderer, true);
+            column.add_attribute(&renderer, "text", index as i32);
+            services_view.append_column(&column);
+        }
+        let scrolled_window = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.add(&services_view);
+        let services_label = gtk::Label::new(Some("No services found yet."));
+        services_label.set_halign(gtk::Align::Start);
+        let content_area = dialog.get_content_area();
+        content_area.set_spacing(6);
+        content_area.set_border_width(10);
+        content_area.pack_start(&transmitter_label, false, false, 0);
+        content_area.pack_start(&frequency_label, false, false, 0);
+        content_area.pack_start(&signal_box, false, false, 0);
+        content_area.pack_start(&progress_bar, false, false, 0);
+        content_area.pack_start(&scrolled_window, true, true, 0);
+        content_area.pack_start(&services_label, false, false, 0);
+        Rc::new(ScanDialog {
+            dialog,
+            frequency_label,
+            signal_bar,
+            lock_label,
+            progress_bar,
+            services_store,
+            services_label,
+            multiplex_count: Cell::new(0),
+            multiplexes_scanned: Cell::new(0),
+            services_found: Cell::new(0),
+            cancel: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Ask the scanner to stop, the dialog stays until the scanner has released the frontend.
+    fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+        self.dialog.set_response_sensitive(gtk::ResponseType::Cancel, false);
+        self.frequency_label.set_text("Cancelling the scan.");
+    }
+
+    fn update_progress(&self) {
+        let count = self.multiplex_count.get();
+        let scanned = self.multiplexes_scanned.get();
+        if count > 0 {
+            self.progress_bar.set_fraction(scanned as f64 / count as f64);
+            self.progress_bar.set_text(Some(&format!("{} of {} multiplexes scanned", scanned, count)));
+        }
+    }
+
+    fn add_row(&self, frequency: u32, text: &str) {
+        self.services_store.insert_with_values(None, &[0, 1], &[&frequency_text(frequency), &text]);
+    }
+
+    /// Show the progress of the scan.
+    fn show(&self, progress: ScanProgress) {
+        match progress {
+            ScanProgress::Tuning{number, count, frequency} => {
+                self.multiplex_count.set(count);
+                self.frequency_label.set_text(&format!("Tuning to {}, multiplex {} of {}.", frequency_text(frequency), number, count));
+                self.signal_bar.set_value(0.0);
+                self.lock_label.set_text("No lock");
+                self.update_progress();
+            },
+            ScanProgress::Signal{frequency, strength, is_locked} => {
+                self.signal_bar.set_value(strength as f64);
+                self.lock_label.set_text(if is_locked { "Locked" } else { "No lock" });
+                if is_locked {
+                    self.frequency_label.set_text(&format!("Reading the services of {}.", frequency_text(frequency)));
+                }
+            },
+            ScanProgress::NoLock{frequency} => {
+                self.add_row(frequency, "No signal");
+                self.multiplexes_scanned.set(self.multiplexes_scanned.get() + 1);
+                self.update_progress();
+            },
+            ScanProgress::ServicesFound{frequency, names} => {
+                if names.is_empty() {
+                    self.add_row(frequency, "No services");
+                }
+                for name in names.iter() {
+                    self.add_row(frequency, name);
+                }
+                self.services_found.set(self.services_found.get() + names.len());
+                self.services_label.set_text(&format!("{} services found.", self.services_found.get()));
+                self.multiplexes_scanned.set(self.multiplexes_scanned.get() + 1);
+                self.update_progress();
+            },
+            ScanProgress::MultiplexesDiscovered{frequencies} => {
+                for frequency in frequencies {
+                    self.add_row(frequency, "Found in the network information");
+                }
+            },
+            ScanProgress::Finished{..} | ScanProgress::Cancelled => (),
+        }
+    }
+}
+
+/// Present a dialog showing the progress of a scan of the multiplexes of a transmitter
+/// file using the given frontend, and start the scan. When the scan finishes the channels
+/// of the control window are updated.
+pub fn present(control_window: &Rc<ControlWindow>, transmitter_file: &str, transmitters: Vec<Transmitter>, frontend: FrontendId) {
+    let scan_dialog = ScanDialog::new(control_window, transmitter_file, &frontend);
+    scan_dialog.dialog.connect_response({
+        let s_d = scan_dialog.clone();
+        move |_, _| s_d.cancel()
+    });
+    scan_dialog.dialog.connect_delete_event({
+        let s_d = scan_dialog.clone();
+        move |_, _| {
+            s_d.cancel();
+            Inhibit(true)
+        }
+    });
+    let (sender, receiver) = glib::MainContext::channel::<ScanProgress>(glib::PRIORITY_DEFAULT);
+    receiver.attach(None, {
+        let c_w = control_window.clone();
+        let s_d = scan_dialog.clone();
+        move |progress| match progress {
+            ScanProgress::Finished{result} => {
+                unsafe { s_d.dialog.destroy(); }
+                match result {
+                    Ok(count) => {
+                        c_w.update_channels_store();
+                        display_an_information_dialog(Some(&c_w.window), &format!("{} channels were found and written to the channels file.", count));
+                    },
+                    Err(e) => display_an_error_dialog(Some(&c_w.window), &format!("The scan for channels failed.\n\n{}", e)),
+                }
+                Continue(false)
+            },
+            ScanProgress::Cancelled => {
+                unsafe { s_d.dialog.destroy(); }
+                Continue(false)
+            },
+            progress => {
+                s_d.show(progress);
+                Continue(true)
+            },
+        }
+    });
+    scan_dialog.dialog.show_all();
+    let cancel = scan_dialog.cancel.clone();
+    thread::spawn(move || channel_scanner::run(transmitters, frontend, cancel, sender));
+}
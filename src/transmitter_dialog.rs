@@ -25,6 +25,7 @@ use std::path;
 use gtk;
 use gtk::prelude::*;
 
+use crate::channel_scan::{self, Transmitter};
 use crate::dialogs::display_an_error_dialog;
 use crate::dvb;
 use crate::preferences;
@@ -53,6 +54,7 @@ fn dvbt_transmitter_files_directory_path() -> Option<path::PathBuf> {
         dvb::DeliverySystem::DVBT => "dvb-t",
         dvb::DeliverySystem::DVBT2 => "dvb-t",
         dvb::DeliverySystem::ISDBT => "isdb-t",
+        _ => "dvb-t",
     });
     if path.is_dir() { Some(path) }
     else { None }
@@ -90,29 +92,66 @@ fn create(parent: Option<&gtk::ApplicationWindow>, transmitter_files_directory_p
     })
 }
 
+/// Present a dialog asking for the frequency of one multiplex, for when there are no
+/// transmitter files. The other multiplexes of the network are found from its NIT.
+///
+/// Returns the frequency in Hz, or `None` if the user cancelled.
+fn present_frequency_chooser(parent: Option<&gtk::ApplicationWindow>, reason: &str) -> Option<u32> {
+    let dialog = gtk::Dialog::with_buttons(
+        Some("Me TV Frequency Chooser"),
+        parent,
+        gtk::DialogFlags::MODAL,
+        &[("Cancel", gtk::ResponseType::Cancel), ("Scan", gtk::ResponseType::Ok)],
+    );
+    let label = gtk::Label::new(Some(&format!("{}\n\nEnter the frequency in MHz of one multiplex you get signal\nfrom, the others will be found from the network information.", reason)));
+    let frequency = gtk::SpinButton::with_range(40.0, 900.0, 0.001);
+    frequency.set_digits(3);
+    frequency.set_value(474.0);
+    let content_area = dialog.get_content_area();
+    content_area.pack_start(&label, false, false, 10);
+    content_area.pack_start(&frequency, false, false, 10);
+    dialog.show_all();
+    let response = gtk::ResponseType::from(dialog.run());
+    let result = (frequency.get_value() * 1_000_000.0).round() as u32;
+    unsafe { dialog.destroy(); }
+    if response == gtk::ResponseType::Ok { Some(result) } else { None }
+}
+
+/// Return the transmitter of a multiplex at a frequency entered by the user.
+fn transmitters_of_frequency(parent: Option<&gtk::ApplicationWindow>, reason: &str) -> Option<(String, Vec<Transmitter>)> {
+    let frequency = present_frequency_chooser(parent, reason)?;
+    Some((
+        format!("{:.3} MHz", frequency as f64 / 1_000_000.0),
+        vec![channel_scan::transmitter_of_frequency(&preferences::get_delivery_system(), frequency)],
+    ))
+}
+
 /// Present a dialog to the user to allow them to select the transmitter file to
 /// use to scan to create a channels file.
 ///
-/// Returns an `Option` with a `Box<Path>` on success. If there are problems finding a
-/// transmitter file, tells the user via a message dialog and returns `None`.
-pub fn present(parent: Option<&gtk::ApplicationWindow>) -> Option<Box<path::Path>> {
+/// Returns an `Option` with the name of the transmitter file and the transmitters it
+/// describes on success. If there are no transmitter files the user is asked for the
+/// frequency of a multiplex instead. If there are problems reading the transmitter file,
+/// tells the user via a message dialog and returns `None`.
+pub fn present(parent: Option<&gtk::ApplicationWindow>) -> Option<(String, Vec<Transmitter>)> {
     match dvbt_transmitter_files_directory_path() {
         Some(transmitter_files_directory_path) =>  match create(parent, transmitter_files_directory_path.as_path()) {
             Some(dialog) => {
                 dialog.dialog.run();
+                let name = dialog.transmitter.get_active_text().unwrap().to_string();
                 let mut path = transmitter_files_directory_path;
-                path.push(dialog.transmitter.get_active_text().unwrap().as_str());
+                path.push(name.as_str());
                 unsafe { dialog.dialog.destroy(); }
-                Some(path.into_boxed_path())
+                match channel_scan::read_transmitters(&path) {
+                    Ok(transmitters) => Some((name, transmitters)),
+                    Err(e) => {
+                        display_an_error_dialog(parent, &e);
+                        None
+                    },
+                }
             },
-            None => {
-                display_an_error_dialog(parent, "There appear to be no transmitter files,\nperhaps the dtv-scan-tables package is not correctly installed.");
-                None
-            }
+            None => transmitters_of_frequency(parent, "There appear to be no transmitter files,\nperhaps the dtv-scan-tables package is not correctly installed."),
         },
-        None => {
-            display_an_error_dialog(parent, "There appear to be no transmitter files directory ,\nperhaps the dtv-scan-tables package is not installed.");
-            None
-        }
+        None => transmitters_of_frequency(parent, "There appear to be no transmitter files directory,\nperhaps the dtv-scan-tables package is not installed."),
     }
 }
@@ -0,0 +1,378 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2018–2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Allocating tuners, i.e. frontends, to scheduled recordings.
+//!
+//! A frontend can only be used by one me-tv-record at a time, but one me-tv-record can
+//! record several channels on the same multiplex. So jobs using the same frontend
+//! whose times overlap can share the frontend if their channels are on the same
+//! multiplex: they form a group that is recorded by one me-tv-record from the earliest
+//! start to the latest end of the jobs. All other jobs whose times overlap each need a
+//! frontend of their own.
+//!
+//! The recording of an EIT event needs the frontend from before its padded start, so
+//! that the event can be followed, until its padded end. It is always a group of its
+//! own since the recording starts and stops when the event does.
+
+use chrono::{Local, TimeZone};
+
+use crate::dvb_devices::FrontendId;
+use crate::multiplexes::MultiplexKey;
+use crate::recording_jobs::{EVENT_MAXIMUM_DELAY, RecordingJob};
+use crate::recording_profiles::DEFAULT_PROFILE_NAME;
+
+/// Jobs recorded by a single me-tv-record using a single frontend.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecordingGroup {
+    pub jobs: Vec<RecordingJob>,
+}
+
+impl RecordingGroup {
+    /// The time from which the group needs its frontend.
+    pub fn start_time(&self) -> i64 { self.jobs.iter().map(|j| j.tuner_start_time()).min().unwrap() }
+
+    /// The time until which the group needs its frontend.
+    pub fn end_time(&self) -> i64 { self.jobs.iter().map(|j| j.tuner_end_time()).max().unwrap() }
+
+    pub fn frontend(&self) -> FrontendId { frontend_of(&self.jobs[0]) }
+
+    /// Are the jobs of the group being recorded.
+    pub fn is_started(&self) -> bool { self.jobs[0].started }
+
+    pub fn ids(&self) -> Vec<u32> { self.jobs.iter().map(|j| j.id).collect() }
+
+    fn overlaps(&self, other: &RecordingGroup) -> bool {
+        self.start_time() < other.end_time() && other.start_time() < self.end_time()
+    }
+
+    /// The arguments to give me-tv-record to record the group if it is started at `now`.
+    ///
+    /// A group started late only records until its scheduled end. The recording of an
+    /// event is stopped by the event finishing, its duration is only a limit in case the
+    /// end of the event is never signalled. Each value is a separate argument so no
+    /// shell quoting of channel names or paths is needed.
+    pub fn record_arguments(&self, now: i64) -> Vec<String> {
+        let start_time = self.start_time().max(now);
+        let end_time = match self.jobs[0].event {
+            Some(_) => self.end_time() + EVENT_MAXIMUM_DELAY,
+            None => self.end_time(),
+        };
+        let duration = (end_time - start_time + 59) / 60;
+        let mut arguments = Vec::new();
+        for job in self.jobs.iter() {
+            arguments.extend(vec!["--channel".to_string(), job.channel.clone(), "--output".to_string(), job.output.clone()]);
+        }
+        let frontend = self.frontend();
+        arguments.extend(vec![
+            "--duration".to_string(), duration.to_string(),
+            "--adapter".to_string(), frontend.adapter.to_string(),
+            "--frontend".to_string(), frontend.frontend.to_string(),
+            "--profile".to_string(), self.jobs[0].profile.clone(),
+        ]);
+        if let Some(ref event) = self.jobs[0].event {
+            arguments.extend(vec![
+                "--event-id".to_string(), event.event_id.to_string(),
+                "--event-start".to_string(), self.jobs[0].start_time.to_string(),
+                "--event-duration".to_string(), self.jobs[0].duration.to_string(),
+                "--padding-before".to_string(), event.padding_before.to_string(),
+                "--padding-after".to_string(), event.padding_after.to_string(),
+            ]);
+        }
+        arguments
+    }
+}
+
+fn frontend_of(job: &RecordingJob) -> FrontendId { FrontendId{adapter: job.adapter, frontend: job.frontend} }
+
+fn overlap(a: &RecordingJob, b: &RecordingJob) -> bool {
+    a.tuner_start_time() < b.tuner_end_time() && b.tuner_start_time() < a.tuner_end_time()
+}
+
+/// Can the two jobs be recorded by the same me-tv-record. Only the ts-passthrough profile
+/// can record several channels, jobs already being recorded cannot be joined by
+/// others, and recordings of events are not shared.
+fn can_share<F>(a: &RecordingJob, b: &RecordingJob, multiplex_of: &F) -> bool where F: Fn(&str) -> Option<MultiplexKey> {
+    frontend_of(a) == frontend_of(b)
+        && a.started == b.started
+        && a.event.is_none() && b.event.is_none()
+        && a.profile == DEFAULT_PROFILE_NAME && b.profile == DEFAULT_PROFILE_NAME
+        && overlap(a, b)
+        && match (multiplex_of(&a.channel), multiplex_of(&b.channel)) {
+            (Some(m_a), Some(m_b)) => m_a == m_b,
+            _ => false,
+        }
+}
+
+/// Return the jobs grouped into the recordings that me-tv-scheduler starts, in start
+/// time order.
+pub fn group_jobs<F>(jobs: &[RecordingJob], multiplex_of: F) -> Vec<RecordingGroup> where F: Fn(&str) -> Option<MultiplexKey> {
+    // The group of each job is the index of another job in the group, following the
+    // indices ends at a job that is its own group.
+    let mut group_of = (0..jobs.len()).collect::<Vec<usize>>();
+    fn root(group_of: &[usize], mut i: usize) -> usize {
+        while group_of[i] != i { i = group_of[i]; }
+        i
+    }
+    for i in 0..jobs.len() {
+        for j in (i + 1)..jobs.len() {
+            if can_share(&jobs[i], &jobs[j], &multiplex_of) {
+                let (r_i, r_j) = (root(&group_of, i), root(&group_of, j));
+                group_of[r_j] = r_i;
+            }
+        }
+    }
+    let mut groups: Vec<(usize, RecordingGroup)> = Vec::new();
+    for (i, job) in jobs.iter().enumerate() {
+        let r = root(&group_of, i);
+        match groups.iter_mut().find(|(g, _)| *g == r) {
+            Some((_, group)) => group.jobs.push(job.clone()),
+            None => groups.push((r, RecordingGroup{jobs: vec![job.clone()]})),
+        }
+    }
+    let mut groups = groups.into_iter().map(|(_, group)| group).collect::<Vec<RecordingGroup>>();
+    groups.sort_by_key(|g| (g.start_time(), g.jobs[0].id));
+    groups
+}
+
+/// A problem with the frontends used by some jobs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Conflict {
+    pub ids: Vec<u32>,
+    pub description: String,
+}
+
+fn format_ids(ids: &[u32]) -> String {
+    ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", ")
+}
+
+fn format_time(timestamp: i64) -> String {
+    Local.timestamp(timestamp, 0).format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// Return the conflicts between jobs: jobs that use the same frontend at the same time
+/// but cannot share it, and times at which more frontends are needed than there are.
+pub fn find_conflicts<F>(jobs: &[RecordingJob], multiplex_of: F, frontends: &[FrontendId]) -> Vec<Conflict> where F: Fn(&str) -> Option<MultiplexKey> {
+    let groups = group_jobs(jobs, multiplex_of);
+    let mut conflicts = Vec::new();
+    for (i, a) in groups.iter().enumerate() {
+        for b in groups[(i + 1)..].iter() {
+            if a.frontend() == b.frontend() && a.overlaps(b) {
+                let ids = a.ids().into_iter().chain(b.ids()).collect::<Vec<u32>>();
+                let frontend = a.frontend();
+                conflicts.push(Conflict {
+                    description: format!(
+                        "Recordings {} and {} both need adapter {} frontend {} at {}, but are not on the same multiplex.",
+                        format_ids(&a.ids()), format_ids(&b.ids()), frontend.adapter, frontend.frontend, format_time(a.start_time().max(b.start_time())),
+                    ),
+                    ids,
+                });
+            }
+        }
+    }
+    let mut reported: Vec<Vec<u32>> = Vec::new();
+    for group in groups.iter() {
+        let time = group.start_time();
+        let active = groups.iter().filter(|g| g.start_time() <= time && time < g.end_time()).collect::<Vec<&RecordingGroup>>();
+        if active.len() > frontends.len() {
+            let ids = active.iter().flat_map(|g| g.ids()).collect::<Vec<u32>>();
+            if ! reported.contains(&ids) {
+                conflicts.push(Conflict {
+                    description: format!(
+                        "At {} recordings {} need {} frontends, but there are only {}.",
+                        format_time(time), format_ids(&ids), active.len(), frontends.len(),
+                    ),
+                    ids: ids.clone(),
+                });
+                reported.push(ids);
+            }
+        }
+    }
+    for group in groups.iter() {
+        if ! frontends.contains(&group.frontend()) {
+            let frontend = group.frontend();
+            conflicts.push(Conflict {
+                description: format!(
+                    "Recordings {} use adapter {} frontend {} which is not currently installed.",
+                    format_ids(&group.ids()), frontend.adapter, frontend.frontend,
+                ),
+                ids: group.ids(),
+            });
+        }
+    }
+    conflicts
+}
+
+/// Return the frontend to use for a job: preferably one that can be shared with other
+/// jobs on the same multiplex, otherwise one not in use at the time of the job. `None`
+/// if every frontend is in use.
+pub fn assign_frontend<F>(job: &RecordingJob, jobs: &[RecordingJob], multiplex_of: F, frontends: &[FrontendId]) -> Option<FrontendId> where F: Fn(&str) -> Option<MultiplexKey> {
+    let others = jobs.iter().filter(|j| j.id != job.id).cloned().collect::<Vec<RecordingJob>>();
+    let group_count = group_jobs(&others, &multiplex_of).len();
+    let usable = frontends.iter()
+        .map(|frontend| {
+            let mut candidate = job.clone();
+            candidate.adapter = frontend.adapter;
+            candidate.frontend = frontend.frontend;
+            let mut all = others.clone();
+            all.push(candidate.clone());
+            let groups = group_jobs(&all, &multiplex_of);
+            let group = groups.iter().find(|g| g.jobs.contains(&candidate)).unwrap();
+            let clash = groups.iter().any(|g| g != group && g.frontend() == *frontend && g.overlaps(group));
+            (frontend, clash, groups.len() == group_count)
+        })
+        .filter(|(_, clash, _)| ! clash)
+        .collect::<Vec<(&FrontendId, bool, bool)>>();
+    usable.iter().find(|(_, _, shared)| *shared)
+        .or_else(|| usable.first())
+        .map(|(frontend, _, _)| (*frontend).clone())
+}
+
+/// Return a frontend, other than those in use, that no recording needs between the start
+/// and end times, e.g. for collecting the programme guide. `None` if every frontend is
+/// needed. A recording of an event that has started may run late so it is assumed to
+/// need its frontend until the maximum delay has passed.
+pub fn free_frontend(jobs: &[RecordingJob], frontends: &[FrontendId], in_use: &[FrontendId], start_time: i64, end_time: i64) -> Option<FrontendId> {
+    let is_needed = |frontend: &FrontendId| jobs.iter().any(|j| {
+        let tuner_end_time = match j.event {
+            Some(_) if j.started => j.end_time() + EVENT_MAXIMUM_DELAY,
+            _ => j.tuner_end_time(),
+        };
+        frontend_of(j) == *frontend && j.tuner_start_time() < end_time && start_time < tuner_end_time
+    });
+    frontends.iter().find(|f| ! in_use.contains(f) && ! is_needed(f)).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording_jobs::{EVENT_LEAD_TIME, ScheduledEvent};
+
+    fn multiplex_of(channel: &str) -> Option<MultiplexKey> {
+        let frequency = match channel {
+            "BBC ONE Lon" | "BBC TWO" => 490000000,
+            "ITV" | "Channel 4" => 514000000,
+            _ => return None,
+        };
+        Some(MultiplexKey{delivery_system: "DVBT".to_string(), frequency, polarization: None, stream_id: None})
+    }
+
+    fn job(id: u32, channel: &str, start_time: i64, duration: u32, frontend: u8) -> RecordingJob {
+        RecordingJob {
+            id,
+            channel: channel.to_string(),
+            start_time,
+            duration,
+            output: format!("/tmp/{}.ts", channel),
+            adapter: 0,
+            frontend,
+            profile: DEFAULT_PROFILE_NAME.to_string(),
+            started: false,
+            event: None,
+        }
+    }
+
+    fn frontends(count: u8) -> Vec<FrontendId> {
+        (0..count).map(|f| FrontendId{adapter: 0, frontend: f}).collect()
+    }
+
+    #[test]
+    fn overlapping_jobs_on_one_multiplex_are_grouped() {
+        let jobs = vec![
+            job(1, "BBC ONE Lon", 0, 60, 0),
+            job(2, "BBC TWO", 1800, 60, 0),
+            job(3, "ITV", 1800, 60, 0),
+            job(4, "BBC TWO", 7200, 60, 0),
+        ];
+        let groups = group_jobs(&jobs, multiplex_of);
+        assert_eq!(groups.iter().map(|g| g.ids()).collect::<Vec<Vec<u32>>>(), vec![vec![1, 2], vec![3], vec![4]]);
+        assert_eq!((groups[0].start_time(), groups[0].end_time()), (0, 5400));
+        let arguments = groups[0].record_arguments(600);
+        assert_eq!(arguments.iter().filter(|a| *a == "--channel").count(), 2);
+        assert_eq!(arguments[arguments.iter().position(|a| a == "--duration").unwrap() + 1], "80");
+    }
+
+    #[test]
+    fn event_recordings_are_not_grouped() {
+        let mut event_job = job(2, "BBC TWO", 1800, 60, 0);
+        event_job.event = Some(ScheduledEvent{event_id: 4242, padding_before: 2, padding_after: 10});
+        let jobs = vec![job(1, "BBC ONE Lon", 0, 60, 0), event_job];
+        let groups = group_jobs(&jobs, multiplex_of);
+        assert_eq!(groups.iter().map(|g| g.ids()).collect::<Vec<Vec<u32>>>(), vec![vec![1], vec![2]]);
+        assert_eq!((groups[1].start_time(), groups[1].end_time()), (1800 - 120 - EVENT_LEAD_TIME, 5400 + 600));
+        let arguments = groups[1].record_arguments(1800);
+        let value_of = |name: &str| arguments[arguments.iter().position(|a| a == name).unwrap() + 1].clone();
+        assert_eq!(value_of("--event-id"), "4242");
+        assert_eq!(value_of("--event-start"), "1800");
+        assert_eq!(value_of("--duration"), ((6000 + EVENT_MAXIMUM_DELAY - 1800) / 60).to_string());
+        assert!(find_conflicts(&jobs, multiplex_of, &frontends(2)).iter().any(|c| c.ids == vec![1, 2]));
+    }
+
+    #[test]
+    fn jobs_needing_the_same_frontend_are_conflicts() {
+        let jobs = vec![
+            job(1, "BBC ONE Lon", 0, 60, 0),
+            job(2, "BBC TWO", 1800, 60, 0),
+            job(3, "ITV", 1800, 60, 0),
+        ];
+        let conflicts = find_conflicts(&jobs, multiplex_of, &frontends(2));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].ids, vec![1, 2, 3]);
+        assert!(find_conflicts(&jobs[..2], multiplex_of, &frontends(1)).is_empty());
+    }
+
+    #[test]
+    fn needing_more_frontends_than_there_are_is_a_conflict() {
+        let jobs = vec![
+            job(1, "BBC ONE Lon", 0, 60, 0),
+            job(2, "ITV", 0, 60, 1),
+        ];
+        assert!(find_conflicts(&jobs, multiplex_of, &frontends(2)).is_empty());
+        let conflicts = find_conflicts(&jobs, multiplex_of, &frontends(1));
+        assert!(conflicts.iter().any(|c| c.description.contains("need 2 frontends, but there are only 1")));
+        assert!(conflicts.iter().any(|c| c.description.contains("not currently installed")));
+    }
+
+    #[test]
+    fn frontends_are_assigned_sharing_a_multiplex_where_possible() {
+        let jobs = vec![
+            job(1, "ITV", 0, 60, 0),
+            job(2, "BBC ONE Lon", 0, 60, 1),
+        ];
+        assert_eq!(assign_frontend(&job(3, "BBC TWO", 1800, 60, 0), &jobs, multiplex_of, &frontends(3)), Some(FrontendId{adapter: 0, frontend: 1}));
+        assert_eq!(assign_frontend(&job(3, "Dave", 1800, 60, 0), &jobs, multiplex_of, &frontends(3)), Some(FrontendId{adapter: 0, frontend: 2}));
+        assert_eq!(assign_frontend(&job(3, "Dave", 1800, 60, 0), &jobs, multiplex_of, &frontends(2)), None);
+        assert_eq!(assign_frontend(&job(3, "Dave", 3600, 60, 0), &jobs, multiplex_of, &frontends(2)), Some(FrontendId{adapter: 0, frontend: 0}));
+    }
+
+    #[test]
+    fn free_frontends_are_not_in_use_or_needed_by_recordings() {
+        let mut jobs = vec![job(1, "ITV", 3600, 60, 0)];
+        assert_eq!(free_frontend(&jobs, &frontends(2), &[], 0, 600), Some(FrontendId{adapter: 0, frontend: 0}));
+        assert_eq!(free_frontend(&jobs, &frontends(2), &[], 3000, 3600), Some(FrontendId{adapter: 0, frontend: 0}));
+        assert_eq!(free_frontend(&jobs, &frontends(2), &[], 3000, 3601), Some(FrontendId{adapter: 0, frontend: 1}));
+        assert_eq!(free_frontend(&jobs, &frontends(2), &frontends(1), 0, 600), Some(FrontendId{adapter: 0, frontend: 1}));
+        assert_eq!(free_frontend(&jobs, &frontends(2), &frontends(2), 0, 600), None);
+        jobs[0].started = true;
+        jobs[0].event = Some(ScheduledEvent{event_id: 1, padding_before: 0, padding_after: 0});
+        assert_eq!(free_frontend(&jobs, &frontends(1), &[], 7200 + EVENT_MAXIMUM_DELAY - 1, 7200 + EVENT_MAXIMUM_DELAY), None);
+        assert_eq!(free_frontend(&jobs, &frontends(1), &[], 7200 + EVENT_MAXIMUM_DELAY, 7800 + EVENT_MAXIMUM_DELAY), Some(FrontendId{adapter: 0, frontend: 0}));
+    }
+}
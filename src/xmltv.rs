@@ -0,0 +1,489 @@
+/*
+ *  Me TV — It's TV for me computer.
+ *
+ *  A GTK+/GStreamer client for watching and recording DVB.
+ *
+ *  Copyright © 2020  Russel Winder
+ *
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Export and import of the programme guide as XMLTV.
+//!
+//! XMLTV, see http://wiki.xmltv.org/index.php/XMLTVFormat, is the guide format read by
+//! Kodi, TVHeadend, and many other programs. The id of a channel is made from its
+//! logical channel number and service_id, e.g. "1.4164.me-tv", or just the service_id
+//! if the logical channel number is not yet known. The logical channel number is also
+//! given as a display name since that is what front ends use to match channels.
+//!
+//! The over-air EIT often only covers a day or so ahead, an XMLTV file can supplement
+//! it. The channels of the XMLTV file are matched to channels using the mappings in
+//! the XMLTV channels file, xmltv_channels.yml in the Me TV XDG config directory, and
+//! failing that by display name, or by id if the file came from an export. Programmes
+//! of channels that do not match are ignored.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use roxmltree;
+use serde_derive::{Deserialize, Serialize};
+use xdg;
+
+use crate::channels_data::{self, ChannelData};
+use crate::data_files;
+use crate::epg_data;
+use crate::epg_event::{Event, EventKey, IMPORTED_NETWORK_ID, RunningStatus};
+use crate::multiplexes;
+
+/// The mapping of the id of an XMLTV channel to a channel, by name or by service_id.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ChannelMapping {
+    pub xmltv_id: String,
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub service_id: Option<u16>,
+}
+
+/// A channel of an XMLTV file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmltvChannel {
+    pub id: String,
+    pub display_names: Vec<String>,
+}
+
+/// A programme of an XMLTV file. Only the first of each element is used, XMLTV allows
+/// a title, etc. in each of several languages.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Programme {
+    pub channel: String,
+    pub start_time: i64, // Seconds since the Unix epoch, UTC.
+    pub stop_time: Option<i64>, // Seconds since the Unix epoch, UTC.
+    pub title: String,
+    pub sub_title: String,
+    pub description: String,
+    pub category: Option<String>,
+}
+
+/// Return the XMLTV id of a channel.
+pub fn channel_id(service_id: u16, logical_channel_number: u16) -> String {
+    if logical_channel_number == 0 {
+        format!("{}.me-tv", service_id)
+    } else {
+        format!("{}.{}.me-tv", logical_channel_number, service_id)
+    }
+}
+
+/// Escape the characters that cannot appear literally in XML text or attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than tab and newline are not allowed in XML 1.0.
+            c if c < ' ' && c != '\t' && c != '\n' => {},
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format a time, seconds since the Unix epoch UTC, as an XMLTV date.
+fn format_time(time: i64) -> String {
+    Utc.timestamp(time, 0).format("%Y%m%d%H%M%S +0000").to_string()
+}
+
+/// Write the channels and the events of those channels as an XMLTV document. The
+/// events are written in channel order and then start time order.
+pub fn write_xmltv<W: Write>(writer: &mut W, channels: &[ChannelData], events: &[Event]) -> io::Result<()> {
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(writer, "<!DOCTYPE tv SYSTEM \"xmltv.dtd\">")?;
+    writeln!(writer, "<tv generator-info-name=\"Me TV {}\">", env!("CARGO_PKG_VERSION"))?;
+    for channel in channels.iter() {
+        writeln!(writer, "  <channel id=\"{}\">", channel_id(channel.service_id, channel.logical_channel_number))?;
+        writeln!(writer, "    <display-name>{}</display-name>", escape(&channel.name))?;
+        if channel.logical_channel_number != 0 {
+            writeln!(writer, "    <display-name>{}</display-name>", channel.logical_channel_number)?;
+        }
+        writeln!(writer, "  </channel>")?;
+    }
+    for channel in channels.iter() {
+        let id = channel_id(channel.service_id, channel.logical_channel_number);
+        let mut channel_events = events.iter().filter(|e| e.key.service_id == channel.service_id).collect::<Vec<&Event>>();
+        channel_events.sort_by_key(|e| e.start_time);
+        for event in channel_events {
+            writeln!(writer, "  <programme start=\"{}\" stop=\"{}\" channel=\"{}\">", format_time(event.start_time), format_time(event.end_time()), id)?;
+            writeln!(writer, "    <title>{}</title>", escape(&event.title))?;
+            let synopsis = event.synopsis();
+            if ! synopsis.is_empty() {
+                writeln!(writer, "    <desc>{}</desc>", escape(&synopsis))?;
+            }
+            if let Some(ref genre) = event.genre {
+                writeln!(writer, "    <category>{}</category>", escape(genre))?;
+            }
+            writeln!(writer, "  </programme>")?;
+        }
+    }
+    writeln!(writer, "</tv>")
+}
+
+/// Write the programme guide currently known as XMLTV to the file at `path`, or to
+/// standard output if the path is "-".
+pub fn export(path: &str) -> Result<(), String> {
+    let channels = channels_data::get_channels_data_ordered_by_logical_channel_number()
+        .ok_or_else(|| format!("There is no channels file {:?}, the programme guide cannot be exported.", multiplexes::channels_file_path()))?;
+    let events = epg_data::get_events_between(i64::MIN, i64::MAX);
+    let result = if path == "-" {
+        write_xmltv(&mut io::stdout().lock(), &channels, &events)
+    } else {
+        let mut file = io::BufWriter::new(File::create(path).map_err(|e| format!("Failed to create {} – {}", path, e))?);
+        write_xmltv(&mut file, &channels, &events).and_then(|_| file.flush())
+    };
+    result.map_err(|e| format!("Failed to write {} – {}", path, e))
+}
+
+/// Return a `PathBuf` to the XMLTV channels file using the XDG directory structure.
+pub fn xmltv_channels_path() -> PathBuf {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("me-tv").expect("Cannot set XDG prefix.");
+    let mut path_buf = xdg_dirs.get_config_home();
+    path_buf.push("xmltv_channels.yml");
+    path_buf
+}
+
+/// Read the channel mappings from a file, there are no mappings if there is no file.
+pub fn read_channel_mappings(path: &Path) -> Result<Vec<ChannelMapping>, String> { data_files::read_yaml(path) }
+
+/// Read the channel mappings from the file at `path`, apply `change` to them, and write
+/// them back if they have been changed, returning the result of `change`.
+pub fn update_channel_mappings<F, T>(path: &Path, change: F) -> Result<T, String> where F: FnOnce(&mut Vec<ChannelMapping>) -> T {
+    data_files::update_yaml(path, change)
+}
+
+/// Parse an XMLTV date, e.g. "20201025180000 +0000". Missing trailing fields of the
+/// time are taken to be zero and a missing time zone is taken to be UTC.
+fn parse_time(text: &str) -> Option<i64> {
+    let mut parts = text.split_whitespace();
+    let digits = parts.next()?;
+    if digits.len() < 8 || digits.len() > 14 || ! digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits = format!("{:0<14}", digits);
+    match parts.next() {
+        Some(zone) => DateTime::parse_from_str(&format!("{} {}", digits, zone), "%Y%m%d%H%M%S %z").ok().map(|t| t.timestamp()),
+        None => NaiveDateTime::parse_from_str(&digits, "%Y%m%d%H%M%S").ok().map(|t| t.timestamp()),
+    }
+}
+
+/// Return the text of the first child element with the given name, if there is one.
+fn child_text(node: &roxmltree::Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|n| n.has_tag_name(name))
+        .map(|n| n.text().unwrap_or("").trim().to_string())
+}
+
+/// Parse the channels and programmes of an XMLTV document. Programmes without a
+/// channel or valid start time are skipped.
+pub fn parse_xmltv(text: &str) -> Result<(Vec<XmltvChannel>, Vec<Programme>), String> {
+    // XMLTV files usually have a DOCTYPE.
+    let options = roxmltree::ParsingOptions{allow_dtd: true, ..roxmltree::ParsingOptions::default()};
+    let document = roxmltree::Document::parse_with_options(text, options).map_err(|e| format!("Failed to parse XMLTV – {}", e))?;
+    let tv = document.root_element();
+    if ! tv.has_tag_name("tv") {
+        return Err(format!("Failed to parse XMLTV – the root element is {} not tv", tv.tag_name().name()));
+    }
+    let channels = tv.children()
+        .filter(|n| n.has_tag_name("channel"))
+        .filter_map(|n| Some(XmltvChannel {
+            id: n.attribute("id")?.to_string(),
+            display_names: n.children()
+                .filter(|d| d.has_tag_name("display-name"))
+                .filter_map(|d| d.text().map(|t| t.trim().to_string()))
+                .collect(),
+        }))
+        .collect();
+    let programmes = tv.children()
+        .filter(|n| n.has_tag_name("programme"))
+        .filter_map(|n| Some(Programme {
+            channel: n.attribute("channel")?.to_string(),
+            start_time: parse_time(n.attribute("start")?)?,
+            stop_time: n.attribute("stop").and_then(parse_time),
+            title: child_text(&n, "title").unwrap_or_default(),
+            sub_title: child_text(&n, "sub-title").unwrap_or_default(),
+            description: child_text(&n, "desc").unwrap_or_default(),
+            category: child_text(&n, "category"),
+        }))
+        .collect();
+    Ok((channels, programmes))
+}
+
+/// Return the service_id of the channel each XMLTV channel id is for. A mapping takes
+/// precedence, then an id as written by `write_xmltv`, then a display name that is the
+/// name of a channel, ignoring case.
+pub fn map_channels(xmltv_channels: &[XmltvChannel], mappings: &[ChannelMapping], channels: &[ChannelData]) -> HashMap<String, u16> {
+    let mut channel_map = HashMap::new();
+    for xmltv_channel in xmltv_channels {
+        let service_id = channels.iter()
+            .find(|c| channel_id(c.service_id, c.logical_channel_number) == xmltv_channel.id
+                || xmltv_channel.id == channel_id(c.service_id, 0))
+            .or_else(|| channels.iter().find(|c| xmltv_channel.display_names.iter().any(|n| n.to_lowercase() == c.name.to_lowercase())))
+            .map(|c| c.service_id);
+        if let Some(service_id) = service_id {
+            channel_map.insert(xmltv_channel.id.clone(), service_id);
+        }
+    }
+    for mapping in mappings {
+        let service_id = match (&mapping.channel, mapping.service_id) {
+            (_, Some(service_id)) => Some(service_id),
+            (Some(name), None) => channels.iter().find(|c| &c.name == name).map(|c| c.service_id),
+            (None, None) => None,
+        };
+        match service_id {
+            Some(service_id) => { channel_map.insert(mapping.xmltv_id.clone(), service_id); },
+            None => println!("The XMLTV channel {} is not mapped to a known channel.", mapping.xmltv_id),
+        }
+    }
+    channel_map
+}
+
+/// Create the imported events for the programmes of the mapped channels. A programme
+/// without a stop time finishes when the next programme of its channel starts.
+///
+/// XMLTV has no event ids so the id of an event is made from its start time, in
+/// minutes, which is unique within a service for the 45 days that fit in a u16.
+pub fn events_of_programmes(programmes: &[Programme], channel_map: &HashMap<String, u16>) -> Vec<Event> {
+    let mut programmes = programmes.iter()
+        .filter_map(|p| channel_map.get(&p.channel).map(|service_id| (*service_id, p)))
+        .collect::<Vec<(u16, &Programme)>>();
+    programmes.sort_by_key(|(service_id, p)| (*service_id, p.start_time));
+    let mut events = Vec::new();
+    for (index, (service_id, programme)) in programmes.iter().enumerate() {
+        let stop_time = match programme.stop_time {
+            Some(stop_time) => stop_time,
+            None => match programmes.get(index + 1) {
+                Some((next_service_id, next)) if next_service_id == service_id => next.start_time,
+                _ => continue,
+            },
+        };
+        if stop_time <= programme.start_time {
+            continue;
+        }
+        events.push(Event {
+            key: EventKey {
+                original_network_id: IMPORTED_NETWORK_ID,
+                transport_stream_id: 0,
+                service_id: *service_id,
+                event_id: (programme.start_time / 60) as u16,
+            },
+            start_time: programme.start_time,
+            duration: (stop_time - programme.start_time) as u32,
+            title: programme.title.clone(),
+            short_text: programme.sub_title.clone(),
+            extended_text: programme.description.clone(),
+            items: vec![],
+            genre: programme.category.clone(),
+            running_status: RunningStatus::Undefined,
+            programme_crid: None,
+            series_crid: None,
+        });
+    }
+    events
+}
+
+/// Import the programme guide of the XMLTV file at `path`, replacing any previously
+/// imported programmes. Returns the number of programmes added to the guide.
+pub fn import(path: &Path) -> Result<usize, String> {
+    let channels = channels_data::get_channels_data_ordered_by_logical_channel_number()
+        .ok_or_else(|| format!("There is no channels file {:?}, the programme guide cannot be imported.", multiplexes::channels_file_path()))?;
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {} – {}", path.to_str().unwrap(), e))?;
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer).map_err(|e| format!("Failed to read {} – {}", path.to_str().unwrap(), e))?;
+    let (xmltv_channels, programmes) = parse_xmltv(&buffer).map_err(|e| format!("{} – {}", path.to_str().unwrap(), e))?;
+    let mappings = read_channel_mappings(&xmltv_channels_path())?;
+    let channel_map = map_channels(&xmltv_channels, &mappings, &channels);
+    Ok(epg_data::replace_imported_events(events_of_programmes(&programmes, &channel_map)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(service_id: u16, event_id: u16, start_time: i64, title: &str, short_text: &str) -> Event {
+        Event {
+            key: EventKey{original_network_id: 9018, transport_stream_id: 4164, service_id, event_id},
+            start_time,
+            duration: 1800,
+            title: title.to_string(),
+            short_text: short_text.to_string(),
+            extended_text: String::new(),
+            items: vec![],
+            genre: Some("News/Current affairs".to_string()),
+            running_status: RunningStatus::NotRunning,
+            programme_crid: None,
+            series_crid: None,
+        }
+    }
+
+    #[test]
+    fn channel_ids_use_the_logical_channel_number_when_known() {
+        assert_eq!(channel_id(4164, 1), "1.4164.me-tv");
+        assert_eq!(channel_id(4164, 0), "4164.me-tv");
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(escape("Fish & <Chips> \"Tonight\"\u{1}"), "Fish &amp; &lt;Chips&gt; &quot;Tonight&quot;");
+    }
+
+    fn channels() -> Vec<ChannelData> {
+        vec![
+            ChannelData{name: "BBC ONE Lon".to_string(), service_id: 4164, logical_channel_number: 1, ..Default::default()},
+            ChannelData{name: "BBC NEWS".to_string(), service_id: 4415, logical_channel_number: 0, ..Default::default()},
+            ChannelData{name: "ITV".to_string(), service_id: 8261, logical_channel_number: 3, ..Default::default()},
+        ]
+    }
+
+    #[test]
+    fn parse_xmltv_times() {
+        assert_eq!(parse_time("20201025180000 +0000"), Some(1603648800));
+        assert_eq!(parse_time("20201025190000 +0100"), Some(1603648800));
+        assert_eq!(parse_time("202010251800"), Some(1603648800));
+        assert_eq!(parse_time("2020"), None);
+        assert_eq!(parse_time("2020102518000x +0000"), None);
+    }
+
+    #[test]
+    fn parse_channels_and_programmes() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE tv SYSTEM "xmltv.dtd">
+<tv source-info-name="Listings">
+  <channel id="bbc1.london">
+    <display-name lang="en">BBC One London</display-name>
+    <display-name>BBC1</display-name>
+  </channel>
+  <programme start="20201025180000 +0000" stop="20201025183000 +0000" channel="bbc1.london">
+    <title lang="en">The News at Ten</title>
+    <title lang="cy">Y Newyddion</title>
+    <sub-title>Sunday</sub-title>
+    <desc>The latest news &amp; weather.</desc>
+    <category>News</category>
+  </programme>
+  <programme start="20201025183000 +0000" channel="bbc1.london">
+    <title>Newsnight</title>
+  </programme>
+  <programme start="not a time" channel="bbc1.london">
+    <title>Broken</title>
+  </programme>
+</tv>
+"#;
+        let (channels, programmes) = parse_xmltv(text).unwrap();
+        assert_eq!(channels, vec![XmltvChannel{id: "bbc1.london".to_string(), display_names: vec!["BBC One London".to_string(), "BBC1".to_string()]}]);
+        assert_eq!(programmes.len(), 2);
+        assert_eq!(programmes[0], Programme {
+            channel: "bbc1.london".to_string(),
+            start_time: 1603648800,
+            stop_time: Some(1603650600),
+            title: "The News at Ten".to_string(),
+            sub_title: "Sunday".to_string(),
+            description: "The latest news & weather.".to_string(),
+            category: Some("News".to_string()),
+        });
+        assert_eq!((programmes[1].title.as_str(), programmes[1].stop_time), ("Newsnight", None));
+        assert!(parse_xmltv("<guide/>").is_err());
+    }
+
+    #[test]
+    fn channels_are_mapped_by_mapping_then_id_then_display_name() {
+        let xmltv_channel = |id: &str, name: &str| XmltvChannel{id: id.to_string(), display_names: vec![name.to_string()]};
+        let xmltv_channels = vec![
+            xmltv_channel("1.4164.me-tv", "BBC ONE Lon"),
+            xmltv_channel("bbcnews.uk", "bbc news"),
+            xmltv_channel("itv1.london", "ITV1 London"),
+            xmltv_channel("bbc1.london", "BBC One London"),
+            xmltv_channel("unknown", "Unknown"),
+        ];
+        let mappings = vec![
+            ChannelMapping{xmltv_id: "itv1.london".to_string(), channel: Some("ITV".to_string()), service_id: None},
+            ChannelMapping{xmltv_id: "bbc1.london".to_string(), channel: None, service_id: Some(4164)},
+        ];
+        let channel_map = map_channels(&xmltv_channels, &mappings, &channels());
+        assert_eq!(channel_map.get("1.4164.me-tv"), Some(&4164));
+        assert_eq!(channel_map.get("bbcnews.uk"), Some(&4415));
+        assert_eq!(channel_map.get("itv1.london"), Some(&8261));
+        assert_eq!(channel_map.get("bbc1.london"), Some(&4164));
+        assert_eq!(channel_map.get("unknown"), None);
+    }
+
+    #[test]
+    fn programmes_of_mapped_channels_become_imported_events() {
+        let programme = |channel: &str, start_time: i64, stop_time: Option<i64>, title: &str| Programme {
+            channel: channel.to_string(),
+            start_time,
+            stop_time,
+            title: title.to_string(),
+            sub_title: String::new(),
+            description: String::new(),
+            category: None,
+        };
+        let programmes = vec![
+            programme("bbc1.london", 1603650600, None, "Newsnight"),
+            programme("bbc1.london", 1603648800, Some(1603650600), "The News at Ten"),
+            programme("bbc1.london", 1603653000, None, "Last, no stop time"),
+            programme("unknown", 1603648800, Some(1603650600), "Unknown"),
+        ];
+        let channel_map = vec![("bbc1.london".to_string(), 4164)].into_iter().collect::<HashMap<String, u16>>();
+        let events = events_of_programmes(&programmes, &channel_map);
+        assert_eq!(events.iter().map(|e| (e.title.as_str(), e.start_time, e.duration)).collect::<Vec<(&str, i64, u32)>>(),
+                   vec![("The News at Ten", 1603648800, 1800), ("Newsnight", 1603650600, 2400)]);
+        assert!(events.iter().all(|e| e.is_imported() && e.key.service_id == 4164));
+        assert_ne!(events[0].key, events[1].key);
+    }
+
+    #[test]
+    fn write_channels_and_programmes() {
+        let channels = vec![
+            ChannelData{name: "BBC ONE Lon".to_string(), service_id: 4164, logical_channel_number: 1, ..Default::default()},
+            ChannelData{name: "BBC NEWS".to_string(), service_id: 4415, logical_channel_number: 0, ..Default::default()},
+        ];
+        let events = vec![
+            event(4164, 2, 1603650600, "Newsnight", ""),
+            event(4164, 1, 1603648800, "The News at Ten", "The latest news & weather."),
+            event(5000, 1, 1603648800, "Not a known channel", ""),
+        ];
+        let mut buffer = Vec::new();
+        write_xmltv(&mut buffer, &channels, &events).unwrap();
+        let xmltv = String::from_utf8(buffer).unwrap();
+        let expected_lines = [
+            "  <channel id=\"1.4164.me-tv\">",
+            "    <display-name>BBC ONE Lon</display-name>",
+            "    <display-name>1</display-name>",
+            "  <channel id=\"4415.me-tv\">",
+            "  <programme start=\"20201025180000 +0000\" stop=\"20201025183000 +0000\" channel=\"1.4164.me-tv\">",
+            "    <desc>The latest news &amp; weather.</desc>",
+            "    <category>News/Current affairs</category>",
+            "</tv>",
+        ];
+        for line in expected_lines.iter() {
+            assert!(xmltv.lines().any(|l| l == *line), "missing line {}", line);
+        }
+        assert!(xmltv.find("The News at Ten").unwrap() < xmltv.find("Newsnight").unwrap());
+        assert!(! xmltv.contains("Not a known channel"));
+    }
+}